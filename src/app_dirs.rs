@@ -0,0 +1,166 @@
+//! Per-user directories for an application's files, following platform conventions.
+use crate::{dirs::ensure_dir, instance::runtime_dir};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Where an application keeps its configuration, caches, data, logs and
+/// runtime files. Made by [`app_dirs`].
+///
+/// | | Linux and other Unix | macOS | Windows |
+/// |---|---|---|---|
+/// | config | `$XDG_CONFIG_HOME/<app>` or `~/.config/<app>` | `~/Library/Application Support/<app>` | `%APPDATA%\<app>\config` |
+/// | cache | `$XDG_CACHE_HOME/<app>` or `~/.cache/<app>` | `~/Library/Caches/<app>` | `%LOCALAPPDATA%\<app>\cache` |
+/// | data | `$XDG_DATA_HOME/<app>` or `~/.local/share/<app>` | `~/Library/Application Support/<app>` | `%APPDATA%\<app>\data` |
+/// | log | `$XDG_STATE_HOME/<app>/log` or `~/.local/state/<app>/log` | `~/Library/Logs/<app>` | `%LOCALAPPDATA%\<app>\log` |
+/// | runtime | `$XDG_RUNTIME_DIR/<app>`, or `<app>` in the temporary directory | `<app>` in the temporary directory | `<app>` in the temporary directory |
+///
+/// The accessors create their directory on first use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppDirs {
+    config: PathBuf,
+    cache: PathBuf,
+    data: PathBuf,
+    log: PathBuf,
+    runtime: PathBuf,
+}
+
+/// Resolve the directories of the application called `app_name` for the current user.
+///
+/// # Errors
+/// `InvalidInput` if `app_name` is empty, starts with `.` or contains a path
+/// separator, and `NotFound` if the home directory is unknown.
+pub fn app_dirs(app_name: &str) -> io::Result<AppDirs> {
+    resolve(app_name, |var| env::var_os(var))
+}
+
+impl AppDirs {
+    /// The configuration directory, created if needed.
+    pub fn config(&self) -> io::Result<&Path> {
+        ensure_dir(&self.config).map(|_| self.config.as_path())
+    }
+
+    /// The cache directory, created if needed. Its contents may be deleted at any time.
+    pub fn cache(&self) -> io::Result<&Path> {
+        ensure_dir(&self.cache).map(|_| self.cache.as_path())
+    }
+
+    /// The data directory, created if needed.
+    pub fn data(&self) -> io::Result<&Path> {
+        ensure_dir(&self.data).map(|_| self.data.as_path())
+    }
+
+    /// The log directory, created if needed.
+    pub fn log(&self) -> io::Result<&Path> {
+        ensure_dir(&self.log).map(|_| self.log.as_path())
+    }
+
+    /// The directory for sockets, locks and other files that only matter while
+    /// the application runs, created if needed.
+    pub fn runtime(&self) -> io::Result<&Path> {
+        ensure_dir(&self.runtime).map(|_| self.runtime.as_path())
+    }
+}
+
+/// Helper function to resolve the directories of `app` with environment
+/// variables from `var`.
+fn resolve(app: &str, var: impl Fn(&str) -> Option<OsString>) -> io::Result<AppDirs> {
+    if app.is_empty() || app.starts_with('.') || app.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid application name `{}`", app),
+        ));
+    }
+    // Only absolute paths count; the XDG spec says to ignore relative ones.
+    let dir_var = |name: &str| var(name).map(PathBuf::from).filter(|p| p.is_absolute());
+    let missing = |name: &str| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("cannot find the user's directories: {} is not set", name),
+        )
+    };
+    let runtime = runtime_dir().join(app);
+
+    if cfg!(windows) {
+        let roaming = dir_var("APPDATA").ok_or_else(|| missing("APPDATA"))?;
+        let local = dir_var("LOCALAPPDATA").ok_or_else(|| missing("LOCALAPPDATA"))?;
+        return Ok(AppDirs {
+            config: roaming.join(app).join("config"),
+            cache: local.join(app).join("cache"),
+            data: roaming.join(app).join("data"),
+            log: local.join(app).join("log"),
+            runtime,
+        });
+    }
+    let home = dir_var("HOME").ok_or_else(|| missing("HOME"))?;
+    if cfg!(target_os = "macos") {
+        let library = home.join("Library");
+        return Ok(AppDirs {
+            config: library.join("Application Support").join(app),
+            cache: library.join("Caches").join(app),
+            data: library.join("Application Support").join(app),
+            log: library.join("Logs").join(app),
+            runtime,
+        });
+    }
+    let xdg = |name: &str, default: &str| dir_var(name).unwrap_or_else(|| home.join(default));
+    Ok(AppDirs {
+        config: xdg("XDG_CONFIG_HOME", ".config").join(app),
+        cache: xdg("XDG_CACHE_HOME", ".cache").join(app),
+        data: xdg("XDG_DATA_HOME", ".local/share").join(app),
+        log: xdg("XDG_STATE_HOME", ".local/state").join(app).join("log"),
+        runtime,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn app_dirs_follow_platform_conventions() {
+        // arrange
+        let dir = "assets/app_dirs_test";
+        let _ = fs::remove_dir_all(dir);
+        let home = env::current_dir().unwrap().join(dir);
+        let fake_env = |var: &str| match var {
+            "HOME" => Some(home.clone().into_os_string()),
+            "APPDATA" => Some(home.join("Roaming").into_os_string()),
+            "LOCALAPPDATA" => Some(home.join("Local").into_os_string()),
+            "XDG_CACHE_HOME" => Some(home.join("xdg-cache").into_os_string()),
+            "XDG_DATA_HOME" => Some("relative/is/ignored".into()),
+            _ => None,
+        };
+
+        // act
+        let dirs = resolve("demo", fake_env).unwrap();
+        let config = dirs.config().unwrap().to_path_buf();
+        let invalid = resolve("../demo", fake_env);
+        let homeless = resolve("demo", |_| None);
+
+        // assert
+        let expected = if cfg!(windows) {
+            (
+                home.join("Roaming/demo/config"),
+                home.join("Local/demo/cache"),
+            )
+        } else if cfg!(target_os = "macos") {
+            (
+                home.join("Library/Application Support/demo"),
+                home.join("Library/Caches/demo"),
+            )
+        } else {
+            assert_eq!(home.join(".local/share/demo"), dirs.data);
+            (home.join(".config/demo"), home.join("xdg-cache/demo"))
+        };
+        assert_eq!(expected, (config.clone(), dirs.cache.clone()));
+        assert!(config.is_dir());
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        assert_eq!(ErrorKind::NotFound, homeless.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
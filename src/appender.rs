@@ -0,0 +1,553 @@
+//! Appenders for files that must stay bounded in size or are shared by many writers.
+use crate::write_atomic;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// Appends lines to a file and keeps it under a maximum size by dropping the
+/// oldest lines, like a ring buffer on disk.
+///
+/// When a line would push the file over the cap, the head is cut at a line
+/// boundary with an extra eighth of the cap to spare, so the file is not
+/// rewritten on every append once full.
+#[derive(Debug)]
+pub struct CappedAppender {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+    len: u64,
+}
+
+impl CappedAppender {
+    /// Open the file at `path` for appending, creating it if needed. An existing
+    /// file over `max_bytes` is trimmed right away.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_appending(&path)?;
+        let len = file.metadata()?.len();
+        let mut appender = CappedAppender {
+            path,
+            file,
+            max_bytes,
+            len,
+        };
+        if len > max_bytes {
+            appender.trim(0)?;
+        }
+        Ok(appender)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// The current size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append `line` followed by a newline, dropping the oldest lines first if
+    /// the file would grow past the cap.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `line` alone does not fit under the cap.
+    pub fn append_line(&mut self, line: &str) -> io::Result<()> {
+        let mut record = String::with_capacity(line.len() + 1);
+        record.push_str(line);
+        record.push('\n');
+        let needed = record.len() as u64;
+        if needed > self.max_bytes {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "a line of {} bytes does not fit in {} capped at {} bytes",
+                    needed,
+                    self.path.display(),
+                    self.max_bytes
+                ),
+            ));
+        }
+        if self.len + needed > self.max_bytes {
+            self.trim(needed)?;
+        }
+        self.file.write_all(record.as_bytes())?;
+        self.len += needed;
+        Ok(())
+    }
+
+    /// Helper function to drop whole lines from the head until `reserve` more
+    /// bytes fit with slack to spare, then reopen the rewritten file.
+    fn trim(&mut self, reserve: u64) -> io::Result<()> {
+        let contents = fs::read(&self.path)?;
+        let slack = self.max_bytes / 8;
+        let target = self.max_bytes.saturating_sub(slack).max(reserve) - reserve;
+        let mut start = 0;
+        while (contents.len() - start) as u64 > target {
+            match contents[start..].iter().position(|&b| b == b'\n') {
+                Some(end) => start += end + 1,
+                None => start = contents.len(),
+            }
+        }
+        write_atomic(&self.path, &contents[start..])?;
+        self.file = open_appending(&self.path)?;
+        self.len = (contents.len() - start) as u64;
+        Ok(())
+    }
+}
+
+/// Appends lines to a segment file of its own in a shared directory, so any
+/// number of threads and processes can log to the directory without their
+/// writes ever interleaving. [`SegmentedAppender::merge`] reads the segments
+/// back as one stream ordered by time.
+///
+/// Each line is stored as `<nanoseconds since the epoch>\t<line>`.
+#[derive(Debug)]
+pub struct SegmentedAppender {
+    path: PathBuf,
+    writer: String,
+    file: File,
+    last_nanos: u64,
+}
+
+impl SegmentedAppender {
+    /// Open the segment of `writer` in `dir` for appending, creating both if needed.
+    /// Two appenders must not share a writer name.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `writer` is empty, starts with `.` or contains a path separator.
+    pub fn open<P: AsRef<Path>>(dir: P, writer: &str) -> io::Result<Self> {
+        if writer.is_empty() || writer.starts_with('.') || writer.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid segment writer name `{}`", writer),
+            ));
+        }
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir
+            .as_ref()
+            .join(format!("{}.{}", writer, SEGMENT_EXTENSION));
+        let file = open_appending(&path)?;
+        Ok(SegmentedAppender {
+            path,
+            writer: writer.to_owned(),
+            file,
+            last_nanos: 0,
+        })
+    }
+
+    /// Open a new segment in `dir` named after the current process and a
+    /// counter, unique among the appenders of this process.
+    pub fn open_unique<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        SegmentedAppender::open(dir, &format!("{}-{}", std::process::id(), n))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn writer(&self) -> &str {
+        &self.writer
+    }
+
+    /// Append `line`, stamped with the current time.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `line` contains a line break.
+    pub fn append_line(&mut self, line: &str) -> io::Result<()> {
+        if line.contains(['\n', '\r']) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "segment lines cannot contain line breaks",
+            ));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        // Never go back in time within a segment, even if the clock does.
+        self.last_nanos = now.max(self.last_nanos);
+        // One write per line, so a crash leaves at most a torn last line.
+        self.file
+            .write_all(format!("{}\t{}\n", self.last_nanos, line).as_bytes())
+    }
+
+    /// Read every segment in `dir` as one stream, ordered by the time lines
+    /// were appended. Lines with equal times are ordered by writer name, and
+    /// lines of the same writer keep their order.
+    pub fn merge<P: AsRef<Path>>(dir: P) -> io::Result<SegmentReader> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut reader = SegmentReader {
+            segments: Vec::with_capacity(paths.len()),
+            heads: BinaryHeap::new(),
+        };
+        for path in paths {
+            let writer = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let file = BufReader::new(File::open(&path)?);
+            reader.segments.push(Segment { path, writer, file });
+            reader.advance(reader.segments.len() - 1)?;
+        }
+        Ok(reader)
+    }
+}
+
+/// One line read back from a [`SegmentedAppender`] segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentRecord {
+    pub writer: String,
+    pub appended_at: SystemTime,
+    pub line: String,
+}
+
+/// The lines of all segments in a directory in time order, read lazily. Made
+/// by [`SegmentedAppender::merge`].
+#[derive(Debug)]
+pub struct SegmentReader {
+    segments: Vec<Segment>,
+    /// The next unread line of each segment that has one, by time then segment.
+    heads: BinaryHeap<Reverse<(u64, usize, String)>>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    writer: String,
+    file: BufReader<File>,
+}
+
+impl SegmentReader {
+    /// Helper function to queue the next line of segment `index`, if it has
+    /// a complete one.
+    fn advance(&mut self, index: usize) -> io::Result<()> {
+        let segment = &mut self.segments[index];
+        let mut line = String::new();
+        segment.file.read_line(&mut line)?;
+        // A line without its newline is still being written, or was torn by a crash.
+        let Some(line) = line.strip_suffix('\n') else {
+            return Ok(());
+        };
+        let (nanos, line) = line
+            .split_once('\t')
+            .and_then(|(nanos, line)| Some((nanos.parse().ok()?, line)))
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid segment {}", segment.path.display()),
+                )
+            })?;
+        self.heads.push(Reverse((nanos, index, line.to_owned())));
+        Ok(())
+    }
+}
+
+impl Iterator for SegmentReader {
+    type Item = io::Result<SegmentRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((nanos, index, line)) = self.heads.pop()?;
+        if let Err(e) = self.advance(index) {
+            return Some(Err(e));
+        }
+        Some(Ok(SegmentRecord {
+            writer: self.segments[index].writer.clone(),
+            appended_at: UNIX_EPOCH + Duration::from_nanos(nanos),
+            line,
+        }))
+    }
+}
+
+/// When a [`RotatingAppender`] starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Before a write would grow the file past this many bytes.
+    Size(u64),
+    /// On the first write of each new day, in UTC.
+    Daily,
+}
+
+/// Appends to a log file, moving it aside to `<name>.1` when it rotates and
+/// shifting older archives to `<name>.2`, `<name>.3` and so on.
+///
+/// Writes are buffered; call [`Write::flush`] to push them to the file.
+#[derive(Debug)]
+pub struct RotatingAppender {
+    path: PathBuf,
+    file: BufWriter<File>,
+    rotation: Rotation,
+    keep: usize,
+    compress: bool,
+    len: u64,
+    day: u64,
+}
+
+impl RotatingAppender {
+    /// Open the file at `path` for appending, creating it if needed, keeping
+    /// five archives by default.
+    pub fn open<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_appending(&path)?;
+        let metadata = file.metadata()?;
+        Ok(RotatingAppender {
+            path,
+            file: BufWriter::new(file),
+            rotation,
+            keep: 5,
+            compress: false,
+            len: metadata.len(),
+            day: day_of(metadata.modified()?),
+        })
+    }
+
+    /// Keep `keep` archives, deleting older ones; 0 discards the old file.
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Gzip archives from `<name>.2.gz` on. The newest archive stays plain,
+    /// so a process still writing to it when it was moved loses nothing.
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `line` followed by a newline, rotating first if due, so lines
+    /// are never split across files.
+    pub fn append_line(&mut self, line: &str) -> io::Result<()> {
+        let mut record = String::with_capacity(line.len() + 1);
+        record.push_str(line);
+        record.push('\n');
+        self.write_all(record.as_bytes())
+    }
+
+    /// Rotate now, whether or not it is due.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let archive = |n: usize, gz: bool| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}{}", n, if gz { ".gz" } else { "" }));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for gz in [false, true] {
+                remove_if_exists(&archive(self.keep, gz))?;
+            }
+            for n in (1..self.keep).rev() {
+                for gz in [false, true] {
+                    if archive(n, gz).exists() {
+                        fs::rename(archive(n, gz), archive(n + 1, gz))?;
+                    }
+                }
+            }
+            fs::rename(&self.path, archive(1, false))?;
+            let second = archive(2, false);
+            if self.compress && second.exists() {
+                gzip_file(&second, &archive(2, true))?;
+                fs::remove_file(second)?;
+            }
+        }
+        self.file = BufWriter::new(open_appending(&self.path)?);
+        self.len = 0;
+        self.day = day_of(SystemTime::now());
+        Ok(())
+    }
+
+    /// Helper function to decide whether writing `incoming` more bytes is due
+    /// to rotate the file.
+    fn due(&self, incoming: usize) -> bool {
+        match self.rotation {
+            Rotation::Size(max_bytes) => self.len > 0 && self.len + incoming as u64 > max_bytes,
+            Rotation::Daily => self.len > 0 && day_of(SystemTime::now()) != self.day,
+        }
+    }
+}
+
+impl Write for RotatingAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Helper function to count the days since the epoch at `time`.
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Helper function to compress the file at `src` into a new gzip file at `dst`.
+fn gzip_file(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(dst)?, Compression::default());
+    io::copy(&mut File::open(src)?, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+fn open_appending(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn capped_appender_drops_oldest_lines() {
+        // arrange
+        let dir = "assets/appender_capped_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/device.log", dir);
+        let mut appender = CappedAppender::open(&path, 100).unwrap();
+
+        // act
+        for i in 0..30 {
+            appender.append_line(&format!("line {:02}", i)).unwrap();
+        }
+        let too_long = appender.append_line(&"x".repeat(100));
+        let reopened = CappedAppender::open(&path, 40).unwrap();
+
+        // assert
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("line "));
+        assert!(contents.ends_with("line 29\n"));
+        assert!(reopened.len() <= 40);
+        assert_eq!(reopened.len(), contents.len() as u64);
+        assert_eq!(ErrorKind::InvalidInput, too_long.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segmented_appender_merges_writers_in_order() {
+        // arrange
+        let dir = "assets/appender_segmented_test";
+        let _ = fs::remove_dir_all(dir);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let mut appender = SegmentedAppender::open_unique(dir).unwrap();
+                    for i in 0..50 {
+                        appender.append_line(&format!("event {}", i)).unwrap();
+                    }
+                    appender.writer().to_owned()
+                })
+            })
+            .collect();
+        let writers: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let mut torn = SegmentedAppender::open(dir, "torn").unwrap();
+        torn.append_line("whole").unwrap();
+        torn.file.write_all(b"123\tpartial").unwrap();
+
+        // act
+        let merged = SegmentedAppender::merge(dir)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let invalid = torn.append_line("two\nlines");
+
+        // assert
+        assert_eq!(201, merged.len());
+        assert!(merged
+            .windows(2)
+            .all(|pair| pair[0].appended_at <= pair[1].appended_at));
+        for writer in &writers {
+            let lines: Vec<_> = merged
+                .iter()
+                .filter(|record| &record.writer == writer)
+                .map(|record| record.line.clone())
+                .collect();
+            let expected: Vec<_> = (0..50).map(|i| format!("event {}", i)).collect();
+            assert_eq!(expected, lines);
+        }
+        assert_eq!("whole", merged.last().unwrap().line);
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotating_appender_keeps_compressed_archives() {
+        // arrange
+        let dir = "assets/appender_rotating_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/app.log", dir);
+        let read = |name: &str| fs::read_to_string(format!("{}/{}", dir, name)).unwrap();
+
+        // act
+        let mut appender = RotatingAppender::open(&path, Rotation::Size(20))
+            .unwrap()
+            .keep(2)
+            .compress(true);
+        for i in 1..=7 {
+            appender.append_line(&format!("line {:04}", i)).unwrap();
+        }
+        appender.flush().unwrap();
+        let mut unzipped = String::new();
+        flate2::read::GzDecoder::new(File::open(format!("{}/app.log.2.gz", dir)).unwrap())
+            .read_to_string(&mut unzipped)
+            .unwrap();
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        // assert
+        assert_eq!("line 0007\n", read("app.log"));
+        assert_eq!("line 0005\nline 0006\n", read("app.log.1"));
+        assert_eq!("line 0003\nline 0004\n", unzipped);
+        assert_eq!(vec!["app.log", "app.log.1", "app.log.2.gz"], names);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
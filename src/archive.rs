@@ -0,0 +1,1390 @@
+//! Tar archives.
+use crate::{
+    checksum::{self, Sha256},
+    conflict::{local_side, resolved_path, ConflictResolver, FileConflict, FileSide},
+    error::{Context, Operation},
+    temp_sibling, OverwritePolicy,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+const BLOCK_SIZE: u64 = 512;
+/// Largest pax extended header we are willing to buffer.
+const MAX_PAX_HEADER: u64 = 1024 * 1024;
+/// Number of archived files whose contents are re-hashed during verification.
+const SPOT_CHECKS: usize = 8;
+
+/// The kind of entry stored in a tar archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Other(u8),
+}
+
+impl EntryType {
+    fn from_flag(flag: u8) -> Self {
+        match flag {
+            b'0' | 0 | b'7' => EntryType::File,
+            b'5' => EntryType::Dir,
+            b'2' => EntryType::Symlink,
+            other => EntryType::Other(other),
+        }
+    }
+
+    fn flag(&self) -> u8 {
+        match self {
+            EntryType::File => b'0',
+            EntryType::Dir => b'5',
+            EntryType::Symlink => b'2',
+            EntryType::Other(flag) => *flag,
+        }
+    }
+}
+
+/// Header information for one tar entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TarHeader {
+    pub(crate) path: String,
+    pub(crate) entry_type: EntryType,
+    pub(crate) size: u64,
+    pub(crate) mode: u32,
+    pub(crate) mtime: u64,
+    pub(crate) link_name: Option<String>,
+}
+
+/// Writes entries in ustar format, falling back to pax extended headers for long names.
+pub(crate) struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        TarWriter { inner }
+    }
+
+    pub(crate) fn append_dir(&mut self, path: &str, mode: u32, mtime: u64) -> io::Result<()> {
+        let path = if path.ends_with('/') {
+            path.to_owned()
+        } else {
+            format!("{}/", path)
+        };
+        self.write_header(&TarHeader {
+            path,
+            entry_type: EntryType::Dir,
+            size: 0,
+            mode,
+            mtime,
+            link_name: None,
+        })
+    }
+
+    pub(crate) fn append_symlink(
+        &mut self,
+        path: &str,
+        target: &str,
+        mtime: u64,
+    ) -> io::Result<()> {
+        self.write_header(&TarHeader {
+            path: path.to_owned(),
+            entry_type: EntryType::Symlink,
+            size: 0,
+            mode: 0o777,
+            mtime,
+            link_name: Some(target.to_owned()),
+        })
+    }
+
+    /// Append a file entry of exactly `size` bytes read from `data`.
+    /// The data is also fed to `hasher` when given.
+    pub(crate) fn append_file<R: Read>(
+        &mut self,
+        path: &str,
+        mode: u32,
+        mtime: u64,
+        size: u64,
+        mut data: R,
+        mut hasher: Option<&mut Sha256>,
+    ) -> io::Result<()> {
+        self.write_header(&TarHeader {
+            path: path.to_owned(),
+            entry_type: EntryType::File,
+            size,
+            mode,
+            mtime,
+            link_name: None,
+        })?;
+
+        let mut buf = [0; 64 * 1024];
+        let mut written = 0u64;
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            written += n as u64;
+            if written > size {
+                break;
+            }
+            if let Some(hasher) = hasher.as_deref_mut() {
+                hasher.update(&buf[..n]);
+            }
+            self.inner.write_all(&buf[..n])?;
+        }
+        if written != size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} changed size while being archived", path),
+            ));
+        }
+        self.write_padding(size)
+    }
+
+    /// Write the end-of-archive marker and return the underlying writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE as usize])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_padding(&mut self, size: u64) -> io::Result<()> {
+        let padding = padding_for(size);
+        self.inner
+            .write_all(&[0; BLOCK_SIZE as usize][..padding as usize])
+    }
+
+    fn write_header(&mut self, header: &TarHeader) -> io::Result<()> {
+        let split = split_ustar_name(&header.path);
+        let link_fits = header.link_name.as_ref().is_none_or(|l| l.len() <= 100);
+
+        if split.is_none() || !link_fits {
+            // Store the full names in a pax extended header preceding the entry.
+            let mut records = pax_record("path", &header.path);
+            if let Some(link) = header.link_name.as_ref().filter(|_| !link_fits) {
+                records.push_str(&pax_record("linkpath", link));
+            }
+            let pax = TarHeader {
+                path: "././@PaxHeader".to_owned(),
+                entry_type: EntryType::Other(b'x'),
+                size: records.len() as u64,
+                mode: 0o644,
+                mtime: header.mtime,
+                link_name: None,
+            };
+            self.inner
+                .write_all(&encode_header(&pax, ("", &pax.path)))?;
+            self.inner.write_all(records.as_bytes())?;
+            self.write_padding(records.len() as u64)?;
+        }
+
+        let (prefix, name) = split.unwrap_or(("", truncate_str(&header.path, 100)));
+        self.inner.write_all(&encode_header(header, (prefix, name)))
+    }
+}
+
+/// Reads entries sequentially from a tar stream.
+pub(crate) struct TarReader<R: Read> {
+    inner: R,
+    remaining: u64,
+    padding: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        TarReader {
+            inner,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// Advance to the next entry, skipping any unread data of the current one.
+    pub(crate) fn next_entry(&mut self) -> io::Result<Option<TarHeader>> {
+        self.skip_data()?;
+
+        let mut long_path: Option<String> = None;
+        let mut long_link: Option<String> = None;
+        let mut pax_size: Option<u64> = None;
+        loop {
+            let mut block = [0; BLOCK_SIZE as usize];
+            self.inner.read_exact(&mut block)?;
+            if block.iter().all(|b| *b == 0) {
+                return Ok(None);
+            }
+            let mut header = decode_header(&block)?;
+            self.remaining = header.size;
+            self.padding = padding_for(header.size);
+
+            match header.entry_type {
+                EntryType::Other(b'x') | EntryType::Other(b'g') => {
+                    let data = self.read_small_data()?;
+                    if header.entry_type == EntryType::Other(b'x') {
+                        for (key, value) in parse_pax_records(&data)? {
+                            match key.as_str() {
+                                "path" => long_path = Some(value),
+                                "linkpath" => long_link = Some(value),
+                                "size" => pax_size = value.parse().ok(),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                EntryType::Other(b'L') => {
+                    long_path = Some(nul_terminated(&self.read_small_data()?));
+                }
+                EntryType::Other(b'K') => {
+                    long_link = Some(nul_terminated(&self.read_small_data()?));
+                }
+                _ => {
+                    if let Some(path) = long_path.take() {
+                        header.path = path;
+                    }
+                    if let Some(link) = long_link.take() {
+                        header.link_name = Some(link);
+                    }
+                    if let Some(size) = pax_size.take() {
+                        header.size = size;
+                        self.remaining = size;
+                        self.padding = padding_for(size);
+                    }
+                    return Ok(Some(header));
+                }
+            }
+        }
+    }
+
+    /// A reader over the data of the current entry.
+    pub(crate) fn data(&mut self) -> EntryData<'_, R> {
+        EntryData { reader: self }
+    }
+
+    fn read_small_data(&mut self) -> io::Result<Vec<u8>> {
+        if self.remaining > MAX_PAX_HEADER {
+            return Err(invalid_archive("extended header is too large"));
+        }
+        let mut data = vec![0; self.remaining as usize];
+        self.inner.read_exact(&mut data)?;
+        self.remaining = 0;
+        self.skip_data()?;
+        Ok(data)
+    }
+
+    fn skip_data(&mut self) -> io::Result<()> {
+        let skip = self.remaining + self.padding;
+        let skipped = io::copy(&mut (&mut self.inner).take(skip), &mut io::sink())?;
+        if skipped != skip {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "tar archive is truncated",
+            ));
+        }
+        self.remaining = 0;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+/// Reader over the data of a single tar entry.
+pub(crate) struct EntryData<'a, R: Read> {
+    reader: &'a mut TarReader<R>,
+}
+
+impl<R: Read> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.reader.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.reader.remaining as usize);
+        let n = self.reader.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "tar archive is truncated",
+            ));
+        }
+        self.reader.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn padding_for(size: u64) -> u64 {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+/// Split `path` into ustar `(prefix, name)` fields, or `None` if it does not fit.
+fn split_ustar_name(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.char_indices()
+        .filter(|(_, c)| *c == '/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+fn truncate_str(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn pax_record(key: &str, value: &str) -> String {
+    // The length prefix counts itself, so grow it until it is self-consistent.
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len();
+    loop {
+        let candidate = len.to_string().len() + body.len();
+        if candidate == len {
+            return format!("{}{}", len, body);
+        }
+        len = candidate;
+    }
+}
+
+fn parse_pax_records(data: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|b| *b == b' ')
+            .ok_or_else(|| invalid_archive("malformed pax record"))?;
+        let len: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .filter(|l| *l > space && *l <= rest.len())
+            .ok_or_else(|| invalid_archive("malformed pax record"))?;
+        let record = String::from_utf8_lossy(&rest[space + 1..len - 1]);
+        if let Some((key, value)) = record.split_once('=') {
+            records.push((key.to_owned(), value.to_owned()));
+        }
+        rest = &rest[len..];
+    }
+    Ok(records)
+}
+
+fn nul_terminated(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn write_field(block: &mut [u8], offset: usize, len: usize, value: &[u8]) {
+    let n = value.len().min(len);
+    block[offset..offset + n].copy_from_slice(&value[..n]);
+}
+
+/// Write `value` as a NUL terminated octal number, or in base-256 if it is too large.
+fn write_number(block: &mut [u8], offset: usize, len: usize, value: u64) {
+    let octal = format!("{:0width$o}", value, width = len - 1);
+    if octal.len() < len {
+        write_field(block, offset, len, octal.as_bytes());
+        block[offset + len - 1] = 0;
+    } else {
+        let field = &mut block[offset..offset + len];
+        field.fill(0);
+        field[len - 8..].copy_from_slice(&value.to_be_bytes());
+        field[0] = 0x80;
+    }
+}
+
+fn read_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value = 0u64;
+        for (i, byte) in field.iter().enumerate() {
+            let byte = if i == 0 { byte & 0x7f } else { *byte };
+            value = value
+                .checked_mul(256)
+                .and_then(|v| v.checked_add(u64::from(byte)))
+                .ok_or_else(|| invalid_archive("numeric field overflow"))?;
+        }
+        return Ok(value);
+    }
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid_archive("invalid numeric field"))
+}
+
+fn header_checksum(block: &[u8; BLOCK_SIZE as usize]) -> u64 {
+    block
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (148..156).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(*b)
+            }
+        })
+        .sum()
+}
+
+fn encode_header(header: &TarHeader, (prefix, name): (&str, &str)) -> [u8; BLOCK_SIZE as usize] {
+    let mut block = [0; BLOCK_SIZE as usize];
+    write_field(&mut block, 0, 100, name.as_bytes());
+    write_number(&mut block, 100, 8, u64::from(header.mode & 0o7777));
+    write_number(&mut block, 108, 8, 0);
+    write_number(&mut block, 116, 8, 0);
+    write_number(&mut block, 124, 12, header.size);
+    write_number(&mut block, 136, 12, header.mtime);
+    block[156] = header.entry_type.flag();
+    if let Some(link) = &header.link_name {
+        write_field(&mut block, 157, 100, truncate_str(link, 100).as_bytes());
+    }
+    write_field(&mut block, 257, 6, b"ustar\0");
+    write_field(&mut block, 263, 2, b"00");
+    write_field(&mut block, 345, 155, prefix.as_bytes());
+
+    let checksum = format!("{:06o}\0 ", header_checksum(&block));
+    write_field(&mut block, 148, 8, checksum.as_bytes());
+    block
+}
+
+fn decode_header(block: &[u8; BLOCK_SIZE as usize]) -> io::Result<TarHeader> {
+    if read_number(&block[148..156])? != header_checksum(block) {
+        return Err(invalid_archive("header checksum mismatch"));
+    }
+    let name = nul_terminated(&block[0..100]);
+    let path = if &block[257..262] == b"ustar" && block[345] != 0 {
+        format!("{}/{}", nul_terminated(&block[345..500]), name)
+    } else {
+        name
+    };
+    let link = nul_terminated(&block[157..257]);
+
+    Ok(TarHeader {
+        path,
+        entry_type: EntryType::from_flag(block[156]),
+        size: read_number(&block[124..136])?,
+        mode: read_number(&block[100..108])? as u32,
+        mtime: read_number(&block[136..148])?,
+        link_name: if link.is_empty() { None } else { Some(link) },
+    })
+}
+
+fn invalid_archive(msg: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid tar archive: {}", msg),
+    )
+}
+
+/// A file recorded in an archive manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedFile {
+    /// Path of the entry inside the archive, starting with the archived directory's name.
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Hex encoded SHA-256 of the file's contents.
+    pub sha256: String,
+}
+
+/// Everything written to the archive, used to verify it and then remove the originals.
+#[derive(Default)]
+struct ArchivePlan {
+    files: Vec<ArchivedFile>,
+    /// Source paths of archived files and symlinks.
+    sources: Vec<PathBuf>,
+    /// Archived directories, parents before children.
+    dirs: Vec<PathBuf>,
+}
+
+/// Archive `dir` into a tar file at `archive_path`, verify the archive, and only then
+/// delete the archived originals.
+///
+/// Verification lists the archive to make sure every file is present with the expected
+/// size, and re-hashes a sample of the archived contents against the source hashes.
+/// Entries in the archive are prefixed with the name of `dir`. Files that appear in `dir`
+/// while it is being archived are left in place, along with the directories containing them.
+///
+/// # Returns
+/// A manifest of every archived file.
+pub fn archive_and_remove<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    archive_path: Q,
+) -> io::Result<Vec<ArchivedFile>> {
+    let archive_path = archive_path.as_ref();
+    let (dir, root_name) = archive_source(dir.as_ref(), archive_path)?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(archive_path)?;
+    let result = write_archive(file, &dir, &root_name)
+        .and_then(|plan| verify_archive(archive_path, &plan).map(|_| plan));
+    let plan = match result {
+        Ok(plan) => plan,
+        Err(e) => {
+            let _ = fs::remove_file(archive_path);
+            return Err(e);
+        }
+    };
+
+    for source in &plan.sources {
+        fs::remove_file(source)?;
+    }
+    for dir in plan.dirs.iter().rev() {
+        if let Err(e) = fs::remove_dir(dir) {
+            if e.kind() != ErrorKind::DirectoryNotEmpty {
+                return Err(e);
+            }
+        }
+    }
+    Ok(plan.files)
+}
+
+/// Archive `dir` into a tar file at `archive_path`, replacing any file there.
+///
+/// Entries in the archive are prefixed with the name of `dir`, so
+/// [`extract_tar`] recreates the directory inside its destination. Symlinks
+/// are archived as links, not followed.
+///
+/// # Returns
+/// A manifest of every archived file.
+pub fn create_tar<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    archive_path: Q,
+) -> io::Result<Vec<ArchivedFile>> {
+    let archive_path = archive_path.as_ref();
+    let (dir, root_name) = archive_source(dir.as_ref(), archive_path)?;
+    let tmp_path = temp_sibling(archive_path, "tmp");
+    let result = File::create_new(&tmp_path)
+        .and_then(|file| write_archive(file, &dir, &root_name))
+        .and_then(|plan| fs::rename(&tmp_path, archive_path).map(|_| plan.files));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Helper function to resolve the directory to archive and the name its
+/// entries are prefixed with, refusing to write the archive inside it.
+pub(crate) fn archive_source(dir: &Path, archive_path: &Path) -> io::Result<(PathBuf, String)> {
+    let dir = fs::canonicalize(dir)?;
+    let archive_parent = match archive_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::canonicalize(parent)?,
+        _ => std::env::current_dir()?,
+    };
+    if archive_parent.starts_with(&dir) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the archive cannot be written inside the directory being archived",
+        ));
+    }
+    let root_name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "directory has no usable name"))?
+        .to_owned();
+    Ok((dir, root_name))
+}
+
+fn write_archive(file: File, dir: &Path, root_name: &str) -> io::Result<ArchivePlan> {
+    let mut tar = TarWriter::new(BufWriter::new(file));
+    let mut plan = ArchivePlan::default();
+    append_dir_recursive(&mut tar, dir, root_name, &mut plan)?;
+    let file = tar.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(plan)
+}
+
+fn append_dir_recursive<W: Write>(
+    tar: &mut TarWriter<W>,
+    dir: &Path,
+    archive_name: &str,
+    plan: &mut ArchivePlan,
+) -> io::Result<()> {
+    let metadata = fs::metadata(dir)?;
+    tar.append_dir(archive_name, file_mode(&metadata), mtime_secs(&metadata))?;
+    plan.dirs.push(dir.to_path_buf());
+
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("cannot archive non UTF-8 path {}", path.display()),
+            )
+        })?;
+        let entry_name = format!("{}/{}", archive_name, name);
+        let metadata = fs::symlink_metadata(&path)?;
+
+        if metadata.is_dir() {
+            append_dir_recursive(tar, &path, &entry_name, plan)?;
+        } else if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path)?;
+            let target = target.to_str().ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("cannot archive non UTF-8 link {}", path.display()),
+                )
+            })?;
+            tar.append_symlink(&entry_name, target, mtime_secs(&metadata))?;
+            plan.sources.push(path);
+        } else if metadata.is_file() {
+            let mut hasher = Sha256::new();
+            tar.append_file(
+                &entry_name,
+                file_mode(&metadata),
+                mtime_secs(&metadata),
+                metadata.len(),
+                File::open(&path)?,
+                Some(&mut hasher),
+            )?;
+            plan.files.push(ArchivedFile {
+                path: PathBuf::from(entry_name),
+                size: metadata.len(),
+                sha256: checksum::to_hex(&hasher.finish()),
+            });
+            plan.sources.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Check that every planned file is in the archive with the right size and
+/// that a sample of them hash to the same value as their sources.
+fn verify_archive(archive_path: &Path, plan: &ArchivePlan) -> io::Result<()> {
+    let count = plan.files.len();
+    let spot_checks: HashSet<usize> = (0..SPOT_CHECKS.min(count))
+        .map(|i| i * count / SPOT_CHECKS.min(count))
+        .collect();
+    let mut expected: HashMap<&str, (usize, &ArchivedFile)> = plan
+        .files
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| f.path.to_str().map(|p| (p, (i, f))))
+        .collect();
+
+    let mut tar = TarReader::new(BufReader::new(File::open(archive_path)?));
+    while let Some(header) = tar.next_entry()? {
+        if header.entry_type != EntryType::File {
+            continue;
+        }
+        let Some((index, file)) = expected.remove(header.path.as_str()) else {
+            continue;
+        };
+        if header.size != file.size {
+            return Err(verification_failed(&file.path, "size mismatch"));
+        }
+        if spot_checks.contains(&index) && checksum::sha256_reader(tar.data())? != file.sha256 {
+            return Err(verification_failed(&file.path, "checksum mismatch"));
+        }
+    }
+
+    match expected.values().next() {
+        Some((_, missing)) => Err(verification_failed(&missing.path, "missing from archive")),
+        None => Ok(()),
+    }
+}
+
+fn verification_failed(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "archive verification failed for {}: {}",
+            path.display(),
+            reason
+        ),
+    )
+}
+
+/// Guards applied while extracting an archive, against archives crafted to
+/// fill the disk or write outside the destination. `None` disables a guard.
+/// Entries that would land outside the destination are always rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractionLimits {
+    /// Total bytes written across all entries, 4 GiB by default.
+    pub max_total_bytes: Option<u64>,
+    /// Number of entries of any kind, 100 000 by default.
+    pub max_entries: Option<usize>,
+    /// Bytes written per byte of archive, 100 by default. Only bites for
+    /// compressed archives, where a tiny file can expand enormously.
+    pub max_compression_ratio: Option<u64>,
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        ExtractionLimits {
+            max_total_bytes: Some(4 * 1024 * 1024 * 1024),
+            max_entries: Some(100_000),
+            max_compression_ratio: Some(100),
+        }
+    }
+}
+
+/// The guard an archive tripped during extraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractionLimit {
+    TotalBytes,
+    Entries,
+    CompressionRatio,
+    /// An entry or link pointed outside the destination.
+    PathTraversal,
+}
+
+/// The error payload when an archive trips an [`ExtractionLimits`] guard.
+/// Use [`extraction_limit_exceeded`] to recognise it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractionLimitExceeded {
+    pub limit: ExtractionLimit,
+    /// The entry being extracted when the guard tripped.
+    pub entry: String,
+}
+
+impl fmt::Display for ExtractionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.limit {
+            ExtractionLimit::TotalBytes => "the total size limit",
+            ExtractionLimit::Entries => "the entry count limit",
+            ExtractionLimit::CompressionRatio => "the compression ratio limit",
+            ExtractionLimit::PathTraversal => "the destination directory",
+        };
+        write!(f, "extracting `{}` would exceed {}", self.entry, what)
+    }
+}
+
+impl Error for ExtractionLimitExceeded {}
+
+/// The [`ExtractionLimitExceeded`] that caused `err`, if any.
+pub fn extraction_limit_exceeded(err: &io::Error) -> Option<&ExtractionLimitExceeded> {
+    err.get_ref()?.downcast_ref()
+}
+
+fn limit_exceeded(limit: ExtractionLimit, entry: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        ExtractionLimitExceeded {
+            limit,
+            entry: entry.to_owned(),
+        },
+    )
+}
+
+/// Counts what an extraction has produced and enforces [`ExtractionLimits`].
+pub(crate) struct LimitTracker {
+    limits: ExtractionLimits,
+    archive_len: u64,
+    entries: usize,
+    total_bytes: u64,
+}
+
+impl LimitTracker {
+    /// Track an extraction from an archive that is `archive_len` bytes on disk.
+    pub(crate) fn new(limits: ExtractionLimits, archive_len: u64) -> Self {
+        LimitTracker {
+            limits,
+            archive_len,
+            entries: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Count one more entry.
+    pub(crate) fn entry(&mut self, entry: &str) -> io::Result<()> {
+        self.entries += 1;
+        if self
+            .limits
+            .max_entries
+            .is_some_and(|max| self.entries > max)
+        {
+            return Err(limit_exceeded(ExtractionLimit::Entries, entry));
+        }
+        Ok(())
+    }
+
+    /// Count `n` more bytes written for `entry`.
+    fn bytes(&mut self, entry: &str, n: u64) -> io::Result<()> {
+        self.total_bytes += n;
+        if self
+            .limits
+            .max_total_bytes
+            .is_some_and(|max| self.total_bytes > max)
+        {
+            return Err(limit_exceeded(ExtractionLimit::TotalBytes, entry));
+        }
+        if self
+            .limits
+            .max_compression_ratio
+            .is_some_and(|ratio| self.total_bytes > self.archive_len.max(1).saturating_mul(ratio))
+        {
+            return Err(limit_exceeded(ExtractionLimit::CompressionRatio, entry));
+        }
+        Ok(())
+    }
+
+    /// Copy `reader` to `writer`, counting every chunk before it is written.
+    pub(crate) fn copy<R: Read, W: Write>(
+        &mut self,
+        entry: &str,
+        mut reader: R,
+        mut writer: W,
+    ) -> io::Result<u64> {
+        let mut buf = [0; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(copied),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.bytes(entry, n as u64)?;
+            writer.write_all(&buf[..n])?;
+            copied += n as u64;
+        }
+    }
+}
+
+/// Helper function to resolve the archive entry `entry` inside `dest`.
+///
+/// Rejects absolute paths, `..` components and paths that pass through or end
+/// at a symlink already on disk, any of which could reach outside `dest`.
+pub(crate) fn safe_join(dest: &Path, entry: &str) -> io::Result<PathBuf> {
+    let mut path = dest.to_path_buf();
+    for component in Path::new(entry).components() {
+        match component {
+            Component::Normal(name) => {
+                if is_symlink(&path) {
+                    return Err(limit_exceeded(ExtractionLimit::PathTraversal, entry));
+                }
+                path.push(name);
+            }
+            Component::CurDir => {}
+            _ => return Err(limit_exceeded(ExtractionLimit::PathTraversal, entry)),
+        }
+    }
+    if is_symlink(&path) {
+        return Err(limit_exceeded(ExtractionLimit::PathTraversal, entry));
+    }
+    Ok(path)
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Helper function to create a new file at `path`, never following a symlink
+/// that appeared there since it was checked.
+pub(crate) fn create_new_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Helper function to check that a symlink at `entry` pointing to `target`
+/// stays inside the extraction root.
+fn check_link_target(entry: &str, target: &str) -> io::Result<()> {
+    // Only named components nest; a leading `./` does not.
+    let mut depth = Path::new(entry)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count() as isize
+        - 1;
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return Err(limit_exceeded(ExtractionLimit::PathTraversal, entry)),
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to check that resolving `target` from the symlink at
+/// `link` does not pass through another symlink, on disk or among `links`
+/// still to be created, whose own target [`check_link_target`] cannot see.
+#[cfg(unix)]
+fn check_link_hops(
+    link: &Path,
+    entry: &str,
+    target: &str,
+    links: &HashSet<PathBuf>,
+) -> io::Result<()> {
+    let mut path = link.parent().unwrap_or(link).to_path_buf();
+    let components: Vec<_> = Path::new(target).components().collect();
+    for (i, component) in components.iter().enumerate() {
+        match component {
+            Component::Normal(name) => {
+                path.push(name);
+                let last = i + 1 == components.len();
+                if !last && (links.contains(&path) || is_symlink(&path)) {
+                    return Err(limit_exceeded(ExtractionLimit::PathTraversal, entry));
+                }
+            }
+            Component::ParentDir => {
+                path.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to apply the modes of extracted directories, children
+/// first, once nothing more has to be written into them.
+pub(crate) fn set_dir_modes(dirs: &[(PathBuf, u32)]) -> io::Result<()> {
+    for (dir, mode) in dirs.iter().rev() {
+        set_mode(dir, *mode).context(Operation::Write, dir)?;
+    }
+    Ok(())
+}
+
+/// Extract the tar archive at `archive_path` into `dest`, with the default
+/// [`ExtractionLimits`].
+///
+/// Entries with absolute paths or `..` components, or that would be written
+/// through a symlink, are rejected before anything is written for them.
+///
+/// # Returns
+/// The paths of the extracted files.
+pub fn extract_tar<P: AsRef<Path>, Q: AsRef<Path>>(
+    archive_path: P,
+    dest: Q,
+) -> io::Result<Vec<PathBuf>> {
+    extract_tar_with_limits(archive_path, dest, &ExtractionLimits::default())
+}
+
+/// Extract the tar archive at `archive_path` into `dest`, enforcing `limits`.
+///
+/// Files, directories and, on Unix, symlinks are extracted with their
+/// permission bits; other entry types are skipped. Symlinks are created after
+/// everything else, so no entry is ever written through one, and directory
+/// permissions are applied last, so read-only trees extract. A symlink whose
+/// target passes through another symlink is rejected. Nothing is cleaned up
+/// when a guard trips, so extract into a fresh directory.
+///
+/// # Returns
+/// The paths of the extracted files.
+///
+/// # Errors
+/// An [`ExtractionLimitExceeded`] payload when a guard trips; check with
+/// [`extraction_limit_exceeded`].
+pub fn extract_tar_with_limits<P: AsRef<Path>, Q: AsRef<Path>>(
+    archive_path: P,
+    dest: Q,
+    limits: &ExtractionLimits,
+) -> io::Result<Vec<PathBuf>> {
+    extract_tar_with_resolver(archive_path, dest, limits, &mut OverwritePolicy::Overwrite)
+}
+
+/// Like [`extract_tar_with_limits`], asking `resolver` what to do with each
+/// file that already exists in `dest`. Such entries are first extracted next
+/// to the existing file, so the resolver sees both in full.
+pub fn extract_tar_with_resolver<P, Q, R>(
+    archive_path: P,
+    dest: Q,
+    limits: &ExtractionLimits,
+    resolver: &mut R,
+) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: ConflictResolver + ?Sized,
+{
+    let archive_path = archive_path.as_ref();
+    let archive = File::open(archive_path).context(Operation::Open, archive_path)?;
+    let archive_len = archive
+        .metadata()
+        .context(Operation::Stat, archive_path)?
+        .len();
+    let mut tracker = LimitTracker::new(*limits, archive_len);
+    let dest = dest.as_ref();
+    fs::create_dir_all(dest).context(Operation::Create, dest)?;
+    let mut tar = TarReader::new(BufReader::new(archive));
+    let mut extracted = Vec::new();
+    let mut dirs = Vec::new();
+    let mut links = Vec::new();
+    while let Some(header) = tar.next_entry().context(Operation::Read, archive_path)? {
+        tracker.entry(&header.path)?;
+        let path = safe_join(dest, &header.path)?;
+        match header.entry_type {
+            EntryType::Dir => {
+                fs::create_dir_all(&path).context(Operation::Create, &path)?;
+                dirs.push((path, header.mode));
+            }
+            EntryType::File => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).context(Operation::Create, parent)?;
+                }
+                if fs::symlink_metadata(&path).is_err() {
+                    let mut file = create_new_file(&path).context(Operation::Create, &path)?;
+                    tracker.copy(&header.path, tar.data(), &mut file)?;
+                    set_mode(&path, header.mode).context(Operation::Write, &path)?;
+                    extracted.push(path);
+                    continue;
+                }
+                let staged = temp_sibling(&path, "extract");
+                let result = (|| -> io::Result<Option<PathBuf>> {
+                    let mut file = create_new_file(&staged).context(Operation::Create, &staged)?;
+                    tracker.copy(&header.path, tar.data(), &mut file)?;
+                    let hash = resolver.wants_hashes();
+                    let conflict = FileConflict {
+                        source: FileSide {
+                            path: PathBuf::from(&header.path),
+                            sha256: local_side(&staged, hash)?.sha256,
+                            size: header.size,
+                            modified: Some(UNIX_EPOCH + Duration::from_secs(header.mtime)),
+                        },
+                        destination: local_side(&path, hash)?,
+                    };
+                    let resolution = resolver.resolve(&conflict)?;
+                    match resolved_path(resolution, &path, "extract", Path::exists)? {
+                        Some(target) => {
+                            fs::rename(&staged, &target).context(Operation::Move, &staged)?;
+                            set_mode(&target, header.mode).context(Operation::Write, &target)?;
+                            Ok(Some(target))
+                        }
+                        None => Ok(None),
+                    }
+                })();
+                let _ = fs::remove_file(&staged);
+                extracted.extend(result?);
+            }
+            EntryType::Symlink => {
+                let target = header.link_name.unwrap_or_default();
+                check_link_target(&header.path, &target)?;
+                links.push((header.path, target));
+            }
+            EntryType::Other(_) => {}
+        }
+    }
+    #[cfg(unix)]
+    {
+        let link_paths = links
+            .iter()
+            .map(|(entry, _)| safe_join(dest, entry))
+            .collect::<io::Result<HashSet<_>>>()?;
+        for (entry, target) in &links {
+            // Checked again: an earlier entry may have put a symlink on the way.
+            let path = safe_join(dest, entry)?;
+            check_link_hops(&path, entry, target, &link_paths)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context(Operation::Create, parent)?;
+            }
+            std::os::unix::fs::symlink(target, &path).context(Operation::Create, &path)?;
+        }
+    }
+    set_dir_modes(&dirs)?;
+    Ok(extracted)
+}
+
+#[cfg(unix)]
+pub(crate) fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn mtime_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+pub(crate) fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_mode(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Make sure names longer than the ustar fields survive a round trip.
+    fn tar_round_trip_works() {
+        // arrange
+        let long_name = format!("{}/{}.txt", "d".repeat(160), "f".repeat(120));
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_dir("root", 0o755, 1).unwrap();
+        tar.append_file("root/a.txt", 0o644, 2, 5, &b"hello"[..], None)
+            .unwrap();
+        tar.append_file(&long_name, 0o600, 3, 3, &b"abc"[..], None)
+            .unwrap();
+        tar.append_symlink("root/link", "a.txt", 4).unwrap();
+        let bytes = tar.finish().unwrap();
+
+        // act
+        let mut reader = TarReader::new(bytes.as_slice());
+        let mut entries = Vec::new();
+        while let Some(header) = reader.next_entry().unwrap() {
+            let mut data = String::new();
+            reader.data().read_to_string(&mut data).unwrap();
+            entries.push((header, data));
+        }
+
+        // assert
+        assert_eq!(4, entries.len());
+        assert_eq!("root/", entries[0].0.path);
+        assert_eq!(EntryType::Dir, entries[0].0.entry_type);
+        assert_eq!(
+            ("root/a.txt", "hello"),
+            (entries[1].0.path.as_str(), entries[1].1.as_str())
+        );
+        assert_eq!(long_name, entries[2].0.path);
+        assert_eq!("abc", entries[2].1);
+        assert_eq!(0o600, entries[2].0.mode);
+        assert_eq!(Some("a.txt".to_owned()), entries[3].0.link_name);
+    }
+
+    #[test]
+    fn archive_and_remove_works() {
+        // arrange
+        let dir = "assets/archive_purge_test";
+        let archive = "assets/archive_purge_test.tar";
+        let _ = fs::remove_dir_all(dir);
+        let _ = fs::remove_file(archive);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        fs::write(format!("{}/a.txt", dir), "first").unwrap();
+        fs::write(format!("{}/nested/b.txt", dir), "second").unwrap();
+
+        // act
+        let manifest = archive_and_remove(dir, archive).unwrap();
+        let mut reader = TarReader::new(File::open(archive).unwrap());
+        let mut listed = Vec::new();
+        while let Some(header) = reader.next_entry().unwrap() {
+            listed.push(header.path);
+        }
+
+        // assert
+        assert_eq!(2, manifest.len());
+        assert_eq!(PathBuf::from("archive_purge_test/a.txt"), manifest[0].path);
+        assert_eq!(5, manifest[0].size);
+        assert_eq!(
+            checksum::sha256_reader(&b"second"[..]).unwrap(),
+            manifest[1].sha256
+        );
+        assert_eq!(
+            vec![
+                "archive_purge_test/",
+                "archive_purge_test/a.txt",
+                "archive_purge_test/nested/",
+                "archive_purge_test/nested/b.txt",
+            ],
+            listed
+        );
+        assert!(!Path::new(dir).exists());
+        fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn create_tar_round_trips_through_extract_tar() {
+        // arrange
+        let dir = "assets/archive_create_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/backup/nested", dir)).unwrap();
+        fs::write(format!("{}/backup/a.txt", dir), "first").unwrap();
+        fs::write(format!("{}/backup/nested/b.txt", dir), "second").unwrap();
+        let archive = format!("{}/backup.tar", dir);
+        fs::write(&archive, "stale").unwrap();
+
+        // act
+        let manifest = create_tar(format!("{}/backup", dir), &archive).unwrap();
+        let extracted = extract_tar(&archive, format!("{}/restored", dir)).unwrap();
+
+        // assert
+        assert_eq!(2, manifest.len());
+        assert_eq!(2, extracted.len());
+        assert_eq!(
+            "second",
+            fs::read_to_string(format!("{}/restored/backup/nested/b.txt", dir)).unwrap()
+        );
+        assert!(Path::new(&format!("{}/backup/a.txt", dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn archive_and_remove_rejects_archive_inside_dir() {
+        // arrange
+        let dir = "assets/archive_inside_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{}/a.txt", dir), "keep").unwrap();
+
+        // act
+        let result = archive_and_remove(dir, format!("{}/self.tar", dir));
+
+        // assert
+        assert_eq!(ErrorKind::InvalidInput, result.unwrap_err().kind());
+        assert!(Path::new(&format!("{}/a.txt", dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_tar_enforces_limits() {
+        // arrange
+        let dir = "assets/archive_extract_limits_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let write_tar = |name: &str, entries: &[(&str, &[u8])]| {
+            let path = format!("{}/{}", dir, name);
+            let mut tar = TarWriter::new(File::create(&path).unwrap());
+            for (entry, data) in entries {
+                tar.append_file(entry, 0o644, 0, data.len() as u64, *data, None)
+                    .unwrap();
+            }
+            tar.finish().unwrap();
+            path
+        };
+        let good = write_tar("good.tar", &[("a.txt", b"alpha"), ("sub/b.txt", b"beta")]);
+        let evil = write_tar(
+            "evil.tar",
+            &[("ok.txt", b"ok"), ("../escape.txt", b"gotcha")],
+        );
+        let limits = ExtractionLimits {
+            max_entries: Some(1),
+            ..ExtractionLimits::default()
+        };
+
+        // act
+        let extracted =
+            extract_tar_with_limits(&good, format!("{}/good", dir), &ExtractionLimits::default())
+                .unwrap();
+        let traversal =
+            extract_tar_with_limits(&evil, format!("{}/evil", dir), &ExtractionLimits::default())
+                .unwrap_err();
+        let too_many =
+            extract_tar_with_limits(&good, format!("{}/limited", dir), &limits).unwrap_err();
+
+        // assert
+        assert_eq!(2, extracted.len());
+        assert_eq!(
+            "beta",
+            fs::read_to_string(format!("{}/good/sub/b.txt", dir)).unwrap()
+        );
+        assert_eq!(
+            Some(ExtractionLimit::PathTraversal),
+            extraction_limit_exceeded(&traversal).map(|e| e.limit)
+        );
+        assert!(!Path::new(&format!("{}/escape.txt", dir)).exists());
+        assert_eq!(
+            Some(ExtractionLimit::Entries),
+            extraction_limit_exceeded(&too_many).map(|e| e.limit)
+        );
+        assert!(check_link_target("a/link", "../b").is_ok());
+        assert!(check_link_target("a/link", "../../b").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // A file written over a symlink the archive chained through another one.
+    fn extract_tar_rejects_writes_through_archived_symlinks() {
+        // arrange
+        let dir = "assets/archive_symlink_escape_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let archive = format!("{}/evil.tar", dir);
+        let mut tar = TarWriter::new(File::create(&archive).unwrap());
+        tar.append_symlink("d", ".", 0).unwrap();
+        tar.append_symlink("f", "d/../escape.txt", 0).unwrap();
+        tar.append_file("f", 0o644, 0, 6, &b"gotcha"[..], None)
+            .unwrap();
+        tar.finish().unwrap();
+
+        // act
+        let result = extract_tar(&archive, format!("{}/out", dir));
+
+        // assert
+        assert_eq!(
+            Some(ExtractionLimit::PathTraversal),
+            extraction_limit_exceeded(&result.unwrap_err()).map(|e| e.limit)
+        );
+        assert!(!Path::new(&format!("{}/escape.txt", dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_tar_rejects_dot_prefixed_links_out_of_dest() {
+        // arrange
+        let dir = "assets/archive_dot_link_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let archive = format!("{}/dot.tar", dir);
+        let mut tar = TarWriter::new(File::create(&archive).unwrap());
+        tar.append_symlink("./link", "../outside_target", 0)
+            .unwrap();
+        tar.finish().unwrap();
+
+        // act
+        let result = extract_tar(&archive, format!("{}/out", dir));
+
+        // assert
+        assert_eq!(
+            Some(ExtractionLimit::PathTraversal),
+            extraction_limit_exceeded(&result.unwrap_err()).map(|e| e.limit)
+        );
+        assert!(fs::symlink_metadata(format!("{}/out/link", dir)).is_err());
+        assert!(check_link_target("./a/link", "../b").is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_tar_applies_directory_modes_last() {
+        // arrange
+        use std::os::unix::fs::PermissionsExt;
+        let dir = "assets/archive_readonly_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let archive = format!("{}/readonly.tar", dir);
+        let mut tar = TarWriter::new(File::create(&archive).unwrap());
+        tar.append_dir("ro", 0o555, 0).unwrap();
+        tar.append_dir("ro/sub", 0o555, 0).unwrap();
+        tar.append_file("ro/sub/a.txt", 0o444, 0, 1, &b"a"[..], None)
+            .unwrap();
+        tar.finish().unwrap();
+
+        // act
+        let extracted = extract_tar(&archive, format!("{}/out", dir)).unwrap();
+        let mode = fs::metadata(format!("{}/out/ro", dir))
+            .unwrap()
+            .permissions()
+            .mode();
+
+        // assert
+        assert_eq!(1, extracted.len());
+        assert_eq!(0o555, mode & 0o777);
+        for sub in ["out/ro", "out/ro/sub"] {
+            fs::set_permissions(
+                format!("{}/{}", dir, sub),
+                fs::Permissions::from_mode(0o755),
+            )
+            .unwrap();
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Async versions of the top-level file functions for tokio runtimes.
+//!
+//! Each function runs its blocking counterpart on tokio's blocking thread
+//! pool, as `tokio::fs` does, so the results and errors are the same.
+use crate::{
+    dirs,
+    error::{Context, Operation},
+    FileManagerError,
+};
+use std::{
+    io, panic,
+    path::{Path, PathBuf},
+};
+use tokio::task;
+
+/// Read the whole file at `file_path`, or stdin if it is `"-"`, into a string.
+/// See [`crate::read_file_to_string`].
+pub async fn read_file_to_string<P: AsRef<Path>>(file_path: P) -> Result<String, FileManagerError> {
+    blocking(Operation::Read, file_path.as_ref(), |path| {
+        crate::read_file_to_string(path)
+    })
+    .await
+}
+
+/// Read the whole file at `file_path`, or stdin if it is `"-"`.
+/// See [`crate::read_file_to_bytes`].
+pub async fn read_file_to_bytes<P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>, FileManagerError> {
+    blocking(Operation::Read, file_path.as_ref(), |path| {
+        crate::read_file_to_bytes(path)
+    })
+    .await
+}
+
+/// Write `contents` to the file at `file_path`, or stdout if it is `"-"`.
+/// See [`crate::write_to_file`].
+pub async fn write_to_file<P: AsRef<Path>>(
+    file_path: P,
+    truncate: bool,
+    contents: String,
+) -> Result<(), FileManagerError> {
+    blocking(Operation::Write, file_path.as_ref(), move |path| {
+        crate::write_to_file(path, truncate, &contents)
+    })
+    .await
+}
+
+/// Replace the file at `file_path` with `contents` atomically.
+/// See [`crate::write_to_file_atomic`].
+pub async fn write_to_file_atomic<P: AsRef<Path>>(
+    file_path: P,
+    contents: Vec<u8>,
+) -> Result<(), FileManagerError> {
+    blocking(Operation::Write, file_path.as_ref(), move |path| {
+        crate::write_to_file_atomic(path, contents)
+    })
+    .await
+}
+
+/// Append `contents` and a newline to the file at `file_path`, or stdout if it
+/// is `"-"`. See [`crate::append_to_file`].
+pub async fn append_to_file<P: AsRef<Path>>(
+    file_path: P,
+    contents: String,
+) -> Result<(), FileManagerError> {
+    blocking(Operation::Append, file_path.as_ref(), move |path| {
+        crate::append_to_file(path, &contents)
+    })
+    .await
+}
+
+/// Create an empty file at `file_path`. See [`crate::create_file`].
+pub async fn create_file<P: AsRef<Path>>(
+    file_path: P,
+    truncate: bool,
+) -> Result<(), FileManagerError> {
+    blocking(Operation::Create, file_path.as_ref(), move |path| {
+        crate::create_file(path, truncate)
+    })
+    .await
+}
+
+/// Delete the file at `file_path` if it exists. See [`crate::delete_file`].
+pub async fn delete_file<P: AsRef<Path>>(file_path: P) -> Result<(), FileManagerError> {
+    blocking(Operation::Delete, file_path.as_ref(), |path| {
+        crate::delete_file(path)
+    })
+    .await
+}
+
+/// Move the file at `src` to `dst`. See [`crate::move_file`].
+pub async fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> Result<(), FileManagerError> {
+    let dst = dst.as_ref().to_path_buf();
+    blocking(Operation::Move, src.as_ref(), move |path| {
+        crate::move_file(path, dst)
+    })
+    .await
+}
+
+/// Move the directory at `src` to `dst`. See [`crate::move_dir`].
+pub async fn move_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> Result<(), FileManagerError> {
+    let dst = dst.as_ref().to_path_buf();
+    blocking(Operation::Move, src.as_ref(), move |path| {
+        crate::move_dir(path, dst)
+    })
+    .await
+}
+
+/// Create the directory at `dir_path` and any missing parents.
+/// See [`dirs::ensure_dir`].
+pub async fn ensure_dir<P: AsRef<Path>>(dir_path: P) -> Result<(), FileManagerError> {
+    blocking(Operation::Create, dir_path.as_ref(), |path| {
+        dirs::ensure_dir(&path).context(Operation::Create, &path)
+    })
+    .await
+}
+
+/// Create an empty directory at `dir_path`, replacing any directory there.
+/// See [`dirs::create_dir_clean`].
+pub async fn create_dir_clean<P: AsRef<Path>>(dir_path: P) -> Result<(), FileManagerError> {
+    blocking(Operation::Create, dir_path.as_ref(), |path| {
+        dirs::create_dir_clean(&path).context(Operation::Create, &path)
+    })
+    .await
+}
+
+/// Delete the directory at `dir_path` and everything in it, refusing shallow
+/// paths. See [`crate::remove_dir_recursive`].
+pub async fn remove_dir_recursive<P: AsRef<Path>>(dir_path: P) -> Result<(), FileManagerError> {
+    blocking(Operation::Delete, dir_path.as_ref(), |path| {
+        crate::remove_dir_recursive(path)
+    })
+    .await
+}
+
+/// Helper function to run `f` on the file at `path` on the blocking thread pool.
+async fn blocking<T, F>(operation: Operation, path: &Path, f: F) -> Result<T, FileManagerError>
+where
+    T: Send + 'static,
+    F: FnOnce(PathBuf) -> Result<T, FileManagerError> + Send + 'static,
+{
+    let owned = path.to_path_buf();
+    match task::spawn_blocking(move || f(owned)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        // The runtime is shutting down.
+        Err(e) => Err(FileManagerError::new(operation, path, io::Error::other(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Builder;
+
+    #[test]
+    fn async_functions_mirror_blocking_ones() {
+        // arrange
+        let dir = "assets/async_fs_test";
+        let _ = std::fs::remove_dir_all(dir);
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let path = |name: &str| Path::new(dir).join(name);
+
+        // act
+        let (read, moved, missing) = runtime.block_on(async {
+            ensure_dir(path("nested")).await.unwrap();
+            write_to_file(path("nested/a.txt"), true, "hello".to_owned())
+                .await
+                .unwrap();
+            append_to_file(path("nested/a.txt"), " world".to_owned())
+                .await
+                .unwrap();
+            move_dir(path("nested"), path("moved")).await.unwrap();
+            let read = read_file_to_string(path("moved/a.txt")).await.unwrap();
+            let moved = !path("nested").exists();
+            let missing = read_file_to_bytes(path("missing.txt")).await;
+            remove_dir_recursive(dir).await.unwrap();
+            (read, moved, missing)
+        });
+
+        // assert
+        assert!(read.starts_with("hello world"));
+        assert!(moved);
+        let missing = missing.unwrap_err();
+        assert_eq!(Operation::Open, missing.operation());
+        assert_eq!(io::ErrorKind::NotFound, missing.kind());
+        assert!(!Path::new(dir).exists());
+    }
+}
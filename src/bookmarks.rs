@@ -0,0 +1,338 @@
+//! Named, tagged shortcuts to paths, persisted between runs.
+use crate::{json::Value, write_atomic};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Whether a bookmark still points where it did when it was saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookmarkStatus {
+    Valid,
+    /// Nothing exists at the path any more.
+    Missing,
+    /// The path exists but is a different file or directory, e.g. because the
+    /// original was moved away and something else took its place.
+    Replaced,
+}
+
+/// A named shortcut to a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    pub name: String,
+    /// The canonical path of the target.
+    pub path: PathBuf,
+    pub tags: Vec<String>,
+    /// Checked when the bookmarks are opened and whenever a bookmark changes.
+    pub status: BookmarkStatus,
+    /// Identifies the target independent of its path, where the platform allows.
+    id: Option<String>,
+}
+
+/// A set of [`Bookmark`]s saved as JSON after every change.
+#[derive(Debug)]
+pub struct Bookmarks {
+    store_path: PathBuf,
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// Load the bookmarks stored at `store_path`, or start empty if it does
+    /// not exist, and check the status of every bookmark.
+    pub fn open<P: AsRef<Path>>(store_path: P) -> io::Result<Self> {
+        let store_path = store_path.as_ref().to_path_buf();
+        let mut bookmarks = match fs::read_to_string(&store_path) {
+            Ok(text) => parse_store(&store_path, &text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for bookmark in &mut bookmarks {
+            bookmark.status = status_of(&bookmark.path, bookmark.id.as_deref())?;
+        }
+        Ok(Bookmarks {
+            store_path,
+            bookmarks,
+        })
+    }
+
+    /// Bookmark the existing file or directory at `path` as `name`.
+    ///
+    /// # Errors
+    /// `AlreadyExists` if `name` is taken.
+    pub fn add<P: AsRef<Path>>(&mut self, name: &str, path: P, tags: &[&str]) -> io::Result<()> {
+        if self.get(name).is_some() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("bookmark `{}` already exists", name),
+            ));
+        }
+        let (path, id) = target(path.as_ref())?;
+        self.bookmarks.push(Bookmark {
+            name: name.to_owned(),
+            path,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            status: BookmarkStatus::Valid,
+            id,
+        });
+        self.save()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.name == name)
+    }
+
+    /// Every bookmark, in the order they were added.
+    pub fn list(&self) -> &[Bookmark] {
+        &self.bookmarks
+    }
+
+    pub fn with_tag(&self, tag: &str) -> Vec<&Bookmark> {
+        self.bookmarks
+            .iter()
+            .filter(|b| b.tags.iter().any(|t| t == tag))
+            .collect()
+    }
+
+    /// The bookmarks whose targets were moved or deleted.
+    pub fn broken(&self) -> Vec<&Bookmark> {
+        self.bookmarks
+            .iter()
+            .filter(|b| b.status != BookmarkStatus::Valid)
+            .collect()
+    }
+
+    /// Point the bookmark `name` at `path`, e.g. after its target was moved.
+    pub fn retarget<P: AsRef<Path>>(&mut self, name: &str, path: P) -> io::Result<()> {
+        let (path, id) = target(path.as_ref())?;
+        let bookmark = self.get_mut(name)?;
+        bookmark.path = path;
+        bookmark.id = id;
+        bookmark.status = BookmarkStatus::Valid;
+        self.save()
+    }
+
+    /// # Errors
+    /// `AlreadyExists` if `new_name` is taken.
+    pub fn rename(&mut self, name: &str, new_name: &str) -> io::Result<()> {
+        if name != new_name && self.get(new_name).is_some() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("bookmark `{}` already exists", new_name),
+            ));
+        }
+        self.get_mut(name)?.name = new_name.to_owned();
+        self.save()
+    }
+
+    pub fn set_tags(&mut self, name: &str, tags: &[&str]) -> io::Result<()> {
+        self.get_mut(name)?.tags = tags.iter().map(|t| t.to_string()).collect();
+        self.save()
+    }
+
+    /// # Returns
+    /// `false` if there was no bookmark called `name`.
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|b| b.name != name);
+        if self.bookmarks.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn get_mut(&mut self, name: &str) -> io::Result<&mut Bookmark> {
+        self.bookmarks
+            .iter_mut()
+            .find(|b| b.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("no bookmark called `{}`", name),
+                )
+            })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let bookmarks = self
+            .bookmarks
+            .iter()
+            .map(|b| {
+                let mut fields = vec![
+                    ("name".to_owned(), Value::from(b.name.as_str())),
+                    // Checked to be UTF-8 by `target`.
+                    ("path".to_owned(), Value::from(b.path.to_str().unwrap())),
+                    (
+                        "tags".to_owned(),
+                        Value::Array(b.tags.iter().map(|t| Value::from(t.as_str())).collect()),
+                    ),
+                ];
+                if let Some(id) = &b.id {
+                    fields.push(("id".to_owned(), Value::from(id.as_str())));
+                }
+                Value::Object(fields)
+            })
+            .collect();
+        let store = Value::Object(vec![("bookmarks".to_owned(), Value::Array(bookmarks))]);
+        write_atomic(&self.store_path, format!("{}\n", store).as_bytes())
+    }
+}
+
+/// Helper function to resolve a new bookmark target to its canonical path and id.
+fn target(path: &Path) -> io::Result<(PathBuf, Option<String>)> {
+    let path = path.canonicalize()?;
+    if path.to_str().is_none() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("path is not valid UTF-8: {}", path.display()),
+        ));
+    }
+    let id = file_id(&path)?;
+    Ok((path, id))
+}
+
+fn status_of(path: &Path, id: Option<&str>) -> io::Result<BookmarkStatus> {
+    match file_id(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BookmarkStatus::Missing),
+        Err(e) => Err(e),
+        Ok(current) if id.is_some() && current.as_deref() != id => Ok(BookmarkStatus::Replaced),
+        Ok(_) => Ok(BookmarkStatus::Valid),
+    }
+}
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> io::Result<Option<String>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)?;
+    Ok(Some(format!("{}:{}", metadata.dev(), metadata.ino())))
+}
+
+#[cfg(windows)]
+fn file_id(path: &Path) -> io::Result<Option<String>> {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+    };
+
+    // Backup semantics allow opening directories.
+    let file = fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(format!(
+        "{}:{}",
+        info.dwVolumeSerialNumber,
+        (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow)
+    )))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn file_id(path: &Path) -> io::Result<Option<String>> {
+    fs::metadata(path)?;
+    Ok(None)
+}
+
+fn parse_store(store_path: &Path, text: &str) -> io::Result<Vec<Bookmark>> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid bookmarks store {}", store_path.display()),
+        )
+    };
+    let store = Value::parse(text)?;
+    let items = store
+        .get("bookmarks")
+        .and_then(Value::as_array)
+        .ok_or_else(invalid)?;
+    items
+        .iter()
+        .map(|item| {
+            let string = |key: &str| item.get(key).and_then(Value::as_str).ok_or_else(invalid);
+            let tags = item
+                .get("tags")
+                .and_then(Value::as_array)
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|t| t.as_str().map(str::to_owned).ok_or_else(invalid))
+                .collect::<io::Result<_>>()?;
+            Ok(Bookmark {
+                name: string("name")?.to_owned(),
+                path: PathBuf::from(string("path")?),
+                tags,
+                status: BookmarkStatus::Valid,
+                id: string("id").ok().map(str::to_owned),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bookmarks_persist_and_flag_broken_targets() {
+        // arrange
+        let dir = "assets/bookmarks_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/projects", dir)).unwrap();
+        fs::write(format!("{}/todo.txt", dir), "").unwrap();
+        fs::write(format!("{}/old.txt", dir), "").unwrap();
+        let store = format!("{}/bookmarks.json", dir);
+
+        // act
+        let mut bookmarks = Bookmarks::open(&store).unwrap();
+        bookmarks
+            .add("projects", format!("{}/projects", dir), &["work"])
+            .unwrap();
+        bookmarks
+            .add("todo", format!("{}/todo.txt", dir), &["work", "daily"])
+            .unwrap();
+        bookmarks
+            .add("old", format!("{}/old.txt", dir), &[])
+            .unwrap();
+        let duplicate = bookmarks.add("todo", dir, &[]);
+        bookmarks.rename("old", "archive").unwrap();
+        bookmarks.set_tags("projects", &["code"]).unwrap();
+        fs::remove_file(format!("{}/todo.txt", dir)).unwrap();
+        fs::rename(format!("{}/old.txt", dir), format!("{}/moved.txt", dir)).unwrap();
+        fs::write(format!("{}/old.txt", dir), "").unwrap();
+        let mut reopened = Bookmarks::open(&store).unwrap();
+        let status = |b: &Bookmarks, name: &str| b.get(name).unwrap().status;
+        let statuses = [
+            status(&reopened, "projects"),
+            status(&reopened, "todo"),
+            status(&reopened, "archive"),
+        ];
+        reopened
+            .retarget("archive", format!("{}/moved.txt", dir))
+            .unwrap();
+        let removed = reopened.remove("todo").unwrap();
+
+        // assert
+        assert_eq!(ErrorKind::AlreadyExists, duplicate.unwrap_err().kind());
+        assert_eq!(BookmarkStatus::Valid, statuses[0]);
+        assert_eq!(BookmarkStatus::Missing, statuses[1]);
+        if cfg!(any(unix, windows)) {
+            assert_eq!(BookmarkStatus::Replaced, statuses[2]);
+        }
+        assert!(removed);
+        assert!(reopened.broken().is_empty());
+        assert_eq!(vec!["projects"], names(reopened.with_tag("code")));
+        assert_eq!(
+            vec!["projects", "archive"],
+            names(Bookmarks::open(&store).unwrap().list().iter().collect())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn names(bookmarks: Vec<&Bookmark>) -> Vec<&str> {
+        bookmarks.iter().map(|b| b.name.as_str()).collect()
+    }
+}
@@ -0,0 +1,393 @@
+//! Streaming file checksums.
+use std::{
+    fmt::Write as FmtWrite,
+    fs::File,
+    io::{self, ErrorKind, Read},
+    path::Path,
+};
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A checksum algorithm supported by [`hash_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    /// Only for matching checksums published elsewhere; not collision resistant.
+    Sha1,
+    /// Only for detecting accidental corruption.
+    Crc32,
+}
+
+impl Algorithm {
+    /// The length of a digest in hex digits.
+    fn hex_len(&self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Sha1 => 40,
+            Algorithm::Crc32 => 8,
+        }
+    }
+}
+
+/// Hash the file at `path` with `algorithm`, reading it in chunks.
+///
+/// # Returns
+/// The digest as lowercase hex.
+pub fn hash_file<P: AsRef<Path>>(path: P, algorithm: Algorithm) -> io::Result<String> {
+    let file = File::open(path)?;
+    match algorithm {
+        Algorithm::Sha256 => sha256_reader(file),
+        Algorithm::Sha1 => {
+            let mut hasher = Sha1::new();
+            stream(file, |chunk| hasher.update(chunk))?;
+            Ok(to_hex(&hasher.finish()))
+        }
+        Algorithm::Crc32 => {
+            let mut crc = Crc32::new();
+            stream(file, |chunk| crc.update(chunk))?;
+            Ok(to_hex(&crc.finish().to_be_bytes()))
+        }
+    }
+}
+
+/// Check the file at `path` against the hex digest `expected`. The algorithm
+/// is chosen by the digest length: 64 digits for SHA-256, 40 for SHA-1 and 8
+/// for CRC32.
+///
+/// # Returns
+/// `Ok(true)` if the file matches and `Ok(false)` if it doesn't.
+///
+/// # Errors
+/// `InvalidInput` if `expected` is not a digest of one of those lengths.
+pub fn verify_file<P: AsRef<Path>>(path: P, expected: &str) -> io::Result<bool> {
+    let expected = expected.trim();
+    let algorithm = [Algorithm::Sha256, Algorithm::Sha1, Algorithm::Crc32]
+        .into_iter()
+        .find(|a| a.hex_len() == expected.len())
+        .filter(|_| expected.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid checksum `{}`", expected),
+            )
+        })?;
+    Ok(hash_file(path, algorithm)?.eq_ignore_ascii_case(expected))
+}
+
+/// Incremental SHA-256 hasher.
+#[derive(Clone)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// Incremental SHA-1 hasher.
+#[derive(Clone)]
+struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha1 {
+    fn new() -> Self {
+        Sha1 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 20];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// Incremental CRC-32 (IEEE) checksum.
+pub(crate) struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Crc32 { crc: !0 }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.crc & 1).wrapping_neg();
+                self.crc = (self.crc >> 1) ^ (0xedb88320 & mask);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.crc
+    }
+}
+
+/// Format `bytes` as lowercase hex.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// Stream `reader` to the end and return its SHA-256 digest as hex.
+pub(crate) fn sha256_reader<R: Read>(reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    stream(reader, |chunk| hasher.update(chunk))?;
+    Ok(to_hex(&hasher.finish()))
+}
+
+/// Helper function to feed `reader` to `update` in chunks until it ends.
+fn stream<R: Read>(mut reader: R, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => update(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_works() {
+        // arrange
+        let long = vec![b'a'; 1_000_000];
+
+        // act
+        let empty = sha256_reader(&b""[..]).unwrap();
+        let abc = sha256_reader(&b"abc"[..]).unwrap();
+        let million = sha256_reader(long.as_slice()).unwrap();
+
+        // assert
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            empty
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            abc
+        );
+        assert_eq!(
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            million
+        );
+    }
+
+    #[test]
+    fn hash_file_supports_each_algorithm() {
+        // arrange
+        let dir = "assets/checksum_test";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/fox.txt", dir);
+        std::fs::write(&path, "The quick brown fox jumps over the lazy dog").unwrap();
+
+        // act
+        let sha1 = hash_file(&path, Algorithm::Sha1).unwrap();
+        let crc32 = hash_file(&path, Algorithm::Crc32).unwrap();
+        let sha256 = hash_file(&path, Algorithm::Sha256).unwrap();
+        let invalid = verify_file(&path, "not a checksum");
+
+        // assert
+        assert_eq!("2fd4e1c67a2d28fced849ee1bb76e7391b93eb12", sha1);
+        assert_eq!("414fa339", crc32);
+        assert!(verify_file(&path, &sha256.to_uppercase()).unwrap());
+        assert!(verify_file(&path, &sha1).unwrap());
+        assert!(!verify_file(&path, "00000000").unwrap());
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sha256_retries_interrupted_reads() {
+        // arrange
+        struct Interrupting<'a>(&'a [u8], bool);
+        impl Read for Interrupting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 {
+                    return Err(ErrorKind::Interrupted.into());
+                }
+                self.0.read(buf)
+            }
+        }
+
+        // act
+        let digest = sha256_reader(Interrupting(b"abc", false));
+
+        // assert
+        assert_eq!(sha256_reader(&b"abc"[..]).unwrap(), digest.unwrap());
+    }
+}
@@ -0,0 +1,254 @@
+//! File age predicates and age-based cleanup.
+use crate::{
+    error::{Context, Operation},
+    glob,
+};
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Which timestamp decides how old a file is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AgeCriterion {
+    /// Last modification time (mtime).
+    #[default]
+    Modified,
+    /// Last access time (atime).
+    Accessed,
+    /// Last status change time (ctime) on Unix. Other platforms use the creation time.
+    Changed,
+}
+
+/// Options for [`delete_older_than`].
+#[derive(Debug, Clone, Default)]
+pub struct CleanupOptions {
+    /// Only consider files whose name matches this wildcard pattern, e.g. `"*.log"`.
+    pub pattern: Option<String>,
+    /// Descend into subdirectories. Symlinked directories are never followed.
+    pub recursive: bool,
+    /// Report what would be deleted without deleting anything.
+    pub dry_run: bool,
+    /// The timestamp used to determine a file's age.
+    pub criterion: AgeCriterion,
+}
+
+/// Delete files in `dir` that are older than `max_age`.
+/// Only regular files are removed; directories are left in place.
+///
+/// # Returns
+/// The paths that were deleted, or that would be deleted when `options.dry_run == true`.
+pub fn delete_older_than<P: AsRef<Path>>(
+    dir: P,
+    max_age: Duration,
+    options: &CleanupOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let mut deleted = Vec::new();
+    cleanup_dir(dir.as_ref(), cutoff(max_age), options, &mut deleted)?;
+    Ok(deleted)
+}
+
+/// Returns `true` if the file at `path` was last modified more than `age` ago.
+/// A modification time in the future counts as brand new.
+pub fn is_older_than<P: AsRef<Path>>(path: P, age: Duration) -> io::Result<bool> {
+    Ok(fs::metadata(path)?.modified()? < cutoff(age))
+}
+
+/// Returns `true` if the file at `path` was modified after `since`.
+pub fn modified_since<P: AsRef<Path>>(path: P, since: SystemTime) -> io::Result<bool> {
+    Ok(fs::metadata(path)?.modified()? > since)
+}
+
+/// Find the most recently modified regular file directly inside `dir`.
+///
+/// # Returns
+/// The file's path, or `None` if `dir` contains no regular files.
+pub fn newest_in<P: AsRef<Path>>(dir: P) -> io::Result<Option<PathBuf>> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            newest = Some((modified, entry.path()));
+        }
+    }
+    Ok(newest.map(|(_, path)| path))
+}
+
+/// Helper function to get the point in time `age` before now.
+fn cutoff(age: Duration) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(age)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+fn cleanup_dir(
+    dir: &Path,
+    cutoff: SystemTime,
+    options: &CleanupOptions,
+    deleted: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir).context(Operation::Read, dir)? {
+        let entry = entry.context(Operation::Read, dir)?;
+        let path = entry.path();
+        let file_type = entry.file_type().context(Operation::Stat, &path)?;
+
+        if file_type.is_dir() {
+            if options.recursive {
+                cleanup_dir(&path, cutoff, options, deleted)?;
+            }
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+
+        if let Some(pattern) = &options.pattern {
+            if !glob::matches(pattern, &entry.file_name().to_string_lossy()) {
+                continue;
+            }
+        }
+
+        let age_time = entry
+            .metadata()
+            .and_then(|metadata| file_time(&metadata, options.criterion))
+            .context(Operation::Stat, &path)?;
+        if age_time < cutoff {
+            if !options.dry_run {
+                fs::remove_file(&path).context(Operation::Delete, &path)?;
+            }
+            deleted.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Read the timestamp selected by `criterion` from `metadata`.
+fn file_time(metadata: &Metadata, criterion: AgeCriterion) -> io::Result<SystemTime> {
+    match criterion {
+        AgeCriterion::Modified => metadata.modified(),
+        AgeCriterion::Accessed => metadata.accessed(),
+        AgeCriterion::Changed => changed_time(metadata),
+    }
+}
+
+#[cfg(unix)]
+fn changed_time(metadata: &Metadata) -> io::Result<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    let secs = metadata.ctime();
+    let nanos = metadata.ctime_nsec() as u32;
+    let time = if secs >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::new(secs.unsigned_abs(), 0) + Duration::new(0, nanos)
+    };
+    Ok(time)
+}
+
+#[cfg(not(unix))]
+fn changed_time(metadata: &Metadata) -> io::Result<SystemTime> {
+    metadata.created()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_file;
+    use std::fs::File;
+
+    fn age_file(path: &str, age: Duration) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn delete_older_than_works() {
+        // arrange
+        let dir = "assets/cleanup_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        let old_log = format!("{}/old.log", dir);
+        let old_txt = format!("{}/old.txt", dir);
+        let new_log = format!("{}/new.log", dir);
+        let nested_log = format!("{}/nested/old.log", dir);
+        for path in [&old_log, &old_txt, &new_log, &nested_log] {
+            create_file(path, true).unwrap();
+        }
+        for path in [&old_log, &old_txt, &nested_log] {
+            age_file(path, Duration::from_secs(3 * 86_400));
+        }
+        let options = CleanupOptions {
+            pattern: Some("*.log".to_owned()),
+            ..Default::default()
+        };
+
+        // act
+        let result = delete_older_than(dir, Duration::from_secs(86_400), &options);
+
+        // assert
+        assert_eq!(vec![PathBuf::from(&old_log)], result.unwrap());
+        assert!(!Path::new(&old_log).exists());
+        assert!(Path::new(&old_txt).exists());
+        assert!(Path::new(&new_log).exists());
+        assert!(Path::new(&nested_log).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn delete_older_than_dry_run_keeps_files() {
+        // arrange
+        let dir = "assets/cleanup_dry_run_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        let nested = format!("{}/nested/old.txt", dir);
+        create_file(&nested, true).unwrap();
+        age_file(&nested, Duration::from_secs(7200));
+        let options = CleanupOptions {
+            recursive: true,
+            dry_run: true,
+            ..Default::default()
+        };
+
+        // act
+        let result = delete_older_than(dir, Duration::from_secs(3600), &options);
+
+        // assert
+        assert_eq!(vec![PathBuf::from(&nested)], result.unwrap());
+        assert!(Path::new(&nested).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn staleness_predicates_work() {
+        // arrange
+        let dir = "assets/cleanup_staleness_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        let (old, new) = (format!("{}/old.txt", dir), format!("{}/new.txt", dir));
+        create_file(&old, true).unwrap();
+        create_file(&new, true).unwrap();
+        age_file(&old, Duration::from_secs(7200));
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+
+        // act
+        let stale = is_older_than(&old, Duration::from_secs(3600)).unwrap();
+        let fresh = is_older_than(&new, Duration::from_secs(3600)).unwrap();
+        let changed = modified_since(&new, hour_ago).unwrap();
+        let unchanged = modified_since(&old, hour_ago).unwrap();
+        let newest = newest_in(dir).unwrap();
+        let missing = is_older_than(format!("{}/missing", dir), Duration::ZERO);
+
+        // assert
+        assert!(stale && !fresh);
+        assert!(changed && !unchanged);
+        assert_eq!(Some(PathBuf::from(&new)), newest);
+        assert_eq!(io::ErrorKind::NotFound, missing.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+//! Hard links and copy-on-write clones, which share data instead of copying it.
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+/// How [`clone_file`] copied a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloneMethod {
+    /// The copy shares the source's blocks until either is written: `FICLONE`
+    /// on Linux (Btrfs, XFS, bcachefs) or `clonefile` on macOS (APFS).
+    Reflink,
+    /// The kernel copied the data with `copy_file_range` on Linux, which some
+    /// filesystems, such as NFS and CIFS, turn into a server-side copy.
+    CopyFileRange,
+    /// The data was read and written.
+    Copy,
+}
+
+/// Create `dst` as another name for the file at `src`. Both names refer to the
+/// same data, so writing through one changes the other.
+///
+/// # Errors
+/// `AlreadyExists` if `dst` exists and `CrossesDevices` if the paths are on
+/// different filesystems.
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
+    fs::hard_link(src, dst)
+}
+
+/// Copy the file at `src` to `dst` with its permissions, replacing any file
+/// there, using the cheapest method the platform and filesystem support.
+/// Returns the method used.
+///
+/// A [`CloneMethod::Reflink`] is instant whatever the size of the file, and
+/// the copy takes no space until it or the source is modified.
+pub fn clone_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<CloneMethod> {
+    clone_or_copy(src.as_ref(), dst.as_ref())
+}
+
+#[cfg(target_os = "linux")]
+fn clone_or_copy(src: &Path, dst: &Path) -> io::Result<CloneMethod> {
+    use std::os::fd::AsRawFd;
+
+    let mut input = File::open(src)?;
+    let metadata = input.metadata()?;
+    let mut output = File::create(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let cloned = unsafe { libc::ioctl(output.as_raw_fd(), libc::FICLONE, input.as_raw_fd()) };
+    let method = if cloned == 0 {
+        CloneMethod::Reflink
+    } else if copy_file_range(&input, &output, metadata.len())? {
+        CloneMethod::CopyFileRange
+    } else {
+        io::copy(&mut input, &mut output)?;
+        CloneMethod::Copy
+    };
+    output.set_permissions(metadata.permissions())?;
+    Ok(method)
+}
+
+/// Helper function to copy `len` bytes from `input` to `output` in the kernel.
+/// Returns `false`, having copied nothing, if the filesystems do not support it.
+#[cfg(target_os = "linux")]
+fn copy_file_range(input: &File, output: &File, len: u64) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let mut copied = 0;
+    while copied < len {
+        // SAFETY: both descriptors are open and null offsets use the file positions.
+        let n = unsafe {
+            libc::copy_file_range(
+                input.as_raw_fd(),
+                std::ptr::null_mut(),
+                output.as_raw_fd(),
+                std::ptr::null_mut(),
+                (len - copied) as usize,
+                0,
+            )
+        };
+        match n {
+            // The source shrank while copying.
+            0 => break,
+            n if n > 0 => copied += n as u64,
+            _ => {
+                let e = io::Error::last_os_error();
+                let unsupported = matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL)
+                );
+                return if copied == 0 && unsupported {
+                    Ok(false)
+                } else {
+                    Err(e)
+                };
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(target_os = "macos")]
+fn clone_or_copy(src: &Path, dst: &Path) -> io::Result<CloneMethod> {
+    use crate::temp_sibling;
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    // `clonefile` will not replace an existing file, so clone next to it and rename.
+    let tmp_path = temp_sibling(dst, "clone");
+    let (c_src, c_tmp) = (c_path(src)?, c_path(&tmp_path)?);
+    // SAFETY: both are valid C strings for the duration of the call.
+    if unsafe { libc::clonefile(c_src.as_ptr(), c_tmp.as_ptr(), 0) } == 0 {
+        fs::rename(&tmp_path, dst).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })?;
+        return Ok(CloneMethod::Reflink);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::ENOTSUP | libc::EXDEV) => {
+            copy(src, dst)?;
+            Ok(CloneMethod::Copy)
+        }
+        _ => Err(e),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_or_copy(src: &Path, dst: &Path) -> io::Result<CloneMethod> {
+    copy(src, dst)?;
+    Ok(CloneMethod::Copy)
+}
+
+/// Helper function to copy `src` to `dst` with its permissions by reading and writing.
+#[cfg(not(target_os = "linux"))]
+fn copy(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut input = File::open(src)?;
+    let metadata = input.metadata()?;
+    let mut output = File::create(dst)?;
+    io::copy(&mut input, &mut output)?;
+    output.set_permissions(metadata.permissions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_and_links_share_contents() {
+        // arrange
+        let dir = "assets/clone_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (src, clone, link) = (
+            format!("{}/model.bin", dir),
+            format!("{}/model.clone.bin", dir),
+            format!("{}/model.link.bin", dir),
+        );
+        let contents: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(&src, &contents).unwrap();
+        fs::write(&clone, "stale").unwrap();
+
+        // act
+        clone_file(&src, &clone).unwrap();
+        hard_link(&src, &link).unwrap();
+        let existing = hard_link(&src, &link);
+        fs::write(&src, "changed").unwrap();
+
+        // assert
+        assert_eq!(contents, fs::read(&clone).unwrap());
+        assert_eq!("changed", fs::read_to_string(&link).unwrap());
+        assert_eq!(io::ErrorKind::AlreadyExists, existing.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
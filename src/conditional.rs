@@ -0,0 +1,203 @@
+//! Conditional writes for safe read-modify-write cycles on shared files.
+use crate::{checksum::sha256_reader, sidecar::update_sidecar, write_atomic};
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// What a file looked like when it was read, to detect later changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileVersion {
+    /// The file did not exist.
+    Missing,
+    /// The SHA-256 of the contents as hex. Catches every change, at the cost of
+    /// reading the whole file.
+    Hash(String),
+    /// The modification time. Cheap, but misses changes made within the
+    /// filesystem's timestamp granularity.
+    Modified(SystemTime),
+}
+
+impl FileVersion {
+    /// The content hash of the file at `path`, or [`FileVersion::Missing`].
+    pub fn hash_of<P: AsRef<Path>>(path: P) -> io::Result<FileVersion> {
+        match File::open(path) {
+            Ok(file) => Ok(FileVersion::Hash(sha256_reader(file)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(FileVersion::Missing),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The modification time of the file at `path`, or [`FileVersion::Missing`].
+    pub fn modified_of<P: AsRef<Path>>(path: P) -> io::Result<FileVersion> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(FileVersion::Modified(metadata.modified()?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(FileVersion::Missing),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Helper function to read the version of `path` of the same kind as `self`.
+    fn current(&self, path: &Path) -> io::Result<FileVersion> {
+        match self {
+            FileVersion::Modified(_) => FileVersion::modified_of(path),
+            FileVersion::Missing | FileVersion::Hash(_) => FileVersion::hash_of(path),
+        }
+    }
+}
+
+/// The error payload when a file changed since it was read. Use [`is_conflict`]
+/// to recognise it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub expected: FileVersion,
+    pub found: FileVersion,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} changed since it was read", self.path.display())
+    }
+}
+
+impl Error for Conflict {}
+
+/// Returns `true` if `err` was caused by a [`Conflict`].
+pub fn is_conflict(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<Conflict>())
+}
+
+/// Replace the file at `path` with `contents`, but only if it still matches
+/// `expected`, the version the caller read.
+///
+/// The check and the write happen under a lock on a `.<name>.lock` file next
+/// to `path`, so concurrent callers of this function cannot both succeed.
+/// Writers that bypass it are still detected, unless they write between the
+/// check and the final rename.
+///
+/// # Returns
+/// The version of the new contents, of the same kind as `expected`, to pass
+/// to the next call.
+///
+/// # Errors
+/// A [`Conflict`] if the file changed; check with [`is_conflict`].
+pub fn write_if_match<P: AsRef<Path>>(
+    path: P,
+    expected: &FileVersion,
+    contents: &[u8],
+) -> io::Result<FileVersion> {
+    let path = path.as_ref();
+    let _lock = lock_file(path)?;
+    let found = expected.current(path)?;
+    if found != *expected {
+        return Err(io::Error::other(Conflict {
+            path: path.to_path_buf(),
+            expected: expected.clone(),
+            found,
+        }));
+    }
+    write_atomic(path, contents)?;
+    update_sidecar(path)?;
+    expected.current(path)
+}
+
+/// Lock the file at `path`, read it, and replace it atomically with what
+/// `update` returns. A missing file reads as empty.
+///
+/// This uses the same lock as [`write_if_match`], so concurrent updates of the
+/// same file run one after another and none is lost.
+///
+/// # Returns
+/// The new contents.
+pub fn update_file<P, F>(path: P, update: F) -> io::Result<String>
+where
+    P: AsRef<Path>,
+    F: FnOnce(String) -> String,
+{
+    let path = path.as_ref();
+    let _lock = lock_file(path)?;
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let updated = update(contents);
+    write_atomic(path, updated.as_bytes())?;
+    update_sidecar(path)?;
+    Ok(updated)
+}
+
+/// Helper function to take the exclusive lock guarding writes to `path`.
+/// The lock is released when the returned file is closed.
+fn lock_file(path: &Path) -> io::Result<File> {
+    let mut lock_name = OsString::from(".");
+    lock_name.push(path.file_name().unwrap_or(path.as_os_str()));
+    lock_name.push(".lock");
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.with_file_name(lock_name))?;
+    file.lock()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_if_match_detects_changes() {
+        // arrange
+        let dir = "assets/conditional_write_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/state.toml", dir);
+
+        // act
+        let created = write_if_match(&path, &FileVersion::Missing, b"count = 1").unwrap();
+        let stale = FileVersion::hash_of(&path).unwrap();
+        let updated = write_if_match(&path, &created, b"count = 2").unwrap();
+        let conflict = write_if_match(&path, &stale, b"count = 99").unwrap_err();
+        let recreate = write_if_match(&path, &FileVersion::Missing, b"count = 0").unwrap_err();
+
+        // assert
+        assert_eq!(stale, created);
+        assert_eq!(FileVersion::hash_of(&path).unwrap(), updated);
+        assert!(is_conflict(&conflict));
+        assert!(is_conflict(&recreate));
+        assert_eq!("count = 2", fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn update_file_serializes_updates() {
+        // arrange
+        let dir = "assets/conditional_update_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/counter.txt", dir);
+        let increment = |contents: String| (contents.parse().unwrap_or(0) + 1).to_string();
+
+        // act
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || update_file(&path, increment).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // assert
+        assert_eq!("8", fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
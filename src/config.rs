@@ -0,0 +1,160 @@
+//! Configuration loaded from layered TOML files.
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+/// A configuration merged from several files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layered<T> {
+    pub value: T,
+    /// The file each final value came from, keyed by its dotted path such as `server.port`.
+    pub sources: BTreeMap<String, PathBuf>,
+}
+
+impl<T> Layered<T> {
+    /// The file the value at the dotted `key` came from.
+    pub fn source(&self, key: &str) -> Option<&Path> {
+        self.sources.get(key).map(PathBuf::as_path)
+    }
+}
+
+/// Load `paths` in order, each one deep-merged over the ones before it, and
+/// deserialize the result into `T`.
+///
+/// Tables are merged key by key; any other value, including an array, replaces
+/// the earlier one. Files that don't exist are skipped, so optional overlays such
+/// as `local.toml` can simply be listed.
+pub fn load_layered<T, P>(paths: impl IntoIterator<Item = P>) -> io::Result<Layered<T>>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let mut merged = Table::new();
+    let mut sources = BTreeMap::new();
+    for path in paths {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let layer: Table = contents.parse().map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid TOML in {}: {}", path.display(), e),
+            )
+        })?;
+        merge(&mut merged, layer, "", path, &mut sources);
+    }
+    let value = Value::Table(merged)
+        .try_into()
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(Layered { value, sources })
+}
+
+/// Helper function to merge `layer` from `path` into `base`, whose keys start with `prefix`.
+fn merge(
+    base: &mut Table,
+    layer: Table,
+    prefix: &str,
+    path: &Path,
+    sources: &mut BTreeMap<String, PathBuf>,
+) {
+    for (key, value) in layer {
+        let dotted = format!("{}{}", prefix, key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, &format!("{}.", dotted), path, sources);
+            }
+            (_, value) => {
+                // Whatever was here before, including a whole table, is replaced.
+                let nested = format!("{}.", dotted);
+                sources.retain(|k, _| k != &dotted && !k.starts_with(&nested));
+                record(&value, &dotted, path, sources);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Helper function to record `path` as the source of `value` and everything in it.
+fn record(value: &Value, dotted: &str, path: &Path, sources: &mut BTreeMap<String, PathBuf>) {
+    match value {
+        Value::Table(table) if !table.is_empty() => {
+            for (key, value) in table {
+                record(value, &format!("{}.{}", dotted, key), path, sources);
+            }
+        }
+        _ => {
+            sources.insert(dotted.to_owned(), path.to_path_buf());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Settings {
+        name: String,
+        server: Server,
+    }
+
+    #[test]
+    fn load_layered_merges_and_tracks_sources() {
+        // arrange
+        let dir = "assets/config_layered_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/env", dir)).unwrap();
+        let base = format!("{}/base.toml", dir);
+        let prod = format!("{}/env/prod.toml", dir);
+        let local = format!("{}/local.toml", dir);
+        fs::write(
+            &base,
+            "name = \"app\"\n[server]\nhost = \"localhost\"\nport = 80\ntags = [\"a\", \"b\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            &prod,
+            "[server]\nhost = \"example.com\"\ntags = [\"prod\"]\n",
+        )
+        .unwrap();
+
+        // act
+        let layered: Layered<Settings> = load_layered([&base, &prod, &local]).unwrap();
+        fs::write(&local, "server = 1\n").unwrap();
+        let invalid = load_layered::<Settings, _>([&base, &local]);
+
+        // assert
+        assert_eq!(
+            Settings {
+                name: "app".to_owned(),
+                server: Server {
+                    host: "example.com".to_owned(),
+                    port: 80,
+                    tags: vec!["prod".to_owned()],
+                },
+            },
+            layered.value
+        );
+        assert_eq!(Some(Path::new(&base)), layered.source("server.port"));
+        assert_eq!(Some(Path::new(&prod)), layered.source("server.host"));
+        assert_eq!(Some(Path::new(&prod)), layered.source("server.tags"));
+        assert_eq!(ErrorKind::InvalidData, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+//! Decide per file what happens when an operation's destination already exists.
+use crate::{checksum::sha256_reader, numbered_file_name, OverwritePolicy};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// One of the two files in a [`FileConflict`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSide {
+    /// For an archive entry, its path inside the archive.
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// The SHA-256 of the contents as hex, if the resolver asked for hashes
+    /// with [`ConflictResolver::wants_hashes`].
+    pub sha256: Option<String>,
+}
+
+/// A file about to be written over an existing one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    /// The incoming file.
+    pub source: FileSide,
+    /// The file already at the destination.
+    pub destination: FileSide,
+}
+
+/// Decides what to do with each [`FileConflict`] met by
+/// [`sync_dir_with_resolver`](crate::sync::sync_dir_with_resolver),
+/// [`transfer_with_resolver`](crate::transfer::transfer_with_resolver) and
+/// [`extract_tar_with_resolver`](crate::archive::extract_tar_with_resolver).
+///
+/// An [`OverwritePolicy`] resolves every conflict the same way, and closures
+/// taking a `&FileConflict` and returning one are resolvers too.
+pub trait ConflictResolver {
+    /// Returning [`OverwritePolicy::Fail`] stops the operation with an
+    /// `AlreadyExists` error, as does returning an error.
+    fn resolve(&mut self, conflict: &FileConflict) -> io::Result<OverwritePolicy>;
+
+    /// Whether to fill in [`FileSide::sha256`], which reads both files.
+    fn wants_hashes(&self) -> bool {
+        false
+    }
+}
+
+impl ConflictResolver for OverwritePolicy {
+    fn resolve(&mut self, _conflict: &FileConflict) -> io::Result<OverwritePolicy> {
+        Ok(*self)
+    }
+}
+
+impl<F: FnMut(&FileConflict) -> OverwritePolicy> ConflictResolver for F {
+    fn resolve(&mut self, conflict: &FileConflict) -> io::Result<OverwritePolicy> {
+        Ok(self(conflict))
+    }
+}
+
+/// Helper function to describe the local file at `path`, hashing it if `hash`.
+pub(crate) fn local_side(path: &Path, hash: bool) -> io::Result<FileSide> {
+    let metadata = fs::metadata(path)?;
+    Ok(FileSide {
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        sha256: if hash {
+            Some(sha256_reader(File::open(path)?)?)
+        } else {
+            None
+        },
+    })
+}
+
+/// Helper function to turn the resolution of a conflict at `dst` into the path
+/// to write to, or `None` to skip it. `exists` reports whether a path is taken,
+/// and `verb` names the operation in errors.
+pub(crate) fn resolved_path(
+    resolution: OverwritePolicy,
+    dst: &Path,
+    verb: &str,
+    exists: impl Fn(&Path) -> bool,
+) -> io::Result<Option<PathBuf>> {
+    match resolution {
+        OverwritePolicy::Skip => Ok(None),
+        OverwritePolicy::Overwrite => Ok(Some(dst.to_path_buf())),
+        OverwritePolicy::Rename => {
+            let name = dst.file_name().unwrap_or(dst.as_os_str());
+            let mut n = 1;
+            loop {
+                let candidate = dst.with_file_name(numbered_file_name(name, n));
+                if !exists(&candidate) {
+                    return Ok(Some(candidate));
+                }
+                n += 1;
+            }
+        }
+        OverwritePolicy::Fail => Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("cannot {} to {}: it already exists", verb, dst.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::{extract_tar_with_resolver, ExtractionLimits, TarWriter},
+        sync::{sync_dir_with_resolver, SyncOptions},
+    };
+
+    #[test]
+    fn resolvers_see_both_sides() {
+        // arrange
+        let dir = "assets/conflict_test";
+        let _ = fs::remove_dir_all(dir);
+        let (src, dst) = (format!("{}/src", dir), format!("{}/dst", dir));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(format!("{}/notes.txt", src), "new notes").unwrap();
+        fs::write(format!("{}/notes.txt", dst), "old").unwrap();
+        fs::write(format!("{}/data.bin", src), "new data").unwrap();
+        fs::write(format!("{}/data.bin", dst), "much older data").unwrap();
+        let archive = format!("{}/archive.tar", dir);
+        let mut tar = TarWriter::new(File::create(&archive).unwrap());
+        tar.append_file("notes.txt", 0o644, 0, 3, &b"tar"[..], None)
+            .unwrap();
+        tar.finish().unwrap();
+        let mut seen = Vec::new();
+        let mut keep_both_if_larger = |conflict: &FileConflict| {
+            seen.push(conflict.clone());
+            if conflict.source.size > conflict.destination.size {
+                OverwritePolicy::Rename
+            } else {
+                OverwritePolicy::Skip
+            }
+        };
+
+        // act
+        let report = sync_dir_with_resolver(
+            &src,
+            &dst,
+            &SyncOptions::default(),
+            &mut keep_both_if_larger,
+        )
+        .unwrap();
+        let extracted = extract_tar_with_resolver(
+            &archive,
+            &dst,
+            &ExtractionLimits::default(),
+            &mut |_: &FileConflict| OverwritePolicy::Overwrite,
+        )
+        .unwrap();
+        let refused = extract_tar_with_resolver(
+            &archive,
+            &dst,
+            &ExtractionLimits::default(),
+            &mut OverwritePolicy::Fail,
+        );
+
+        // assert
+        let file = |name: &str| fs::read_to_string(Path::new(&dst).join(name)).unwrap();
+        assert_eq!(vec![PathBuf::from("notes (1).txt")], report.copied);
+        assert_eq!(vec![PathBuf::from("data.bin")], report.skipped);
+        assert_eq!(2, seen.len());
+        assert!(seen.iter().all(|c| c.source.sha256.is_none()));
+        assert_eq!(vec![Path::new(&dst).join("notes.txt")], extracted);
+        assert_eq!(
+            ("tar", "new notes"),
+            (file("notes.txt").as_str(), file("notes (1).txt").as_str())
+        );
+        assert_eq!("much older data", file("data.bin"));
+        assert_eq!(ErrorKind::AlreadyExists, refused.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
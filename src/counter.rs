@@ -0,0 +1,237 @@
+//! Persistent counters shared between processes.
+use crate::write_atomic;
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Crockford's base32 alphabet, whose ASCII order matches its numeric order.
+const BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Bits of a sequence value below the millisecond timestamp.
+const COUNTER_BITS: u32 = 16;
+
+/// A `u64` counter stored as text in a file, for sequence numbers that must
+/// survive restarts, such as rotated file names, invoice or build numbers.
+///
+/// Updates hold an exclusive lock on a `.lock` file next to the counter and
+/// replace the counter file atomically, so concurrent processes never hand out
+/// the same value and a crash never leaves a torn number behind.
+#[derive(Debug, Clone)]
+pub struct CounterFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl CounterFile {
+    /// Open the counter at `path`. A missing file counts as zero.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut lock_name = OsString::from(path.file_name().unwrap_or(path.as_os_str()));
+        lock_name.push(".lock");
+        let counter = CounterFile {
+            lock_path: path.with_file_name(lock_name),
+            path,
+        };
+        counter.get()?;
+        Ok(counter)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current value.
+    pub fn get(&self) -> io::Result<u64> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.trim().parse().map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid counter file: {}", self.path.display()),
+                )
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add one to the counter and return the new value.
+    pub fn increment(&self) -> io::Result<u64> {
+        self.increment_by(1)
+    }
+
+    /// Add `n` to the counter and return the new value.
+    /// Fails with `InvalidInput` if the counter would overflow.
+    pub fn increment_by(&self, n: u64) -> io::Result<u64> {
+        self.update(|value| {
+            value.checked_add(n).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("counter overflow: {}", self.path.display()),
+                )
+            })
+        })
+    }
+
+    /// Set the counter to `value`.
+    pub fn set(&self, value: u64) -> io::Result<()> {
+        self.update(|_| Ok(value)).map(drop)
+    }
+
+    /// Replace the value with `f(value)` while holding the lock and return the new value.
+    pub(crate) fn update<F>(&self, f: F) -> io::Result<u64>
+    where
+        F: FnOnce(u64) -> io::Result<u64>,
+    {
+        let _lock = self.lock()?;
+        let value = f(self.get()?)?;
+        write_atomic(&self.path, format!("{}\n", value).as_bytes())?;
+        Ok(value)
+    }
+
+    fn lock(&self) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.lock_path)?;
+        file.lock()?;
+        Ok(file)
+    }
+}
+
+/// Hands out strictly increasing identifiers that are safe to use in file names.
+///
+/// Each value is a millisecond timestamp shifted left by 16 bits plus a counter,
+/// persisted in a [`CounterFile`], so identifiers keep increasing across
+/// processes and restarts even if the clock steps backwards. Identifiers are 13
+/// characters of Crockford base32 and sort in the order they were allocated.
+#[derive(Debug, Clone)]
+pub struct SequenceAllocator {
+    counter: CounterFile,
+}
+
+impl SequenceAllocator {
+    /// Open the allocator whose state lives at `state_path`.
+    pub fn open<P: AsRef<Path>>(state_path: P) -> io::Result<Self> {
+        Ok(SequenceAllocator {
+            counter: CounterFile::open(state_path)?,
+        })
+    }
+
+    /// Allocate the next value.
+    pub fn next_value(&self) -> io::Result<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.counter.update(|last| {
+            let next = last
+                .checked_add(1)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "sequence exhausted"))?;
+            Ok(next.max(now << COUNTER_BITS))
+        })
+    }
+
+    /// Allocate the next identifier.
+    pub fn next_id(&self) -> io::Result<String> {
+        self.next_value().map(encode_id)
+    }
+}
+
+/// Format a sequence value as a fixed-width, sortable identifier.
+pub fn encode_id(value: u64) -> String {
+    (0..13)
+        .rev()
+        .map(|i| BASE32[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+/// The time a [`SequenceAllocator`] identifier was allocated, to the millisecond.
+pub fn id_timestamp(id: &str) -> Option<SystemTime> {
+    if id.len() != 13 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for (i, c) in id.bytes().enumerate() {
+        let digit = BASE32.iter().position(|&b| b == c.to_ascii_uppercase())? as u64;
+        // The first character only holds 4 bits of a 64-bit value.
+        if i == 0 && digit > 0xf {
+            return None;
+        }
+        value = (value << 5) | digit;
+    }
+    Some(UNIX_EPOCH + Duration::from_millis(value >> COUNTER_BITS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn counter_file_increment_is_unique() {
+        // arrange
+        let dir = "assets/counter_increment_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/build-number", dir);
+
+        // act
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let counter = CounterFile::open(path).unwrap();
+                    (0..25)
+                        .map(|_| counter.increment().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut values: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        values.sort();
+        let last = CounterFile::open(&path).unwrap().get().unwrap();
+
+        // assert
+        assert_eq!((1..=100).collect::<Vec<u64>>(), values);
+        assert_eq!(100, last);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sequence_allocator_is_monotonic() {
+        // arrange
+        let dir = "assets/counter_sequence_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/sequence", dir);
+        let (first, second) = (
+            SequenceAllocator::open(&path).unwrap(),
+            SequenceAllocator::open(&path).unwrap(),
+        );
+        let before = SystemTime::now() - Duration::from_secs(1);
+
+        // act
+        let ids: Vec<String> = (0..50)
+            .map(|i| if i % 2 == 0 { &first } else { &second })
+            .map(|allocator| allocator.next_id().unwrap())
+            .collect();
+
+        // assert
+        let mut sorted = ids.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(ids, sorted);
+        assert!(ids.iter().all(|id| id.len() == 13));
+        assert!(id_timestamp(&ids[0]).unwrap() > before);
+        assert_eq!(None, id_timestamp("not-an-id"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+//! Streaming operations on large delimited text files.
+use crate::{open_input, transform::write_output};
+use std::{
+    fmt,
+    io::{self, BufRead, ErrorKind},
+    path::Path,
+};
+
+type FieldPredicate = Box<dyn FnMut(&str) -> bool>;
+
+/// A column of a delimited file, by position or header name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// The zero-based position of the column.
+    Index(usize),
+    /// The column with this name in the header row.
+    Name(String),
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Self {
+        Column::Index(index)
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(name.to_owned())
+    }
+}
+
+/// Which columns and rows [`filter_delimited`] keeps, configured with builder
+/// methods. By default the input is comma separated with a header row, and
+/// every column and row is kept.
+pub struct FilterSpec {
+    delimiter: char,
+    header: bool,
+    columns: Option<Vec<Column>>,
+    predicates: Vec<(Column, FieldPredicate)>,
+}
+
+impl FilterSpec {
+    pub fn new() -> Self {
+        FilterSpec {
+            delimiter: ',',
+            header: true,
+            columns: None,
+            predicates: Vec::new(),
+        }
+    }
+
+    /// Separate fields with `delimiter`, e.g. `'\t'` for TSV.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Whether the first row names the columns. It is projected like any other
+    /// row but never filtered out.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Keep only `columns`, in the given order.
+    pub fn columns<C: Into<Column>>(mut self, columns: impl IntoIterator<Item = C>) -> Self {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Keep only rows whose `column` satisfies `predicate`. Rows must satisfy
+    /// every filter; missing fields are passed as empty strings.
+    pub fn filter<C, F>(mut self, column: C, predicate: F) -> Self
+    where
+        C: Into<Column>,
+        F: FnMut(&str) -> bool + 'static,
+    {
+        self.predicates.push((column.into(), Box::new(predicate)));
+        self
+    }
+}
+
+impl Default for FilterSpec {
+    fn default() -> Self {
+        FilterSpec::new()
+    }
+}
+
+impl fmt::Debug for FilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterSpec")
+            .field("delimiter", &self.delimiter)
+            .field("header", &self.header)
+            .field("columns", &self.columns)
+            .field("filters", &self.predicates.len())
+            .finish()
+    }
+}
+
+/// Stream the delimited file `src` into `dst`, keeping the rows and columns
+/// selected by `spec`. Either path may be `"-"` for stdin or stdout.
+///
+/// Only one row is held in memory at a time. Quoted fields may contain the
+/// delimiter, quotes and line breaks, and output fields are quoted as needed.
+///
+/// # Returns
+/// The number of rows written, not counting the header.
+///
+/// # Errors
+/// `InvalidInput` if a column is named that is not in the header, or columns
+/// are named in a file without one.
+pub fn filter_delimited<P, Q>(src: P, dst: Q, mut spec: FilterSpec) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut input = open_input(src)?;
+    let delimiter = spec.delimiter;
+    write_output(dst.as_ref(), move |output| {
+        let mut record = String::new();
+        let mut header = None;
+        if spec.header && read_record(&mut input, &mut record)? {
+            header = Some(split_record(&record, delimiter));
+        }
+        let resolve = |column: &Column| match (column, &header) {
+            (Column::Index(index), _) => Ok(*index),
+            (Column::Name(name), Some(header)) => header
+                .iter()
+                .position(|field| field == name)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("no column named `{}`", name),
+                    )
+                }),
+            (Column::Name(name), None) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot select column `{}` without a header row", name),
+            )),
+        };
+        let columns = match &spec.columns {
+            Some(columns) => Some(
+                columns
+                    .iter()
+                    .map(resolve)
+                    .collect::<io::Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        let mut predicates = Vec::new();
+        for (column, predicate) in &mut spec.predicates {
+            predicates.push((resolve(column)?, predicate));
+        }
+
+        let write_row = |output: &mut dyn io::Write, fields: &[String]| {
+            let field = |index: usize| fields.get(index).map_or("", String::as_str);
+            let projected: Vec<&str> = match &columns {
+                Some(columns) => columns.iter().map(|&index| field(index)).collect(),
+                None => fields.iter().map(String::as_str).collect(),
+            };
+            for (i, value) in projected.iter().enumerate() {
+                if i > 0 {
+                    write!(output, "{}", delimiter)?;
+                }
+                output.write_all(quote_field(value, delimiter).as_bytes())?;
+            }
+            output.write_all(b"\n")
+        };
+        if let Some(header) = &header {
+            write_row(output, header)?;
+        }
+        let mut written = 0;
+        while read_record(&mut input, &mut record)? {
+            let fields = split_record(&record, delimiter);
+            let keep = predicates
+                .iter_mut()
+                .all(|(index, predicate)| predicate(fields.get(*index).map_or("", String::as_str)));
+            if keep {
+                write_row(output, &fields)?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    })
+}
+
+/// Helper function to read the next record into `record` without its line
+/// terminator, joining lines while a quoted field is open.
+///
+/// # Returns
+/// `Ok(false)` at the end of the input.
+fn read_record(input: &mut dyn BufRead, record: &mut String) -> io::Result<bool> {
+    record.clear();
+    if input.read_line(record)? == 0 {
+        return Ok(false);
+    }
+    while record.matches('"').count() % 2 == 1 && input.read_line(record)? > 0 {}
+    let content = record.strip_suffix('\n').unwrap_or(record);
+    let content = content.strip_suffix('\r').unwrap_or(content);
+    record.truncate(content.len());
+    Ok(true)
+}
+
+/// Split `record` into fields, honouring quoted fields.
+fn split_record(record: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Quote `field` if it contains the delimiter, a quote or a line break.
+fn quote_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Which lines of the first file [`join_files_by_key_with`] writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JoinKind {
+    /// Only lines with a matching key in the second file.
+    #[default]
+    Inner,
+    /// Every line, unchanged where the second file has no matching key.
+    Left,
+}
+
+/// Options for [`join_files_by_key_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinOptions {
+    /// Placed between the joined lines.
+    pub delimiter: char,
+    pub kind: JoinKind,
+}
+
+impl Default for JoinOptions {
+    fn default() -> Self {
+        JoinOptions {
+            delimiter: ',',
+            kind: JoinKind::default(),
+        }
+    }
+}
+
+/// Inner join the lines of `a` and `b` on the key `key_fn` extracts from each
+/// line, writing every matching pair to `out` as `<a line>,<b line>`.
+/// Any path may be `"-"` for stdin or stdout.
+///
+/// Both inputs must be sorted by key. They are read in a single pass, holding
+/// only the lines of `b` that share the current key in memory.
+///
+/// # Returns
+/// The number of lines written.
+///
+/// # Errors
+/// `InvalidData` if either input is not sorted by key.
+pub fn join_files_by_key<P, Q, R, F, K>(a: P, b: Q, key_fn: F, out: R) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+    F: FnMut(&str) -> K,
+    K: Ord,
+{
+    join_files_by_key_with(a, b, key_fn, out, &JoinOptions::default())
+}
+
+/// Like [`join_files_by_key`], with the given `options`.
+pub fn join_files_by_key_with<P, Q, R, F, K>(
+    a: P,
+    b: Q,
+    mut key_fn: F,
+    out: R,
+    options: &JoinOptions,
+) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+    F: FnMut(&str) -> K,
+    K: Ord,
+{
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let mut lines_a = open_input(a)?.lines();
+    let mut lines_b = open_input(b)?.lines();
+    let delimiter = options.delimiter.to_string();
+    write_output(out.as_ref(), |output| {
+        let mut next_b = next_keyed(&mut lines_b, &mut key_fn, None, b)?;
+        let mut last_a: Option<K> = None;
+        let mut group: Option<(K, Vec<String>)> = None;
+        let mut written = 0;
+        while let Some((key, line)) = next_keyed(&mut lines_a, &mut key_fn, last_a.as_ref(), a)? {
+            if group
+                .as_ref()
+                .is_none_or(|(group_key, _)| *group_key != key)
+            {
+                let mut matches = Vec::new();
+                let mut matched_key = None;
+                loop {
+                    match next_b.take() {
+                        Some((key_b, line_b)) if key_b <= key => {
+                            next_b = next_keyed(&mut lines_b, &mut key_fn, Some(&key_b), b)?;
+                            if key_b == key {
+                                matches.push(line_b);
+                                matched_key = Some(key_b);
+                            }
+                        }
+                        other => {
+                            next_b = other;
+                            break;
+                        }
+                    }
+                }
+                group = matched_key.map(|key_b| (key_b, matches));
+            }
+            match &group {
+                Some((_, matches)) => {
+                    for line_b in matches {
+                        output.write_all(line.as_bytes())?;
+                        output.write_all(delimiter.as_bytes())?;
+                        output.write_all(line_b.as_bytes())?;
+                        output.write_all(b"\n")?;
+                        written += 1;
+                    }
+                }
+                None if options.kind == JoinKind::Left => {
+                    output.write_all(line.as_bytes())?;
+                    output.write_all(b"\n")?;
+                    written += 1;
+                }
+                None => {}
+            }
+            last_a = Some(key);
+        }
+        Ok(written)
+    })
+}
+
+/// Helper function to read the next line of `lines` with its key, checking
+/// that the key does not sort before `previous`.
+fn next_keyed<K: Ord>(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    key_fn: &mut impl FnMut(&str) -> K,
+    previous: Option<&K>,
+    path: &Path,
+) -> io::Result<Option<(K, String)>> {
+    let Some(line) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+    let key = key_fn(&line);
+    if previous.is_some_and(|previous| key < *previous) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not sorted by key at `{}`", path.display(), line),
+        ));
+    }
+    Ok(Some((key, line)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cmp::Reverse, fs};
+
+    #[test]
+    fn join_files_by_key_matches_sorted_runs() {
+        // arrange
+        let dir = "assets/delimited_join_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (a, b) = (format!("{}/a.csv", dir), format!("{}/b.csv", dir));
+        let (inner, left) = (format!("{}/inner.csv", dir), format!("{}/left.csv", dir));
+        fs::write(&a, "1,alice\n2,bob\n2,bobby\n4,dan\n").unwrap();
+        fs::write(&b, "1,x\n2,y\n2,z\n3,w\n").unwrap();
+        let id = |line: &str| line.split(',').next().unwrap_or("").to_owned();
+        let options = JoinOptions {
+            kind: JoinKind::Left,
+            ..JoinOptions::default()
+        };
+
+        // act
+        let joined = join_files_by_key(&a, &b, id, &inner).unwrap();
+        join_files_by_key_with(&a, &b, id, &left, &options).unwrap();
+        let descending = |line: &str| Reverse(id(line));
+        let unsorted = join_files_by_key(&a, &b, descending, format!("{}/unsorted.csv", dir));
+
+        // assert
+        assert_eq!(5, joined);
+        assert_eq!(
+            "1,alice,1,x\n2,bob,2,y\n2,bob,2,z\n2,bobby,2,y\n2,bobby,2,z\n",
+            fs::read_to_string(&inner).unwrap()
+        );
+        assert!(fs::read_to_string(&left)
+            .unwrap()
+            .ends_with("2,bobby,2,z\n4,dan\n"));
+        assert_eq!(ErrorKind::InvalidData, unsorted.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn filter_delimited_projects_and_filters() {
+        // arrange
+        let dir = "assets/delimited_filter_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (src, dst) = (format!("{}/people.tsv", dir), format!("{}/out.tsv", dir));
+        fs::write(
+            &src,
+            "name\tage\tcity\nada\t36\tLondon\nbob\t25\t\"New\nYork\"\nalan\t41\tWilmslow\n",
+        )
+        .unwrap();
+        let spec = FilterSpec::new()
+            .delimiter('\t')
+            .columns(["city", "name"])
+            .filter("age", |age| age.parse::<u32>().is_ok_and(|age| age > 30))
+            .filter(0, |name| name.starts_with('a'));
+
+        // act
+        let written = filter_delimited(&src, &dst, spec).unwrap();
+        let projected = fs::read_to_string(&dst).unwrap();
+        let missing = filter_delimited(&src, &dst, FilterSpec::new().columns(["zip"]));
+        let multiline = filter_delimited(
+            &src,
+            &dst,
+            FilterSpec::new().delimiter('\t').header(false).columns([2]),
+        )
+        .unwrap();
+
+        // assert
+        assert_eq!(2, written);
+        assert_eq!("city\tname\nLondon\tada\nWilmslow\talan\n", projected);
+        assert_eq!(ErrorKind::InvalidInput, missing.unwrap_err().kind());
+        assert_eq!(4, multiline);
+        assert_eq!(
+            "city\nLondon\n\"New\nYork\"\nWilmslow\n",
+            fs::read_to_string(&dst).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
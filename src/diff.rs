@@ -0,0 +1,121 @@
+//! Line-level diffs of text.
+
+/// Whether a line was added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+}
+
+/// One line that differs between two texts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+    pub kind: ChangeKind,
+    /// The 1-based line number, in the old text for removals and in the new
+    /// text for additions.
+    pub line: usize,
+    pub text: String,
+}
+
+/// Changed regions with more line pairs than this are reported as wholly
+/// replaced instead of diffed, bounding the memory a diff takes.
+const MAX_CELLS: usize = 4_000_000;
+
+/// The lines removed from `old` and added in `new`, in order, with the
+/// removals of each changed region before its additions.
+///
+/// The diff is minimal: lines common to both texts in order are never
+/// reported, except in changed regions too large to compare line by line.
+pub fn diff_lines(old: &str, new: &str) -> Vec<LineChange> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    let change = |kind, i: usize, text: &str| LineChange {
+        kind,
+        line: prefix + i + 1,
+        text: text.to_owned(),
+    };
+
+    let mut changes = Vec::new();
+    if a.len().saturating_mul(b.len()) > MAX_CELLS {
+        changes.extend(
+            a.iter()
+                .enumerate()
+                .map(|(i, t)| change(ChangeKind::Removed, i, t)),
+        );
+        changes.extend(
+            b.iter()
+                .enumerate()
+                .map(|(j, t)| change(ChangeKind::Added, j, t)),
+        );
+        return changes;
+    }
+    // `lcs[i * width + j]` is the length of the longest common subsequence of `a[i..]` and `b[j..]`.
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if i < a.len()
+            && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            changes.push(change(ChangeKind::Removed, i, a[i]));
+            i += 1;
+        } else {
+            changes.push(change(ChangeKind::Added, j, b[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lines_reports_minimal_changes() {
+        // arrange
+        let old = "port = 80\nhost = a\nlog = info\ntimeout = 5\n";
+        let new = "port = 80\nhost = b\nlog = info\nretries = 3\ntimeout = 5\n";
+
+        // act
+        let changes = diff_lines(old, new);
+        let unchanged = diff_lines(old, old);
+
+        // assert
+        let change = |kind, line, text: &str| LineChange {
+            kind,
+            line,
+            text: text.to_owned(),
+        };
+        assert_eq!(
+            vec![
+                change(ChangeKind::Removed, 2, "host = a"),
+                change(ChangeKind::Added, 2, "host = b"),
+                change(ChangeKind::Added, 4, "retries = 3"),
+            ],
+            changes
+        );
+        assert!(unchanged.is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! `mkdir -p` style directory creation.
+use crate::remove_dir_recursive;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Create the directory at `path` and any missing parents. Succeeds if it
+/// already exists.
+///
+/// # Errors
+/// `AlreadyExists` if `path` exists but is not a directory.
+pub fn ensure_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    fs::create_dir_all(path)?;
+    if !path.is_dir() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a directory", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Create the directory a file at `path` would be written to, so that it can be.
+pub fn ensure_parent_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    match path.as_ref().parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => ensure_dir(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Create an empty directory at `path`, deleting any directory already there
+/// with everything in it. The deletion is guarded like
+/// [`remove_dir_recursive`], so shallow paths such as `/` or `/home` are refused.
+pub fn create_dir_clean<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()) {
+        remove_dir_recursive(path)?;
+    }
+    ensure_dir(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirs_are_created_and_cleaned() {
+        // arrange
+        let dir = "assets/dirs_test";
+        let _ = fs::remove_dir_all(dir);
+        let file = format!("{}/a/b/file.txt", dir);
+        let clean = format!("{}/clean", dir);
+
+        // act
+        ensure_parent_dir(&file).unwrap();
+        fs::write(&file, "").unwrap();
+        ensure_dir(format!("{}/a/b", dir)).unwrap();
+        let not_a_dir = ensure_dir(&file);
+        fs::create_dir_all(format!("{}/stale", clean)).unwrap();
+        create_dir_clean(&clean).unwrap();
+
+        // assert
+        assert!(Path::new(&file).is_file());
+        assert_eq!(ErrorKind::AlreadyExists, not_a_dir.unwrap_err().kind());
+        assert!(Path::new(&clean).is_dir());
+        assert_eq!(0, fs::read_dir(&clean).unwrap().count());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
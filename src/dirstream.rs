@@ -0,0 +1,178 @@
+//! Lazy directory listings for directories too large to collect.
+use crate::fd::{self, FdPermit};
+use std::{
+    fs::{self, DirEntry, ReadDir},
+    io,
+    ops::ControlFlow,
+    path::Path,
+    vec,
+};
+
+/// A directory being listed by a [`DirStream`].
+#[derive(Debug)]
+enum Level {
+    Open {
+        entries: Box<ReadDir>,
+        _permit: FdPermit,
+    },
+    /// Read in full because the descriptor budget was used up.
+    Buffered(vec::IntoIter<DirEntry>),
+}
+
+impl Level {
+    /// Open `dir`, buffering its entries if no descriptor can be spared.
+    fn nested(dir: &Path) -> io::Result<Self> {
+        match fd::try_acquire(1) {
+            Some(permit) => Ok(Level::Open {
+                entries: Box::new(fs::read_dir(dir)?),
+                _permit: permit,
+            }),
+            None => Ok(Level::Buffered(
+                fs::read_dir(dir)?
+                    .collect::<io::Result<Vec<_>>>()?
+                    .into_iter(),
+            )),
+        }
+    }
+
+    fn next(&mut self) -> Option<io::Result<DirEntry>> {
+        match self {
+            Level::Open { entries, .. } => entries.next(),
+            Level::Buffered(entries) => entries.next().map(Ok),
+        }
+    }
+}
+
+/// A lazy iterator over directory entries, in the order the OS returns them.
+///
+/// Only one batch of entries per open directory is held in memory at a time,
+/// so millions of entries can be visited without collecting them. Open
+/// directories count against the [descriptor budget](crate::fd::set_fd_limit);
+/// when it runs out, nested directories are read in full instead.
+#[derive(Debug)]
+pub struct DirStream {
+    // One directory per level of nesting, innermost last.
+    stack: Vec<Level>,
+    recursive: bool,
+}
+
+impl Iterator for DirStream {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.stack.last_mut()?.next() {
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(entry)) => entry,
+            };
+            if self.recursive {
+                // Symlinks aren't followed, so `file_type` doesn't need a stat.
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => match Level::nested(&entry.path()) {
+                        Ok(children) => self.stack.push(children),
+                        Err(e) => return Some(Err(e)),
+                    },
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            return Some(Ok(entry));
+        }
+    }
+}
+
+/// Lazily list the entries of `dir`, waiting for the descriptor budget if needed.
+pub fn stream_dir<P: AsRef<Path>>(dir: P) -> io::Result<DirStream> {
+    Ok(DirStream {
+        stack: vec![open(dir.as_ref())?],
+        recursive: false,
+    })
+}
+
+/// Lazily list everything under `dir`, depth first. Each directory is yielded
+/// before its contents. Symlinks are listed but not followed.
+pub fn stream_tree<P: AsRef<Path>>(dir: P) -> io::Result<DirStream> {
+    Ok(DirStream {
+        stack: vec![open(dir.as_ref())?],
+        recursive: true,
+    })
+}
+
+fn open(dir: &Path) -> io::Result<Level> {
+    let permit = fd::acquire(1);
+    Ok(Level::Open {
+        entries: Box::new(fs::read_dir(dir)?),
+        _permit: permit,
+    })
+}
+
+/// Call `f` with each entry of `dir` until it returns [`ControlFlow::Break`].
+///
+/// # Returns
+/// The value `f` broke with, or `None` if every entry was visited.
+pub fn for_each_entry<P, F, B>(dir: P, mut f: F) -> io::Result<Option<B>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&DirEntry) -> ControlFlow<B>,
+{
+    for entry in stream_dir(dir)? {
+        if let ControlFlow::Break(value) = f(&entry?) {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_dir_works() {
+        // arrange
+        let dir = "assets/dirstream_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/sub/deeper", dir)).unwrap();
+        for i in 0..100 {
+            fs::write(format!("{}/file{}.txt", dir, i), "").unwrap();
+        }
+        fs::write(format!("{}/sub/deeper/nested.txt", dir), "").unwrap();
+
+        // act
+        let flat = stream_dir(dir).unwrap().count();
+        let mut tree: Vec<String> = stream_tree(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path().to_string_lossy().replace('\\', "/"))
+            .filter(|p| p.contains("sub"))
+            .collect();
+        tree.sort();
+        let mut seen = 0;
+        let found = for_each_entry(dir, |entry| {
+            seen += 1;
+            if entry.file_name() == "sub" {
+                ControlFlow::Break(entry.path())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+
+        // assert
+        assert_eq!(101, flat);
+        assert_eq!(
+            vec![
+                format!("{}/sub", dir),
+                format!("{}/sub/deeper", dir),
+                format!("{}/sub/deeper/nested.txt", dir),
+            ],
+            tree
+        );
+        assert_eq!(Some(Path::new(dir).join("sub")), found);
+        assert!(seen <= 101);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
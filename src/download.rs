@@ -0,0 +1,355 @@
+//! Download files over HTTP(S) to disk, with resume and verification.
+use crate::{checksum::sha256_reader, sidecar::update_sidecar};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, ErrorKind, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Options for [`download`].
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Continue a partial download left by an earlier attempt with an HTTP
+    /// `Range` request. The request carries the `ETag` or `Last-Modified` date
+    /// the server first sent in `If-Range`, so a changed file is downloaded
+    /// again instead of being spliced onto the old part. Servers that sent
+    /// neither, or ignore the range, restart from scratch.
+    pub resume: bool,
+    /// The expected SHA-256 of the whole file as hex. On a mismatch the partial
+    /// file is removed and `dest` is left alone.
+    pub sha256: Option<String>,
+    /// Replace `dest` if it already exists.
+    pub overwrite: bool,
+    /// Give up when the whole download takes longer.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            resume: true,
+            sha256: None,
+            overwrite: false,
+            timeout: None,
+        }
+    }
+}
+
+/// Stream the response for `url` to `dest`.
+///
+/// The body is written to `.<name>.part` next to `dest` and only renamed into
+/// place once complete and verified, so `dest` never holds a partial file.
+/// An interrupted download keeps its `.part` file, and the response's validator
+/// in `.<name>.part.validator`, for the next attempt to resume. The `.part` file
+/// is locked while it is written, so only one download of `dest` runs at a time.
+///
+/// # Returns
+/// The size of the downloaded file.
+///
+/// # Errors
+/// `AlreadyExists` if `dest` exists and `options.overwrite == false`,
+/// `InvalidData` if the checksum does not match, or `Other` for HTTP errors.
+pub fn download<P: AsRef<Path>>(url: &str, dest: P, options: &DownloadOptions) -> io::Result<u64> {
+    let dest = dest.as_ref();
+    if !options.overwrite && fs::symlink_metadata(dest).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("cannot download to {}: it already exists", dest.display()),
+        ));
+    }
+    let part = part_path(dest);
+    let validator = validator_path(&part);
+    // Only resume a part file whose validator lets the server confirm it
+    // still serves the same file.
+    let resume_from = match (fs::metadata(&part), fs::read_to_string(&validator)) {
+        (Ok(metadata), Ok(tag)) if options.resume && metadata.len() > 0 => {
+            Some((metadata.len(), tag))
+        }
+        _ => None,
+    };
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(options.timeout)
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let mut request = agent.get(url);
+    if let Some((offset, tag)) = &resume_from {
+        request = request
+            .header("Range", format!("bytes={}-", offset))
+            .header("If-Range", tag.trim());
+    }
+    let response = request.call().map_err(ureq::Error::into_io)?;
+    let status = response.status().as_u16();
+    let content_range = response
+        .headers()
+        .get("content-range")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let append = match (status, &resume_from) {
+        (206, Some((offset, _))) => {
+            if content_range_start(content_range.as_deref()) != Some(*offset) {
+                return restart(url, &part, dest, options);
+            }
+            true
+        }
+        (416, Some((offset, _))) => {
+            // The part file is already complete, if the server says so.
+            if content_range_len(content_range.as_deref()) == Some(*offset)
+                || options.sha256.is_some()
+            {
+                return finish(open_part(&part)?, &part, dest, options);
+            }
+            return restart(url, &part, dest, options);
+        }
+        (200..=299, _) if status != 206 => false,
+        _ => {
+            return Err(io::Error::other(format!(
+                "downloading {} failed with HTTP status {}",
+                url, status
+            )))
+        }
+    };
+
+    let mut file = open_part(&part)?;
+    if append {
+        file.seek(SeekFrom::End(0))?;
+    } else {
+        file.set_len(0)?;
+        match strong_validator(&response) {
+            Some(tag) => fs::write(&validator, tag)?,
+            None => remove_if_exists(&validator)?,
+        }
+    }
+    io::copy(&mut response.into_body().into_reader(), &mut file)?;
+    file.sync_all()?;
+    finish(file, &part, dest, options)
+}
+
+/// Helper function to open the `part` file and lock it for this download.
+fn open_part(part: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(part)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(
+            ErrorKind::WouldBlock,
+            format!("{} is already being downloaded", part.display()),
+        )),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Helper function to throw away a `part` file the server can't continue and
+/// download from the start.
+fn restart(url: &str, part: &Path, dest: &Path, options: &DownloadOptions) -> io::Result<u64> {
+    remove_if_exists(part)?;
+    remove_if_exists(&validator_path(part))?;
+    download(url, dest, options)
+}
+
+/// The `ETag`, unless weak, or else the `Last-Modified` date of `response`:
+/// the values `If-Range` accepts.
+fn strong_validator<B>(response: &ureq::http::Response<B>) -> Option<String> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    match header("etag") {
+        Some(tag) if !tag.starts_with("W/") => Some(tag.to_owned()),
+        _ => header("last-modified").map(str::to_owned),
+    }
+}
+
+/// The first byte of a `Content-Range: bytes <start>-<end>/<len>` header.
+fn content_range_start(header: Option<&str>) -> Option<u64> {
+    let range = header?.trim().strip_prefix("bytes ")?;
+    range.split_once('-')?.0.trim().parse().ok()
+}
+
+/// The length in a `Content-Range: bytes */<len>` header.
+fn content_range_len(header: Option<&str>) -> Option<u64> {
+    let range = header?.trim().strip_prefix("bytes ")?;
+    range.strip_prefix("*/")?.trim().parse().ok()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Helper function to verify the complete `part` file, open as `file`, and move
+/// it to `dest`. The lock on `file` is held until it is in place.
+fn finish(mut file: File, part: &Path, dest: &Path, options: &DownloadOptions) -> io::Result<u64> {
+    if let Some(expected) = &options.sha256 {
+        file.seek(SeekFrom::Start(0))?;
+        let actual = sha256_reader(&mut file)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            fs::remove_file(part)?;
+            remove_if_exists(&validator_path(part))?;
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "checksum mismatch for {}: expected {}, got {}",
+                    dest.display(),
+                    expected,
+                    actual
+                ),
+            ));
+        }
+    }
+    let len = file.metadata()?.len();
+    fs::rename(part, dest)?;
+    remove_if_exists(&validator_path(part))?;
+    update_sidecar(dest)?;
+    Ok(len)
+}
+
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(dest.file_name().unwrap_or(dest.as_os_str()));
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// Where the validator of the response being saved to `part` is kept.
+fn validator_path(part: &Path) -> PathBuf {
+    let mut name = part.as_os_str().to_owned();
+    name.push(".validator");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::to_hex;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve `body` with `etag` to `requests` connections, honouring
+    /// `Range: bytes=N-` unless `If-Range` names another tag.
+    fn serve(body: &'static [u8], etag: &'static str, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut start = 0;
+                let mut if_range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    let lower = line.to_ascii_lowercase();
+                    if let Some(range) = lower.strip_prefix("range: bytes=") {
+                        start = range.trim().trim_end_matches('-').parse().unwrap();
+                    } else if lower.starts_with("if-range:") {
+                        if_range = Some(line["if-range:".len()..].trim().to_owned());
+                    }
+                    line.clear();
+                }
+                if if_range.is_some_and(|tag| tag != etag) {
+                    start = 0;
+                }
+                let (status, range) = if start >= body.len() && start > 0 {
+                    start = body.len();
+                    (
+                        "416 Range Not Satisfiable",
+                        format!("bytes */{}", body.len()),
+                    )
+                } else if start > 0 {
+                    let range = format!("bytes {}-{}/{}", start, body.len() - 1, body.len());
+                    ("206 Partial Content", range)
+                } else {
+                    ("200 OK", String::new())
+                };
+                let rest = &body[start..];
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nETag: {}\r\nContent-Range: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    etag,
+                    range,
+                    rest.len()
+                )
+                .unwrap();
+                stream.write_all(rest).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn download_resumes_and_verifies() {
+        // arrange
+        let dir = "assets/download_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let body: &[u8] = b"0123456789abcdefghij";
+        let dest = Path::new(dir).join("file.bin");
+        fs::write(part_path(&dest), &body[..8]).unwrap();
+        fs::write(validator_path(&part_path(&dest)), "\"v1\"").unwrap();
+        let url = serve(body, "\"v1\"", 1);
+        let mut hash = crate::checksum::Sha256::new();
+        hash.update(body);
+        let options = DownloadOptions {
+            sha256: Some(to_hex(&hash.finish())),
+            ..DownloadOptions::default()
+        };
+
+        // act
+        let len = download(&url, &dest, &options).unwrap();
+        let again = download(&url, &dest, &options);
+
+        // assert
+        assert_eq!(20, len);
+        assert_eq!(body, fs::read(&dest).unwrap());
+        assert!(!part_path(&dest).exists());
+        assert_eq!(ErrorKind::AlreadyExists, again.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn download_restarts_when_the_file_changed() {
+        // arrange
+        let dir = "assets/download_restart_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let body: &[u8] = b"0123456789";
+        let changed = Path::new(dir).join("changed.bin");
+        let stale = Path::new(dir).join("stale.bin");
+        let unvalidated = Path::new(dir).join("unvalidated.bin");
+        fs::write(part_path(&changed), b"old-").unwrap();
+        fs::write(validator_path(&part_path(&changed)), "\"v0\"").unwrap();
+        fs::write(part_path(&stale), b"0123456789-old").unwrap();
+        fs::write(validator_path(&part_path(&stale)), "\"v1\"").unwrap();
+        fs::write(part_path(&unvalidated), b"old-").unwrap();
+        let url = serve(body, "\"v1\"", 4);
+
+        // act
+        let changed_len = download(&url, &changed, &DownloadOptions::default()).unwrap();
+        let stale_len = download(&url, &stale, &DownloadOptions::default()).unwrap();
+        let unvalidated_len = download(&url, &unvalidated, &DownloadOptions::default()).unwrap();
+
+        // assert
+        assert_eq!((10, 10, 10), (changed_len, stale_len, unvalidated_len));
+        for dest in [&changed, &stale, &unvalidated] {
+            assert_eq!(body, fs::read(dest).unwrap());
+            assert!(!part_path(dest).exists());
+            assert!(!validator_path(&part_path(dest)).exists());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
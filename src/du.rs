@@ -0,0 +1,138 @@
+//! Disk usage of directory trees, like `du`.
+use crate::walk::walk;
+use std::{collections::HashSet, fs::Metadata, io, path::Path};
+
+/// Options for [`dir_size_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeOptions {
+    /// Count the targets of symlinks, walking into linked directories, instead
+    /// of the links themselves.
+    pub follow_symlinks: bool,
+    /// Sum the lengths of files, as `du --apparent-size` does. Otherwise sum the
+    /// space allocated on disk to every entry, directories included, which is
+    /// smaller for sparse and compressed files and larger for small ones. Only
+    /// Unix reports allocated space; elsewhere lengths are used either way.
+    pub apparent_size: bool,
+}
+
+impl Default for SizeOptions {
+    fn default() -> Self {
+        SizeOptions {
+            follow_symlinks: false,
+            apparent_size: true,
+        }
+    }
+}
+
+/// The total length of the files in the tree at `path`, or of `path` itself if
+/// it is a file. Files with several hard links are counted once.
+pub fn dir_size<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    dir_size_with(path, &SizeOptions::default(), |_| {})
+}
+
+/// Like [`dir_size`], with the given `options`, calling `progress` with the
+/// total so far each time a directory is entered, for reporting on long scans.
+pub fn dir_size_with<P, F>(path: P, options: &SizeOptions, mut progress: F) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    F: FnMut(u64),
+{
+    let mut total = 0;
+    let mut linked = HashSet::new();
+    for entry in walk(path).follow_symlinks(options.follow_symlinks) {
+        let entry = entry?;
+        if entry.file_type.is_dir() {
+            progress(total);
+        }
+        if let Some(id) = hard_link_id(&entry.metadata) {
+            if !linked.insert(id) {
+                continue;
+            }
+        }
+        total += if options.apparent_size {
+            if entry.file_type.is_dir() {
+                0
+            } else {
+                entry.metadata.len()
+            }
+        } else {
+            allocated_size(&entry.metadata)
+        };
+    }
+    Ok(total)
+}
+
+/// Helper function to identify a file that has other hard links.
+#[cfg(unix)]
+fn hard_link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    (!metadata.is_dir() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hard_link_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn allocated_size(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // `st_blocks` is always in 512-byte units.
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn dir_size_sums_files_once() {
+        // arrange
+        let dir = "assets/du_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested/deeper", dir)).unwrap();
+        fs::write(format!("{}/a.txt", dir), "12345").unwrap();
+        fs::write(format!("{}/nested/b.txt", dir), "123").unwrap();
+        fs::write(format!("{}/nested/deeper/c.txt", dir), "1").unwrap();
+        fs::hard_link(
+            format!("{}/a.txt", dir),
+            format!("{}/nested/a-link.txt", dir),
+        )
+        .unwrap();
+        let mut totals = Vec::new();
+
+        // act
+        let apparent = dir_size(dir).unwrap();
+        let single = dir_size(format!("{}/nested/b.txt", dir)).unwrap();
+        let on_disk = dir_size_with(
+            dir,
+            &SizeOptions {
+                apparent_size: false,
+                ..SizeOptions::default()
+            },
+            |total| totals.push(total),
+        )
+        .unwrap();
+        let missing = dir_size(format!("{}/missing", dir));
+
+        // assert
+        assert_eq!(9, apparent);
+        assert_eq!(3, single);
+        assert_eq!(3, totals.len());
+        assert!(totals.windows(2).all(|pair| pair[0] <= pair[1]));
+        #[cfg(unix)]
+        assert!(on_disk >= apparent);
+        #[cfg(not(unix))]
+        assert_eq!(apparent, on_disk);
+        assert_eq!(io::ErrorKind::NotFound, missing.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
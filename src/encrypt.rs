@@ -0,0 +1,138 @@
+//! Files encrypted with ChaCha20-Poly1305, for secrets kept on disk.
+//!
+//! An encrypted file is laid out as:
+//!
+//! | Offset | Length | Contents                                  |
+//! |--------|--------|-------------------------------------------|
+//! | 0      | 4      | Magic bytes `FMEC`                        |
+//! | 4      | 1      | Format version, `1`                       |
+//! | 5      | 1      | Cipher, `1` for ChaCha20-Poly1305         |
+//! | 6      | 12     | Random nonce, new for every write         |
+//! | 18     | n + 16 | Ciphertext followed by the Poly1305 tag   |
+//!
+//! The 18 header bytes are authenticated as associated data, so changing any
+//! byte of the file makes it fail to decrypt.
+use crate::secret::{read_secret, write_secret, SecretBytes};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, AeadInPlace, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"FMEC";
+const VERSION: u8 = 1;
+const CIPHER_CHACHA20_POLY1305: u8 = 1;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 2 + NONCE_LEN;
+
+/// Encrypt `contents` with the 256-bit `key` and write them to `path`, which
+/// is created readable only by its owner, as [`write_secret`] does.
+pub fn write_encrypted<P: AsRef<Path>>(path: P, key: &[u8; 32], contents: &[u8]) -> io::Result<()> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut data = Vec::with_capacity(HEADER_LEN + contents.len() + 16);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&[VERSION, CIPHER_CHACHA20_POLY1305]);
+    data.extend_from_slice(&nonce);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: contents,
+                aad: &data,
+            },
+        )
+        .map_err(|_| io::Error::other("encryption failed"))?;
+    data.extend_from_slice(&ciphertext);
+    write_secret(path, &data)
+}
+
+/// Read and decrypt the file at `path` written by [`write_encrypted`] with
+/// the same `key`.
+///
+/// # Errors
+/// `InvalidData` if the file is not in this format, was modified, or `key` is
+/// wrong; the last two cannot be told apart. `Unsupported` for files written
+/// by a newer version of the format.
+pub fn read_encrypted<P: AsRef<Path>>(path: P, key: &[u8; 32]) -> io::Result<SecretBytes> {
+    let path = path.as_ref();
+    let data = read_secret(path)?;
+    let data = data.expose();
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not an encrypted file", path.display()),
+        ));
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    if header[4] != VERSION || header[5] != CIPHER_CHACHA20_POLY1305 {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} uses format version {} with cipher {}",
+                path.display(),
+                header[4],
+                header[5]
+            ),
+        ));
+    }
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = Nonce::from_slice(&header[6..]);
+    // Decrypt in place so the plaintext only ever lives in the zeroized buffer.
+    let mut contents = SecretBytes::from(ciphertext.to_vec());
+    cipher
+        .decrypt_in_place(nonce, header, contents.as_mut_vec())
+        .map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "cannot decrypt {}: wrong key or modified file",
+                    path.display()
+                ),
+            )
+        })?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn encrypted_round_trip_detects_tampering() {
+        // arrange
+        let dir = "assets/encrypt_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/token.enc", dir);
+        let key = [7; 32];
+
+        // act
+        write_encrypted(&path, &key, b"hunter2").unwrap();
+        let data = fs::read(&path).unwrap();
+        let contents = read_encrypted(&path, &key).unwrap();
+        let wrong_key = read_encrypted(&path, &[8; 32]).map(|_| ());
+        let mut tampered = data.clone();
+        tampered[5] ^= 1;
+        fs::write(&path, &tampered).unwrap();
+        let bad_header = read_encrypted(&path, &key).map(|_| ());
+        tampered[5] ^= 1;
+        *tampered.last_mut().unwrap() ^= 1;
+        fs::write(&path, &tampered).unwrap();
+        let bad_tag = read_encrypted(&path, &key).map(|_| ());
+
+        // assert
+        assert_eq!(b"hunter2", contents.expose());
+        assert_eq!(b"FMEC\x01\x01", &data[..6]);
+        assert_eq!(HEADER_LEN + 7 + 16, data.len());
+        assert!(!data.windows(7).any(|w| w == b"hunter2"));
+        assert_eq!(ErrorKind::InvalidData, wrong_key.unwrap_err().kind());
+        assert_eq!(ErrorKind::Unsupported, bad_header.unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidData, bad_tag.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
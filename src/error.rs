@@ -0,0 +1,150 @@
+//! The error type of the top-level file functions.
+//!
+//! The file functions at the crate root return [`FileManagerError`] directly.
+//! The modules return `io::Result` so they compose with `std`, and their errors
+//! carry a `FileManagerError` inside wherever a path is involved; get at it with
+//! [`FileManagerError::from_io`].
+use crate::is_stdio_path;
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// The kind of operation that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Open,
+    Create,
+    Read,
+    Write,
+    Append,
+    Delete,
+    Move,
+    Copy,
+    /// Reading the file's metadata.
+    Stat,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Open => "open",
+            Operation::Create => "create",
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Append => "append to",
+            Operation::Delete => "delete",
+            Operation::Move => "move",
+            Operation::Copy => "copy",
+            Operation::Stat => "read metadata of",
+        })
+    }
+}
+
+/// An I/O error together with the operation and path it happened on.
+///
+/// Converts into an `io::Error` of the same kind, so `?` still works in
+/// functions returning `io::Result`.
+#[derive(Debug)]
+pub enum FileManagerError {
+    /// `operation` on the file at `path` failed.
+    Io {
+        operation: Operation,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// `operation` on stdin or stdout failed, for paths given as `"-"`.
+    Stdio {
+        operation: Operation,
+        source: io::Error,
+    },
+}
+
+impl FileManagerError {
+    pub(crate) fn new(operation: Operation, path: &Path, source: io::Error) -> Self {
+        if is_stdio_path(path) {
+            FileManagerError::Stdio { operation, source }
+        } else {
+            FileManagerError::Io {
+                operation,
+                path: path.to_path_buf(),
+                source,
+            }
+        }
+    }
+
+    /// The `FileManagerError` inside `error`, if it came from this crate with
+    /// the operation and path attached.
+    pub fn from_io(error: &io::Error) -> Option<&FileManagerError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    pub fn operation(&self) -> Operation {
+        match self {
+            FileManagerError::Io { operation, .. } | FileManagerError::Stdio { operation, .. } => {
+                *operation
+            }
+        }
+    }
+
+    /// The path involved, or `None` for stdin and stdout.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FileManagerError::Io { path, .. } => Some(path),
+            FileManagerError::Stdio { .. } => None,
+        }
+    }
+
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            FileManagerError::Io { source, .. } | FileManagerError::Stdio { source, .. } => source,
+        }
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+        self.io_error().kind()
+    }
+}
+
+impl fmt::Display for FileManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileManagerError::Io {
+                operation,
+                path,
+                source,
+            } => write!(f, "cannot {} {}: {}", operation, path.display(), source),
+            FileManagerError::Stdio { operation, source } => {
+                let stream = match operation {
+                    Operation::Open | Operation::Read => "stdin",
+                    _ => "stdout",
+                };
+                write!(f, "cannot {} {}: {}", operation, stream, source)
+            }
+        }
+    }
+}
+
+impl Error for FileManagerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<FileManagerError> for io::Error {
+    fn from(e: FileManagerError) -> Self {
+        io::Error::new(e.kind(), e)
+    }
+}
+
+/// Attaches the operation and path to an `io::Result`.
+pub(crate) trait Context<T> {
+    fn context(self, operation: Operation, path: &Path) -> Result<T, FileManagerError>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, operation: Operation, path: &Path) -> Result<T, FileManagerError> {
+        self.map_err(|e| FileManagerError::new(operation, path, e))
+    }
+}
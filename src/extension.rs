@@ -0,0 +1,155 @@
+//! File extensions that may span several dots, like `tar.gz`.
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// Extensions that wrap another file format, so the extension before them is
+/// part of the file's extension too: `notes.txt.gz` is a gzipped `.txt`.
+const WRAPPERS: &[&str] = &[
+    "age", "br", "bz2", "enc", "gpg", "gz", "lz", "lz4", "lzma", "pgp", "xz", "z", "zst",
+];
+
+/// The longest extension an inner part of a multi-part extension may have.
+const MAX_INNER_LEN: usize = 5;
+
+/// The extension of the file name of `path`, including every part when the
+/// last one wraps another format: `tar.gz` for `backup.tar.gz`, `json.gz.gpg`
+/// for `data.json.gz.gpg`, but `pdf` for `q1.report.pdf`.
+///
+/// Like [`Path::extension`], names that start with their only dot, such as
+/// `.bashrc`, have no extension. Names that are not valid UTF-8 only get their
+/// last extension.
+pub fn full_extension(path: &Path) -> Option<&OsStr> {
+    let name = path.file_name()?;
+    split_name(name).1
+}
+
+/// `path` without its full extension: `backup.tar.gz` becomes `backup`.
+pub fn strip_extensions<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    match path.file_name() {
+        Some(name) => path.with_file_name(split_name(name).0),
+        None => path.to_path_buf(),
+    }
+}
+
+/// `path` with its full extension replaced by `extension`, which may have
+/// several parts and is removed when empty: `backup.tar.gz` with `zip`
+/// becomes `backup.zip`.
+pub fn replace_extension<P: AsRef<Path>>(path: P, extension: &str) -> PathBuf {
+    let path = path.as_ref();
+    let Some(name) = path.file_name() else {
+        return path.to_path_buf();
+    };
+    let mut renamed = split_name(name).0.to_os_string();
+    let extension = extension.trim_start_matches('.');
+    if !extension.is_empty() {
+        renamed.push(".");
+        renamed.push(extension);
+    }
+    path.with_file_name(renamed)
+}
+
+/// `path` with `suffix` added to the file name before its full extension:
+/// `backup.tar.gz` with `-old` becomes `backup-old.tar.gz`.
+pub fn with_suffix<P: AsRef<Path>>(path: P, suffix: &str) -> PathBuf {
+    let path = path.as_ref();
+    match path.file_name() {
+        Some(name) => path.with_file_name(name_with_suffix(name, suffix)),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Helper function to add `suffix` to the file `name` before its full extension.
+pub(crate) fn name_with_suffix(name: &OsStr, suffix: &str) -> OsString {
+    let (stem, extension) = split_name(name);
+    let mut renamed = stem.to_os_string();
+    renamed.push(suffix);
+    if let Some(extension) = extension {
+        renamed.push(".");
+        renamed.push(extension);
+    }
+    renamed
+}
+
+/// Helper function to split a file name into its stem and full extension.
+fn split_name(name: &OsStr) -> (&OsStr, Option<&OsStr>) {
+    let Some(text) = name.to_str() else {
+        let path = Path::new(name);
+        return (path.file_stem().unwrap_or(name), path.extension());
+    };
+    // A leading dot starts the name, not an extension.
+    let Some(mut start) = text[1.min(text.len())..].rfind('.').map(|i| i + 1) else {
+        return (name, None);
+    };
+    loop {
+        let outer = text[start + 1..].split('.').next().unwrap_or_default();
+        if !WRAPPERS.contains(&outer.to_ascii_lowercase().as_str()) {
+            break;
+        }
+        let Some(dot) = text[1..start].rfind('.').map(|i| i + 1) else {
+            break;
+        };
+        let inner = &text[dot + 1..start];
+        let plausible = (1..=MAX_INNER_LEN).contains(&inner.len())
+            && inner.bytes().all(|b| b.is_ascii_alphanumeric())
+            && inner.bytes().any(|b| b.is_ascii_alphabetic());
+        if !plausible {
+            break;
+        }
+        start = dot;
+    }
+    (
+        OsStr::new(&text[..start]),
+        Some(OsStr::new(&text[start + 1..])),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_span_wrapped_formats() {
+        // arrange
+        let archive = Path::new("backups/site.tar.gz");
+
+        // act
+        let extensions: Vec<_> = [
+            "site.tar.gz",
+            "data.json.gz.gpg",
+            "q1.report.pdf",
+            "app.2024.gz",
+            ".bashrc",
+            "README",
+        ]
+        .iter()
+        .map(|name| full_extension(Path::new(name)).and_then(OsStr::to_str))
+        .collect();
+
+        // assert
+        assert_eq!(
+            vec![
+                Some("tar.gz"),
+                Some("json.gz.gpg"),
+                Some("pdf"),
+                Some("gz"),
+                None,
+                None
+            ],
+            extensions
+        );
+        assert_eq!(Path::new("backups/site"), strip_extensions(archive));
+        assert_eq!(
+            Path::new("backups/site.zip"),
+            replace_extension(archive, ".zip")
+        );
+        assert_eq!(Path::new("backups/site"), replace_extension(archive, ""));
+        assert_eq!(
+            Path::new("backups/site-backup.tar.gz"),
+            with_suffix(archive, "-backup")
+        );
+        assert_eq!(Path::new(".bashrc-old"), with_suffix(".bashrc", "-old"));
+    }
+}
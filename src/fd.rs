@@ -0,0 +1,131 @@
+//! A process-wide budget for the file descriptors this crate keeps open.
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// How much of the descriptor budget is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdUsage {
+    /// Descriptors currently held by the crate.
+    pub in_use: usize,
+    /// The most descriptors held at once since the process started.
+    pub peak: usize,
+    pub limit: usize,
+    /// Operations queued until descriptors are released.
+    pub waiting: usize,
+}
+
+struct Budget {
+    usage: Mutex<FdUsage>,
+    released: Condvar,
+}
+
+fn budget() -> &'static Budget {
+    static BUDGET: OnceLock<Budget> = OnceLock::new();
+    BUDGET.get_or_init(|| Budget {
+        usage: Mutex::new(FdUsage {
+            in_use: 0,
+            peak: 0,
+            limit: default_limit(),
+            waiting: 0,
+        }),
+        released: Condvar::new(),
+    })
+}
+
+/// Half the soft `RLIMIT_NOFILE`, leaving the rest to the application.
+#[cfg(unix)]
+fn default_limit() -> usize {
+    // SAFETY: a zeroed rlimit is a valid value of the plain C struct.
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    // SAFETY: `limit` is a valid rlimit to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return 256;
+    }
+    (limit.rlim_cur / 2).clamp(16, 65536) as usize
+}
+
+#[cfg(not(unix))]
+fn default_limit() -> usize {
+    4096
+}
+
+/// Limit how many descriptors the crate's long-lived and bulk operations hold at
+/// once. Operations beyond the limit wait for others to finish or fall back to
+/// approaches that need fewer descriptors, instead of failing with `EMFILE`.
+///
+/// The default is half the process's soft `RLIMIT_NOFILE` on Unix and 4096 elsewhere.
+pub fn set_fd_limit(limit: usize) {
+    let budget = budget();
+    budget.usage.lock().unwrap().limit = limit.max(1);
+    budget.released.notify_all();
+}
+
+/// The current use of the descriptor budget.
+pub fn fd_usage() -> FdUsage {
+    *budget().usage.lock().unwrap()
+}
+
+/// Descriptors reserved from the budget, returned when dropped.
+#[derive(Debug)]
+pub(crate) struct FdPermit {
+    count: usize,
+}
+
+/// Wait until `count` descriptors are available and reserve them.
+///
+/// A request larger than the whole limit is granted once nothing else is held.
+pub(crate) fn acquire(count: usize) -> FdPermit {
+    let budget = budget();
+    let mut usage = budget.usage.lock().unwrap();
+    usage.waiting += 1;
+    while usage.in_use > 0 && usage.in_use + count > usage.limit {
+        usage = budget.released.wait(usage).unwrap();
+    }
+    usage.waiting -= 1;
+    reserve(&mut usage, count)
+}
+
+/// Reserve `count` descriptors if that is possible without waiting.
+pub(crate) fn try_acquire(count: usize) -> Option<FdPermit> {
+    let mut usage = budget().usage.lock().unwrap();
+    (usage.in_use + count <= usage.limit).then(|| reserve(&mut usage, count))
+}
+
+fn reserve(usage: &mut FdUsage, count: usize) -> FdPermit {
+    usage.in_use += count;
+    usage.peak = usage.peak.max(usage.in_use);
+    FdPermit { count }
+}
+
+impl Drop for FdPermit {
+    fn drop(&mut self) {
+        let budget = budget();
+        budget.usage.lock().unwrap().in_use -= self.count;
+        budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn fd_budget_queues_over_limit() {
+        // arrange
+        let limit = fd_usage().limit;
+        let held = acquire(limit);
+
+        // act
+        let refused = try_acquire(1).is_none();
+        let waiter = thread::spawn(|| drop(acquire(1)));
+        thread::sleep(Duration::from_millis(50));
+        let queued = fd_usage().waiting;
+        drop(held);
+        waiter.join().unwrap();
+
+        // assert
+        assert!(refused);
+        assert!(queued >= 1);
+        assert!(fd_usage().peak >= limit);
+    }
+}
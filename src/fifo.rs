@@ -0,0 +1,203 @@
+//! Named pipes for streaming data between local processes.
+//!
+//! On Unix these are FIFOs in the filesystem. On Windows they are named pipes,
+//! whose paths must start with `\\.\pipe\`.
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Create a named pipe at `path`.
+///
+/// On Unix this makes a FIFO readable and writable by the owner. On Windows a
+/// pipe only exists while a reader has it open, so this only checks the name;
+/// [`open_fifo_reader`] creates the pipe itself.
+#[cfg(unix)]
+pub fn create_fifo<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    // SAFETY: `path` is a valid C string for the duration of the call.
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+pub fn create_fifo<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    pipe_name(path.as_ref()).map(drop)
+}
+
+/// Returns `true` if `path` is a named pipe.
+#[cfg(unix)]
+pub fn is_fifo<P: AsRef<Path>>(path: P) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+/// Open the named pipe at `path` for reading, blocking until a writer connects.
+#[cfg(unix)]
+pub fn open_fifo_reader<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let path = path.as_ref();
+    check_fifo(path)?;
+    File::open(path)
+}
+
+#[cfg(windows)]
+pub fn open_fifo_reader<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE},
+        Storage::FileSystem::PIPE_ACCESS_INBOUND,
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+            PIPE_WAIT,
+        },
+    };
+
+    let name = pipe_name(path.as_ref())?;
+    // SAFETY: `name` is NUL terminated, and the handle is closed on failure or
+    // owned by the returned file.
+    unsafe {
+        let handle = CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_INBOUND,
+            PIPE_TYPE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            0,
+            64 * 1024,
+            0,
+            std::ptr::null(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        if ConnectNamedPipe(handle, std::ptr::null_mut()) == 0 {
+            let e = io::Error::last_os_error();
+            // The writer connected between creating and waiting.
+            if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                CloseHandle(handle);
+                return Err(e);
+            }
+        }
+        Ok(File::from_raw_handle(handle))
+    }
+}
+
+/// Open the named pipe at `path` for writing, blocking until a reader connects.
+#[cfg(unix)]
+pub fn open_fifo_writer<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let path = path.as_ref();
+    check_fifo(path)?;
+    std::fs::OpenOptions::new().write(true).open(path)
+}
+
+#[cfg(windows)]
+pub fn open_fifo_writer<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
+
+    let path = path.as_ref();
+    pipe_name(path)?;
+    loop {
+        match std::fs::OpenOptions::new().write(true).open(path) {
+            Ok(file) => return Ok(file),
+            // No reader is waiting yet.
+            Err(e)
+                if e.kind() == ErrorKind::NotFound
+                    || e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) =>
+            {
+                std::thread::sleep(std::time::Duration::from_millis(10))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Helper function to refuse opening something that isn't a FIFO, which would
+/// silently read or truncate a regular file instead.
+#[cfg(unix)]
+fn check_fifo(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::metadata(path)?.file_type().is_fifo() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("not a named pipe: {}", path.display()),
+        ))
+    }
+}
+
+/// Helper function to convert `path` to a NUL-terminated pipe name.
+#[cfg(windows)]
+fn pipe_name(path: &Path) -> io::Result<Vec<u16>> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let prefix = r"\\.\pipe\";
+    let valid = path
+        .to_str()
+        .and_then(|p| p.get(..prefix.len()))
+        .is_some_and(|p| p.eq_ignore_ascii_case(prefix));
+    if !valid {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "named pipe paths must start with {}: {}",
+                prefix,
+                path.display()
+            ),
+        ));
+    }
+    Ok(path.as_os_str().encode_wide().chain(Some(0)).collect())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        io::{Read, Write},
+        thread,
+    };
+
+    #[test]
+    fn fifo_streams_between_threads() {
+        // arrange
+        let dir = "assets/fifo_stream_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/pipe", dir);
+        let file = format!("{}/regular", dir);
+        fs::write(&file, "data").unwrap();
+
+        // act
+        create_fifo(&path).unwrap();
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut pipe = open_fifo_writer(path).unwrap();
+                pipe.write_all(b"hello through a pipe").unwrap();
+            })
+        };
+        let mut received = String::new();
+        open_fifo_reader(&path)
+            .unwrap()
+            .read_to_string(&mut received)
+            .unwrap();
+        writer.join().unwrap();
+        let not_fifo = open_fifo_writer(&file);
+
+        // assert
+        assert!(is_fifo(&path));
+        assert_eq!("hello through a pipe", received);
+        assert_eq!(ErrorKind::InvalidInput, not_fifo.unwrap_err().kind());
+        assert_eq!("data", fs::read_to_string(&file).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
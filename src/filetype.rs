@@ -0,0 +1,205 @@
+//! Detect file types and dispatch files to handlers registered per type.
+use crate::{
+    dirstream::stream_tree,
+    watch::{Event, EventKind},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Magic numbers recognised by [`detect_type`]: offset, signature and MIME type.
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x7fELF", "application/x-elf"),
+    (257, b"ustar", "application/x-tar"),
+];
+
+/// Sniff the MIME type of the file at `path` from its first bytes.
+///
+/// # Returns
+/// The MIME type, or `None` if no known signature matches.
+pub fn detect_type<P: AsRef<Path>>(path: P) -> io::Result<Option<&'static str>> {
+    let mut head = Vec::with_capacity(512);
+    File::open(path)?.take(512).read_to_end(&mut head)?;
+    Ok(SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| head.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime))
+}
+
+type Handler = Box<dyn Fn(&Path) -> io::Result<()> + Send + Sync>;
+
+/// Maps file extensions and sniffed MIME types to handlers, the "open with"
+/// table of a file manager.
+///
+/// ```
+/// use file_manager::filetype::TypeRegistry;
+///
+/// let registry = TypeRegistry::new()
+///     .on_extension("md", |path| Ok(println!("render {}", path.display())))
+///     .on_type("image/png", |path| Ok(println!("preview {}", path.display())));
+/// ```
+#[derive(Default)]
+pub struct TypeRegistry {
+    extensions: HashMap<String, Handler>,
+    types: HashMap<String, Handler>,
+    fallback: Option<Handler>,
+}
+
+impl fmt::Debug for TypeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypeRegistry")
+            .field("extensions", &self.extensions.keys().collect::<Vec<_>>())
+            .field("types", &self.types.keys().collect::<Vec<_>>())
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        TypeRegistry::default()
+    }
+
+    /// Handle files ending in `.<extension>`, matched case-insensitively.
+    pub fn on_extension<F>(mut self, extension: &str, handler: F) -> Self
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.extensions.insert(extension, Box::new(handler));
+        self
+    }
+
+    /// Handle files whose contents [`detect_type`] identifies as `mime`.
+    pub fn on_type<F>(mut self, mime: &str, handler: F) -> Self
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.types.insert(mime.to_owned(), Box::new(handler));
+        self
+    }
+
+    /// Handle files nothing else matched.
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Run the handler for the file at `path`. Extensions are checked first,
+    /// then the sniffed type, then the fallback.
+    ///
+    /// # Returns
+    /// `true` if a handler ran.
+    pub fn dispatch<P: AsRef<Path>>(&self, path: P) -> io::Result<bool> {
+        let path = path.as_ref();
+        let by_extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.extensions.get(&ext.to_ascii_lowercase()));
+        let handler = match by_extension {
+            Some(handler) => Some(handler),
+            None if self.types.is_empty() => None,
+            None => detect_type(path)?.and_then(|mime| self.types.get(mime)),
+        };
+        match handler.or(self.fallback.as_ref()) {
+            Some(handler) => handler(path).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Dispatch every regular file under `root`.
+    ///
+    /// # Returns
+    /// The number of files a handler ran for.
+    pub fn dispatch_tree<P: AsRef<Path>>(&self, root: P) -> io::Result<usize> {
+        let mut handled = 0;
+        for entry in stream_tree(root)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && self.dispatch(entry.path())? {
+                handled += 1;
+            }
+        }
+        Ok(handled)
+    }
+
+    /// Dispatch the file a [`Watcher`](crate::watch::Watcher) reported as
+    /// created or modified. Other events and directories are ignored.
+    ///
+    /// # Returns
+    /// `true` if a handler ran.
+    pub fn dispatch_event(&self, event: &Event) -> io::Result<bool> {
+        if event.kind == EventKind::Removed || !event.path.is_file() {
+            return Ok(false);
+        }
+        self.dispatch(&event.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn type_registry_dispatches_by_extension_and_magic() {
+        // arrange
+        let dir = "assets/filetype_dispatch_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        fs::write(format!("{}/README.MD", dir), "# hi").unwrap();
+        fs::write(format!("{}/nested/logo", dir), b"\x89PNG\r\n\x1a\n....").unwrap();
+        fs::write(format!("{}/notes.txt", dir), "plain").unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = |tag: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |path: &Path| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                seen.lock().unwrap().push((tag, name));
+                Ok(())
+            }
+        };
+        let registry = TypeRegistry::new()
+            .on_extension(".md", record("markdown"))
+            .on_type("image/png", record("image"));
+
+        // act
+        let handled = registry.dispatch_tree(dir).unwrap();
+
+        // assert
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(2, handled);
+        assert_eq!(
+            vec![
+                ("image", "logo".to_owned()),
+                ("markdown", "README.MD".to_owned())
+            ],
+            seen
+        );
+        assert_eq!(
+            Some("image/png"),
+            detect_type(format!("{}/nested/logo", dir)).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
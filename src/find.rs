@@ -0,0 +1,534 @@
+//! Find files under a directory by name, size and age, or by glob pattern, and
+//! find marker files above a directory.
+use crate::{
+    dirstream::{stream_tree, DirStream},
+    glob,
+    json::Value,
+};
+use std::{
+    fs::{self, DirEntry, Metadata},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+    vec,
+};
+
+/// A search for entries under a root directory, built up from criteria that
+/// must all match.
+///
+/// ```
+/// use file_manager::find::FindQuery;
+/// use std::time::Duration;
+///
+/// let query = FindQuery::new("reports")
+///     .name("*-final*")
+///     .extension("pdf")
+///     .min_size(1024)
+///     .modified_within(Duration::from_secs(7 * 24 * 60 * 60));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindQuery {
+    root: PathBuf,
+    name: Option<String>,
+    extensions: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_within: Option<Duration>,
+    include_dirs: bool,
+}
+
+impl FindQuery {
+    /// Find regular files anywhere under `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        FindQuery {
+            root: root.as_ref().to_path_buf(),
+            name: None,
+            extensions: Vec::new(),
+            min_size: None,
+            max_size: None,
+            modified_within: None,
+            include_dirs: false,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Only match file names matching the wildcard `pattern`, with `*`, `?`
+    /// and `[...]` classes.
+    pub fn name(mut self, pattern: &str) -> Self {
+        self.name = Some(pattern.to_owned());
+        self
+    }
+
+    /// Only match names ending in `.<extension>`, case-insensitively. Calling
+    /// this again allows further extensions.
+    pub fn extension(mut self, extension: &str) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.extensions.push(extension);
+        self
+    }
+
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Only match entries modified less than `age` before the search runs.
+    pub fn modified_within(mut self, age: Duration) -> Self {
+        self.modified_within = Some(age);
+        self
+    }
+
+    /// Match directories too. Size criteria never match directories.
+    pub fn include_dirs(mut self, include: bool) -> Self {
+        self.include_dirs = include;
+        self
+    }
+
+    /// Returns `true` if the entry at `path` with `metadata` matches, without
+    /// checking that it is under the root.
+    pub fn matches(&self, path: &Path, metadata: &Metadata) -> bool {
+        self.matches_name(path) && self.matches_metadata(metadata, SystemTime::now())
+    }
+
+    /// Lazily yield the matching paths, in the order the directories are read.
+    pub fn iter(&self) -> io::Result<FindIter> {
+        Ok(FindIter {
+            query: self.clone(),
+            entries: stream_tree(&self.root)?,
+            now: SystemTime::now(),
+        })
+    }
+
+    /// Every matching path, sorted.
+    pub fn run(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = self.iter()?.collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn matches_name(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return self.name.is_none() && self.extensions.is_empty();
+        };
+        if let Some(pattern) = &self.name {
+            if !glob::matches(pattern, name) {
+                return false;
+            }
+        }
+        let name = name.to_ascii_lowercase();
+        self.extensions.is_empty()
+            || self.extensions.iter().any(|ext| {
+                name.strip_suffix(ext.as_str())
+                    .is_some_and(|stem| stem.ends_with('.'))
+            })
+    }
+
+    fn matches_metadata(&self, metadata: &Metadata, now: SystemTime) -> bool {
+        if !(metadata.is_file() || self.include_dirs && metadata.is_dir()) {
+            return false;
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            if !metadata.is_file() {
+                return false;
+            }
+            let len = metadata.len();
+            if self.min_size.is_some_and(|min| len < min)
+                || self.max_size.is_some_and(|max| len > max)
+            {
+                return false;
+            }
+        }
+        match self.modified_within {
+            Some(age) => metadata
+                .modified()
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() < age),
+            None => true,
+        }
+    }
+
+    /// Helper function to serialize the query for [`SavedSearch`](crate::search::SavedSearch).
+    pub(crate) fn to_value(&self) -> io::Result<Value> {
+        let root = self.root.to_str().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("path is not valid UTF-8: {}", self.root.display()),
+            )
+        })?;
+        let mut fields = vec![("root".to_owned(), Value::from(root))];
+        if let Some(name) = &self.name {
+            fields.push(("name".to_owned(), Value::from(name.as_str())));
+        }
+        if !self.extensions.is_empty() {
+            let extensions = self.extensions.iter().map(|e| Value::from(e.as_str()));
+            fields.push(("extensions".to_owned(), Value::Array(extensions.collect())));
+        }
+        if let Some(min) = self.min_size {
+            fields.push(("min_size".to_owned(), Value::from(min)));
+        }
+        if let Some(max) = self.max_size {
+            fields.push(("max_size".to_owned(), Value::from(max)));
+        }
+        if let Some(age) = self.modified_within {
+            fields.push((
+                "modified_within_secs".to_owned(),
+                Value::from(age.as_secs()),
+            ));
+        }
+        if self.include_dirs {
+            fields.push(("include_dirs".to_owned(), Value::Bool(true)));
+        }
+        Ok(Value::Object(fields))
+    }
+
+    pub(crate) fn from_value(value: &Value) -> io::Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid find query");
+        let root = value
+            .get("root")
+            .and_then(Value::as_str)
+            .ok_or_else(invalid)?;
+        let mut query = FindQuery::new(root);
+        query.name = value.get("name").and_then(Value::as_str).map(str::to_owned);
+        if let Some(extensions) = value.get("extensions") {
+            query.extensions = extensions
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|e| e.as_str().map(str::to_owned).ok_or_else(invalid))
+                .collect::<io::Result<_>>()?;
+        }
+        query.min_size = value.get("min_size").and_then(Value::as_u64);
+        query.max_size = value.get("max_size").and_then(Value::as_u64);
+        query.modified_within = value
+            .get("modified_within_secs")
+            .and_then(Value::as_u64)
+            .map(Duration::from_secs);
+        query.include_dirs = value.get("include_dirs") == Some(&Value::Bool(true));
+        Ok(query)
+    }
+}
+
+/// A lazy iterator over the paths matching a [`FindQuery`].
+#[derive(Debug)]
+pub struct FindIter {
+    query: FindQuery,
+    entries: DirStream,
+    /// Ages are measured from when the search started.
+    now: SystemTime,
+}
+
+impl Iterator for FindIter {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.entries.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let path = entry.path();
+            if !self.query.matches_name(&path) {
+                continue;
+            }
+            match entry.metadata() {
+                Ok(metadata) if self.query.matches_metadata(&metadata, self.now) => {
+                    return Some(Ok(path))
+                }
+                Ok(_) => {}
+                // Removed since the directory was read.
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Every path matching the glob `pattern`, sorted. See [`find_iter`].
+pub fn find(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut paths = find_iter(pattern)?.collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Lazily yield the paths matching the glob `pattern`, such as `logs/**/*.json`.
+///
+/// Components are separated by `/` (or `\` on Windows) and matched with `*`,
+/// `?` and `[...]` classes, which never match a separator. A `**` component
+/// matches any number of directories, including none. Hidden files are
+/// matched like any other, and symlinked directories are not followed.
+/// A pattern whose directory doesn't exist matches nothing.
+pub fn find_iter(pattern: &str) -> io::Result<GlobIter> {
+    let is_separator = |c: char| c == '/' || cfg!(windows) && c == '\\';
+    let is_wild = |segment: &str| segment.contains(['*', '?', '[']);
+    // The directory to start from is the longest prefix without wildcards.
+    let mut base_len = 0;
+    for (i, c) in pattern.char_indices() {
+        if is_separator(c) {
+            if is_wild(&pattern[base_len..i]) {
+                break;
+            }
+            base_len = i + 1;
+        }
+    }
+    let (base, rest) = pattern.split_at(base_len);
+    let segments: Vec<String> = rest
+        .split(is_separator)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect();
+    let mut iter = GlobIter {
+        segments,
+        stack: Vec::new(),
+        literal: None,
+    };
+    if !iter.segments.iter().any(|s| is_wild(s)) {
+        // Nothing to expand, the pattern is a plain path.
+        let path = PathBuf::from(pattern);
+        iter.literal = fs::symlink_metadata(&path).is_ok().then_some(path);
+        return Ok(iter);
+    }
+    let base = PathBuf::from(base);
+    let states = closure(&iter.segments, vec![0]);
+    iter.descend(base, states)?;
+    Ok(iter)
+}
+
+/// A lazy iterator over the paths matching a glob pattern, made by [`find_iter`].
+#[derive(Debug)]
+pub struct GlobIter {
+    segments: Vec<String>,
+    /// Directories being listed, each with the entries left to visit and the
+    /// pattern positions its entries are matched against.
+    stack: Vec<(PathBuf, vec::IntoIter<DirEntry>, Vec<usize>)>,
+    literal: Option<PathBuf>,
+}
+
+impl GlobIter {
+    /// Helper function to start listing `dir` with its entries at pattern positions `states`.
+    fn descend(&mut self, dir: PathBuf, states: Vec<usize>) -> io::Result<()> {
+        let listed = if dir.as_os_str().is_empty() {
+            fs::read_dir(".")
+        } else {
+            fs::read_dir(&dir)
+        };
+        let entries = match listed {
+            Ok(entries) => entries.collect::<io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.stack.push((dir, entries.into_iter(), states));
+        Ok(())
+    }
+}
+
+impl Iterator for GlobIter {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(path) = self.literal.take() {
+            return Some(Ok(path));
+        }
+        loop {
+            let (dir, entries, states) = self.stack.last_mut()?;
+            let Some(entry) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str() else {
+                continue;
+            };
+            let next = step(&self.segments, states, name);
+            if next.is_empty() {
+                continue;
+            }
+            let path = dir.join(name);
+            let matched = next.contains(&self.segments.len());
+            // Symlinks aren't followed, so `file_type` doesn't need a stat.
+            let is_dir = match entry.file_type() {
+                Ok(file_type) => file_type.is_dir(),
+                Err(e) => return Some(Err(e)),
+            };
+            if is_dir && next.iter().any(|&state| state < self.segments.len()) {
+                if let Err(e) = self.descend(path.clone(), next) {
+                    return Some(Err(e));
+                }
+            }
+            if matched {
+                return Some(Ok(path));
+            }
+        }
+    }
+}
+
+/// Find the nearest directory containing `marker`, such as `.git` or
+/// `Cargo.toml`, starting at `start` and walking up to the filesystem root.
+///
+/// Relative paths are resolved against the current directory without
+/// following symlinks, so the walk goes up the path as given.
+///
+/// # Returns
+/// The directory containing `marker`, or `None` if no ancestor does.
+pub fn find_upwards<P: AsRef<Path>>(start: P, marker: &str) -> io::Result<Option<PathBuf>> {
+    let start = std::path::absolute(start)?;
+    for dir in start.ancestors() {
+        if dir.join(marker).try_exists()? {
+            return Ok(Some(dir.to_path_buf()));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the nearest regular file called `name` in `start` or one of its
+/// ancestors, like a tool looking for its configuration file.
+///
+/// # Returns
+/// The path of the file, or `None` if no ancestor has one.
+pub fn find_ancestor_file<P: AsRef<Path>>(start: P, name: &str) -> io::Result<Option<PathBuf>> {
+    let start = std::path::absolute(start)?;
+    for dir in start.ancestors() {
+        let path = dir.join(name);
+        if path.is_file() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Helper function to add the positions past each `**` in `states`, since it may match nothing.
+fn closure(segments: &[String], mut states: Vec<usize>) -> Vec<usize> {
+    let mut i = 0;
+    while i < states.len() {
+        let state = states[i];
+        if segments.get(state).is_some_and(|s| s == "**") && !states.contains(&(state + 1)) {
+            states.push(state + 1);
+        }
+        i += 1;
+    }
+    states
+}
+
+/// Helper function to get the pattern positions after matching `name` at `states`.
+fn step(segments: &[String], states: &[usize], name: &str) -> Vec<usize> {
+    let mut next = Vec::new();
+    for &state in states {
+        let Some(segment) = segments.get(state) else {
+            continue;
+        };
+        let target = if segment == "**" {
+            state
+        } else if glob::matches(segment, name) {
+            state + 1
+        } else {
+            continue;
+        };
+        if !next.contains(&target) {
+            next.push(target);
+        }
+    }
+    closure(segments, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn find_query_combines_criteria() {
+        // arrange
+        let dir = "assets/find_query_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/2024/q1", dir)).unwrap();
+        fs::write(format!("{}/2024/q1/report-final.PDF", dir), vec![0; 2048]).unwrap();
+        fs::write(format!("{}/2024/report-final.pdf", dir), "tiny").unwrap();
+        fs::write(format!("{}/2024/report-draft.pdf", dir), vec![0; 2048]).unwrap();
+        fs::write(format!("{}/notes-final.txt", dir), vec![0; 2048]).unwrap();
+        let query = FindQuery::new(dir)
+            .name("*-final*")
+            .extension("pdf")
+            .min_size(1024)
+            .modified_within(Duration::from_secs(60));
+
+        // act
+        let found = query.run().unwrap();
+        let round_trip = FindQuery::from_value(&query.to_value().unwrap()).unwrap();
+        let dirs = FindQuery::new(dir)
+            .name("q?")
+            .include_dirs(true)
+            .run()
+            .unwrap();
+
+        // assert
+        assert_eq!(vec![Path::new(dir).join("2024/q1/report-final.PDF")], found);
+        assert_eq!(query, round_trip);
+        assert_eq!(vec![Path::new(dir).join("2024/q1")], dirs);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_expands_glob_patterns() {
+        // arrange
+        let dir = "assets/find_glob_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/logs/2024/05", dir)).unwrap();
+        fs::write(format!("{}/logs/top.json", dir), "").unwrap();
+        fs::write(format!("{}/logs/2024/a.json", dir), "").unwrap();
+        fs::write(format!("{}/logs/2024/05/b.json", dir), "").unwrap();
+        fs::write(format!("{}/logs/2024/05/c.txt", dir), "").unwrap();
+
+        // act
+        let deep = find(&format!("{}/logs/**/*.json", dir)).unwrap();
+        let shallow = find(&format!("{}/logs/*/?.json", dir)).unwrap();
+        let literal = find(&format!("{}/logs/top.json", dir)).unwrap();
+        let missing = find(&format!("{}/nowhere/*.json", dir)).unwrap();
+
+        // assert
+        let path = |p: &str| PathBuf::from(format!("{}/logs/{}", dir, p));
+        assert_eq!(
+            vec![
+                path("2024/05/b.json"),
+                path("2024/a.json"),
+                path("top.json")
+            ],
+            deep
+        );
+        assert_eq!(vec![path("2024/a.json")], shallow);
+        assert_eq!(vec![path("top.json")], literal);
+        assert!(missing.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_upwards_stops_at_nearest_marker() {
+        // arrange
+        let dir = "assets/find_upwards_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/project/.git", dir)).unwrap();
+        fs::create_dir_all(format!("{}/project/src/nested", dir)).unwrap();
+        fs::write(format!("{}/project/Cargo.toml", dir), "").unwrap();
+        fs::write(format!("{}/project/src/Cargo.toml", dir), "").unwrap();
+        let start = format!("{}/project/src/nested", dir);
+
+        // act
+        let root = find_upwards(&start, ".git").unwrap();
+        let manifest = find_ancestor_file(&start, "Cargo.toml").unwrap();
+        let not_a_file = find_ancestor_file(&start, ".git").unwrap();
+        let missing = find_upwards(&start, "no-such-marker-anywhere").unwrap();
+
+        // assert
+        let project = std::path::absolute(format!("{}/project", dir)).unwrap();
+        assert_eq!(Some(project.clone()), root);
+        assert_eq!(Some(project.join("src/Cargo.toml")), manifest);
+        assert_eq!(None, not_a_file.filter(|p| p.starts_with(&project)));
+        assert_eq!(None, missing);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
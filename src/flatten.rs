@@ -0,0 +1,241 @@
+//! Move files out of nested subdirectories into a single directory.
+use crate::{
+    error::{Context, Operation},
+    numbered_file_name, OverwritePolicy,
+};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Options for [`flatten_dir`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlattenOptions {
+    /// How to resolve a file name that already exists in the root.
+    pub policy: OverwritePolicy,
+    /// Remove subdirectories that are left empty after flattening.
+    pub prune_empty_dirs: bool,
+}
+
+/// Move every file found in the subdirectories of `root` directly into `root`.
+/// Files are processed in sorted path order so collisions resolve deterministically.
+/// Symlinked directories are not followed.
+///
+/// With `OverwritePolicy::Fail`, all collisions are detected before anything is moved.
+/// A file named like a subdirectory of `root` collides with it too; with
+/// `OverwritePolicy::Overwrite` that fails up front rather than replace the directory.
+///
+/// # Returns
+/// The `(from, to)` pairs of every file that was moved.
+pub fn flatten_dir<P: AsRef<Path>>(
+    root: P,
+    options: &FlattenOptions,
+) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let root = root.as_ref();
+
+    let mut taken: HashSet<OsString> = HashSet::new();
+    let mut dir_names: HashSet<OsString> = HashSet::new();
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(root).context(Operation::Read, root)? {
+        let entry = entry.context(Operation::Read, root)?;
+        let file_type = entry.file_type().context(Operation::Stat, &entry.path())?;
+        if file_type.is_dir() {
+            subdirs.push(entry.path());
+            dir_names.insert(entry.file_name());
+        }
+        taken.insert(entry.file_name());
+    }
+
+    let mut files = Vec::new();
+    for dir in &subdirs {
+        collect_files(dir, &mut files)?;
+    }
+    files.sort();
+
+    // Plan every move first so a failing policy leaves the tree untouched.
+    let mut moves = Vec::new();
+    for file in files {
+        let name = match file.file_name() {
+            Some(name) => name.to_os_string(),
+            None => continue,
+        };
+        let target_name = if !taken.contains(&name) {
+            name
+        } else {
+            match options.policy {
+                OverwritePolicy::Skip => continue,
+                OverwritePolicy::Overwrite if !dir_names.contains(&name) => name,
+                OverwritePolicy::Rename => {
+                    let mut n = 1;
+                    loop {
+                        let candidate = numbered_file_name(&name, n);
+                        if !taken.contains(&candidate) {
+                            break candidate;
+                        }
+                        n += 1;
+                    }
+                }
+                OverwritePolicy::Overwrite | OverwritePolicy::Fail => {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!(
+                            "cannot flatten {}: {} already exists",
+                            file.display(),
+                            root.join(&name).display()
+                        ),
+                    ))
+                }
+            }
+        };
+        taken.insert(target_name.clone());
+        moves.push((file, root.join(target_name)));
+    }
+
+    for (from, to) in &moves {
+        fs::rename(from, to).context(Operation::Move, from)?;
+    }
+
+    if options.prune_empty_dirs {
+        for dir in &subdirs {
+            prune_empty_dirs(dir)?;
+        }
+    }
+
+    Ok(moves)
+}
+
+/// Recursively collect all non-directory entries under `dir`.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir).context(Operation::Read, dir)? {
+        let entry = entry.context(Operation::Read, dir)?;
+        let path = entry.path();
+        if entry.file_type().context(Operation::Stat, &path)?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Remove `dir` and its subdirectories if they contain no files.
+/// Returns true if `dir` was removed.
+fn prune_empty_dirs(dir: &Path) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir).context(Operation::Read, dir)? {
+        let entry = entry.context(Operation::Read, dir)?;
+        let path = entry.path();
+        let file_type = entry.file_type().context(Operation::Stat, &path)?;
+        if !(file_type.is_dir() && prune_empty_dirs(&path)?) {
+            empty = false;
+        }
+    }
+    if empty {
+        fs::remove_dir(dir).context(Operation::Delete, dir)?;
+    }
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_to_file;
+
+    fn setup(dir: &str) {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/a/b", dir)).unwrap();
+        fs::create_dir_all(format!("{}/c", dir)).unwrap();
+        write_to_file(format!("{}/notes.txt", dir), true, "root").unwrap();
+        write_to_file(format!("{}/a/notes.txt", dir), true, "a").unwrap();
+        write_to_file(format!("{}/a/b/deep.txt", dir), true, "deep").unwrap();
+        write_to_file(format!("{}/c/notes.txt", dir), true, "c").unwrap();
+    }
+
+    #[test]
+    fn flatten_dir_renames_collisions() {
+        // arrange
+        let dir = "assets/flatten_rename_test";
+        setup(dir);
+        let options = FlattenOptions {
+            policy: OverwritePolicy::Rename,
+            prune_empty_dirs: true,
+        };
+
+        // act
+        let result = flatten_dir(dir, &options);
+
+        // assert
+        assert_eq!(3, result.unwrap().len());
+        assert_eq!(
+            "root",
+            fs::read_to_string(format!("{}/notes.txt", dir)).unwrap()
+        );
+        assert_eq!(
+            "a",
+            fs::read_to_string(format!("{}/notes (1).txt", dir)).unwrap()
+        );
+        assert_eq!(
+            "c",
+            fs::read_to_string(format!("{}/notes (2).txt", dir)).unwrap()
+        );
+        assert_eq!(
+            "deep",
+            fs::read_to_string(format!("{}/deep.txt", dir)).unwrap()
+        );
+        assert!(!Path::new(&format!("{}/a", dir)).exists());
+        assert!(!Path::new(&format!("{}/c", dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    // Make sure nothing is moved when a collision is found with `OverwritePolicy::Fail`.
+    fn flatten_dir_fail_policy_moves_nothing() {
+        // arrange
+        let dir = "assets/flatten_fail_test";
+        setup(dir);
+
+        // act
+        let result = flatten_dir(dir, &FlattenOptions::default());
+
+        // assert
+        assert_eq!(ErrorKind::AlreadyExists, result.unwrap_err().kind());
+        assert!(Path::new(&format!("{}/a/b/deep.txt", dir)).exists());
+        assert!(!Path::new(&format!("{}/deep.txt", dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flatten_dir_treats_subdirectory_names_as_taken() {
+        // arrange
+        let dir = "assets/flatten_subdir_name_test";
+        setup(dir);
+        write_to_file(format!("{}/c/a", dir), true, "file a").unwrap();
+        let rename = FlattenOptions {
+            policy: OverwritePolicy::Rename,
+            prune_empty_dirs: false,
+        };
+        let overwrite = FlattenOptions {
+            policy: OverwritePolicy::Overwrite,
+            prune_empty_dirs: false,
+        };
+
+        // act
+        let overwritten = flatten_dir(dir, &overwrite);
+        let untouched = Path::new(&format!("{}/a/b/deep.txt", dir)).exists();
+        let renamed = flatten_dir(dir, &rename);
+
+        // assert
+        assert_eq!(ErrorKind::AlreadyExists, overwritten.unwrap_err().kind());
+        assert!(untouched);
+        assert!(renamed.is_ok());
+        assert!(Path::new(&format!("{}/a", dir)).is_dir());
+        assert_eq!(
+            "file a",
+            fs::read_to_string(format!("{}/a (1)", dir)).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
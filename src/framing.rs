@@ -0,0 +1,206 @@
+//! Length-prefixed binary records over any reader or writer.
+use crate::checksum::Crc32;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Set in the length word of frames followed by a CRC32 of their payload.
+const CHECKSUM_FLAG: u32 = 1 << 31;
+
+/// The largest payload a frame can carry.
+pub const MAX_FRAME_LEN: usize = (CHECKSUM_FLAG - 1) as usize;
+
+/// Writes records as frames: a little-endian `u32` length, a CRC32 of the
+/// payload if checksums are on, then the payload.
+///
+/// Whether a frame is checksummed is recorded in its header, so a
+/// [`FramedReader`] reads both kinds without being told.
+#[derive(Debug)]
+pub struct FramedWriter<W: Write> {
+    inner: W,
+    checksums: bool,
+}
+
+impl<W: Write> FramedWriter<W> {
+    /// Write frames to `inner`, with checksums.
+    pub fn new(inner: W) -> Self {
+        FramedWriter {
+            inner,
+            checksums: true,
+        }
+    }
+
+    /// Whether to follow each frame's length with a CRC32 of its payload.
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Write `payload` as one frame.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `payload` is longer than [`MAX_FRAME_LEN`].
+    pub fn write_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("frame of {} bytes is too long", payload.len()),
+            ));
+        }
+        let mut frame = Vec::with_capacity(8 + payload.len());
+        if self.checksums {
+            let mut crc = Crc32::new();
+            crc.update(payload);
+            frame.extend((payload.len() as u32 | CHECKSUM_FLAG).to_le_bytes());
+            frame.extend(crc.finish().to_le_bytes());
+        } else {
+            frame.extend((payload.len() as u32).to_le_bytes());
+        }
+        frame.extend_from_slice(payload);
+        // One write per frame keeps concurrent appenders from interleaving.
+        self.inner.write_all(&frame)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads the frames written by a [`FramedWriter`], in order.
+///
+/// Iterating yields each payload and stops at the end of the input. A frame
+/// cut short, as left by a writer that crashed mid-write, ends with an
+/// `UnexpectedEof` error.
+#[derive(Debug)]
+pub struct FramedReader<R: Read> {
+    inner: R,
+    max_len: usize,
+}
+
+impl<R: Read> FramedReader<R> {
+    /// Read frames from `inner`, accepting payloads of up to 64 MiB.
+    pub fn new(inner: R) -> Self {
+        FramedReader {
+            inner,
+            max_len: 64 * 1024 * 1024,
+        }
+    }
+
+    /// The longest payload to accept, so a corrupt length cannot make the
+    /// reader allocate gigabytes.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Read the next frame.
+    ///
+    /// # Returns
+    /// `Ok(None)` at the end of the input.
+    ///
+    /// # Errors
+    /// `InvalidData` if the payload is longer than the maximum or fails its
+    /// checksum, and `UnexpectedEof` if the input ends inside a frame.
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut word = [0; 4];
+        let mut read = 0;
+        while read < word.len() {
+            match self.inner.read(&mut word[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let header = u32::from_le_bytes(word);
+        let len = (header & !CHECKSUM_FLAG) as usize;
+        if len > self.max_len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("frame of {} bytes exceeds the maximum", len),
+            ));
+        }
+        let expected = if header & CHECKSUM_FLAG != 0 {
+            self.inner.read_exact(&mut word)?;
+            Some(u32::from_le_bytes(word))
+        } else {
+            None
+        };
+        let mut payload = vec![0; len];
+        self.inner.read_exact(&mut payload)?;
+        if let Some(expected) = expected {
+            let mut crc = Crc32::new();
+            crc.update(&payload);
+            if crc.finish() != expected {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "frame checksum mismatch",
+                ));
+            }
+        }
+        Ok(Some(payload))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for FramedReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File, OpenOptions};
+
+    #[test]
+    fn frames_round_trip_and_detect_damage() {
+        // arrange
+        let dir = "assets/framing_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/messages.bin", dir);
+
+        // act
+        let mut writer = FramedWriter::new(File::create(&path).unwrap());
+        writer.write_frame(b"first").unwrap();
+        writer.write_frame(b"").unwrap();
+        writer.into_inner().unwrap();
+        let mut writer = FramedWriter::new(OpenOptions::new().append(true).open(&path).unwrap())
+            .checksums(false);
+        writer.write_frame(b"plain").unwrap();
+        writer.into_inner().unwrap();
+        let frames = FramedReader::new(File::open(&path).unwrap())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[9] ^= 1;
+        let corrupt = FramedReader::new(bytes.as_slice()).next().unwrap();
+        let torn = FramedReader::new(&bytes[..bytes.len() - 1]).last().unwrap();
+
+        // assert
+        assert_eq!(vec![b"first".to_vec(), vec![], b"plain".to_vec()], frames);
+        assert_eq!(ErrorKind::InvalidData, corrupt.unwrap_err().kind());
+        assert_eq!(ErrorKind::UnexpectedEof, torn.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
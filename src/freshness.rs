@@ -0,0 +1,153 @@
+//! Make-style checks of whether build outputs are older than their inputs.
+use crate::{checksum::sha256_reader, write_atomic};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// How [`needs_rebuild_with`] decides that inputs changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FreshnessMode {
+    /// An input was modified after the oldest output.
+    #[default]
+    Modified,
+    /// The SHA-256 of an input differs from the one recorded in this stamp
+    /// file by [`mark_built`]. For checkouts, caches and containers where
+    /// modification times are reset or unreliable.
+    Hash(PathBuf),
+}
+
+/// Returns `true` if any output is missing or any input was modified after
+/// the oldest output.
+///
+/// # Errors
+/// `NotFound` if an input does not exist.
+pub fn needs_rebuild<O, I>(outputs: O, inputs: I) -> io::Result<bool>
+where
+    O: IntoIterator,
+    O::Item: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    needs_rebuild_with(outputs, inputs, &FreshnessMode::Modified)
+}
+
+/// Like [`needs_rebuild`], comparing inputs as `mode` says. Missing outputs,
+/// or no outputs at all, always need a rebuild.
+pub fn needs_rebuild_with<O, I>(outputs: O, inputs: I, mode: &FreshnessMode) -> io::Result<bool>
+where
+    O: IntoIterator,
+    O::Item: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let mut oldest_output: Option<SystemTime> = None;
+    for output in outputs {
+        let modified = match fs::metadata(output) {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        oldest_output = Some(oldest_output.map_or(modified, |oldest| oldest.min(modified)));
+    }
+    let Some(oldest_output) = oldest_output else {
+        return Ok(true);
+    };
+    match mode {
+        FreshnessMode::Modified => {
+            for input in inputs {
+                if fs::metadata(input)?.modified()? > oldest_output {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        FreshnessMode::Hash(stamp) => {
+            let recorded = match fs::read_to_string(stamp) {
+                Ok(recorded) => recorded,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+                Err(e) => return Err(e),
+            };
+            Ok(input_stamp(inputs)? != recorded)
+        }
+    }
+}
+
+/// Record the SHA-256 of each input in the `stamp` file after a successful
+/// build, for [`FreshnessMode::Hash`].
+pub fn mark_built<I, P>(inputs: I, stamp: P) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+    P: AsRef<Path>,
+{
+    write_atomic(stamp.as_ref(), input_stamp(inputs)?.as_bytes())
+}
+
+/// Helper function to list the hash of each input in `sha256sum` format.
+fn input_stamp<I>(inputs: I) -> io::Result<String>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let mut stamp = String::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let hash = sha256_reader(File::open(input)?)?;
+        stamp.push_str(&format!("{}  {}\n", hash, input.display()));
+    }
+    Ok(stamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn needs_rebuild_compares_times_and_hashes() {
+        // arrange
+        let dir = "assets/freshness_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (input, output) = (format!("{}/main.c", dir), format!("{}/main.o", dir));
+        let stamp_path = format!("{}/main.stamp", dir);
+        let stamp = FreshnessMode::Hash(stamp_path.clone().into());
+        let now = SystemTime::now();
+        let touch = |path: &str, modified: SystemTime| {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap()
+        };
+        fs::write(&input, "int main() {}").unwrap();
+        touch(&input, now - Duration::from_secs(60));
+
+        // act
+        let missing_output = needs_rebuild([&output], [&input]).unwrap();
+        fs::write(&output, "").unwrap();
+        let built = needs_rebuild([&output], [&input]).unwrap();
+        touch(&input, now + Duration::from_secs(60));
+        let touched = needs_rebuild([&output], [&input]).unwrap();
+        let unstamped = needs_rebuild_with([&output], [&input], &stamp).unwrap();
+        mark_built([&input], &stamp_path).unwrap();
+        let stamped = needs_rebuild_with([&output], [&input], &stamp).unwrap();
+        fs::write(&input, "int main() { return 1; }").unwrap();
+        let edited = needs_rebuild_with([&output], [&input], &stamp).unwrap();
+        let missing_input = needs_rebuild([&output], [format!("{}/gone.c", dir)]);
+
+        // assert
+        assert!(missing_output);
+        assert!(!built);
+        assert!(touched);
+        assert!(unstamped);
+        assert!(!stamped);
+        assert!(edited);
+        assert_eq!(ErrorKind::NotFound, missing_input.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
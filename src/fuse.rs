@@ -0,0 +1,422 @@
+//! Serve a [`FileSystem`] as a FUSE mount.
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use fuser::{
+    BackgroundSession, Config, Errno, FileAttr, FileHandle, FileType, FopenFlags, Generation,
+    INodeNo, LockOwner, MountOption, OpenFlags, RenameFlags, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyWrite, Request, TimeOrNow, WriteFlags,
+};
+
+use crate::vfs::{FileSystem, VfsKind, VfsMetadata};
+
+/// How long the kernel may cache attributes and lookups.
+const TTL: Duration = Duration::from_secs(1);
+
+/// A mounted [`FileSystem`]. The filesystem is unmounted when the handle is dropped.
+pub struct FuseMount {
+    session: Option<BackgroundSession>,
+}
+
+impl FuseMount {
+    /// Unmount the filesystem and wait for the session to finish.
+    pub fn unmount(mut self) -> io::Result<()> {
+        match self.session.take() {
+            Some(session) => session.umount_and_join(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for FuseMount {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            let _ = session.umount_and_join();
+        }
+    }
+}
+
+/// Mount `fs` at `mountpoint`, which must be an existing empty directory.
+///
+/// Requests are served on a background thread until the returned [`FuseMount`]
+/// is dropped or unmounted. Only regular files and directories are supported.
+pub fn mount<F, P>(fs: F, mountpoint: P) -> io::Result<FuseMount>
+where
+    F: FileSystem + 'static,
+    P: AsRef<Path>,
+{
+    let mut config = Config::default();
+    config.mount_options.extend([
+        MountOption::FSName("file-manager".to_string()),
+        MountOption::DefaultPermissions,
+    ]);
+    let session = fuser::spawn_mount(Adapter::new(fs), mountpoint, &config)?;
+    Ok(FuseMount {
+        session: Some(session),
+    })
+}
+
+/// Maps inode numbers handed to the kernel to paths in the backing filesystem.
+#[derive(Default)]
+struct Inodes {
+    paths: HashMap<u64, PathBuf>,
+    inos: HashMap<PathBuf, u64>,
+    next: u64,
+}
+
+impl Inodes {
+    fn path(&self, ino: INodeNo) -> Result<PathBuf, Errno> {
+        self.paths.get(&ino.0).cloned().ok_or(Errno::ENOENT)
+    }
+
+    fn ino(&mut self, path: &Path) -> INodeNo {
+        if let Some(&ino) = self.inos.get(path) {
+            return INodeNo(ino);
+        }
+        self.next += 1;
+        self.paths.insert(self.next, path.to_path_buf());
+        self.inos.insert(path.to_path_buf(), self.next);
+        INodeNo(self.next)
+    }
+
+    fn forget(&mut self, path: &Path) {
+        if let Some(ino) = self.inos.remove(path) {
+            self.paths.remove(&ino);
+        }
+    }
+
+    /// Re-point every inode under `from` at the same location under `to`.
+    fn rename(&mut self, from: &Path, to: &Path) {
+        self.forget(to);
+        let moved: Vec<(PathBuf, u64)> = self
+            .inos
+            .iter()
+            .filter(|(path, _)| path.starts_with(from))
+            .map(|(path, &ino)| (path.clone(), ino))
+            .collect();
+        for (path, ino) in moved {
+            let new = to.join(path.strip_prefix(from).unwrap_or(Path::new("")));
+            self.inos.remove(&path);
+            self.inos.insert(new.clone(), ino);
+            self.paths.insert(ino, new);
+        }
+    }
+}
+
+struct Adapter<F> {
+    fs: F,
+    inodes: Mutex<Inodes>,
+    uid: u32,
+    gid: u32,
+}
+
+impl<F: FileSystem + 'static> Adapter<F> {
+    fn new(fs: F) -> Self {
+        let mut inodes = Inodes {
+            next: INodeNo::ROOT.0,
+            ..Inodes::default()
+        };
+        inodes.paths.insert(INodeNo::ROOT.0, PathBuf::new());
+        inodes.inos.insert(PathBuf::new(), INodeNo::ROOT.0);
+        // SAFETY: getuid and getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Adapter {
+            fs,
+            inodes: Mutex::new(inodes),
+            uid,
+            gid,
+        }
+    }
+
+    fn inodes(&self) -> std::sync::MutexGuard<'_, Inodes> {
+        self.inodes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn path(&self, ino: INodeNo) -> Result<PathBuf, Errno> {
+        self.inodes().path(ino)
+    }
+
+    fn child(&self, parent: INodeNo, name: &OsStr) -> Result<PathBuf, Errno> {
+        Ok(self.path(parent)?.join(name))
+    }
+
+    fn attr(&self, path: &Path) -> Result<FileAttr, Errno> {
+        let metadata = self.fs.metadata(path)?;
+        let ino = self.inodes().ino(path);
+        Ok(self.file_attr(ino, &metadata))
+    }
+
+    fn file_attr(&self, ino: INodeNo, metadata: &VfsMetadata) -> FileAttr {
+        let (kind, perm, nlink) = match metadata.kind {
+            VfsKind::File => (FileType::RegularFile, 0o644, 1),
+            VfsKind::Dir => (FileType::Directory, 0o755, 2),
+        };
+        FileAttr {
+            ino,
+            size: metadata.len,
+            blocks: metadata.len.div_ceil(512),
+            atime: metadata.modified,
+            mtime: metadata.modified,
+            ctime: metadata.modified,
+            crtime: metadata.modified,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+}
+
+impl<F: FileSystem + 'static> fuser::Filesystem for Adapter<F> {
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        match self.child(parent, name).and_then(|path| self.attr(&path)) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.path(ino).and_then(|path| self.attr(&path)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn setattr(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<FileHandle>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<fuser::BsdFileFlags>,
+        reply: ReplyAttr,
+    ) {
+        let result = self.path(ino).and_then(|path| {
+            if let Some(size) = size {
+                self.fs.set_len(&path, size)?;
+            }
+            self.attr(&path)
+        });
+        match result {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        let result = self.path(ino).and_then(|path| {
+            let mut buf = vec![0; size as usize];
+            let n = self.fs.read_at(&path, offset, &mut buf)?;
+            buf.truncate(n);
+            Ok(buf)
+        });
+        match result {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn write(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        data: &[u8],
+        _write_flags: WriteFlags,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyWrite,
+    ) {
+        let result = self
+            .path(ino)
+            .and_then(|path| Ok(self.fs.write_at(&path, offset, data)?));
+        match result {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let result = self.path(ino).and_then(|path| {
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".into()),
+                (ino, FileType::Directory, "..".into()),
+            ];
+            for entry in self.fs.read_dir(&path)? {
+                let child = self.inodes().ino(&path.join(&entry.name));
+                let kind = match entry.kind {
+                    VfsKind::File => FileType::RegularFile,
+                    VfsKind::Dir => FileType::Directory,
+                };
+                entries.push((child, kind, entry.name));
+            }
+            Ok(entries)
+        });
+        match result {
+            Ok(entries) => {
+                for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize)
+                {
+                    if reply.add(ino, i as u64 + 1, kind, name) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn create(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let result = self.child(parent, name).and_then(|path| {
+            self.fs.create_file(&path)?;
+            self.attr(&path)
+        });
+        match result {
+            Ok(attr) => reply.created(
+                &TTL,
+                &attr,
+                Generation(0),
+                FileHandle(0),
+                FopenFlags::empty(),
+            ),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mkdir(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let result = self.child(parent, name).and_then(|path| {
+            self.fs.create_dir(&path)?;
+            self.attr(&path)
+        });
+        match result {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn unlink(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child(parent, name).and_then(|path| {
+            self.fs.remove_file(&path)?;
+            self.inodes().forget(&path);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child(parent, name).and_then(|path| {
+            self.fs.remove_dir(&path)?;
+            self.inodes().forget(&path);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rename(
+        &self,
+        _req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        newparent: INodeNo,
+        newname: &OsStr,
+        _flags: RenameFlags,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.child(parent, name).and_then(|from| {
+            let to = self.child(newparent, newname)?;
+            self.fs.rename(&from, &to)?;
+            self.inodes().rename(&from, &to);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn inodes_follow_renames() {
+        // arrange
+        let adapter = Adapter::new(MemoryFs::new());
+        adapter.fs.create_dir(Path::new("a")).unwrap();
+        adapter.fs.create_file(Path::new("a/f")).unwrap();
+        let file = adapter.attr(Path::new("a/f")).unwrap().ino;
+
+        // act
+        adapter.fs.rename(Path::new("a"), Path::new("b")).unwrap();
+        adapter.inodes().rename(Path::new("a"), Path::new("b"));
+        let dir = adapter.inodes().ino(Path::new("b"));
+        let lookup = adapter.child(dir, OsStr::new("f"));
+
+        // assert
+        assert_eq!(PathBuf::from("b/f"), adapter.path(file).unwrap());
+        assert_eq!(file, adapter.attr(&lookup.unwrap()).unwrap().ino);
+        assert_eq!(Err(Errno::ENOENT), adapter.path(INodeNo(999)));
+    }
+}
@@ -0,0 +1,114 @@
+//! Shell-style wildcard matching.
+
+/// Returns true if `text` matches the wildcard `pattern`.
+///
+/// Supported syntax:
+/// - `*` matches any run of characters (including none)
+/// - `?` matches exactly one character
+/// - `[abc]`, `[a-z]` match one character from the set, `[!abc]` negates the set
+pub(crate) fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position to resume from when backtracking to the last `*`.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern, p, text[t]) {
+                        if matched {
+                            p = next;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == '[' {
+                        // Unterminated class, treat `[` literally.
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c if c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Match `c` against the character class starting at `pattern[start] == '['`.
+/// Returns whether it matched and the index just past the closing `]`,
+/// or `None` if the class is not terminated.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        // A `]` directly after the opening bracket is a literal.
+        if pattern[i] == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            if pattern[i] <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if pattern[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_works() {
+        assert!(matches("*.log", "app.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "app.log.1"));
+        assert!(matches("app.log.?", "app.log.1"));
+        assert!(matches("data_[0-9][0-9].csv", "data_42.csv"));
+        assert!(!matches("data_[!0-9]*", "data_4"));
+        assert!(matches("*a*b*c", "xxaxxbxxc"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("*", ""));
+    }
+}
@@ -0,0 +1,326 @@
+//! A log of completed operations with byte counts and durations.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const HISTORY_HEADER: &str = "file-manager-history v1";
+
+/// The kind of a recorded operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Copy,
+    Move,
+    Delete,
+    Sync,
+    Download,
+    Other,
+}
+
+impl OperationKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OperationKind::Copy => "copy",
+            OperationKind::Move => "move",
+            OperationKind::Delete => "delete",
+            OperationKind::Sync => "sync",
+            OperationKind::Download => "download",
+            OperationKind::Other => "other",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "copy" => OperationKind::Copy,
+            "move" => OperationKind::Move,
+            "delete" => OperationKind::Delete,
+            "sync" => OperationKind::Sync,
+            "download" => OperationKind::Download,
+            "other" => OperationKind::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// One completed operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationRecord {
+    pub kind: OperationKind,
+    /// Names the job, e.g. `"nightly-backup"`, so its runs can be queried together.
+    pub label: String,
+    /// When the operation started, with millisecond precision.
+    pub started_at: SystemTime,
+    /// How long it took, with millisecond precision.
+    pub duration: Duration,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Which records [`History::query`] and [`History::stats`] consider.
+/// Unset fields match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryFilter {
+    pub kind: Option<OperationKind>,
+    pub label: Option<String>,
+    /// Only operations started at or after this time.
+    pub since: Option<SystemTime>,
+    /// Only operations started before this time.
+    pub until: Option<SystemTime>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &OperationRecord) -> bool {
+        self.kind.is_none_or(|kind| kind == record.kind)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|label| *label == record.label)
+            && self.since.is_none_or(|since| record.started_at >= since)
+            && self.until.is_none_or(|until| record.started_at < until)
+    }
+}
+
+/// Totals over a set of records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryStats {
+    pub operations: u64,
+    pub files: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+/// An append-only log of completed operations, one line per operation.
+///
+/// Nothing is recorded automatically; wrap the operations worth keeping with
+/// [`History::start`] and [`OperationTimer::finish`], or call [`History::record`].
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// Use the log at `path`, creating it on the first record.
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        History {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Start timing an operation.
+    pub fn start(&self, kind: OperationKind, label: &str) -> OperationTimer<'_> {
+        OperationTimer {
+            history: self,
+            kind,
+            label: label.to_owned(),
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Append `record` to the log.
+    ///
+    /// # Errors
+    /// `InvalidInput` if the label contains a tab or line break.
+    pub fn record(&self, record: &OperationRecord) -> io::Result<()> {
+        if record.label.contains(['\t', '\n', '\r']) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid operation label `{}`", record.label.escape_debug()),
+            ));
+        }
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        let mut line = String::new();
+        if file.metadata()?.len() == 0 {
+            line.push_str(HISTORY_HEADER);
+            line.push('\n');
+        }
+        let started_at = record
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        line.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            started_at.as_millis(),
+            record.duration.as_millis(),
+            record.kind.as_str(),
+            record.files,
+            record.bytes,
+            record.label
+        ));
+        // One write per record keeps concurrent appenders from interleaving.
+        file.write_all(line.as_bytes())
+    }
+
+    /// The records matching `filter`, oldest first.
+    pub fn query(&self, filter: &HistoryFilter) -> io::Result<Vec<OperationRecord>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+        match lines.next().transpose()? {
+            Some(header) if header == HISTORY_HEADER => {}
+            None => return Ok(Vec::new()),
+            Some(_) => return Err(self.invalid()),
+        }
+        let mut records = Vec::new();
+        for line in lines {
+            let record = self.parse_record(&line?)?;
+            if filter.matches(&record) {
+                records.push(record);
+            }
+        }
+        records.sort_by_key(|r| r.started_at);
+        Ok(records)
+    }
+
+    /// Totals over the records matching `filter`.
+    pub fn stats(&self, filter: &HistoryFilter) -> io::Result<HistoryStats> {
+        let mut stats = HistoryStats::default();
+        for record in self.query(filter)? {
+            stats.operations += 1;
+            stats.files += record.files;
+            stats.bytes += record.bytes;
+            stats.duration += record.duration;
+        }
+        Ok(stats)
+    }
+
+    /// The most recent run of the operation labelled `label`.
+    pub fn last(&self, label: &str) -> io::Result<Option<OperationRecord>> {
+        let filter = HistoryFilter {
+            label: Some(label.to_owned()),
+            ..HistoryFilter::default()
+        };
+        Ok(self.query(&filter)?.pop())
+    }
+
+    fn parse_record(&self, line: &str) -> io::Result<OperationRecord> {
+        let fields: Vec<&str> = line.splitn(6, '\t').collect();
+        let [started_at, duration, kind, files, bytes, label] = fields[..] else {
+            return Err(self.invalid());
+        };
+        let number = |field: &str| field.parse::<u64>().map_err(|_| self.invalid());
+        Ok(OperationRecord {
+            kind: OperationKind::parse(kind).ok_or_else(|| self.invalid())?,
+            label: label.to_owned(),
+            started_at: UNIX_EPOCH + Duration::from_millis(number(started_at)?),
+            duration: Duration::from_millis(number(duration)?),
+            files: number(files)?,
+            bytes: number(bytes)?,
+        })
+    }
+
+    fn invalid(&self) -> io::Error {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid history log {}", self.path.display()),
+        )
+    }
+}
+
+/// Times an operation started with [`History::start`].
+#[derive(Debug)]
+pub struct OperationTimer<'a> {
+    history: &'a History,
+    kind: OperationKind,
+    label: String,
+    started_at: SystemTime,
+    started: Instant,
+}
+
+impl OperationTimer<'_> {
+    /// Record the operation as completed, having processed `files` files
+    /// totalling `bytes` bytes.
+    pub fn finish(self, files: u64, bytes: u64) -> io::Result<OperationRecord> {
+        let millis = |d: Duration| Duration::from_millis(d.as_millis() as u64);
+        let record = OperationRecord {
+            kind: self.kind,
+            label: self.label,
+            started_at: UNIX_EPOCH
+                + millis(
+                    self.started_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default(),
+                ),
+            duration: millis(self.started.elapsed()),
+            files,
+            bytes,
+        };
+        self.history.record(&record)?;
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn history_records_and_summarizes() {
+        // arrange
+        let dir = "assets/history_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let history = History::open(format!("{}/history.log", dir));
+        let night = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let run = |kind, label: &str, at: u64, bytes| OperationRecord {
+            kind,
+            label: label.to_owned(),
+            started_at: night + Duration::from_secs(at),
+            duration: Duration::from_millis(1500),
+            files: 2,
+            bytes,
+        };
+
+        // act
+        history
+            .record(&run(OperationKind::Sync, "backup", 0, 100))
+            .unwrap();
+        history
+            .record(&run(OperationKind::Delete, "cleanup", 60, 0))
+            .unwrap();
+        history
+            .record(&run(OperationKind::Sync, "backup", 120, 50))
+            .unwrap();
+        let timed = history
+            .start(OperationKind::Copy, "adhoc")
+            .finish(1, 7)
+            .unwrap();
+        let backup = history
+            .stats(&HistoryFilter {
+                label: Some("backup".to_owned()),
+                since: Some(night),
+                until: Some(night + Duration::from_secs(3600)),
+                ..HistoryFilter::default()
+            })
+            .unwrap();
+        let invalid = history.record(&run(OperationKind::Other, "bad\tlabel", 0, 0));
+
+        // assert
+        assert_eq!(
+            HistoryStats {
+                operations: 2,
+                files: 4,
+                bytes: 150,
+                duration: Duration::from_secs(3),
+            },
+            backup
+        );
+        assert_eq!(
+            Some(run(OperationKind::Sync, "backup", 120, 50)),
+            history.last("backup").unwrap()
+        );
+        assert_eq!(Some(timed), history.last("adhoc").unwrap());
+        assert_eq!(4, history.query(&HistoryFilter::default()).unwrap().len());
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Detect whether another instance of an application is already running.
+use std::{
+    env,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Holds the single-instance lock of an application while it is the primary instance.
+///
+/// The lock is an OS file lock, so it is released when the handle is dropped or the
+/// process exits, even after a crash. The lock file itself is left in place.
+#[derive(Debug)]
+pub struct SingleInstance {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl SingleInstance {
+    /// Try to become the primary instance of `app_name`, using a lock file in the
+    /// platform's runtime directory: `$XDG_RUNTIME_DIR` where it is set, otherwise
+    /// the temporary directory.
+    ///
+    /// This never waits; check [`SingleInstance::is_primary`] for the outcome.
+    pub fn acquire(app_name: &str) -> io::Result<Self> {
+        if app_name.is_empty() || app_name.contains(['/', '\\']) || app_name.starts_with('.') {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid application name `{}`", app_name),
+            ));
+        }
+        Self::acquire_at(runtime_dir().join(format!("{}.lock", app_name)))
+    }
+
+    /// Like [`SingleInstance::acquire`], but with an explicit lock file path.
+    pub fn acquire_at<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(SingleInstance { path, file: None }),
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        // Record who holds the lock to help with debugging.
+        file.set_len(0)?;
+        file.write_all(format!("{}\n", std::process::id()).as_bytes())?;
+        Ok(SingleInstance {
+            path,
+            file: Some(file),
+        })
+    }
+
+    /// Returns `true` if this process holds the lock.
+    pub fn is_primary(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// The lock file used to coordinate instances.
+    pub fn lock_path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Helper function to find the per-user directory for runtime files such as locks.
+pub(crate) fn runtime_dir() -> PathBuf {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    env::temp_dir()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_instance_works() {
+        // arrange
+        let dir = "assets/instance_acquire_test";
+        let _ = fs::remove_dir_all(dir);
+        let path = format!("{}/app.lock", dir);
+
+        // act
+        let primary = SingleInstance::acquire_at(&path).unwrap();
+        let secondary = SingleInstance::acquire_at(&path).unwrap();
+        let was_primary = primary.is_primary();
+        drop(primary);
+        let next = SingleInstance::acquire_at(&path).unwrap();
+        let invalid = SingleInstance::acquire("../escape");
+
+        // assert
+        assert!(was_primary);
+        assert!(!secondary.is_primary());
+        assert!(next.is_primary());
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        drop(next);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
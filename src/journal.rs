@@ -0,0 +1,553 @@
+//! Journaled bulk copy/move/delete operations that can be resumed after an interruption.
+use crate::error::{Context, FileManagerError, Operation};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+pub(crate) const JOURNAL_HEADER: &str = "file-manager-journal v1";
+
+/// The kind of bulk operation recorded in a journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkOperation {
+    Copy,
+    Move,
+    Delete,
+}
+
+impl BulkOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BulkOperation::Copy => "copy",
+            BulkOperation::Move => "move",
+            BulkOperation::Delete => "delete",
+        }
+    }
+
+    fn parse(s: &str) -> io::Result<Self> {
+        match s {
+            "copy" => Ok(BulkOperation::Copy),
+            "move" => Ok(BulkOperation::Move),
+            "delete" => Ok(BulkOperation::Delete),
+            _ => Err(invalid_journal(format!("unknown operation `{}`", s))),
+        }
+    }
+}
+
+/// A single unit of work in a journal: a source and, for copy/move, a destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    src: PathBuf,
+    dst: Option<PathBuf>,
+}
+
+/// An operation journal backed by a file.
+/// The planned entries are written up front and every completed entry is appended
+/// (and synced) as it finishes, so an interrupted run can pick up where it left off.
+/// The file stays exclusively locked while it is being run, which is how
+/// [`recover`](crate::recover::recover) tells a live run from an interrupted one.
+struct Journal {
+    file: File,
+    path: PathBuf,
+    operation: BulkOperation,
+    entries: Vec<Entry>,
+    done: HashSet<usize>,
+}
+
+impl Journal {
+    /// Write a new journal at `path` describing `entries`.
+    fn create(path: &Path, operation: BulkOperation, entries: Vec<Entry>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .context(Operation::Create, path)?;
+        // Locked before the plan is written, so anyone who sees the header sees the lock.
+        file.lock().context(Operation::Create, path)?;
+
+        let mut plan = String::new();
+        plan.push_str(JOURNAL_HEADER);
+        plan.push('\n');
+        plan.push_str(operation.as_str());
+        plan.push('\n');
+        for entry in &entries {
+            plan.push_str("entry\t");
+            plan.push_str(journal_path_str(&entry.src)?);
+            if let Some(dst) = &entry.dst {
+                plan.push('\t');
+                plan.push_str(journal_path_str(dst)?);
+            }
+            plan.push('\n');
+        }
+        plan.push_str("begin\n");
+
+        (|| {
+            file.write_all(plan.as_bytes())?;
+            file.sync_all()
+        })()
+        .context(Operation::Write, path)?;
+
+        Ok(Journal {
+            file,
+            path: path.to_path_buf(),
+            operation,
+            entries,
+            done: HashSet::new(),
+        })
+    }
+
+    /// Load an existing journal at `path`, including the entries already completed,
+    /// and lock it for running.
+    fn load(path: &Path) -> io::Result<Self> {
+        let file = Self::lock(path)?.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::WouldBlock,
+                format!("journal is in use: {}", path.display()),
+            )
+        })?;
+        Self::parse(path, file)?.ok_or_else(incomplete_journal)
+    }
+
+    /// Open the journal at `path` for appending and lock it,
+    /// or return `None` if another run holds the lock.
+    fn lock(path: &Path) -> io::Result<Option<File>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .context(Operation::Open, path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(file)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => {
+                Err(FileManagerError::new(Operation::Open, path, e).into())
+            }
+        }
+    }
+
+    /// Parse the journal read from `file`.
+    /// Returns `None` if the plan was never fully written, so nothing was started.
+    fn parse(path: &Path, file: File) -> io::Result<Option<Self>> {
+        let mut lines = BufReader::new(&file).lines();
+
+        match lines.next().transpose()? {
+            Some(header) if header == JOURNAL_HEADER => {}
+            _ => return Err(invalid_journal("missing journal header".to_owned())),
+        }
+        let operation = match lines.next().transpose()? {
+            Some(op) => BulkOperation::parse(&op)?,
+            None => return Ok(None),
+        };
+
+        let mut entries = Vec::new();
+        let mut done = HashSet::new();
+        let mut begun = false;
+        for line in lines {
+            let line = line?;
+            if !begun {
+                if line == "begin" {
+                    begun = true;
+                    continue;
+                }
+                let mut fields = line.strip_prefix("entry\t").map(|l| l.split('\t'));
+                let src = fields.as_mut().and_then(|f| f.next());
+                let dst = fields.as_mut().and_then(|f| f.next());
+                match src {
+                    Some(src) => entries.push(Entry {
+                        src: PathBuf::from(src),
+                        dst: dst.map(PathBuf::from),
+                    }),
+                    // A torn line of the plan itself.
+                    None => return Ok(None),
+                }
+            } else if let Some(index) = line.strip_prefix("done ") {
+                // A torn final line from a crash mid-write is simply ignored.
+                if let Ok(index) = index.parse::<usize>() {
+                    if index >= entries.len() {
+                        return Err(invalid_journal(format!(
+                            "entry {} marked done but only {} planned",
+                            index,
+                            entries.len()
+                        )));
+                    }
+                    done.insert(index);
+                }
+            }
+        }
+        if !begun {
+            return Ok(None);
+        }
+
+        Ok(Some(Journal {
+            file,
+            path: path.to_path_buf(),
+            operation,
+            entries,
+            done,
+        }))
+    }
+
+    /// Record entry `index` as completed.
+    fn mark_done(&mut self, index: usize) -> io::Result<()> {
+        (|| {
+            writeln!(self.file, "done {}", index)?;
+            self.file.sync_data()
+        })()
+        .context(Operation::Write, &self.path)?;
+        self.done.insert(index);
+        Ok(())
+    }
+
+    /// Perform every entry not yet marked as done, then remove the journal.
+    /// Returns the number of entries performed by this call.
+    fn run(mut self) -> io::Result<usize> {
+        let mut performed = 0;
+        for index in 0..self.entries.len() {
+            if self.done.contains(&index) {
+                continue;
+            }
+            apply(self.operation, &self.entries[index])?;
+            self.mark_done(index)?;
+            performed += 1;
+        }
+
+        // Removed while still locked, so `recover` cannot pick up the finished journal.
+        fs::remove_file(&self.path).context(Operation::Delete, &self.path)?;
+        Ok(performed)
+    }
+}
+
+/// Apply a single journal entry.
+/// Each operation is written so that re-applying an entry which already completed
+/// (but was not yet marked as done) is harmless.
+fn apply(operation: BulkOperation, entry: &Entry) -> io::Result<()> {
+    match operation {
+        BulkOperation::Copy => {
+            let dst = entry_destination(entry)?;
+            create_parent_dir(dst)?;
+            fs::copy(&entry.src, dst).context(Operation::Copy, &entry.src)?;
+        }
+        BulkOperation::Move => {
+            let dst = entry_destination(entry)?;
+            if !entry.src.exists() && dst.exists() {
+                // Moved before the interruption.
+                return Ok(());
+            }
+            create_parent_dir(dst)?;
+            if let Err(e) = fs::rename(&entry.src, dst) {
+                if e.kind() != ErrorKind::CrossesDevices {
+                    return Err(FileManagerError::new(Operation::Move, &entry.src, e).into());
+                }
+                fs::copy(&entry.src, dst).context(Operation::Copy, &entry.src)?;
+                fs::remove_file(&entry.src).context(Operation::Delete, &entry.src)?;
+            }
+        }
+        BulkOperation::Delete => {
+            if entry.src.exists() {
+                fs::remove_file(&entry.src).context(Operation::Delete, &entry.src)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn entry_destination(entry: &Entry) -> io::Result<&Path> {
+    entry
+        .dst
+        .as_deref()
+        .ok_or_else(|| invalid_journal("entry is missing a destination".to_owned()))
+}
+
+fn create_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            Ok(fs::create_dir_all(parent).context(Operation::Create, parent)?)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Journal entries are stored one per line with tab separated fields,
+/// so paths must be valid UTF-8 and must not contain tabs or newlines.
+fn journal_path_str(path: &Path) -> io::Result<&str> {
+    match path.to_str() {
+        Some(s) if !s.contains(['\t', '\n', '\r']) => Ok(s),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("path cannot be journaled: {}", path.display()),
+        )),
+    }
+}
+
+fn incomplete_journal() -> io::Error {
+    invalid_journal("journal plan is incomplete".to_owned())
+}
+
+fn invalid_journal(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("invalid journal: {}", msg))
+}
+
+/// Copy each `(source, destination)` pair, recording progress in a journal at `journal_path`.
+/// Destination parent directories are created as needed.
+/// If the process is interrupted, call [`resume`] with the same `journal_path` to finish the job.
+/// The journal is removed once every entry has completed.
+///
+/// # Returns
+/// The number of files copied.
+pub fn bulk_copy<P: AsRef<Path>, Q: AsRef<Path>, J: AsRef<Path>>(
+    pairs: &[(P, Q)],
+    journal_path: J,
+) -> io::Result<usize> {
+    let entries = pairs
+        .iter()
+        .map(|(src, dst)| Entry {
+            src: src.as_ref().to_path_buf(),
+            dst: Some(dst.as_ref().to_path_buf()),
+        })
+        .collect();
+    Journal::create(journal_path.as_ref(), BulkOperation::Copy, entries)?.run()
+}
+
+/// Move each `(source, destination)` pair, recording progress in a journal at `journal_path`.
+/// Falls back to copy-then-delete when a rename crosses devices.
+/// If the process is interrupted, call [`resume`] with the same `journal_path` to finish the job.
+///
+/// # Returns
+/// The number of files moved.
+pub fn bulk_move<P: AsRef<Path>, Q: AsRef<Path>, J: AsRef<Path>>(
+    pairs: &[(P, Q)],
+    journal_path: J,
+) -> io::Result<usize> {
+    let entries = pairs
+        .iter()
+        .map(|(src, dst)| Entry {
+            src: src.as_ref().to_path_buf(),
+            dst: Some(dst.as_ref().to_path_buf()),
+        })
+        .collect();
+    Journal::create(journal_path.as_ref(), BulkOperation::Move, entries)?.run()
+}
+
+/// Delete each file in `paths`, recording progress in a journal at `journal_path`.
+/// Files that do not exist are skipped.
+/// If the process is interrupted, call [`resume`] with the same `journal_path` to finish the job.
+///
+/// # Returns
+/// The number of entries processed.
+pub fn bulk_delete<P: AsRef<Path>, J: AsRef<Path>>(
+    paths: &[P],
+    journal_path: J,
+) -> io::Result<usize> {
+    let entries = paths
+        .iter()
+        .map(|src| Entry {
+            src: src.as_ref().to_path_buf(),
+            dst: None,
+        })
+        .collect();
+    Journal::create(journal_path.as_ref(), BulkOperation::Delete, entries)?.run()
+}
+
+/// Continue an interrupted bulk operation from the journal at `journal_path`.
+/// Entries already recorded as done are not repeated.
+/// The journal is removed once every entry has completed.
+///
+/// # Returns
+/// The number of entries performed by this call.
+pub fn resume<P: AsRef<Path>>(journal_path: P) -> io::Result<usize> {
+    Journal::load(journal_path.as_ref())?.run()
+}
+
+/// Returns the operation recorded in the journal at `journal_path`
+/// along with the number of entries still outstanding.
+pub fn pending<P: AsRef<Path>>(journal_path: P) -> io::Result<(BulkOperation, usize)> {
+    let path = journal_path.as_ref();
+    let file = File::open(path).context(Operation::Open, path)?;
+    let journal = Journal::parse(path, file)?.ok_or_else(incomplete_journal)?;
+    let remaining = (0..journal.entries.len())
+        .filter(|index| !journal.done.contains(index))
+        .count();
+    Ok((journal.operation, remaining))
+}
+
+/// Finish the interrupted journal at `journal_path`, or remove it if its plan
+/// was never fully written.
+///
+/// # Returns
+/// `Some(true)` once its operation completed, `Some(false)` if it was removed
+/// unstarted and `None` if a running operation owns it.
+pub(crate) fn recover(journal_path: &Path) -> io::Result<Option<bool>> {
+    let file = match Journal::lock(journal_path) {
+        Ok(Some(file)) => file,
+        Ok(None) => return Ok(None),
+        // Finished and removed by its owner since it was found.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match Journal::parse(journal_path, file)? {
+        Some(journal) => journal.run().map(|_| Some(true)),
+        None => {
+            fs::remove_file(journal_path).context(Operation::Delete, journal_path)?;
+            Ok(Some(false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_file, write_to_file};
+
+    #[test]
+    fn bulk_copy_works() {
+        // arrange
+        let dir = "assets/journal_copy_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let src = format!("{}/a.txt", dir);
+        let dst = format!("{}/out/a.txt", dir);
+        let journal = format!("{}/copy.journal", dir);
+        write_to_file(&src, true, "copied").unwrap();
+
+        // act
+        let result = bulk_copy(&[(&src, &dst)], &journal);
+        let copied = fs::read_to_string(&dst).unwrap();
+
+        // assert
+        assert_eq!(1, result.unwrap());
+        assert_eq!("copied", copied);
+        assert!(Path::new(&src).exists());
+        assert!(!Path::new(&journal).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    // Simulate a crash after the first move completed and make sure resume
+    // only performs the remaining work.
+    fn resume_skips_completed_entries() {
+        // arrange
+        let dir = "assets/journal_resume_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let journal = format!("{}/move.journal", dir);
+        let entries = vec![
+            Entry {
+                src: PathBuf::from(format!("{}/one.txt", dir)),
+                dst: Some(PathBuf::from(format!("{}/moved/one.txt", dir))),
+            },
+            Entry {
+                src: PathBuf::from(format!("{}/two.txt", dir)),
+                dst: Some(PathBuf::from(format!("{}/moved/two.txt", dir))),
+            },
+        ];
+        create_file(format!("{}/two.txt", dir), true).unwrap();
+        fs::create_dir_all(format!("{}/moved", dir)).unwrap();
+        create_file(format!("{}/moved/one.txt", dir), true).unwrap();
+        let mut interrupted = Journal::create(Path::new(&journal), BulkOperation::Move, entries)
+            .unwrap();
+        interrupted.mark_done(0).unwrap();
+        drop(interrupted);
+
+        // act
+        let before = pending(&journal).unwrap();
+        let result = resume(&journal);
+
+        // assert
+        assert_eq!((BulkOperation::Move, 1), before);
+        assert_eq!(1, result.unwrap());
+        assert!(Path::new(&format!("{}/moved/two.txt", dir)).exists());
+        assert!(!Path::new(&format!("{}/two.txt", dir)).exists());
+        assert!(!Path::new(&journal).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bulk_delete_skips_missing_files() {
+        // arrange
+        let dir = "assets/journal_delete_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let existing = format!("{}/exists.txt", dir);
+        let missing = format!("{}/missing.txt", dir);
+        let journal = format!("{}/delete.journal", dir);
+        create_file(&existing, true).unwrap();
+
+        // act
+        let result = bulk_delete(&[&existing, &missing], &journal);
+
+        // assert
+        assert_eq!(2, result.unwrap());
+        assert!(!Path::new(&existing).exists());
+        assert!(!Path::new(&journal).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bulk_copy_errors_name_the_failing_path() {
+        // arrange
+        let dir = "assets/journal_error_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let src = format!("{}/missing.txt", dir);
+        let journal = format!("{}/copy.journal", dir);
+
+        // act
+        let err = bulk_copy(&[(&src, format!("{}/out.txt", dir))], &journal).unwrap_err();
+
+        // assert
+        let context = FileManagerError::from_io(&err).unwrap();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        assert_eq!(Operation::Copy, context.operation());
+        assert_eq!(Some(Path::new(&src)), context.path());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pending_rejects_out_of_range_done_entries() {
+        // arrange
+        let dir = "assets/journal_pending_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let journal = format!("{}/delete.journal", dir);
+        let plan = format!(
+            "{}\ndelete\nentry\t{}/a.txt\nbegin\ndone 0\n",
+            JOURNAL_HEADER, dir
+        );
+        write_to_file(&journal, true, &plan).unwrap();
+
+        // act
+        let valid = pending(&journal).unwrap();
+        write_to_file(&journal, true, &format!("{}done 5\n", plan)).unwrap();
+        let invalid = pending(&journal);
+
+        // assert
+        assert_eq!((BulkOperation::Delete, 0), valid);
+        assert_eq!(ErrorKind::InvalidData, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resume_refuses_a_journal_in_use() {
+        // arrange
+        let dir = "assets/journal_in_use_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let journal = format!("{}/delete.journal", dir);
+        let entries = vec![Entry {
+            src: PathBuf::from(format!("{}/a.txt", dir)),
+            dst: None,
+        }];
+        let running = Journal::create(Path::new(&journal), BulkOperation::Delete, entries).unwrap();
+
+        // act
+        let result = resume(&journal);
+
+        // assert
+        assert_eq!(ErrorKind::WouldBlock, result.unwrap_err().kind());
+        assert_eq!(1, running.run().unwrap());
+        assert!(!Path::new(&journal).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+//! Time-limited locks that work on shared network filesystems.
+use crate::write_atomic;
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const LEASE_HEADER: &str = "file-manager-lease v1";
+
+/// The contents of a lease file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseInfo {
+    /// Identifies the holder; unique per [`Lease::acquire`] call.
+    pub owner: String,
+    /// When the lease runs out unless the holder renews it.
+    pub expires: SystemTime,
+}
+
+/// Shared state between a [`Lease`] and its heartbeat thread.
+#[derive(Default)]
+struct LeaseState {
+    stopped: bool,
+    lost: bool,
+}
+
+/// A lease on a lock file, renewed by a background heartbeat until dropped.
+///
+/// Unlike OS file locks, leases only rely on creating, renaming and reading files,
+/// so they also coordinate processes on different machines sharing an NFS or SMB
+/// volume. The expiry is judged by wall clock time, so the machines' clocks must
+/// agree to well within the TTL.
+pub struct Lease {
+    path: PathBuf,
+    owner: String,
+    ttl: Duration,
+    state: Arc<(Mutex<LeaseState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Lease {
+    /// Take the lease at `path` for `ttl`, renewing it every third of `ttl`.
+    ///
+    /// Fails with `AlreadyExists` if another holder's lease has not expired yet.
+    /// An expired lease is broken and taken over.
+    pub fn acquire<P: AsRef<Path>>(path: P, ttl: Duration) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let owner = format!(
+            "{}-{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+
+        let mut attempts = 0;
+        loop {
+            match create_lease(&path, &owner, ttl) {
+                Ok(()) => break,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            attempts += 1;
+            let current = match read_lease(&path)? {
+                // Released in the meantime.
+                None => continue,
+                Some(current) => current,
+            };
+            let (info, raw) = current;
+            if info.expires > SystemTime::now() || attempts > 3 {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is leased by {}", path.display(), info.owner),
+                ));
+            }
+            break_lease(&path, &raw, &owner)?;
+        }
+
+        let state = Arc::new((Mutex::new(LeaseState::default()), Condvar::new()));
+        let thread_state = Arc::clone(&state);
+        let (thread_path, thread_owner) = (path.clone(), owner.clone());
+        let thread = thread::spawn(move || {
+            let (lock, signal) = &*thread_state;
+            loop {
+                let guard = lock.lock().unwrap();
+                let (guard, _) = signal
+                    .wait_timeout_while(guard, ttl / 3, |s| !s.stopped)
+                    .unwrap();
+                if guard.stopped {
+                    break;
+                }
+                drop(guard);
+                let renewed = match read_lease(&thread_path) {
+                    Ok(Some((info, _))) if info.owner == thread_owner => {
+                        let contents = lease_contents(&thread_owner, ttl);
+                        // A failed write is retried on the next beat, before the lease expires.
+                        let _ = write_atomic(&thread_path, contents.as_bytes());
+                        true
+                    }
+                    Ok(_) => false,
+                    // A transient read error isn't proof that the lease was taken.
+                    Err(_) => true,
+                };
+                if !renewed {
+                    lock.lock().unwrap().lost = true;
+                    break;
+                }
+            }
+        });
+
+        Ok(Lease {
+            path,
+            owner,
+            ttl,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Read the lease file at `path` without taking it.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Option<LeaseInfo>> {
+        Ok(read_lease(path.as_ref())?.map(|(info, _)| info))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns `false` once the heartbeat found the lease taken over by another
+    /// holder, which happens if it could not renew the lease in time.
+    pub fn is_held(&self) -> bool {
+        !self.state.0.lock().unwrap().lost
+    }
+
+    /// Stop the heartbeat and remove the lease file.
+    pub fn release(self) {
+        // Handled by drop.
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.state;
+        lock.lock().unwrap().stopped = true;
+        signal.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Ok(Some((info, _))) = read_lease(&self.path) {
+            if info.owner == self.owner {
+                let _ = fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+fn lease_contents(owner: &str, ttl: Duration) -> String {
+    let expires = (SystemTime::now() + ttl)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{}\nowner {}\nexpires {}\n", LEASE_HEADER, owner, expires)
+}
+
+/// Helper function to build a hidden sibling of `path` used during lease updates.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or(path.as_os_str()));
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Create the lease file with its full contents in one step.
+///
+/// The contents go to a private file that is then hard linked into place. Linking
+/// fails if `path` exists, and unlike `O_EXCL` it is atomic on NFS, so readers
+/// never see a half-written lease.
+fn create_lease(path: &Path, owner: &str, ttl: Duration) -> io::Result<()> {
+    let tmp = sibling(path, &format!(".{}.new", owner));
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(lease_contents(owner, ttl).as_bytes())?;
+        file.sync_all()?;
+        fs::hard_link(&tmp, path)
+    })();
+    let _ = fs::remove_file(&tmp);
+    result
+}
+
+/// Remove the expired lease at `path`, whose contents were `expired`.
+///
+/// The lease is renamed aside before it is deleted. If another process replaced it
+/// between our read and the rename, what we moved is a live lease, so it is
+/// linked back into place.
+fn break_lease(path: &Path, expired: &str, owner: &str) -> io::Result<()> {
+    let tombstone = sibling(path, &format!(".{}.broken", owner));
+    match fs::rename(path, &tombstone) {
+        Ok(()) => {}
+        // Someone else broke it first.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    if fs::read_to_string(&tombstone)? != expired {
+        match fs::hard_link(&tombstone, path) {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+                let _ = fs::remove_file(&tombstone);
+                return Err(e);
+            }
+            _ => {}
+        }
+    }
+    fs::remove_file(&tombstone)
+}
+
+/// Read and parse the lease at `path`, also returning its raw contents.
+fn read_lease(path: &Path) -> io::Result<Option<(LeaseInfo, String)>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = raw.lines();
+    let info = (|| {
+        if lines.next()? != LEASE_HEADER {
+            return None;
+        }
+        let owner = lines.next()?.strip_prefix("owner ")?.to_owned();
+        let expires = lines.next()?.strip_prefix("expires ")?.parse().ok()?;
+        Some(LeaseInfo {
+            owner,
+            expires: UNIX_EPOCH + Duration::from_millis(expires),
+        })
+    })();
+    match info {
+        Some(info) => Ok(Some((info, raw))),
+        None => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid lease file: {}", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_breaks_expired_and_renews() {
+        // arrange
+        let dir = "assets/lease_acquire_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/job.lease", dir);
+        let expired = format!("{}\nowner crashed\nexpires 1000\n", LEASE_HEADER);
+        fs::write(&path, expired).unwrap();
+        let ttl = Duration::from_millis(300);
+
+        // act
+        let lease = Lease::acquire(&path, ttl).unwrap();
+        thread::sleep(ttl * 2);
+        let contended = Lease::acquire(&path, ttl);
+        let info = Lease::read(&path).unwrap().unwrap();
+        let held = lease.is_held();
+        lease.release();
+
+        // assert
+        assert_eq!(
+            Some(ErrorKind::AlreadyExists),
+            contended.err().map(|e| e.kind())
+        );
+        assert_ne!("crashed", info.owner);
+        assert!(info.expires > SystemTime::now());
+        assert!(held);
+        assert!(!Path::new(&path).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,26 +1,581 @@
+use error::{Context, Operation};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
 use std::fmt::Write as FmtWrite;
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{self, BufReader, BufWriter, Error, Write},
-    path::Path,
+    ffi::{OsStr, OsString},
+    fs::{self, File, FileTimes, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
 };
 
+pub mod app_dirs;
+pub mod appender;
+pub mod archive;
+#[cfg(feature = "tokio")]
+pub mod async_fs;
+pub mod bookmarks;
+pub mod checksum;
+pub mod cleanup;
+pub mod clone;
+pub mod conditional;
+#[cfg(feature = "toml")]
+pub mod config;
+pub mod conflict;
+pub mod counter;
+pub mod delimited;
+pub mod diff;
+pub mod dirs;
+pub mod dirstream;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod du;
+#[cfg(feature = "encrypt")]
+pub mod encrypt;
+pub mod error;
+pub mod extension;
+pub mod fd;
+#[cfg(any(unix, windows))]
+pub mod fifo;
+pub mod filetype;
+pub mod find;
+pub mod flatten;
+pub mod framing;
+pub mod freshness;
+#[cfg(all(feature = "fuse", unix))]
+pub mod fuse;
+mod glob;
+pub mod history;
+pub mod instance;
+pub mod journal;
+mod json;
+pub mod lease;
+pub mod listing;
+pub mod lock;
+#[cfg(feature = "media")]
+pub mod media;
+pub mod meta;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(unix)]
+pub mod normalize;
+pub mod permissions;
+pub mod pidfile;
+pub mod quarantine;
+pub mod quota;
+pub mod recent;
+pub mod recover;
+pub mod scaffold;
+pub mod schedule;
+pub mod search;
+pub mod secret;
+pub mod shm;
+pub mod shutdown;
+pub mod sidecar;
+pub mod swap;
+pub mod sync;
+pub mod tags;
+pub mod temp;
+pub mod template;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod tracker;
+pub mod transfer;
+pub mod transform;
+pub mod trash;
+pub mod vfs;
+pub mod volume;
+pub mod walk;
+pub mod watch;
+#[cfg(feature = "zip")]
+pub mod zip;
+
+pub use error::FileManagerError;
+
+/// What to do when an operation's destination path already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Leave the existing file alone and skip the source.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep both by giving the new file a numbered name, e.g. `notes (1).txt`.
+    Rename,
+    /// Stop with an `AlreadyExists` error.
+    #[default]
+    Fail,
+}
+
+/// Helper function to build the `n`th numbered variant of a file name,
+/// e.g. `notes.txt` becomes `notes (1).txt` and `site.tar.gz` `site (1).tar.gz`.
+pub(crate) fn numbered_file_name(name: &OsStr, n: usize) -> OsString {
+    extension::name_with_suffix(name, &format!(" ({})", n))
+}
+
+/// Helper function to replace the file at `path` with `contents` atomically.
+/// The data is written and synced to a temporary file in the same directory,
+/// which is then renamed over `path`, so readers see either the old or the new file.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = temp_sibling(path, "tmp");
+
+    let result = (|| {
+        let mut file = File::create_new(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Helper function to name a hidden sibling of `path` for staging a replacement,
+/// as `.<name>.<purpose>-<pid>-<n>`. The name is unique to the call, so
+/// concurrent writers of the same `path` never share a temporary file.
+fn temp_sibling(path: &Path, purpose: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or(path.as_os_str()));
+    tmp_name.push(format!(".{}-{}-{}", purpose, std::process::id(), n));
+    path.with_file_name(tmp_name)
+}
+
 /// Attempt to open the file at `file_path` and return a BufReader<File>.
-pub fn open_file(file_path: &str) -> Option<BufReader<File>> {
-    // Open the file and read contents
-    // Keep trying until we successfully open a file
-    if let Ok(file) = File::open(file_path) {
-        // Create a BufReader from the File
-        Some(BufReader::new(file))
+pub fn open_file<P: AsRef<Path>>(file_path: P) -> Result<BufReader<File>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = File::open(file_path).context(Operation::Open, file_path)?;
+    Ok(BufReader::new(file))
+}
+
+/// Open the gzip-compressed file at `file_path` for reading its decompressed
+/// contents. Files made of several gzip members, as appending to a `.gz` log
+/// produces, are read through to the end.
+///
+/// The header is only checked on the first read, which fails with
+/// `InvalidInput` if the file is not gzip.
+pub fn open_gzip_reader<P: AsRef<Path>>(
+    file_path: P,
+) -> Result<BufReader<MultiGzDecoder<File>>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = File::open(file_path).context(Operation::Open, file_path)?;
+    Ok(BufReader::new(MultiGzDecoder::new(file)))
+}
+
+/// Create or truncate the file at `file_path` and return a writer that
+/// gzip-compresses everything written to it.
+///
+/// Call `finish` on the returned `GzEncoder` when done: dropping it writes the
+/// trailer too, but ignores any error doing so.
+pub fn open_gzip_writer<P: AsRef<Path>>(
+    file_path: P,
+) -> Result<GzEncoder<BufWriter<File>>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = open_file_for_writing(file_path, true).context(Operation::Open, file_path)?;
+    Ok(GzEncoder::new(BufWriter::new(file), Compression::default()))
+}
+
+/// Deserialize the JSON in the file at `file_path`, or stdin if it is `"-"`,
+/// reading it through a buffer.
+///
+/// # Errors
+/// `InvalidData` if the contents are not valid JSON for `T`.
+#[cfg(feature = "serde")]
+pub fn read_json<T, P>(file_path: P) -> Result<T, FileManagerError>
+where
+    T: serde::de::DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let file_path = file_path.as_ref();
+    serde_json::from_reader(open_input(file_path)?)
+        .map_err(io::Error::from)
+        .context(Operation::Read, file_path)
+}
+
+/// Serialize `value` as JSON to the file at `file_path`, or stdout if it is
+/// `"-"`, followed by a newline. With `pretty`, objects and arrays are
+/// indented.
+///
+/// The JSON is streamed to a temporary sibling that then replaces
+/// `file_path`, so a failure halfway never leaves a truncated file.
+#[cfg(feature = "serde")]
+pub fn write_json<T, P>(file_path: P, value: &T, pretty: bool) -> Result<(), FileManagerError>
+where
+    T: serde::Serialize + ?Sized,
+    P: AsRef<Path>,
+{
+    let file_path = file_path.as_ref();
+    if is_stdio_path(file_path) {
+        let output = open_output(file_path, true)?;
+        return write_json_to(output, value, pretty).context(Operation::Write, file_path);
+    }
+    let tmp_path = temp_sibling(file_path, "tmp");
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create_new(&tmp_path)?);
+        write_json_to(&mut writer, value, pretty)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, file_path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.context(Operation::Write, file_path)
+}
+
+/// Helper function to write `value` as JSON and a newline to `writer`.
+#[cfg(feature = "serde")]
+fn write_json_to<W, T>(mut writer: W, value: &T, pretty: bool) -> io::Result<()>
+where
+    W: Write,
+    T: serde::Serialize + ?Sized,
+{
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Read the whole file at `file_path`, or stdin if it is `"-"`, into a string.
+///
+/// # Errors
+/// `InvalidData` if the contents are not valid UTF-8.
+pub fn read_file_to_string<P: AsRef<Path>>(file_path: P) -> Result<String, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let mut contents = String::new();
+    open_input(file_path)?
+        .read_to_string(&mut contents)
+        .context(Operation::Read, file_path)?;
+    Ok(contents)
+}
+
+/// Read the whole file at `file_path`, or stdin if it is `"-"`.
+pub fn read_file_to_bytes<P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let mut contents = Vec::new();
+    open_input(file_path)?
+        .read_to_end(&mut contents)
+        .context(Operation::Read, file_path)?;
+    Ok(contents)
+}
+
+/// Options for [`read_consistent_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsistentReadOptions {
+    /// How many times to read the file before giving up on it settling.
+    pub attempts: u32,
+    /// Snapshot the file into a temporary file with one `io::copy`, which uses
+    /// in-kernel copies where available, and read that instead. This shortens
+    /// the window in which a writer can interfere with a large file.
+    pub copy_first: bool,
+}
+
+impl Default for ConsistentReadOptions {
+    fn default() -> Self {
+        ConsistentReadOptions {
+            attempts: 5,
+            copy_first: false,
+        }
+    }
+}
+
+/// Read the whole file at `file_path` while another process may be writing it,
+/// rereading it until its size and modification time are the same before and
+/// after the read, so the bytes returned are a single version of the file.
+///
+/// # Errors
+/// `ResourceBusy` if the file kept changing for every attempt of
+/// [`ConsistentReadOptions::default`].
+pub fn read_consistent<P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>, FileManagerError> {
+    read_consistent_with(file_path, &ConsistentReadOptions::default())
+}
+
+/// Like [`read_consistent`], with the given `options`.
+pub fn read_consistent_with<P: AsRef<Path>>(
+    file_path: P,
+    options: &ConsistentReadOptions,
+) -> Result<Vec<u8>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let mut file = File::open(file_path).context(Operation::Open, file_path)?;
+    let version = |file: &File| -> io::Result<_> {
+        let metadata = file.metadata()?;
+        Ok((metadata.len(), metadata.modified().ok()))
+    };
+    for attempt in 0..options.attempts.max(1) {
+        if attempt > 0 {
+            std::thread::sleep(std::time::Duration::from_millis(10 << attempt.min(6)));
+        }
+        let contents = (|| {
+            let before = version(&file)?;
+            file.rewind()?;
+            let mut contents = Vec::new();
+            if options.copy_first {
+                let mut snapshot = temp::TempFile::new()?;
+                io::copy(&mut file, &mut snapshot.file())?;
+                snapshot.rewind()?;
+                snapshot.read_to_end(&mut contents)?;
+            } else {
+                file.read_to_end(&mut contents)?;
+            }
+            let settled = version(&file)? == before && contents.len() as u64 == before.0;
+            Ok(settled.then_some(contents))
+        })()
+        .context(Operation::Read, file_path)?;
+        if let Some(contents) = contents {
+            return Ok(contents);
+        }
+    }
+    Err(FileManagerError::new(
+        Operation::Read,
+        file_path,
+        io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            "the file kept changing while it was read",
+        ),
+    ))
+}
+
+/// Stream the lines of the file at `file_path`, or stdin if it is `"-"`,
+/// without their terminators. Only one line is held in memory at a time.
+pub fn read_lines<P: AsRef<Path>>(
+    file_path: P,
+) -> Result<impl Iterator<Item = io::Result<String>>, FileManagerError> {
+    Ok(open_input(file_path)?.lines())
+}
+
+/// Call `f` with each line of the file at `file_path`, or stdin if it is `"-"`,
+/// until it returns [`ControlFlow::Break`]. Lines are passed without their
+/// terminators and share one buffer.
+///
+/// # Returns
+/// The value `f` broke with, or `None` if every line was visited.
+pub fn for_each_line<P, F, B>(file_path: P, mut f: F) -> Result<Option<B>, FileManagerError>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str) -> ControlFlow<B>,
+{
+    let file_path = file_path.as_ref();
+    let mut input = open_input(file_path)?;
+    let mut line = String::new();
+    while input
+        .read_line(&mut line)
+        .context(Operation::Read, file_path)?
+        > 0
+    {
+        let content = line.strip_suffix('\n').unwrap_or(&line);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        if let ControlFlow::Break(value) = f(content) {
+            return Ok(Some(value));
+        }
+        line.clear();
+    }
+    Ok(None)
+}
+
+/// The conventional path for stdin when reading and stdout when writing.
+pub const STDIO_PATH: &str = "-";
+
+/// Returns `true` if `path` is [`STDIO_PATH`].
+pub fn is_stdio_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref() == Path::new(STDIO_PATH)
+}
+
+/// Open `path` for buffered reading, or stdin if it is `"-"`.
+pub fn open_input<P: AsRef<Path>>(path: P) -> Result<Box<dyn BufRead>, FileManagerError> {
+    let path = path.as_ref();
+    if is_stdio_path(path) {
+        Ok(Box::new(io::stdin().lock()))
     } else {
-        None
+        let file = File::open(path).context(Operation::Open, path)?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// The compression formats [`open_auto`] recognises, by their magic bytes.
+const COMPRESSION_MAGIC: &[(&[u8], &str)] = &[
+    (&[0x1f, 0x8b], "gzip"),
+    (&[0x28, 0xb5, 0x2f, 0xfd], "zstd"),
+    (b"BZh", "bzip2"),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0], "xz"),
+];
+
+/// Open `path` like [`open_input`], decompressing on the fly if its contents
+/// start with the magic bytes of gzip, zstd, bzip2 or xz. Anything else is
+/// read as it is.
+///
+/// gzip is always supported; the other formats need the `zstd`, `bzip2` and
+/// `xz` features.
+///
+/// # Errors
+/// `Unsupported` if the input is compressed in a format whose feature is
+/// disabled.
+pub fn open_auto<P: AsRef<Path>>(path: P) -> Result<Box<dyn BufRead>, FileManagerError> {
+    let path = path.as_ref();
+    let mut input = open_input(path)?;
+    let head = input.fill_buf().context(Operation::Read, path)?;
+    let format = COMPRESSION_MAGIC
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map(|(_, format)| *format);
+    match format {
+        Some(format) => decompress(format, input).context(Operation::Open, path),
+        None => Ok(input),
+    }
+}
+
+/// Helper function to wrap `input` in a decoder for `format`.
+fn decompress(format: &str, input: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    match format {
+        "gzip" => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(input),
+        ))),
+        #[cfg(feature = "zstd")]
+        "zstd" => {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(input)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Box::new(BufReader::new(decoder)))
+        }
+        #[cfg(feature = "bzip2")]
+        "bzip2" => Ok(Box::new(BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(input),
+        ))),
+        #[cfg(feature = "xz")]
+        "xz" => Ok(Box::new(BufReader::new(
+            liblzma::bufread::XzDecoder::new_multi_decoder(input),
+        ))),
+        format => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("reading {} requires the `{}` feature", format, format),
+        )),
     }
 }
 
+/// Open `path` for buffered writing, or stdout if it is `"-"`.
+/// The file is created if needed and truncated if `truncate == true`.
+pub fn open_output<P: AsRef<Path>>(
+    path: P,
+    truncate: bool,
+) -> Result<Box<dyn Write>, FileManagerError> {
+    let path = path.as_ref();
+    if is_stdio_path(path) {
+        Ok(Box::new(io::stdout().lock()))
+    } else {
+        let file = open_file_for_writing(path, truncate).context(Operation::Open, path)?;
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+/// Copy everything from `from` to `to`, replacing its contents, where either may
+/// be `"-"` for stdin or stdout.
+///
+/// # Returns
+/// The number of bytes copied.
+pub fn copy_stream<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+) -> Result<u64, FileManagerError> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    let mut input = open_input(from)?;
+    let mut output = open_output(to, true)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FileManagerError::new(Operation::Read, from, e)),
+        };
+        output.write_all(&buf[..n]).context(Operation::Write, to)?;
+        copied += n as u64;
+    }
+    output.flush().context(Operation::Write, to)?;
+    Ok(copied)
+}
+
+/// Options for [`copy_file_with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Bytes read and written between calls to the progress callback.
+    pub chunk_size: usize,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Copy the file at `src` to `dst` with its permissions, like `std::fs::copy`,
+/// calling `progress(copied, total)` once before the first chunk and after
+/// every chunk of [`CopyOptions::default`].
+///
+/// # Returns
+/// The number of bytes copied.
+pub fn copy_file_with_progress<P, Q, F>(
+    src: P,
+    dst: Q,
+    progress: F,
+) -> Result<u64, FileManagerError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(u64, u64),
+{
+    copy_file_with_options(src, dst, &CopyOptions::default(), progress)
+}
+
+/// Like [`copy_file_with_progress`], with the given `options`.
+pub fn copy_file_with_options<P, Q, F>(
+    src: P,
+    dst: Q,
+    options: &CopyOptions,
+    mut progress: F,
+) -> Result<u64, FileManagerError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(u64, u64),
+{
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    let mut input = File::open(src).context(Operation::Open, src)?;
+    let metadata = input.metadata().context(Operation::Read, src)?;
+    let mut output = File::create(dst).context(Operation::Create, dst)?;
+    let total = metadata.len();
+    let mut buf = vec![0; options.chunk_size.max(1)];
+    let mut copied = 0;
+    progress(copied, total);
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FileManagerError::new(Operation::Read, src, e)),
+        };
+        output.write_all(&buf[..n]).context(Operation::Write, dst)?;
+        copied += n as u64;
+        progress(copied, total);
+    }
+    output
+        .set_permissions(metadata.permissions())
+        .context(Operation::Write, dst)?;
+    Ok(copied)
+}
+
 /// Helper function to open a file with write privelages.
 /// It will create the file if it does not already exist at `file_path`.
 /// If `truncate == true`, the file will be truncated before writing `contents`.
-fn open_file_for_writing(file_path: &str, truncate: bool) -> io::Result<File> {
+fn open_file_for_writing(file_path: &Path, truncate: bool) -> io::Result<File> {
     OpenOptions::new()
         .write(true)
         .truncate(truncate)
@@ -30,39 +585,53 @@ fn open_file_for_writing(file_path: &str, truncate: bool) -> io::Result<File> {
 
 /// Helper function to open a file with append privelages.
 /// It will create the file if it does not already exist at `file_path`.
-fn open_file_for_appending(file_path: &str) -> io::Result<File> {
+fn open_file_for_appending(file_path: &Path) -> io::Result<File> {
     OpenOptions::new().append(true).create(true).open(file_path)
 }
 
 /// Opens a file at `file_path` for appending.
 /// If the file does not exist, it will be created at `file_path`.
 /// Contents written to the returned `BufWriter<File>` will be appended to the end of the file.
+/// For log files that should rotate, use [`appender::RotatingAppender`].
 ///
 /// # Returns
 /// A `BufWriter` for writing contents to the file.
-pub fn open_buffered_file_appender(file_path: &str) -> Result<BufWriter<File>, Error> {
-    let file = open_file_for_appending(file_path)?;
+pub fn open_buffered_file_appender<P: AsRef<Path>>(
+    file_path: P,
+) -> Result<BufWriter<File>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = open_file_for_appending(file_path).context(Operation::Open, file_path)?;
 
     Ok(BufWriter::new(file))
 }
 
-/// Attempts to append `contents` to file at `file_path`.
+/// Attempts to append `contents` to file at `file_path`, or stdout if it is `"-"`.
 /// Will create file at `file_path` if it does not already exist.
 /// Each call to this funciton will append a platform specific newline character.
 /// If `truncate == true`, the file will be truncated before writing `contents`.
-pub fn append_to_file(file_path: &str, contents: &str) -> Result<(), io::Error> {
-    let mut file = open_file_for_appending(file_path)?;
+pub fn append_to_file<P: AsRef<Path>>(
+    file_path: P,
+    contents: &str,
+) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    if is_stdio_path(file_path) {
+        let mut stdout = io::stdout().lock();
+        return writeln!(stdout, "{}", contents)
+            .and_then(|_| stdout.flush())
+            .context(Operation::Append, file_path);
+    }
+    let mut file = open_file_for_appending(file_path).context(Operation::Open, file_path)?;
 
     // Hacky way to get env specific newline char after each function call.
     let mut s = String::new();
     let _ = writeln!(&mut s, "{}", contents);
 
     // Write the string with newline char appended.
-    file.write_all(s.as_bytes())?;
-
     // Make sure all bytes have been written.
-    file.flush()?;
-    Ok(())
+    file.write_all(s.as_bytes())
+        .and_then(|_| file.flush())
+        .and_then(|_| sidecar::update_sidecar(file_path))
+        .context(Operation::Append, file_path)
 }
 
 /// Opens a file at `file_path` for writing.
@@ -71,48 +640,266 @@ pub fn append_to_file(file_path: &str, contents: &str) -> Result<(), io::Error>
 ///
 /// # Returns
 /// A `BufWriter` for writing contents to the file.
-pub fn open_buffered_file_writer(
-    file_path: &str,
+pub fn open_buffered_file_writer<P: AsRef<Path>>(
+    file_path: P,
     truncate: bool,
-) -> Result<BufWriter<File>, Error> {
-    let file = open_file_for_writing(file_path, truncate)?;
+) -> Result<BufWriter<File>, FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = open_file_for_writing(file_path, truncate).context(Operation::Open, file_path)?;
 
     Ok(BufWriter::new(file))
 }
 
-/// Attempts to write `contents` to file at `file_path`.
+/// Attempts to write `contents` to file at `file_path`, or stdout if it is `"-"`.
 /// Will create file at `file_path` if it does not already exist.
 /// If `truncate == true`, the file will be truncated before writing `contents`.
-pub fn write_to_file(file_path: &str, truncate: bool, contents: &str) -> Result<(), io::Error> {
-    let mut file = open_file_for_writing(file_path, truncate)?;
-    file.write_all(contents.as_bytes())?;
-
+pub fn write_to_file<P: AsRef<Path>>(
+    file_path: P,
+    truncate: bool,
+    contents: &str,
+) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    let mut file = open_output(file_path, truncate)?;
     // Make sure all bytes have been written.
-    file.flush()?;
-    Ok(())
+    file.write_all(contents.as_bytes())
+        .and_then(|_| file.flush())
+        .context(Operation::Write, file_path)?;
+    drop(file);
+    if is_stdio_path(file_path) {
+        return Ok(());
+    }
+    sidecar::update_sidecar(file_path).context(Operation::Write, file_path)
+}
+
+/// Replace the file at `file_path` with `contents` so that readers and crashes
+/// never observe partial content. The data is written and synced to a temporary
+/// sibling, which is then renamed over `file_path`.
+pub fn write_to_file_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(
+    file_path: P,
+    contents: C,
+) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    write_atomic(file_path, contents.as_ref())
+        .and_then(|_| sidecar::update_sidecar(file_path))
+        .context(Operation::Write, file_path)
 }
 
 /// This function creates an empty file at `file_path`.
 /// This will truncate an existing file at `file_path` if `truncate == true`.
-pub fn create_file(file_path: &str, truncate: bool) -> io::Result<()> {
-    if Path::new(file_path).exists() && !truncate {
+pub fn create_file<P: AsRef<Path>>(file_path: P, truncate: bool) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    if file_path.exists() && !truncate {
         // If the file exists and we do not want to truncate, do nothing.
         Ok(())
     } else {
         // Otherwise, just create the file. It will be truncated if it already exists.
-        File::create(file_path)?;
+        File::create(file_path).context(Operation::Create, file_path)?;
         Ok(())
     }
 }
 
+/// Create an empty file at `file_path` if it does not exist, or set the
+/// access and modification times of the existing file to now, like `touch`.
+pub fn touch<P: AsRef<Path>>(file_path: P) -> Result<(), FileManagerError> {
+    touch_at(file_path, SystemTime::now())
+}
+
+/// Like [`touch`], setting the access and modification times to `time`.
+pub fn touch_at<P: AsRef<Path>>(file_path: P, time: SystemTime) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(file_path)
+        .context(Operation::Create, file_path)?;
+    file.set_times(FileTimes::new().set_accessed(time).set_modified(time))
+        .context(Operation::Write, file_path)
+}
+
 /// Delete file at `file_path` if it exists.
-pub fn delete_file<P: AsRef<Path>>(file_path: P) -> std::io::Result<()> {
-    if file_path.as_ref().exists() {
-        fs::remove_file(file_path)?;
+pub fn delete_file<P: AsRef<Path>>(file_path: P) -> Result<(), FileManagerError> {
+    let file_path = file_path.as_ref();
+    if file_path.exists() {
+        fs::remove_file(file_path).context(Operation::Delete, file_path)?;
+    }
+    Ok(())
+}
+
+/// Safety rails for [`remove_dir_recursive_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoveGuard {
+    /// Refuse directories with fewer components than this below the filesystem
+    /// root, after resolving the path. `/` and `C:\` have depth 0, `/home` and
+    /// `C:\Users` depth 1. Defaults to 2; 0 disables the guard.
+    pub min_depth: usize,
+}
+
+impl Default for RemoveGuard {
+    fn default() -> Self {
+        RemoveGuard { min_depth: 2 }
+    }
+}
+
+/// Delete the directory at `dir_path` and everything in it, guarded by
+/// [`RemoveGuard::default`]. Symlinks inside are removed, not followed.
+pub fn remove_dir_recursive<P: AsRef<Path>>(dir_path: P) -> Result<(), FileManagerError> {
+    remove_dir_recursive_with(dir_path, &RemoveGuard::default())
+}
+
+/// Like [`remove_dir_recursive`], with the given `guard`.
+///
+/// # Errors
+/// `InvalidInput` if the guard refuses the directory.
+pub fn remove_dir_recursive_with<P: AsRef<Path>>(
+    dir_path: P,
+    guard: &RemoveGuard,
+) -> Result<(), FileManagerError> {
+    let dir_path = dir_path.as_ref();
+    if guard.min_depth > 0 {
+        // Resolve the parent only, so a symlink is judged by where it lives.
+        let resolved = match (dir_path.parent(), dir_path.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+                parent.canonicalize().map(|p| p.join(name))
+            }
+            (_, Some(name)) => std::env::current_dir().map(|p| p.join(name)),
+            _ => dir_path.canonicalize(),
+        }
+        .context(Operation::Delete, dir_path)?;
+        let depth = resolved
+            .components()
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
+            .count();
+        if depth < guard.min_depth {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "refusing to remove {}: it is only {} levels below the root",
+                    resolved.display(),
+                    depth
+                ),
+            );
+            return Err(FileManagerError::new(Operation::Delete, dir_path, e));
+        }
+    }
+    fs::remove_dir_all(dir_path).context(Operation::Delete, dir_path)
+}
+
+/// Move the file at `src` to `dst`, replacing any file there.
+///
+/// Where `src` and `dst` are on different filesystems and cannot be renamed,
+/// the file is copied to a temporary sibling of `dst`, compared with `src` by
+/// checksum and renamed into place. Only then is `src` deleted.
+pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), FileManagerError> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    if let Err(e) = fs::rename(src, dst) {
+        if e.kind() != io::ErrorKind::CrossesDevices {
+            return Err(FileManagerError::new(Operation::Move, src, e));
+        }
+        copy_file_verified(src, dst)
+            .and_then(|_| fs::remove_file(src))
+            .context(Operation::Move, src)?;
+    }
+    Ok(())
+}
+
+/// Move the directory at `src` to `dst`, which must not exist.
+///
+/// Where `src` and `dst` are on different filesystems and cannot be renamed,
+/// the tree is copied to a temporary sibling of `dst`, every file is compared
+/// with its source by checksum and the copy is renamed into place. Only then
+/// is `src` deleted; on failure it is left untouched.
+pub fn move_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), FileManagerError> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    if let Err(e) = fs::rename(src, dst) {
+        if e.kind() != io::ErrorKind::CrossesDevices {
+            return Err(FileManagerError::new(Operation::Move, src, e));
+        }
+        copy_dir_verified(src, dst)
+            .and_then(|_| fs::remove_dir_all(src))
+            .context(Operation::Move, src)?;
+    }
+    Ok(())
+}
+
+/// Helper function to copy the file at `src` over `dst` through a verified
+/// temporary sibling.
+fn copy_file_verified(src: &Path, dst: &Path) -> io::Result<()> {
+    let tmp_path = temp_sibling(dst, "move");
+    let result = copy_verified(src, &tmp_path).and_then(|_| fs::rename(&tmp_path, dst));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Helper function to copy the tree at `src` to `dst` through a verified
+/// temporary sibling.
+fn copy_dir_verified(src: &Path, dst: &Path) -> io::Result<()> {
+    let tmp_path = temp_sibling(dst, "move");
+    let result = copy_tree_verified(src, &tmp_path).and_then(|_| fs::rename(&tmp_path, dst));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&tmp_path);
+    }
+    result
+}
+
+fn copy_tree_verified(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let (from, to) = (entry.path(), dst.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree_verified(&from, &to)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&from, &to)?;
+        } else {
+            copy_verified(&from, &to)?;
+        }
+    }
+    fs::set_permissions(dst, fs::metadata(src)?.permissions())
+}
+
+/// Helper function to copy the file at `src` to `dst` with its permissions and
+/// modification time, sync it and check that the contents match.
+fn copy_verified(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::copy(src, dst)?;
+    let copy = OpenOptions::new().write(true).open(dst)?;
+    copy.set_modified(fs::metadata(src)?.modified()?)?;
+    copy.sync_all()?;
+    let expected = checksum::sha256_reader(File::open(src)?)?;
+    if checksum::sha256_reader(File::open(dst)?)? != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("copy of {} does not match the original", src.display()),
+        ));
     }
     Ok(())
 }
 
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dst)
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    if fs::metadata(src).is_ok_and(|m| m.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_symlink(src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot copy symlink {} on this platform", src.display()),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,7 +915,7 @@ mod tests {
         let result = open_file(file_path);
 
         // assert
-        assert!(result.is_some())
+        assert!(result.is_ok())
     }
 
     #[test]
@@ -140,7 +927,7 @@ mod tests {
         let result = open_file(file_path);
 
         // assert
-        assert!(result.is_none())
+        assert!(result.is_err())
     }
 
     #[test]
@@ -165,7 +952,7 @@ mod tests {
         // act
         let result = write_to_file(file_path, true, content);
         let mut parsed_content = String::new();
-        if let Some(mut file) = open_file(file_path) {
+        if let Ok(mut file) = open_file(file_path) {
             let _ = file.read_to_string(&mut parsed_content);
         }
 
@@ -269,4 +1056,413 @@ mod tests {
         assert_eq!(Some(first_line.to_owned()), lines.next());
         assert_eq!(Some(second_line.to_owned()), lines.next());
     }
+
+    #[test]
+    fn copy_stream_works() {
+        // arrange
+        let from = "assets/copy_stream_from.txt";
+        let to = "assets/copy_stream_to.txt";
+        fs::write(from, "streamed").unwrap();
+        fs::write(to, "previous contents").unwrap();
+
+        // act
+        let copied = copy_stream(from, to).unwrap();
+        let mut copy = String::new();
+        open_input(to).unwrap().read_to_string(&mut copy).unwrap();
+        let _ = delete_file(from);
+        let _ = delete_file(to);
+
+        // assert
+        assert_eq!(8, copied);
+        assert_eq!("streamed", copy);
+        assert!(is_stdio_path("-"));
+        assert!(!is_stdio_path("./-"));
+    }
+
+    #[test]
+    fn path_arguments_accept_path_types() {
+        // arrange
+        let file_path = std::path::PathBuf::from("assets/path_arguments_test.txt");
+
+        // act
+        let written = write_to_file(&file_path, true, "one");
+        let appended = append_to_file(file_path.as_path(), "two");
+        let opened = open_file(file_path.clone().into_os_string());
+
+        // assert
+        assert!(written.is_ok() && appended.is_ok() && opened.is_ok());
+        delete_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn errors_carry_path_and_operation() {
+        // arrange
+        let file_path = "assets/missing_dir/errors_test.txt";
+
+        // act
+        let opened = open_file(file_path).unwrap_err();
+        let written = write_to_file(file_path, true, "content").unwrap_err();
+        let converted: io::Error = written.into();
+
+        // assert
+        assert_eq!(Operation::Open, opened.operation());
+        assert_eq!(Some(Path::new(file_path)), opened.path());
+        assert_eq!(io::ErrorKind::NotFound, opened.kind());
+        assert!(converted
+            .to_string()
+            .starts_with(&format!("cannot open {}", file_path)));
+        assert_eq!(io::ErrorKind::NotFound, converted.kind());
+    }
+
+    #[test]
+    fn read_file_helpers_work() {
+        // arrange
+        let file_path = "assets/read_file_test.txt";
+        fs::write(file_path, b"caf\xc3\xa9").unwrap();
+        let invalid_path = "assets/read_file_invalid_test.bin";
+        fs::write(invalid_path, b"\xff\xfe").unwrap();
+
+        // act
+        let text = read_file_to_string(file_path).unwrap();
+        let bytes = read_file_to_bytes(invalid_path).unwrap();
+        let invalid = read_file_to_string(invalid_path).unwrap_err();
+        let missing = read_file_to_bytes("assets/read_file_missing.txt").unwrap_err();
+        let _ = delete_file(file_path);
+        let _ = delete_file(invalid_path);
+
+        // assert
+        assert_eq!("café", text);
+        assert_eq!(vec![0xff, 0xfe], bytes);
+        assert_eq!(
+            (Operation::Read, io::ErrorKind::InvalidData),
+            (invalid.operation(), invalid.kind())
+        );
+        assert_eq!(
+            (Operation::Open, io::ErrorKind::NotFound),
+            (missing.operation(), missing.kind())
+        );
+    }
+
+    #[test]
+    fn line_readers_stream_lines() {
+        // arrange
+        let file_path = "assets/read_lines_test.log";
+        fs::write(
+            file_path,
+            "INFO start\r\nERROR disk full\nINFO retry\nERROR gone",
+        )
+        .unwrap();
+
+        // act
+        let lines: Vec<String> = read_lines(file_path).unwrap().map(Result::unwrap).collect();
+        let mut seen = 0;
+        let first_error = for_each_line(file_path, |line| {
+            seen += 1;
+            match line.strip_prefix("ERROR ") {
+                Some(message) => ControlFlow::Break(message.to_owned()),
+                None => ControlFlow::Continue(()),
+            }
+        })
+        .unwrap();
+        let _ = delete_file(file_path);
+
+        // assert
+        assert_eq!(
+            vec!["INFO start", "ERROR disk full", "INFO retry", "ERROR gone"],
+            lines
+        );
+        assert_eq!(Some("disk full".to_owned()), first_error);
+        assert_eq!(2, seen);
+    }
+
+    #[test]
+    fn write_to_file_atomic_works() {
+        // arrange
+        let file_path = "assets/write_atomic_test.txt";
+        fs::write(file_path, "old contents").unwrap();
+
+        // act
+        let result = write_to_file_atomic(file_path, "new");
+        let missing_dir = write_to_file_atomic("assets/missing_dir/atomic.txt", b"x");
+        let contents = read_file_to_string(file_path).unwrap();
+        let leftovers = fs::read_dir("assets")
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy()
+                    .starts_with(".write_atomic_test.txt.tmp")
+            })
+            .count();
+        let _ = delete_file(file_path);
+
+        // assert
+        assert!(result.is_ok());
+        assert_eq!("new", contents);
+        assert_eq!(0, leftovers);
+        assert_eq!(io::ErrorKind::NotFound, missing_dir.unwrap_err().kind());
+    }
+
+    #[test]
+    fn remove_dir_recursive_respects_guard() {
+        // arrange
+        let dir = "assets/remove_dir_recursive_test";
+        fs::create_dir_all(format!("{}/nested/deeper", dir)).unwrap();
+        fs::write(format!("{}/nested/deeper/file.txt", dir), "x").unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let root = cwd.ancestors().last().unwrap();
+        let strict = RemoveGuard { min_depth: 64 };
+
+        // act
+        let refused_root = remove_dir_recursive(root).unwrap_err();
+        let refused_deep = remove_dir_recursive_with(dir, &strict).unwrap_err();
+        let removed = remove_dir_recursive(dir);
+
+        // assert
+        assert_eq!(io::ErrorKind::InvalidInput, refused_root.kind());
+        assert_eq!(io::ErrorKind::InvalidInput, refused_deep.kind());
+        assert!(removed.is_ok());
+        assert!(!Path::new(dir).exists());
+    }
+
+    #[test]
+    fn move_file_and_dir_work() {
+        // arrange
+        let dir = "assets/move_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/src/nested", dir)).unwrap();
+        fs::write(format!("{}/src/nested/a.txt", dir), "a").unwrap();
+        fs::write(format!("{}/file.txt", dir), "moved").unwrap();
+        let path = |name: &str| Path::new(dir).join(name);
+        let read = |name: &str| fs::read_to_string(path(name)).unwrap();
+        let modified = |name: &str| fs::metadata(path(name)).unwrap().modified().unwrap();
+
+        // act
+        move_file(path("file.txt"), path("renamed.txt")).unwrap();
+        move_dir(path("src"), path("moved")).unwrap();
+        // The fallback used across filesystems.
+        copy_dir_verified(&path("moved"), &path("copied")).unwrap();
+        let missing = move_file(path("file.txt"), path("other.txt")).unwrap_err();
+
+        // assert
+        assert_eq!("moved", read("renamed.txt"));
+        assert!(!path("src").exists());
+        assert_eq!("a", read("moved/nested/a.txt"));
+        assert_eq!("a", read("copied/nested/a.txt"));
+        assert_eq!(
+            modified("moved/nested/a.txt"),
+            modified("copied/nested/a.txt")
+        );
+        assert_eq!(Operation::Move, missing.operation());
+        assert_eq!(io::ErrorKind::NotFound, missing.kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_file_with_progress_reports_chunks() {
+        // arrange
+        let from = "assets/copy_progress_from.bin";
+        let to = "assets/copy_progress_to.bin";
+        fs::write(from, vec![7; 10]).unwrap();
+        let options = CopyOptions { chunk_size: 4 };
+        let mut reports = Vec::new();
+
+        // act
+        let copied = copy_file_with_options(from, to, &options, |copied, total| {
+            reports.push((copied, total))
+        })
+        .unwrap();
+        let copy = fs::read(to).unwrap();
+        let missing = copy_file_with_progress("assets/copy_progress_missing.bin", to, |_, _| {});
+        let _ = delete_file(from);
+        let _ = delete_file(to);
+
+        // assert
+        assert_eq!(10, copied);
+        assert_eq!(vec![7; 10], copy);
+        assert_eq!(vec![(0, 10), (4, 10), (8, 10), (10, 10)], reports);
+        assert_eq!(Operation::Open, missing.unwrap_err().operation());
+    }
+
+    #[test]
+    fn read_consistent_returns_whole_file() {
+        // arrange
+        let file_path = "assets/read_consistent_test.log";
+        fs::write(file_path, "line 1\nline 2\n").unwrap();
+        let copy_first = ConsistentReadOptions {
+            copy_first: true,
+            ..ConsistentReadOptions::default()
+        };
+
+        // act
+        let direct = read_consistent(file_path).unwrap();
+        let copied = read_consistent_with(file_path, &copy_first).unwrap();
+        let missing = read_consistent("assets/read_consistent_missing.log").unwrap_err();
+
+        // assert
+        assert_eq!(b"line 1\nline 2\n", &direct[..]);
+        assert_eq!(direct, copied);
+        assert_eq!(Operation::Open, missing.operation());
+        fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn touch_creates_or_updates_times() {
+        // arrange
+        let file_path = "assets/touch_test.txt";
+        let _ = fs::remove_file(file_path);
+        let epoch = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+
+        // act
+        touch(file_path).unwrap();
+        let created = fs::metadata(file_path).unwrap();
+        fs::write(file_path, "kept").unwrap();
+        touch_at(file_path, epoch).unwrap();
+        let touched = fs::metadata(file_path).unwrap();
+
+        // assert
+        assert_eq!(0, created.len());
+        assert_eq!(epoch, touched.modified().unwrap());
+        assert_eq!(epoch, touched.accessed().unwrap());
+        assert_eq!("kept", fs::read_to_string(file_path).unwrap());
+        fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn gzip_reader_reads_what_gzip_writer_wrote() {
+        // arrange
+        let file_path = "assets/gzip_test.log.gz";
+        let _ = fs::remove_file(file_path);
+        let mut writer = open_gzip_writer(file_path).unwrap();
+        writeln!(writer, "first").unwrap();
+        writeln!(writer, "second").unwrap();
+        writer.finish().unwrap().flush().unwrap();
+        let mut appended = GzEncoder::new(Vec::new(), Compression::default());
+        writeln!(appended, "third").unwrap();
+        open_buffered_file_appender(file_path)
+            .unwrap()
+            .write_all(&appended.finish().unwrap())
+            .unwrap();
+
+        // act
+        let lines: Vec<String> = open_gzip_reader(file_path)
+            .unwrap()
+            .lines()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let plain = open_gzip_reader("Cargo.toml").unwrap().lines().next();
+
+        // assert
+        assert_eq!(vec!["first", "second", "third"], lines);
+        assert!(plain.unwrap().is_err());
+        fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn open_auto_detects_compression() {
+        // arrange
+        let dir = "assets/open_auto_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"compressed\n").unwrap();
+        fs::write(format!("{}/a.log.gz", dir), gzip.finish().unwrap()).unwrap();
+        fs::write(format!("{}/b.log", dir), "plain\n").unwrap();
+        fs::write(format!("{}/empty.log", dir), "").unwrap();
+        let read = |name: &str| {
+            let mut contents = String::new();
+            open_auto(format!("{}/{}", dir, name))
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        // act
+        let compressed = read("a.log.gz");
+        let plain = read("b.log");
+        let empty = read("empty.log");
+
+        // assert
+        assert_eq!("compressed\n", compressed);
+        assert_eq!("plain\n", plain);
+        assert_eq!("", empty);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_helpers_round_trip() {
+        // arrange
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Settings {
+            name: String,
+            retries: u32,
+        }
+        let dir = "assets/json_helpers_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let settings = Settings {
+            name: "backup".to_owned(),
+            retries: 3,
+        };
+        let (pretty, compact) = (
+            format!("{}/pretty.json", dir),
+            format!("{}/compact.json", dir),
+        );
+
+        // act
+        write_json(&pretty, &settings, true).unwrap();
+        write_json(&compact, &settings, false).unwrap();
+        let read: Settings = read_json(&pretty).unwrap();
+        let asset: std::collections::BTreeMap<String, String> =
+            read_json("assets/test.json").unwrap();
+        let invalid = read_json::<Settings, _>("assets/test.json").unwrap_err();
+
+        // assert
+        assert_eq!(settings, read);
+        assert_eq!(
+            "{\"name\":\"backup\",\"retries\":3}\n",
+            fs::read_to_string(&compact).unwrap()
+        );
+        assert!(fs::read_to_string(&pretty)
+            .unwrap()
+            .contains("\n  \"retries\": 3"));
+        assert_eq!("some dummy json", asset["test"]);
+        assert_eq!(io::ErrorKind::InvalidData, invalid.kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_to_file_atomic_from_threads_works() {
+        // arrange
+        let dir = "assets/write_atomic_threads_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let file_path = format!("{}/shared.txt", dir);
+        let contents = |i: usize| format!("writer {} ", i).repeat(1000);
+
+        // act
+        let results = std::thread::scope(|scope| {
+            let writers = (0..8)
+                .map(|i| {
+                    let (file_path, contents) = (&file_path, contents(i));
+                    scope.spawn(move || {
+                        (0..20).try_for_each(|_| write_to_file_atomic(file_path, &contents))
+                    })
+                })
+                .collect::<Vec<_>>();
+            writers
+                .into_iter()
+                .map(|writer| writer.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let written = fs::read_to_string(&file_path).unwrap();
+        let entries = fs::read_dir(dir).unwrap().count();
+
+        // assert
+        assert!(results.iter().all(Result::is_ok));
+        assert!((0..8).any(|i| written == contents(i)));
+        assert_eq!(1, entries);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
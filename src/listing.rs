@@ -0,0 +1,521 @@
+//! Machine-readable directory inventories.
+use crate::{checksum, json::Value};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Output format of an exported listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    /// A JSON array with one object per entry.
+    Json,
+    /// CSV with a `path,type,size,mtime,sha256` header row.
+    Csv,
+}
+
+/// The type of a listed entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl ListingKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ListingKind::File => "file",
+            ListingKind::Dir => "dir",
+            ListingKind::Symlink => "symlink",
+        }
+    }
+
+    fn parse(s: &str) -> io::Result<Self> {
+        match s {
+            "file" => Ok(ListingKind::File),
+            "dir" => Ok(ListingKind::Dir),
+            "symlink" => Ok(ListingKind::Symlink),
+            _ => Err(invalid_listing(format!("unknown entry type `{}`", s))),
+        }
+    }
+}
+
+/// One entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingEntry {
+    /// Path relative to the listed root, using `/` as the separator.
+    pub path: String,
+    pub kind: ListingKind,
+    /// Size in bytes (zero for directories).
+    pub size: u64,
+    /// Modification time in seconds since the unix epoch.
+    pub mtime: u64,
+    /// Hex encoded SHA-256 of a file's contents, when requested.
+    pub sha256: Option<String>,
+}
+
+/// Recursively list everything under `root` in sorted order.
+/// Symlinks are listed but not followed.
+/// If `include_hashes == true`, the SHA-256 of every file is computed as well.
+pub fn list_tree<P: AsRef<Path>>(root: P, include_hashes: bool) -> io::Result<Vec<ListingEntry>> {
+    let mut entries = Vec::new();
+    list_dir(root.as_ref(), "", include_hashes, &mut entries)?;
+    Ok(entries)
+}
+
+fn list_dir(
+    dir: &Path,
+    prefix: &str,
+    include_hashes: bool,
+    entries: &mut Vec<ListingEntry>,
+) -> io::Result<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|e| e.file_name());
+
+    for child in children {
+        let child_path = child.path();
+        let name = child.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("cannot list non UTF-8 path {}", child_path.display()),
+            )
+        })?;
+        let path = format!("{}{}", prefix, name);
+        let metadata = fs::symlink_metadata(&child_path)?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_dir() {
+            ListingKind::Dir
+        } else if file_type.is_symlink() {
+            ListingKind::Symlink
+        } else {
+            ListingKind::File
+        };
+        let sha256 = if include_hashes && kind == ListingKind::File {
+            Some(checksum::sha256_reader(File::open(&child_path)?)?)
+        } else {
+            None
+        };
+
+        entries.push(ListingEntry {
+            path: path.clone(),
+            kind,
+            size: if kind == ListingKind::Dir {
+                0
+            } else {
+                metadata.len()
+            },
+            mtime,
+            sha256,
+        });
+        if kind == ListingKind::Dir {
+            list_dir(&child_path, &format!("{}/", path), include_hashes, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write an inventory of everything under `root` to `writer` in the given `format`.
+/// Each entry has its path relative to `root`, type, size and modification time.
+pub fn export_listing<P: AsRef<Path>, W: Write>(
+    root: P,
+    format: ListingFormat,
+    writer: W,
+) -> io::Result<()> {
+    write_listing(&list_tree(root, false)?, format, writer)
+}
+
+/// Like [`export_listing`], but also includes the SHA-256 of every file.
+pub fn export_listing_with_hashes<P: AsRef<Path>, W: Write>(
+    root: P,
+    format: ListingFormat,
+    writer: W,
+) -> io::Result<()> {
+    write_listing(&list_tree(root, true)?, format, writer)
+}
+
+/// Write already collected `entries` to `writer` in the given `format`.
+pub fn write_listing<W: Write>(
+    entries: &[ListingEntry],
+    format: ListingFormat,
+    mut writer: W,
+) -> io::Result<()> {
+    match format {
+        ListingFormat::Json => {
+            writer.write_all(b"[")?;
+            for (i, entry) in entries.iter().enumerate() {
+                let mut fields = vec![
+                    ("path".to_owned(), Value::from(entry.path.as_str())),
+                    ("type".to_owned(), Value::from(entry.kind.as_str())),
+                    ("size".to_owned(), Value::from(entry.size)),
+                    ("mtime".to_owned(), Value::from(entry.mtime)),
+                ];
+                if let Some(hash) = &entry.sha256 {
+                    fields.push(("sha256".to_owned(), Value::from(hash.as_str())));
+                }
+                let separator = if i == 0 { "" } else { "," };
+                write!(writer, "{}\n  {}", separator, Value::Object(fields))?;
+            }
+            writer.write_all(if entries.is_empty() { b"]\n" } else { b"\n]\n" })?;
+        }
+        ListingFormat::Csv => {
+            writer.write_all(b"path,type,size,mtime,sha256\n")?;
+            for entry in entries {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    csv_field(&entry.path),
+                    entry.kind.as_str(),
+                    entry.size,
+                    entry.mtime,
+                    entry.sha256.as_deref().unwrap_or_default()
+                )?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Where [`import_listing_with`] gets the contents of recreated files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ContentSource {
+    /// Create every file empty.
+    #[default]
+    Empty,
+    /// Size every file as listed, filled with zeros (sparse where supported).
+    Zeros,
+    /// Copy each file from the same relative path under this directory,
+    /// falling back to an empty file when the source is missing.
+    CopyFrom(PathBuf),
+}
+
+/// Parse a listing previously written by [`export_listing`] or [`write_listing`].
+pub fn read_listing<R: Read>(
+    mut reader: R,
+    format: ListingFormat,
+) -> io::Result<Vec<ListingEntry>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    match format {
+        ListingFormat::Json => {
+            let value = Value::parse(&text)?;
+            let items = value
+                .as_array()
+                .ok_or_else(|| invalid_listing("expected a JSON array".to_owned()))?;
+            items.iter().map(json_entry).collect()
+        }
+        ListingFormat::Csv => {
+            let mut rows = parse_csv(&text).into_iter();
+            match rows.next() {
+                Some(header) if header.first().map(String::as_str) == Some("path") => {}
+                _ => return Err(invalid_listing("missing CSV header row".to_owned())),
+            }
+            rows.map(|row| csv_entry(&row)).collect()
+        }
+    }
+}
+
+fn json_entry(item: &Value) -> io::Result<ListingEntry> {
+    let field = |name: &str| {
+        item.get(name)
+            .ok_or_else(|| invalid_listing(format!("entry is missing `{}`", name)))
+    };
+    let number = |name: &str| {
+        field(name)?
+            .as_u64()
+            .ok_or_else(|| invalid_listing(format!("`{}` must be a number", name)))
+    };
+    let string = |name: &str| {
+        field(name)?
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| invalid_listing(format!("`{}` must be a string", name)))
+    };
+    Ok(ListingEntry {
+        path: string("path")?,
+        kind: ListingKind::parse(&string("type")?)?,
+        size: number("size")?,
+        mtime: number("mtime")?,
+        sha256: item
+            .get("sha256")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+    })
+}
+
+fn csv_entry(row: &[String]) -> io::Result<ListingEntry> {
+    if row.len() != 5 {
+        return Err(invalid_listing(format!(
+            "expected 5 CSV fields, found {}",
+            row.len()
+        )));
+    }
+    let number = |field: &str| {
+        field
+            .parse::<u64>()
+            .map_err(|_| invalid_listing(format!("invalid number `{}`", field)))
+    };
+    Ok(ListingEntry {
+        path: row[0].clone(),
+        kind: ListingKind::parse(&row[1])?,
+        size: number(&row[2])?,
+        mtime: number(&row[3])?,
+        sha256: Some(row[4].clone()).filter(|h| !h.is_empty()),
+    })
+}
+
+/// Split CSV text into rows of fields, honouring quoted fields with embedded line breaks.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Recreate the directories and files described by `listing` under `dest`, with every file empty.
+/// See [`import_listing_with`].
+pub fn import_listing<P: AsRef<Path>>(listing: &[ListingEntry], dest: P) -> io::Result<usize> {
+    import_listing_with(listing, dest, &ContentSource::Empty)
+}
+
+/// Recreate the directories and files described by `listing` under `dest`,
+/// filling files from `source` and restoring their modification times.
+/// Symlinks are skipped since listings do not record their targets.
+/// Files that already exist are left untouched.
+///
+/// # Returns
+/// The number of directories and files created.
+pub fn import_listing_with<P: AsRef<Path>>(
+    listing: &[ListingEntry],
+    dest: P,
+    source: &ContentSource,
+) -> io::Result<usize> {
+    let dest = dest.as_ref();
+    for entry in listing {
+        let valid = !entry.path.is_empty()
+            && Path::new(&entry.path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !valid {
+            return Err(invalid_listing(format!("unsafe path `{}`", entry.path)));
+        }
+    }
+
+    fs::create_dir_all(dest)?;
+    let mut created = 0;
+    for entry in listing {
+        let path = listing_path(dest, &entry.path);
+        match entry.kind {
+            ListingKind::Symlink => continue,
+            ListingKind::Dir => {
+                if !path.is_dir() {
+                    fs::create_dir_all(&path)?;
+                    created += 1;
+                }
+            }
+            ListingKind::File => {
+                if path.exists() {
+                    continue;
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = match source {
+                    ContentSource::CopyFrom(root) if listing_path(root, &entry.path).is_file() => {
+                        fs::copy(listing_path(root, &entry.path), &path)?;
+                        OpenOptions::new().write(true).open(&path)?
+                    }
+                    _ => {
+                        let file = OpenOptions::new()
+                            .write(true)
+                            .create_new(true)
+                            .open(&path)?;
+                        if *source == ContentSource::Zeros {
+                            file.set_len(entry.size)?;
+                        }
+                        file
+                    }
+                };
+                file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime))?;
+                created += 1;
+            }
+        }
+    }
+    Ok(created)
+}
+
+/// Join a listing path onto `root`, converting `/` separators to the platform's.
+fn listing_path(root: &Path, path: &str) -> PathBuf {
+    path.split('/')
+        .fold(root.to_path_buf(), |p, part| p.join(part))
+}
+
+fn invalid_listing(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("invalid listing: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn setup(dir: &str) {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        fs::write(format!("{}/a,b.txt", dir), "abc").unwrap();
+        fs::write(format!("{}/sub/c.txt", dir), "").unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for file in ["a,b.txt", "sub/c.txt"] {
+            File::options()
+                .write(true)
+                .open(format!("{}/{}", dir, file))
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn export_listing_csv_works() {
+        // arrange
+        let dir = "assets/listing_csv_test";
+        setup(dir);
+        let mut out = Vec::new();
+
+        // act
+        let result = export_listing_with_hashes(dir, ListingFormat::Csv, &mut out);
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(4, lines.len());
+        assert_eq!("path,type,size,mtime,sha256", lines[0]);
+        assert_eq!(
+            "\"a,b.txt\",file,3,1700000000,\
+             ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            lines[1]
+        );
+        assert!(lines[2].starts_with("sub,dir,0,"));
+        assert!(lines[3].starts_with("sub/c.txt,file,0,1700000000,e3b0"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn export_listing_json_works() {
+        // arrange
+        let dir = "assets/listing_json_test";
+        setup(dir);
+        let mut out = Vec::new();
+
+        // act
+        let result = export_listing(dir, ListingFormat::Json, &mut out);
+        let parsed = Value::parse(&String::from_utf8(out).unwrap()).unwrap();
+
+        // assert
+        assert!(result.is_ok());
+        let Value::Array(items) = parsed else {
+            panic!("expected an array");
+        };
+        assert_eq!(3, items.len());
+        assert_eq!(
+            Some("sub/c.txt"),
+            items[2].get("path").and_then(Value::as_str)
+        );
+        assert_eq!(Some("dir"), items[1].get("type").and_then(Value::as_str));
+        assert_eq!(None, items[0].get("sha256"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn import_listing_recreates_exported_tree() {
+        // arrange
+        let dir = "assets/listing_import_src_test";
+        let dest = "assets/listing_import_dest_test";
+        setup(dir);
+        let _ = fs::remove_dir_all(dest);
+        let mut exported = Vec::new();
+        export_listing(dir, ListingFormat::Csv, &mut exported).unwrap();
+
+        // act
+        let listing = read_listing(exported.as_slice(), ListingFormat::Csv).unwrap();
+        let result = import_listing_with(&listing, dest, &ContentSource::Zeros);
+
+        // assert
+        // Directory mtimes are not restored, so only compare them for files.
+        let summary = |root: &str| {
+            list_tree(root, false)
+                .unwrap()
+                .into_iter()
+                .map(|e| {
+                    let mtime = (e.kind == ListingKind::File).then_some(e.mtime);
+                    (e.path, e.kind, e.size, mtime)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(3, result.unwrap());
+        assert_eq!(summary(dir), summary(dest));
+        assert_eq!(vec![0; 3], fs::read(format!("{}/a,b.txt", dest)).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn import_listing_rejects_unsafe_paths() {
+        // arrange
+        let listing = read_listing(
+            &br#"[{"path": "../escape.txt", "type": "file", "size": 0, "mtime": 0}]"#[..],
+            ListingFormat::Json,
+        )
+        .unwrap();
+
+        // act
+        let result = import_listing(&listing, "assets/listing_unsafe_test");
+
+        // assert
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+        assert!(!Path::new("assets/escape.txt").exists());
+    }
+}
@@ -0,0 +1,528 @@
+//! Cross-process advisory locks, on named lock files or on the files themselves.
+use crate::{instance::runtime_dir, write_atomic};
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How a [`NamedLock`] or [`LockedFile`] is held.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockMode {
+    /// Any number of processes may hold the lock at once, but not alongside an exclusive holder.
+    Shared,
+    /// Only one process may hold the lock.
+    #[default]
+    Exclusive,
+}
+
+/// The process holding an exclusive lock, from the metadata it recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    /// When the lock was acquired.
+    pub since: SystemTime,
+}
+
+/// A directory of lock files, one per lock name.
+///
+/// Every tool that wants to coordinate on the same names must use the same
+/// directory. [`LockRegistry::default`] uses a well-known directory for the current user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockRegistry {
+    dir: PathBuf,
+}
+
+impl Default for LockRegistry {
+    fn default() -> Self {
+        LockRegistry {
+            dir: runtime_dir().join("file-manager-locks"),
+        }
+    }
+}
+
+impl LockRegistry {
+    /// Use `dir` for lock files. It is created when the first lock is taken.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        LockRegistry {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Block until the lock called `name` is held in `mode`.
+    ///
+    /// Names may contain ASCII letters, digits, `-`, `_` and `.`, and must not start with `.`.
+    pub fn lock(&self, name: &str, mode: LockMode) -> io::Result<NamedLock> {
+        let (path, file) = self.open(name)?;
+        match mode {
+            LockMode::Shared => file.lock_shared()?,
+            LockMode::Exclusive => file.lock()?,
+        }
+        self.acquired(name, mode, path, file)
+    }
+
+    /// Take the lock called `name` in `mode` if that is possible without waiting.
+    pub fn try_lock(&self, name: &str, mode: LockMode) -> io::Result<Option<NamedLock>> {
+        let (path, file) = self.open(name)?;
+        let result = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match result {
+            Ok(()) => self.acquired(name, mode, path, file).map(Some),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// Wait up to `timeout` for the lock called `name` in `mode`.
+    ///
+    /// Fails with `TimedOut` if the lock is still unavailable, naming the
+    /// exclusive holder and how long it has held the lock when that is known.
+    pub fn lock_timeout(
+        &self,
+        name: &str,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> io::Result<NamedLock> {
+        if let Some(lock) = wait_for(timeout, || self.try_lock(name, mode))? {
+            return Ok(lock);
+        }
+        let holder = match self.holder(name)? {
+            Some(holder) => format!(
+                "held by process {} for {:?}",
+                holder.pid,
+                holder.since.elapsed().unwrap_or_default()
+            ),
+            None => "holder unknown".to_owned(),
+        };
+        Err(io::Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "timed out after {:?} waiting for lock `{}` ({})",
+                timeout, name, holder
+            ),
+        ))
+    }
+
+    /// The process holding the lock called `name` exclusively, if any.
+    ///
+    /// Returns `None` when the lock is free, only held in shared mode, or its
+    /// holder didn't record any metadata.
+    pub fn holder(&self, name: &str) -> io::Result<Option<LockHolder>> {
+        let (_, file) = self.open(name)?;
+        match file.try_lock_shared() {
+            // Not held exclusively, so any recorded owner is stale.
+            Ok(()) => return Ok(None),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        let owner = match fs::read_to_string(self.owner_path(name)) {
+            Ok(owner) => owner,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut fields = owner.split_whitespace().map(str::parse::<u64>);
+        match (fields.next(), fields.next()) {
+            (Some(Ok(pid)), Some(Ok(since))) => Ok(Some(LockHolder {
+                pid: pid as u32,
+                since: UNIX_EPOCH + Duration::from_secs(since),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Block until every lock in `names` is held in `mode`.
+    ///
+    /// The locks are always taken in sorted order, so processes locking
+    /// overlapping sets of names can't deadlock each other. Duplicate names are
+    /// locked once. The returned locks are in sorted order.
+    pub fn lock_all(&self, names: &[&str], mode: LockMode) -> io::Result<Vec<NamedLock>> {
+        let mut names = names.to_vec();
+        names.sort_unstable();
+        names.dedup();
+        for name in &names {
+            validate_name(name)?;
+        }
+        names
+            .into_iter()
+            .map(|name| self.lock(name, mode))
+            .collect()
+    }
+
+    fn open(&self, name: &str) -> io::Result<(PathBuf, File)> {
+        validate_name(name)?;
+        let path = self.dir.join(format!("{}.lock", name));
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        Ok((path, file))
+    }
+
+    /// Exclusive holders record who they are next to the lock file. It can't
+    /// go in the lock file itself because Windows locks block other readers.
+    fn owner_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.owner", name))
+    }
+
+    fn acquired(
+        &self,
+        name: &str,
+        mode: LockMode,
+        path: PathBuf,
+        file: File,
+    ) -> io::Result<NamedLock> {
+        if mode == LockMode::Exclusive {
+            let since = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let owner = format!("{} {}\n", std::process::id(), since);
+            write_atomic(&self.owner_path(name), owner.as_bytes())?;
+        }
+        Ok(NamedLock {
+            name: name.to_owned(),
+            mode,
+            path,
+            _file: file,
+        })
+    }
+}
+
+/// Helper function to retry `attempt` with backoff until it succeeds or
+/// `timeout` passes.
+fn wait_for<T>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> io::Result<Option<T>>,
+) -> io::Result<Option<T>> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(1);
+    loop {
+        if let Some(value) = attempt()? {
+            return Ok(Some(value));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(Duration::from_millis(100));
+    }
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid lock name `{}`", name),
+        ))
+    }
+}
+
+/// A held lock from a [`LockRegistry`]. It is released when dropped.
+#[derive(Debug)]
+pub struct NamedLock {
+    name: String,
+    mode: LockMode,
+    path: PathBuf,
+    // The OS lock lives as long as this handle.
+    _file: File,
+}
+
+impl NamedLock {
+    /// Block until the exclusive lock called `name` is held in the default registry.
+    pub fn lock(name: &str) -> io::Result<Self> {
+        LockRegistry::default().lock(name, LockMode::Exclusive)
+    }
+
+    /// Block until the shared lock called `name` is held in the default registry.
+    pub fn lock_shared(name: &str) -> io::Result<Self> {
+        LockRegistry::default().lock(name, LockMode::Shared)
+    }
+
+    /// Take the exclusive lock called `name` in the default registry if it is free.
+    pub fn try_lock(name: &str) -> io::Result<Option<Self>> {
+        LockRegistry::default().try_lock(name, LockMode::Exclusive)
+    }
+
+    /// Wait up to `timeout` for the exclusive lock called `name` in the default registry.
+    pub fn lock_exclusive_timeout(name: &str, timeout: Duration) -> io::Result<Self> {
+        LockRegistry::default().lock_timeout(name, LockMode::Exclusive, timeout)
+    }
+
+    /// Wait up to `timeout` for the shared lock called `name` in the default registry.
+    pub fn lock_shared_timeout(name: &str, timeout: Duration) -> io::Result<Self> {
+        LockRegistry::default().lock_timeout(name, LockMode::Shared, timeout)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// The lock file backing this lock.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A file held under an advisory lock (`flock` on Unix, `LockFileEx` on
+/// Windows) until dropped.
+///
+/// Advisory locks only exclude other lockers, so every process writing the
+/// file must go through `LockedFile`. Shared locks open the file for reading;
+/// exclusive locks open it for reading and writing, creating it if needed.
+///
+/// ```no_run
+/// use file_manager::lock::LockedFile;
+/// use std::io::Write;
+///
+/// let mut log = LockedFile::append("events.log")?;
+/// writeln!(log, "job finished")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct LockedFile {
+    path: PathBuf,
+    mode: LockMode,
+    file: File,
+}
+
+impl LockedFile {
+    /// Block until the file at `path` is locked in `mode`.
+    pub fn lock<P: AsRef<Path>>(path: P, mode: LockMode) -> io::Result<Self> {
+        let file = open_for(path.as_ref(), mode)?;
+        match mode {
+            LockMode::Shared => file.lock_shared()?,
+            LockMode::Exclusive => file.lock()?,
+        }
+        Ok(LockedFile::locked(path.as_ref(), mode, file))
+    }
+
+    /// Lock the file at `path` in `mode` if that is possible without waiting.
+    pub fn try_lock<P: AsRef<Path>>(path: P, mode: LockMode) -> io::Result<Option<Self>> {
+        let file = open_for(path.as_ref(), mode)?;
+        let result = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match result {
+            Ok(()) => Ok(Some(LockedFile::locked(path.as_ref(), mode, file))),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// Wait up to `timeout` to lock the file at `path` in `mode`.
+    ///
+    /// # Errors
+    /// `TimedOut` if the file is still locked by someone else.
+    pub fn lock_timeout<P: AsRef<Path>>(
+        path: P,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        match wait_for(timeout, || LockedFile::try_lock(path, mode))? {
+            Some(locked) => Ok(locked),
+            None => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "timed out after {:?} waiting to lock {}",
+                    timeout,
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    /// Block until the file at `path` is locked exclusively, opened for
+    /// appending. Appends from processes that all lock this way never interleave.
+    pub fn append<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        file.lock()?;
+        Ok(LockedFile::locked(path, LockMode::Exclusive, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// The locked file, for operations `LockedFile` doesn't forward.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Release the lock now, reporting any error. Dropping releases it too.
+    pub fn unlock(self) -> io::Result<()> {
+        self.file.unlock()
+    }
+
+    fn locked(path: &Path, mode: LockMode, file: File) -> Self {
+        LockedFile {
+            path: path.to_path_buf(),
+            mode,
+            file,
+        }
+    }
+}
+
+impl Read for LockedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for LockedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for LockedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+fn open_for(path: &Path, mode: LockMode) -> io::Result<File> {
+    match mode {
+        LockMode::Shared => File::open(path),
+        LockMode::Exclusive => OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn lock_registry_works() {
+        // arrange
+        let dir = "assets/lock_registry_test";
+        let _ = fs::remove_dir_all(dir);
+        let registry = LockRegistry::new(dir);
+
+        // act
+        let shared = registry
+            .lock_all(&["data", "config", "data"], LockMode::Shared)
+            .unwrap();
+        let also_shared = registry.lock("config", LockMode::Shared).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let waiter = {
+            let registry = registry.clone();
+            thread::spawn(move || {
+                let lock = registry.lock("config", LockMode::Exclusive).unwrap();
+                sender.send(lock.mode()).unwrap();
+            })
+        };
+        let blocked = receiver.recv_timeout(Duration::from_millis(100));
+        let names: Vec<&str> = shared.iter().map(|lock| lock.name()).collect();
+        let names = names.join(",");
+        drop(shared);
+        drop(also_shared);
+        let acquired = receiver.recv_timeout(Duration::from_secs(5));
+        waiter.join().unwrap();
+        let invalid = registry.lock("../escape", LockMode::Exclusive);
+
+        // assert
+        assert!(blocked.is_err());
+        assert_eq!("config,data", names);
+        assert_eq!(Ok(LockMode::Exclusive), acquired);
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lock_timeout_reports_holder() {
+        // arrange
+        let dir = "assets/lock_timeout_test";
+        let _ = fs::remove_dir_all(dir);
+        let registry = LockRegistry::new(dir);
+        let held = registry.lock("jobs", LockMode::Exclusive).unwrap();
+
+        // act
+        let tried = registry.try_lock("jobs", LockMode::Shared).unwrap();
+        let timed_out =
+            registry.lock_timeout("jobs", LockMode::Exclusive, Duration::from_millis(50));
+        let holder = registry.holder("jobs").unwrap();
+        drop(held);
+        let released = registry.holder("jobs").unwrap();
+        let retried = registry.try_lock("jobs", LockMode::Exclusive).unwrap();
+
+        // assert
+        assert!(tried.is_none());
+        let e = timed_out.unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, e.kind());
+        assert!(e.to_string().contains(&std::process::id().to_string()));
+        assert_eq!(Some(std::process::id()), holder.map(|h| h.pid));
+        assert!(released.is_none());
+        assert!(retried.is_some());
+        drop(retried);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn locked_file_excludes_other_lockers() {
+        // arrange
+        let dir = "assets/lock_file_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/events.log", dir);
+
+        // act
+        let mut appender = LockedFile::append(&path).unwrap();
+        writeln!(appender, "first").unwrap();
+        let tried = LockedFile::try_lock(&path, LockMode::Shared).unwrap();
+        let timed_out =
+            LockedFile::lock_timeout(&path, LockMode::Exclusive, Duration::from_millis(20));
+        appender.unlock().unwrap();
+        let mut reader = LockedFile::lock(&path, LockMode::Shared).unwrap();
+        let other_reader = LockedFile::try_lock(&path, LockMode::Shared).unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+
+        // assert
+        assert!(tried.is_none());
+        assert_eq!(ErrorKind::TimedOut, timed_out.unwrap_err().kind());
+        assert!(other_reader.is_some());
+        assert_eq!("first\n", contents);
+        drop((reader, other_reader));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+//! Basic metadata of image and audio files for listings.
+use crate::listing::{list_tree, ListingEntry, ListingKind};
+use std::{
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// Metadata read from the headers of a media file. Fields the format does
+/// not carry are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaInfo {
+    /// The MIME type of the detected format.
+    pub format: &'static str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// When a photo was taken according to its EXIF data, as recorded:
+    /// `YYYY:MM:DD HH:MM:SS` in the camera's local time.
+    pub taken_at: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// A [`ListingEntry`] extended with media metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub entry: ListingEntry,
+    /// `None` for directories, symlinks and files in formats not understood.
+    pub media: Option<MediaInfo>,
+}
+
+/// Read the media metadata of the file at `path`. PNG, GIF, BMP and JPEG
+/// (with EXIF dates) images and WAV and FLAC audio are understood.
+///
+/// # Returns
+/// `None` if the file is in none of these formats or its headers are damaged.
+pub fn media_info<P: AsRef<Path>>(path: P) -> io::Result<Option<MediaInfo>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    match file.read_exact(&mut magic) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let parsed = match &magic {
+        [0x89, b'P', b'N', b'G'] => png_info(&mut file),
+        [b'G', b'I', b'F', b'8'] => gif_info(&mut file),
+        [b'B', b'M', ..] => bmp_info(&mut file),
+        [0xff, 0xd8, 0xff, _] => jpeg_info(&mut file),
+        b"RIFF" => wav_info(&mut file),
+        b"fLaC" => flac_info(&mut file),
+        _ => return Ok(None),
+    };
+    match parsed {
+        Ok(info) => Ok(info),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Like [`list_tree`], with the [`media_info`] of every file.
+pub fn list_tree_with_media<P: AsRef<Path>>(
+    root: P,
+    include_hashes: bool,
+) -> io::Result<Vec<FileInfo>> {
+    let root = root.as_ref();
+    list_tree(root, include_hashes)?
+        .into_iter()
+        .map(|entry| {
+            let media = match entry.kind {
+                ListingKind::File => media_info(root.join(&entry.path))?,
+                _ => None,
+            };
+            Ok(FileInfo { entry, media })
+        })
+        .collect()
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid(format: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("malformed {} header", format),
+    )
+}
+
+fn image(format: &'static str, width: u32, height: u32) -> Option<MediaInfo> {
+    Some(MediaInfo {
+        format,
+        width: Some(width),
+        height: Some(height),
+        ..MediaInfo::default()
+    })
+}
+
+fn png_info(file: &mut impl Read) -> io::Result<Option<MediaInfo>> {
+    // The rest of the signature, then the IHDR chunk length and type.
+    let header: [u8; 20] = read_array(file)?;
+    if &header[8..12] != b"IHDR" {
+        return Err(invalid("PNG"));
+    }
+    let width = u32::from_be_bytes(header[12..16].try_into().unwrap());
+    let height = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    Ok(image("image/png", width, height))
+}
+
+fn gif_info(file: &mut impl Read) -> io::Result<Option<MediaInfo>> {
+    let header: [u8; 6] = read_array(file)?;
+    let width = u16::from_le_bytes([header[2], header[3]]);
+    let height = u16::from_le_bytes([header[4], header[5]]);
+    Ok(image("image/gif", width.into(), height.into()))
+}
+
+fn bmp_info(file: &mut impl Read) -> io::Result<Option<MediaInfo>> {
+    let header: [u8; 22] = read_array(file)?;
+    let width = i32::from_le_bytes(header[14..18].try_into().unwrap());
+    // Negative for top-down bitmaps.
+    let height = i32::from_le_bytes(header[18..22].try_into().unwrap());
+    Ok(image(
+        "image/bmp",
+        width.unsigned_abs(),
+        height.unsigned_abs(),
+    ))
+}
+
+/// Helper function to walk the JPEG segments up to the first frame header,
+/// picking up the EXIF date on the way.
+fn jpeg_info(file: &mut (impl Read + Seek)) -> io::Result<Option<MediaInfo>> {
+    file.seek(SeekFrom::Start(2))?;
+    let mut taken_at = None;
+    loop {
+        let [marker_start, mut marker] = read_array(file)?;
+        if marker_start != 0xff {
+            return Err(invalid("JPEG"));
+        }
+        while marker == 0xff {
+            [marker] = read_array(file)?;
+        }
+        if matches!(marker, 0x01 | 0xd0..=0xd7) {
+            continue;
+        }
+        if marker == 0xda || marker == 0xd9 {
+            // Image data without a frame header.
+            return Err(invalid("JPEG"));
+        }
+        let len = u16::from_be_bytes(read_array(file)?);
+        let len = len.checked_sub(2).ok_or_else(|| invalid("JPEG"))?;
+        match marker {
+            0xe1 if taken_at.is_none() => {
+                let mut segment = vec![0; len.into()];
+                file.read_exact(&mut segment)?;
+                if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                    taken_at = exif_date(tiff);
+                }
+            }
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let frame: [u8; 5] = read_array(file)?;
+                let height = u16::from_be_bytes([frame[1], frame[2]]);
+                let width = u16::from_be_bytes([frame[3], frame[4]]);
+                let mut info = image("image/jpeg", width.into(), height.into());
+                if let Some(info) = &mut info {
+                    info.taken_at = taken_at;
+                }
+                return Ok(info);
+            }
+            _ => {
+                file.seek(SeekFrom::Current(len.into()))?;
+            }
+        }
+    }
+}
+
+/// Helper function to find `DateTimeOriginal`, or failing that `DateTime`,
+/// in the TIFF structure of an EXIF segment.
+fn exif_date(tiff: &[u8]) -> Option<String> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let bytes = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let offset_at = |at: usize| -> Option<usize> {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        let value = if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        };
+        usize::try_from(value).ok()
+    };
+    // Returns the value offset of `tag` in the IFD at `ifd`.
+    let find_tag = |ifd: usize, tag: u16| {
+        let count = usize::from(u16_at(ifd)?);
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+            .map(|entry| entry + 8)
+    };
+    let ascii = |value: usize| {
+        let offset = offset_at(value)?;
+        let text = tiff.get(offset..offset + 19)?;
+        Some(String::from_utf8_lossy(text).into_owned())
+    };
+
+    let ifd0 = offset_at(4)?;
+    let original = find_tag(ifd0, 0x8769)
+        .and_then(offset_at)
+        .and_then(|exif_ifd| find_tag(exif_ifd, 0x9003))
+        .and_then(ascii);
+    original.or_else(|| find_tag(ifd0, 0x0132).and_then(ascii))
+}
+
+fn wav_info(file: &mut (impl Read + Seek)) -> io::Result<Option<MediaInfo>> {
+    // The RIFF size, then the form type.
+    let header: [u8; 8] = read_array(file)?;
+    if &header[4..] != b"WAVE" {
+        return Ok(None);
+    }
+    let mut byte_rate = None;
+    loop {
+        let header: [u8; 8] = read_array(file)?;
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap());
+        match &header[..4] {
+            b"fmt " => {
+                let fmt: [u8; 12] = read_array(file)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..].try_into().unwrap()));
+                file.seek(SeekFrom::Current(
+                    i64::from(size) - 12 + i64::from(size & 1),
+                ))?;
+            }
+            b"data" => {
+                let byte_rate = byte_rate.filter(|&r| r > 0).ok_or_else(|| invalid("WAV"))?;
+                return Ok(Some(MediaInfo {
+                    format: "audio/wav",
+                    duration: Some(Duration::from_secs_f64(
+                        f64::from(size) / f64::from(byte_rate),
+                    )),
+                    ..MediaInfo::default()
+                }));
+            }
+            _ => {
+                file.seek(SeekFrom::Current(i64::from(size) + i64::from(size & 1)))?;
+            }
+        }
+    }
+}
+
+fn flac_info(file: &mut impl Read) -> io::Result<Option<MediaInfo>> {
+    // The STREAMINFO block always comes first.
+    let header: [u8; 4] = read_array(file)?;
+    if header[0] & 0x7f != 0 {
+        return Err(invalid("FLAC"));
+    }
+    let info: [u8; 18] = read_array(file)?;
+    let packed = u64::from_be_bytes(info[10..18].try_into().unwrap());
+    let sample_rate = packed >> 44;
+    let samples = packed & ((1 << 36) - 1);
+    Ok(Some(MediaInfo {
+        format: "audio/flac",
+        // Zero when the encoder did not know.
+        duration: (sample_rate > 0 && samples > 0)
+            .then(|| Duration::from_secs_f64(samples as f64 / sample_rate as f64)),
+        ..MediaInfo::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A minimal JPEG with an EXIF `DateTimeOriginal` and a 640x480 frame header.
+    fn jpeg() -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        // IFD0: one entry pointing at the Exif IFD at offset 26.
+        tiff.extend([1, 0, 0x69, 0x87, 4, 0, 1, 0, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0]);
+        // Exif IFD: DateTimeOriginal, 20 ASCII bytes at offset 44.
+        tiff.extend([1, 0, 0x03, 0x90, 2, 0, 20, 0, 0, 0, 44, 0, 0, 0, 0, 0, 0, 0]);
+        tiff.extend(b"2024:05:17 09:30:00\0");
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff);
+
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend(((app1.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(app1);
+        jpeg.extend([0xff, 0xc0, 0, 11, 8, 0x01, 0xe0, 0x02, 0x80, 1, 1, 0x11, 0]);
+        jpeg.extend([0xff, 0xd9]);
+        jpeg
+    }
+
+    /// One second of 8 kHz mono 16-bit silence.
+    fn wav() -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend([2, 0, 16, 0]);
+        wav.extend(b"data");
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend(vec![0; 16000]);
+        wav
+    }
+
+    #[test]
+    fn list_tree_with_media_reads_headers() {
+        // arrange
+        let dir = "assets/media_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/photos", dir)).unwrap();
+        fs::write(format!("{}/photos/a.jpg", dir), jpeg()).unwrap();
+        fs::write(format!("{}/beep.wav", dir), wav()).unwrap();
+        fs::write(format!("{}/notes.txt", dir), "not media").unwrap();
+        fs::write(format!("{}/broken.png", dir), b"\x89PNG\r\n").unwrap();
+
+        // act
+        let infos = list_tree_with_media(dir, false).unwrap();
+        let media = |path: &str| {
+            infos
+                .iter()
+                .find(|info| info.entry.path == path)
+                .unwrap()
+                .media
+                .clone()
+        };
+
+        // assert
+        let photo = media("photos/a.jpg").unwrap();
+        assert_eq!((Some(640), Some(480)), (photo.width, photo.height));
+        assert_eq!(Some("2024:05:17 09:30:00"), photo.taken_at.as_deref());
+        assert_eq!(
+            Some(Duration::from_secs(1)),
+            media("beep.wav").unwrap().duration
+        );
+        assert_eq!(None, media("notes.txt"));
+        assert_eq!(None, media("broken.png"));
+        assert_eq!(None, media("photos"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+//! Metadata queries that report which path they failed on.
+use crate::error::{Context, FileManagerError, Operation};
+use std::{fs, path::Path, time::SystemTime};
+
+/// The commonly needed metadata of a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    /// The length in bytes; for directories, whatever the platform reports.
+    pub size: u64,
+    pub is_dir: bool,
+    pub readonly: bool,
+    /// `None` where the platform or filesystem does not record it.
+    pub modified: Option<SystemTime>,
+    /// `None` where the platform or filesystem does not record it.
+    pub created: Option<SystemTime>,
+}
+
+/// The metadata of the file or directory at `path`, following symlinks.
+pub fn file_info<P: AsRef<Path>>(path: P) -> Result<FileInfo, FileManagerError> {
+    let metadata = metadata(path.as_ref())?;
+    Ok(FileInfo {
+        size: metadata.len(),
+        is_dir: metadata.is_dir(),
+        readonly: metadata.permissions().readonly(),
+        modified: metadata.modified().ok(),
+        created: metadata.created().ok(),
+    })
+}
+
+/// The length of the file at `path` in bytes.
+pub fn file_size<P: AsRef<Path>>(path: P) -> Result<u64, FileManagerError> {
+    Ok(metadata(path.as_ref())?.len())
+}
+
+/// When the file at `path` was last modified.
+///
+/// # Errors
+/// `Unsupported` where the platform does not record modification times.
+pub fn modified_time<P: AsRef<Path>>(path: P) -> Result<SystemTime, FileManagerError> {
+    let path = path.as_ref();
+    metadata(path)?.modified().context(Operation::Stat, path)
+}
+
+/// When the file at `path` was created.
+///
+/// # Errors
+/// `Unsupported` where the platform or filesystem does not record creation
+/// times, as on many Linux filesystems before kernel 4.11.
+pub fn created_time<P: AsRef<Path>>(path: P) -> Result<SystemTime, FileManagerError> {
+    let path = path.as_ref();
+    metadata(path)?.created().context(Operation::Stat, path)
+}
+
+/// Whether the file at `path` is read-only for everyone: on Unix, whether no
+/// write permission bit is set, not whether the current user may write it.
+pub fn is_readonly<P: AsRef<Path>>(path: P) -> Result<bool, FileManagerError> {
+    Ok(metadata(path.as_ref())?.permissions().readonly())
+}
+
+/// Helper function to read the metadata of `path` with the path attached to errors.
+fn metadata(path: &Path) -> Result<fs::Metadata, FileManagerError> {
+    fs::metadata(path).context(Operation::Stat, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn metadata_helpers_agree_and_name_the_path() {
+        // arrange
+        let dir = "assets/meta_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let file = format!("{}/notes.txt", dir);
+        fs::write(&file, "twelve bytes").unwrap();
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions).unwrap();
+        let missing = format!("{}/missing.txt", dir);
+
+        // act
+        let info = file_info(&file).unwrap();
+        let size = file_size(&file).unwrap();
+        let modified = modified_time(&file).unwrap();
+        let readonly = is_readonly(&file).unwrap();
+        let dir_info = file_info(dir).unwrap();
+        let error = file_size(&missing).unwrap_err();
+
+        // assert
+        assert_eq!(12, size);
+        assert_eq!(size, info.size);
+        assert_eq!(Some(modified), info.modified);
+        assert!(readonly && info.readonly);
+        assert!(!info.is_dir && dir_info.is_dir);
+        assert_eq!(ErrorKind::NotFound, error.kind());
+        assert_eq!(Some(Path::new(&missing)), error.path());
+        assert!(error.to_string().starts_with("cannot read metadata of"));
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&file, permissions).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
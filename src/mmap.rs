@@ -0,0 +1,151 @@
+//! Read and write large files through memory mappings.
+use crate::shm::Mapping;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    ops::{Deref, DerefMut},
+    path::Path,
+    slice,
+};
+
+/// A read-only mapping of a whole file, used as a byte slice.
+///
+/// The mapping reflects the file, so if another process truncates it while
+/// mapped, reading past the new end crashes the process. Map files that are
+/// not changed underneath, such as finished indexes.
+pub struct Mmap {
+    map: Option<Mapping>,
+    len: usize,
+}
+
+// SAFETY: the mapping is only read, and unmapped when dropped.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+/// Map the file at `path` for reading.
+pub fn open_mmap<P: AsRef<Path>>(path: P) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    let len = mapped_len(&file)?;
+    let map = map(&file, len, false)?;
+    Ok(Mmap { map, len })
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.map {
+            // SAFETY: the mapping covers `len` readable bytes.
+            Some(map) => unsafe { slice::from_raw_parts(map.ptr, self.len) },
+            None => &[],
+        }
+    }
+}
+
+/// A read-write mapping of a whole file, used as a byte slice. Changes reach
+/// the file eventually, or when [`MmapMut::flush`] returns.
+///
+/// The same caveat as for [`Mmap`] applies: the file must not be truncated
+/// while mapped.
+pub struct MmapMut {
+    map: Option<Mapping>,
+    len: usize,
+    file: File,
+}
+
+// SAFETY: writes need `&mut self`, and the mapping is unmapped when dropped.
+unsafe impl Send for MmapMut {}
+unsafe impl Sync for MmapMut {}
+
+/// Map the file at `path` for reading and writing. The mapping cannot grow the
+/// file; use `File::set_len` first to make room.
+pub fn open_mmap_mut<P: AsRef<Path>>(path: P) -> io::Result<MmapMut> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = mapped_len(&file)?;
+    let map = map(&file, len, true)?;
+    Ok(MmapMut { map, len, file })
+}
+
+impl MmapMut {
+    /// Write changes back to the file and wait for them to reach the disk.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(map) = &self.map {
+            map.flush(self.len)?;
+        }
+        self.file.sync_data()
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.map {
+            // SAFETY: the mapping covers `len` readable bytes.
+            Some(map) => unsafe { slice::from_raw_parts(map.ptr, self.len) },
+            None => &[],
+        }
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &self.map {
+            // SAFETY: the mapping covers `len` writable bytes, borrowed uniquely.
+            Some(map) => unsafe { slice::from_raw_parts_mut(map.ptr, self.len) },
+            None => &mut [],
+        }
+    }
+}
+
+/// Helper function to find the length of `file` as an address space size.
+fn mapped_len(file: &File) -> io::Result<usize> {
+    let len = file.metadata()?.len();
+    usize::try_from(len).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("a file of {} bytes does not fit in memory", len),
+        )
+    })
+}
+
+/// Helper function to map `len` bytes of `file`, or nothing for an empty
+/// file, which cannot be mapped.
+fn map(file: &File, len: usize, writable: bool) -> io::Result<Option<Mapping>> {
+    if len == 0 {
+        return Ok(None);
+    }
+    Mapping::new(file, len, writable).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn mmap_reads_and_writes_through() {
+        // arrange
+        let dir = "assets/mmap_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (path, empty) = (format!("{}/index.bin", dir), format!("{}/empty.bin", dir));
+        fs::write(&path, b"header:records").unwrap();
+        fs::write(&empty, b"").unwrap();
+
+        // act
+        let mut writable = open_mmap_mut(&path).unwrap();
+        writable[..6].copy_from_slice(b"HEADER");
+        writable.flush().unwrap();
+        drop(writable);
+        let readable = open_mmap(&path).unwrap();
+        let nothing = open_mmap(&empty).unwrap();
+
+        // assert
+        assert_eq!(b"HEADER:records", &readable[..]);
+        assert_eq!(b"records", &readable[7..]);
+        assert!(nothing.is_empty());
+        assert_eq!(b"HEADER:records", fs::read(&path).unwrap().as_slice());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
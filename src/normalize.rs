@@ -0,0 +1,167 @@
+//! Bring the permissions and ownership of a whole tree in line with a spec.
+use crate::dirstream::stream_tree;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// The permissions and ownership every entry in a tree should have.
+/// `None` leaves that property alone.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionSpec {
+    /// Mode for directories, e.g. `0o755`.
+    pub dir_mode: Option<u32>,
+    /// Mode for regular files, e.g. `0o644`.
+    pub file_mode: Option<u32>,
+    /// Keep files executable that already are, by adding an execute bit
+    /// wherever `file_mode` grants read.
+    pub preserve_executable: bool,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Report what would change without changing anything.
+    pub dry_run: bool,
+}
+
+/// A change made, or that would be made, to one entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionChange {
+    pub path: PathBuf,
+    /// The permission bits before and after.
+    pub mode: Option<(u32, u32)>,
+    /// The `(uid, gid)` before and after.
+    pub owner: Option<((u32, u32), (u32, u32))>,
+}
+
+/// Apply `spec` to `root` and everything beneath it. Symlinks are left alone
+/// and not followed.
+///
+/// # Returns
+/// The entries that changed, or that would change when `spec.dry_run == true`.
+pub fn normalize_tree<P: AsRef<Path>>(
+    root: P,
+    spec: &PermissionSpec,
+) -> io::Result<Vec<PermissionChange>> {
+    let root = root.as_ref();
+    let mut changes = Vec::new();
+    let mut paths = vec![root.to_path_buf()];
+    if fs::symlink_metadata(root)?.is_dir() {
+        for entry in stream_tree(root)? {
+            paths.push(entry?.path());
+        }
+    }
+    for path in paths {
+        if let Some(change) = normalize_entry(&path, spec)? {
+            changes.push(change);
+        }
+    }
+    Ok(changes)
+}
+
+fn normalize_entry(path: &Path, spec: &PermissionSpec) -> io::Result<Option<PermissionChange>> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    let wanted_mode = if file_type.is_dir() {
+        spec.dir_mode
+    } else if file_type.is_file() {
+        spec.file_mode.map(|mode| {
+            if spec.preserve_executable && metadata.mode() & 0o111 != 0 {
+                // Each read bit (0o4 in its triad) maps to the execute bit (0o1).
+                mode | (mode & 0o444) >> 2
+            } else {
+                mode
+            }
+        })
+    } else {
+        return Ok(None);
+    };
+
+    let current_mode = metadata.mode() & 0o7777;
+    let mode = wanted_mode
+        .map(|mode| mode & 0o7777)
+        .filter(|&mode| mode != current_mode)
+        .map(|mode| (current_mode, mode));
+    let current_owner = (metadata.uid(), metadata.gid());
+    let wanted_owner = (
+        spec.uid.unwrap_or(current_owner.0),
+        spec.gid.unwrap_or(current_owner.1),
+    );
+    let owner = (wanted_owner != current_owner).then_some((current_owner, wanted_owner));
+    if mode.is_none() && owner.is_none() {
+        return Ok(None);
+    }
+
+    if !spec.dry_run {
+        // Ownership first: chown may clear setuid and setgid bits.
+        if owner.is_some() {
+            std::os::unix::fs::chown(path, spec.uid, spec.gid)?;
+        }
+        if let Some((_, mode)) = mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(Some(PermissionChange {
+        path: path.to_path_buf(),
+        mode,
+        owner,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_tree_works() {
+        // arrange
+        let dir = "assets/normalize_tree_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/bin", dir)).unwrap();
+        let (script, readme) = (format!("{}/bin/run.sh", dir), format!("{}/README", dir));
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::write(&readme, "docs").unwrap();
+        fs::set_permissions(dir, Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(&script, Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(&readme, Permissions::from_mode(0o666)).unwrap();
+        let spec = PermissionSpec {
+            dir_mode: Some(0o755),
+            file_mode: Some(0o644),
+            preserve_executable: true,
+            dry_run: true,
+            ..PermissionSpec::default()
+        };
+        let mode = |path: &str| fs::metadata(path).unwrap().mode() & 0o777;
+
+        // act
+        let planned = normalize_tree(dir, &spec).unwrap();
+        let untouched = mode(&readme);
+        let applied = normalize_tree(
+            dir,
+            &PermissionSpec {
+                dry_run: false,
+                ..spec.clone()
+            },
+        )
+        .unwrap();
+        let again = normalize_tree(dir, &spec).unwrap();
+
+        // assert
+        assert_eq!(0o666, untouched);
+        assert_eq!(planned, applied);
+        assert_eq!(
+            Some((0o700, 0o755)),
+            planned
+                .iter()
+                .find(|c| c.path == Path::new(dir))
+                .unwrap()
+                .mode
+        );
+        assert_eq!(
+            (0o755, 0o755, 0o644),
+            (mode(dir), mode(&script), mode(&readme))
+        );
+        assert!(again.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
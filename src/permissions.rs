@@ -0,0 +1,141 @@
+//! Change file permissions with one call on Unix and Windows.
+use std::{fs, io, path::Path};
+
+/// Make the file at `path` read-only, or writable again.
+///
+/// On Unix, `true` clears every write bit and `false` sets the owner's, unlike
+/// `std::fs::Permissions::set_readonly(false)`, which makes the file writable
+/// by everyone. On Windows this sets or clears the read-only attribute.
+pub fn set_readonly<P: AsRef<Path>>(path: P, readonly: bool) -> io::Result<()> {
+    Permissions::new().readonly(readonly).apply(path)
+}
+
+/// Make the file at `path` executable, or not.
+///
+/// On Unix, `true` sets the execute bit for everyone who may read the file and
+/// `false` clears every execute bit. Windows decides what runs by extension, so
+/// there this does nothing.
+pub fn set_executable<P: AsRef<Path>>(path: P, executable: bool) -> io::Result<()> {
+    Permissions::new().executable(executable).apply(path)
+}
+
+/// Builds a change of permissions, applied with [`Permissions::apply`].
+/// Only what was set is changed.
+///
+/// # Example
+/// ```no_run
+/// use file_manager::permissions::Permissions;
+///
+/// Permissions::new()
+///     .mode(0o640)
+///     .executable(true)
+///     .apply("deploy.sh")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    mode: Option<u32>,
+    readonly: Option<bool>,
+    executable: Option<bool>,
+}
+
+impl Permissions {
+    pub fn new() -> Self {
+        Permissions::default()
+    }
+
+    /// Replace the permission bits, e.g. `0o644`, before the other changes are
+    /// made. On Windows the file becomes read-only when no write bit is set.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode & 0o7777);
+        self
+    }
+
+    /// See [`set_readonly`].
+    pub fn readonly(mut self, readonly: bool) -> Self {
+        self.readonly = Some(readonly);
+        self
+    }
+
+    /// See [`set_executable`].
+    pub fn executable(mut self, executable: bool) -> Self {
+        self.executable = Some(executable);
+        self
+    }
+
+    /// Change the permissions of the file or directory at `path`.
+    pub fn apply<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let current = fs::metadata(path)?.permissions();
+        let updated = self.update(current.clone());
+        if updated != current {
+            fs::set_permissions(path, updated)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn update(&self, permissions: fs::Permissions) -> fs::Permissions {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut mode = self.mode.unwrap_or(permissions.mode() & 0o7777);
+        match self.readonly {
+            Some(true) => mode &= !0o222,
+            Some(false) => mode |= 0o200,
+            None => {}
+        }
+        match self.executable {
+            Some(true) => mode |= (mode & 0o444) >> 2,
+            Some(false) => mode &= !0o111,
+            None => {}
+        }
+        fs::Permissions::from_mode(mode)
+    }
+
+    #[cfg(not(unix))]
+    fn update(&self, mut permissions: fs::Permissions) -> fs::Permissions {
+        if let Some(mode) = self.mode {
+            permissions.set_readonly(mode & 0o222 == 0);
+        }
+        if let Some(readonly) = self.readonly {
+            // Only clears the read-only attribute on Windows.
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(readonly);
+        }
+        permissions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_round_trip() {
+        // arrange
+        let dir = "assets/permissions_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let file = format!("{}/run.sh", dir);
+        fs::write(&file, "#!/bin/sh\n").unwrap();
+
+        // act
+        Permissions::new().mode(0o640).apply(&file).unwrap();
+        set_executable(&file, true).unwrap();
+        set_readonly(&file, true).unwrap();
+        let locked = fs::metadata(&file).unwrap().permissions();
+        set_readonly(&file, false).unwrap();
+        let unlocked = fs::metadata(&file).unwrap().permissions();
+
+        // assert
+        assert!(locked.readonly());
+        assert!(!unlocked.readonly());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o550, locked.mode() & 0o777);
+            assert_eq!(0o750, unlocked.mode() & 0o777);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
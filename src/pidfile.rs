@@ -0,0 +1,171 @@
+//! PID files that keep a daemon from running twice.
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// A PID file owned by the current process. It is removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl PidFile {
+    /// Create `path` exclusively and write the current PID to it.
+    ///
+    /// If the file already exists and names a process that is still running, this
+    /// fails with `AlreadyExists`. A file left behind by a process that has exited
+    /// is treated as stale and replaced.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let pid = std::process::id();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    let written = file
+                        .write_all(format!("{}\n", pid).as_bytes())
+                        .and_then(|_| file.sync_all());
+                    if let Err(e) = written {
+                        let _ = fs::remove_file(path);
+                        return Err(e);
+                    }
+                    return Ok(PidFile {
+                        path: path.to_path_buf(),
+                        pid,
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+
+            match read_pid(path)? {
+                // Removed by its owner in the meantime; try again.
+                None => continue,
+                Some(Some(owner)) if !process_alive(owner) => remove_if_owned_by(path, owner)?,
+                Some(Some(owner)) => {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{} is held by running process {}", path.display(), owner),
+                    ))
+                }
+                Some(None) => {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{} exists but does not contain a PID", path.display()),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// The PID of the running process holding `path`, if any.
+    pub fn owner<P: AsRef<Path>>(path: P) -> io::Result<Option<u32>> {
+        Ok(read_pid(path.as_ref())?
+            .flatten()
+            .filter(|&pid| process_alive(pid)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = remove_if_owned_by(&self.path, self.pid);
+    }
+}
+
+/// Helper function to read the PID stored at `path`.
+/// Returns `None` if the file does not exist and `Some(None)` if it can't be parsed.
+fn read_pid(path: &Path) -> io::Result<Option<Option<u32>>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents.trim().parse().ok())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove `path` only if it still names `pid`, so a file that another process
+/// has just recreated is left alone.
+fn remove_if_owned_by(path: &Path, pid: u32) -> io::Result<()> {
+    if read_pid(path)? == Some(Some(pid)) {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if a process with `pid` exists.
+#[cfg(unix)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // SAFETY: signal 0 only checks whether the process exists.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // EPERM means the process exists but belongs to another user.
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Returns `true` if a process with `pid` exists.
+#[cfg(windows)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, STILL_ACTIVE},
+        System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+
+    // SAFETY: the handle is checked for null and closed before returning.
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+        let mut code = 0;
+        let ok = GetExitCodeProcess(handle, &mut code);
+        CloseHandle(handle);
+        ok != 0 && code == STILL_ACTIVE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_acquire_works() {
+        // arrange
+        let dir = "assets/pidfile_acquire_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/daemon.pid", dir);
+        // Far above any real PID, so it names a process that has exited.
+        fs::write(&path, "1073741800\n").unwrap();
+
+        // act
+        let pid_file = PidFile::acquire(&path).unwrap();
+        let second = PidFile::acquire(&path);
+        let owner = PidFile::owner(&path).unwrap();
+        drop(pid_file);
+
+        // assert
+        assert_eq!(ErrorKind::AlreadyExists, second.unwrap_err().kind());
+        assert_eq!(Some(std::process::id()), owner);
+        assert!(!Path::new(&path).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
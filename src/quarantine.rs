@@ -0,0 +1,285 @@
+//! Move suspicious files aside and restore them later.
+use crate::{counter::encode_id, write_atomic};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const RECORD_HEADER: &str = "file-manager-quarantine v1";
+
+/// A file held in quarantine, as recorded when it was moved there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantineRecord {
+    /// Identifies the file within its quarantine root.
+    pub id: String,
+    /// Where the file came from, as an absolute path.
+    pub original_path: PathBuf,
+    /// Where the file is now.
+    pub quarantined_path: PathBuf,
+    pub quarantined_at: SystemTime,
+    /// The permission bits before execute permissions were stripped, on Unix.
+    pub mode: Option<u32>,
+    pub len: u64,
+}
+
+/// Move the file at `path` into `quarantine_root`, strip its execute
+/// permissions and record where it came from, so [`restore`] can put it back.
+///
+/// Each file gets its own `<id>/` directory in the root, next to an `<id>.record`
+/// with the origin metadata. Execute permissions only exist on Unix; elsewhere
+/// the file is moved unchanged.
+pub fn quarantine<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    quarantine_root: Q,
+) -> io::Result<QuarantineRecord> {
+    let path = path.as_ref();
+    let root = quarantine_root.as_ref();
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("only regular files can be quarantined: {}", path.display()),
+        ));
+    }
+    let original_path = path.canonicalize()?;
+    let name = original_path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+
+    fs::create_dir_all(root)?;
+    // Milliseconds, as stored in the record.
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let (id, dir) = create_entry_dir(root, now)?;
+    let record = QuarantineRecord {
+        quarantined_path: dir.join(name),
+        id,
+        original_path,
+        quarantined_at: UNIX_EPOCH + Duration::from_millis(now),
+        mode: file_mode(&metadata),
+        len: metadata.len(),
+    };
+    let result = (|| {
+        write_atomic(
+            &root.join(format!("{}.record", record.id)),
+            to_record(&record)?.as_bytes(),
+        )?;
+        move_file(path, &record.quarantined_path)?;
+        if let Some(mode) = record.mode {
+            set_file_mode(&record.quarantined_path, mode & 0o666)?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(root.join(format!("{}.record", record.id)));
+        let _ = fs::remove_dir(&dir);
+        return Err(e);
+    }
+    Ok(record)
+}
+
+/// Every file currently in `quarantine_root`, oldest first.
+pub fn list_quarantined<P: AsRef<Path>>(quarantine_root: P) -> io::Result<Vec<QuarantineRecord>> {
+    let root = quarantine_root.as_ref();
+    let mut records = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "record") {
+            records.push(read_record(&path)?);
+        }
+    }
+    records.sort_by_key(|r| r.quarantined_at);
+    Ok(records)
+}
+
+/// Move the file quarantined as `id` back to where it came from, with its
+/// original permissions.
+///
+/// # Errors
+/// `AlreadyExists` if something now occupies the original path.
+pub fn restore<P: AsRef<Path>>(quarantine_root: P, id: &str) -> io::Result<PathBuf> {
+    let root = quarantine_root.as_ref();
+    let record = read_record(&root.join(format!("{}.record", id)))?;
+    if fs::symlink_metadata(&record.original_path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "cannot restore {}: it already exists",
+                record.original_path.display()
+            ),
+        ));
+    }
+    if let Some(parent) = record.original_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_file(&record.quarantined_path, &record.original_path)?;
+    if let Some(mode) = record.mode {
+        set_file_mode(&record.original_path, mode)?;
+    }
+    remove_entry(root, id)?;
+    Ok(record.original_path)
+}
+
+/// Delete the file quarantined as `id` for good.
+pub fn purge<P: AsRef<Path>>(quarantine_root: P, id: &str) -> io::Result<()> {
+    let root = quarantine_root.as_ref();
+    let record = read_record(&root.join(format!("{}.record", id)))?;
+    fs::remove_file(&record.quarantined_path)?;
+    remove_entry(root, id)
+}
+
+/// Helper function to create a fresh `<id>/` directory in `root`, with the id
+/// based on `now` in milliseconds.
+fn create_entry_dir(root: &Path, now: u64) -> io::Result<(String, PathBuf)> {
+    let mut seq = (std::process::id() & 0xff) << 8;
+    loop {
+        let id = encode_id(now << 16 | (seq & 0xffff) as u64);
+        let dir = root.join(&id);
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok((id, dir)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => seq += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn remove_entry(root: &Path, id: &str) -> io::Result<()> {
+    fs::remove_dir(root.join(id))?;
+    fs::remove_file(root.join(format!("{}.record", id)))
+}
+
+/// Helper function to rename `from` to `to`, copying across filesystems.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Err(e) = fs::rename(from, to) {
+        if e.kind() != ErrorKind::CrossesDevices {
+            return Err(e);
+        }
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+fn to_record(record: &QuarantineRecord) -> io::Result<String> {
+    // One field per line, so paths must be valid UTF-8 without line breaks.
+    let path_str = |path: &Path| match path.to_str() {
+        Some(s) if !s.contains(['\n', '\r']) => Ok(s.to_owned()),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("path cannot be recorded: {}", path.display()),
+        )),
+    };
+    let quarantined_at = record
+        .quarantined_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut contents = format!(
+        "{}\nid {}\noriginal_path {}\nquarantined_path {}\nquarantined_at_ms {}\n",
+        RECORD_HEADER,
+        record.id,
+        path_str(&record.original_path)?,
+        path_str(&record.quarantined_path)?,
+        quarantined_at.as_millis()
+    );
+    if let Some(mode) = record.mode {
+        contents.push_str(&format!("mode {}\n", mode));
+    }
+    contents.push_str(&format!("len {}\n", record.len));
+    Ok(contents)
+}
+
+fn read_record(path: &Path) -> io::Result<QuarantineRecord> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid quarantine record {}", path.display()),
+        )
+    };
+    let raw = fs::read_to_string(path)?;
+    let mut lines = raw.lines();
+    if lines.next() != Some(RECORD_HEADER) {
+        return Err(invalid());
+    }
+    let fields = lines
+        .map(|line| line.split_once(' ').ok_or_else(invalid))
+        .collect::<io::Result<BTreeMap<_, _>>>()?;
+    let string = |key: &str| fields.get(key).copied().ok_or_else(invalid);
+    let integer = |key: &str| {
+        fields
+            .get(key)
+            .map(|value| value.parse::<u64>().map_err(|_| invalid()))
+    };
+    Ok(QuarantineRecord {
+        id: string("id")?.to_owned(),
+        original_path: PathBuf::from(string("original_path")?),
+        quarantined_path: PathBuf::from(string("quarantined_path")?),
+        quarantined_at: UNIX_EPOCH
+            + Duration::from_millis(integer("quarantined_at_ms").ok_or_else(invalid)??),
+        mode: integer("mode").transpose()?.map(|mode| mode as u32),
+        len: integer("len").ok_or_else(invalid)??,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_and_restore_work() {
+        // arrange
+        let dir = "assets/quarantine_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/downloads", dir)).unwrap();
+        let root = format!("{}/quarantine", dir);
+        let file = format!("{}/downloads/setup.sh", dir);
+        fs::write(&file, "#!/bin/sh\n").unwrap();
+        set_file_mode(Path::new(&file), 0o755).unwrap();
+
+        // act
+        let record = quarantine(&file, &root).unwrap();
+        let moved = !Path::new(&file).exists();
+        let listed = list_quarantined(&root).unwrap();
+        let held_mode = file_mode(&fs::metadata(&record.quarantined_path).unwrap());
+        let restored = restore(&root, &record.id).unwrap();
+
+        // assert
+        assert!(moved);
+        assert_eq!(vec![record.clone()], listed);
+        assert_eq!(Path::new(&file).canonicalize().unwrap(), restored);
+        assert_eq!(file_mode(&fs::metadata(&file).unwrap()), record.mode);
+        if cfg!(unix) {
+            assert_eq!(Some(0o644), held_mode);
+        }
+        assert_eq!(0, fs::read_dir(&root).unwrap().count());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
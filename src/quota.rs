@@ -0,0 +1,307 @@
+//! Track the disk usage of a directory tree against a limit.
+use crate::{
+    cleanup::{delete_older_than, CleanupOptions},
+    dirstream::stream_tree,
+    watch::{EventKind, Watcher},
+};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+type AlertHandler = Box<dyn FnMut(&QuotaAlert) + Send>;
+
+/// Reported by a [`QuotaMonitor`] when usage crosses a threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaAlert {
+    /// The threshold crossed, in percent of the limit.
+    pub threshold: u32,
+    /// `true` if usage rose to or above the threshold, `false` if it fell below again.
+    pub exceeded: bool,
+    /// The total size of the files in the tree, in bytes.
+    pub usage: u64,
+    pub limit: u64,
+}
+
+/// Keeps the total size of the regular files under a directory up to date
+/// with a recursive [`Watcher`], and calls back when it crosses thresholds.
+///
+/// Only changed paths are restatted on each [`QuotaMonitor::poll`]; call
+/// [`QuotaMonitor::rescan`] to recount the whole tree.
+///
+/// ```no_run
+/// use file_manager::quota::QuotaMonitor;
+///
+/// let mut monitor = QuotaMonitor::new("/var/spool/uploads", 10 << 30)?
+///     .thresholds(&[80, 100])
+///     .on_alert(|alert| eprintln!("uploads at {}% of quota", alert.threshold));
+/// monitor.poll()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct QuotaMonitor {
+    dir: PathBuf,
+    limit: u64,
+    watcher: Watcher,
+    sizes: HashMap<PathBuf, u64>,
+    usage: u64,
+    /// Percentages of the limit, ascending.
+    thresholds: Vec<u32>,
+    /// How many thresholds usage was at or above when last checked.
+    crossed: usize,
+    on_alert: Option<AlertHandler>,
+    cleanup: Option<(Duration, CleanupOptions)>,
+}
+
+impl QuotaMonitor {
+    /// Start tracking the tree at `dir` against `limit` bytes, with a single
+    /// threshold at 100%.
+    pub fn new<P: AsRef<Path>>(dir: P, limit: u64) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        // Start watching before counting so no change slips in between.
+        let watcher = Watcher::new(&dir, true)?;
+        let mut monitor = QuotaMonitor {
+            dir,
+            limit,
+            watcher,
+            sizes: HashMap::new(),
+            usage: 0,
+            thresholds: vec![100],
+            crossed: 0,
+            on_alert: None,
+            cleanup: None,
+        };
+        monitor.count()?;
+        Ok(monitor)
+    }
+
+    /// Alert at each of `percents` of the limit instead.
+    pub fn thresholds(mut self, percents: &[u32]) -> Self {
+        self.thresholds = percents.to_vec();
+        self.thresholds.sort_unstable();
+        self.thresholds.dedup();
+        self
+    }
+
+    /// Call `f` for every threshold crossed, in either direction. Thresholds
+    /// already exceeded when the monitor starts are reported by the first poll.
+    pub fn on_alert<F: FnMut(&QuotaAlert) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_alert = Some(Box::new(f));
+        self
+    }
+
+    /// Whenever usage reaches the limit, delete files older than `max_age` as
+    /// [`delete_older_than`] would. `options.dry_run` is ignored.
+    pub fn cleanup_older_than(mut self, max_age: Duration, options: CleanupOptions) -> Self {
+        self.cleanup = Some((
+            max_age,
+            CleanupOptions {
+                dry_run: false,
+                ..options
+            },
+        ));
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The total size of the files in the tree as of the last poll, in bytes.
+    pub fn usage(&self) -> u64 {
+        self.usage
+    }
+
+    /// Apply the changes since the last poll, then alert and clean up as configured.
+    ///
+    /// # Returns
+    /// The usage in bytes.
+    pub fn poll(&mut self) -> io::Result<u64> {
+        for event in self.watcher.poll()? {
+            match event.kind {
+                EventKind::Removed | EventKind::RenamedFrom => self.set_size(&event.path, None),
+                EventKind::Created | EventKind::Modified | EventKind::RenamedTo => {
+                    let size = file_size(&event.path)?;
+                    self.set_size(&event.path, size);
+                }
+            }
+        }
+        self.check()?;
+        Ok(self.usage)
+    }
+
+    /// Recount the whole tree, then alert and clean up as configured.
+    ///
+    /// # Returns
+    /// The usage in bytes.
+    pub fn rescan(&mut self) -> io::Result<u64> {
+        // Drain pending events, the count below supersedes them.
+        self.watcher.poll()?;
+        self.count()?;
+        self.check()?;
+        Ok(self.usage)
+    }
+
+    /// Helper function to count every file in the tree from scratch.
+    fn count(&mut self) -> io::Result<()> {
+        self.sizes.clear();
+        self.usage = 0;
+        for entry in stream_tree(&self.dir)? {
+            let entry = entry?;
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => {
+                    self.set_size(&entry.path(), Some(metadata.len()));
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_size(&mut self, path: &Path, size: Option<u64>) {
+        let old = match size {
+            Some(size) => self.sizes.insert(path.to_path_buf(), size),
+            None => self.sizes.remove(path),
+        };
+        self.usage = self.usage - old.unwrap_or(0) + size.unwrap_or(0);
+    }
+
+    /// Helper function to alert on thresholds crossed since the last check,
+    /// then clean up and alert again if the limit is reached.
+    fn check(&mut self) -> io::Result<()> {
+        self.alert();
+        if self.usage >= self.limit {
+            if let Some((max_age, options)) = &self.cleanup {
+                for path in delete_older_than(&self.dir, *max_age, options)? {
+                    self.set_size(&path, None);
+                }
+                self.alert();
+            }
+        }
+        Ok(())
+    }
+
+    fn alert(&mut self) {
+        let crossed = self
+            .thresholds
+            .iter()
+            .take_while(|&&percent| {
+                self.usage as u128 * 100 >= self.limit as u128 * percent as u128
+            })
+            .count();
+        let (range, exceeded) = if crossed > self.crossed {
+            (self.crossed..crossed, true)
+        } else {
+            (crossed..self.crossed, false)
+        };
+        let mut alerts: Vec<_> = self.thresholds[range]
+            .iter()
+            .map(|&threshold| QuotaAlert {
+                threshold,
+                exceeded,
+                usage: self.usage,
+                limit: self.limit,
+            })
+            .collect();
+        if !exceeded {
+            alerts.reverse();
+        }
+        self.crossed = crossed;
+        if let Some(on_alert) = &mut self.on_alert {
+            alerts.iter().for_each(on_alert);
+        }
+    }
+}
+
+impl fmt::Debug for QuotaMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuotaMonitor")
+            .field("dir", &self.dir)
+            .field("limit", &self.limit)
+            .field("usage", &self.usage)
+            .field("thresholds", &self.thresholds)
+            .field("cleanup", &self.cleanup)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Helper function to get the size of the regular file at `path`, or `None` for
+/// anything else, including paths removed since they were reported.
+fn file_size(path: &Path) -> io::Result<Option<u64>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(Some(metadata.len())),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    };
+
+    #[test]
+    fn quota_monitor_alerts_and_cleans_up() {
+        // arrange
+        let dir = "assets/quota_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/nested", dir)).unwrap();
+        fs::write(format!("{}/old.log", dir), vec![0; 400]).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(format!("{}/old.log", dir))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let mut monitor = QuotaMonitor::new(dir, 1000)
+            .unwrap()
+            .thresholds(&[100, 50])
+            .on_alert({
+                let alerts = alerts.clone();
+                move |alert| {
+                    alerts
+                        .lock()
+                        .unwrap()
+                        .push((alert.threshold, alert.exceeded))
+                }
+            })
+            .cleanup_older_than(
+                Duration::from_secs(60),
+                CleanupOptions {
+                    recursive: true,
+                    ..CleanupOptions::default()
+                },
+            );
+
+        // act
+        let initial = monitor.poll().unwrap();
+        fs::write(format!("{}/nested/new.bin", dir), vec![0; 200]).unwrap();
+        let grown = monitor.poll().unwrap();
+        fs::write(format!("{}/nested/big.bin", dir), vec![0; 500]).unwrap();
+        let cleaned = monitor.poll().unwrap();
+        let rescanned = monitor.rescan().unwrap();
+
+        // assert
+        assert_eq!((400, 600, 700, 700), (initial, grown, cleaned, rescanned));
+        assert!(!Path::new(dir).join("old.log").exists());
+        assert_eq!(
+            vec![(50, true), (100, true), (100, false)],
+            *alerts.lock().unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+//! A persisted, bounded list of recently used files.
+use crate::{json::Value, write_atomic};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// An entry of a [`RecentFiles`] list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFile {
+    /// The canonical path of the file.
+    pub path: PathBuf,
+    /// When the file was last touched, with millisecond precision.
+    pub used_at: SystemTime,
+}
+
+/// A most-recently-used list of files, saved as JSON after every change.
+#[derive(Debug)]
+pub struct RecentFiles {
+    store_path: PathBuf,
+    capacity: usize,
+    /// Most recent first.
+    files: Vec<RecentFile>,
+}
+
+impl RecentFiles {
+    /// Load the list stored at `store_path`, or start empty if it does not
+    /// exist. At most `capacity` files are kept.
+    pub fn open<P: AsRef<Path>>(store_path: P, capacity: usize) -> io::Result<Self> {
+        let store_path = store_path.as_ref().to_path_buf();
+        let mut files = match fs::read_to_string(&store_path) {
+            Ok(text) => parse_store(&store_path, &text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        files.truncate(capacity);
+        Ok(RecentFiles {
+            store_path,
+            capacity,
+            files,
+        })
+    }
+
+    /// Move the file at `path` to the front of the list, dropping the least
+    /// recently used file if the list is full.
+    pub fn touch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref().canonicalize()?;
+        if path.to_str().is_none() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("path is not valid UTF-8: {}", path.display()),
+            ));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.files.retain(|f| f.path != path);
+        self.files.insert(
+            0,
+            RecentFile {
+                path,
+                used_at: UNIX_EPOCH + Duration::from_millis(now),
+            },
+        );
+        self.files.truncate(self.capacity);
+        self.save()
+    }
+
+    /// The recently used files, most recent first.
+    pub fn list(&self) -> &[RecentFile] {
+        &self.files
+    }
+
+    /// Forget files that no longer exist.
+    ///
+    /// # Returns
+    /// The number of files removed from the list.
+    pub fn prune(&mut self) -> io::Result<usize> {
+        let before = self.files.len();
+        self.files.retain(|f| f.path.exists());
+        let pruned = before - self.files.len();
+        if pruned > 0 {
+            self.save()?;
+        }
+        Ok(pruned)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let files = self
+            .files
+            .iter()
+            .map(|f| {
+                let used_at = f.used_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Value::Object(vec![
+                    // Checked to be UTF-8 by `touch`.
+                    ("path".to_owned(), Value::from(f.path.to_str().unwrap())),
+                    (
+                        "used_at_ms".to_owned(),
+                        Value::from(used_at.as_millis() as u64),
+                    ),
+                ])
+            })
+            .collect();
+        let store = Value::Object(vec![("files".to_owned(), Value::Array(files))]);
+        write_atomic(&self.store_path, format!("{}\n", store).as_bytes())
+    }
+}
+
+fn parse_store(store_path: &Path, text: &str) -> io::Result<Vec<RecentFile>> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid recent files store {}", store_path.display()),
+        )
+    };
+    let store = Value::parse(text)?;
+    let items = store
+        .get("files")
+        .and_then(Value::as_array)
+        .ok_or_else(invalid)?;
+    items
+        .iter()
+        .map(|item| {
+            let path = item
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(invalid)?;
+            let used_at = item
+                .get("used_at_ms")
+                .and_then(Value::as_u64)
+                .ok_or_else(invalid)?;
+            Ok(RecentFile {
+                path: PathBuf::from(path),
+                used_at: UNIX_EPOCH + Duration::from_millis(used_at),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_files_are_bounded_persisted_and_pruned() {
+        // arrange
+        let dir = "assets/recent_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let store = format!("{}/recent.json", dir);
+        let names = ["a.txt", "b.txt", "c.txt"].map(|name| format!("{}/{}", dir, name));
+        for name in &names {
+            fs::write(name, name).unwrap();
+        }
+        let canonical = |name: &str| Path::new(name).canonicalize().unwrap();
+
+        // act
+        let mut recent = RecentFiles::open(&store, 2).unwrap();
+        for name in &names {
+            recent.touch(name).unwrap();
+        }
+        recent.touch(&names[1]).unwrap();
+        let expected = vec![canonical(&names[1]), canonical(&names[2])];
+        fs::remove_file(&names[2]).unwrap();
+        let mut reopened = RecentFiles::open(&store, 2).unwrap();
+        let listed: Vec<PathBuf> = reopened.list().iter().map(|f| f.path.clone()).collect();
+        let pruned = reopened.prune().unwrap();
+
+        // assert
+        assert_eq!(expected, listed);
+        assert_eq!(1, pruned);
+        assert_eq!(
+            vec![canonical(&names[1])],
+            RecentFiles::open(&store, 2)
+                .unwrap()
+                .list()
+                .iter()
+                .map(|f| f.path.clone())
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
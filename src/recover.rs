@@ -0,0 +1,300 @@
+//! Clean up after a crash: finish, undo or remove what this crate left half-done.
+use crate::{
+    journal::{self, JOURNAL_HEADER},
+    lease::{Lease, LEASE_HEADER},
+    pidfile::process_alive,
+};
+use std::{
+    fs::{self, File, TryLockError},
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+
+/// The temporary names used next to the files they stand in for, as
+/// `.<name>.<purpose>-<pid>-<n>`.
+const SIBLING_PURPOSES: &[&str] = &[
+    "clone",
+    "extract",
+    "link",
+    "move",
+    "old",
+    "scaffold",
+    "secret",
+    "tmp",
+    "transform",
+];
+
+/// What [`recover`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Journals of interrupted bulk operations that were resumed and finished.
+    pub completed: Vec<PathBuf>,
+    /// Changes that were undone: directories set aside by
+    /// [`publish_dir`](crate::swap::publish_dir) put back in place, and journals
+    /// that were never fully written, so their operation never started.
+    pub rolled_back: Vec<PathBuf>,
+    /// Temporary files and directories of exited processes, partial downloads
+    /// whose destination exists, with their validators, and stale PID and
+    /// lease files.
+    pub removed: Vec<PathBuf>,
+    /// Partial downloads and transfers, kept so they can resume.
+    pub kept: Vec<PathBuf>,
+}
+
+/// Scan `root` for what this crate's subsystems leave behind when a process
+/// dies mid-operation, and complete, roll back or clean up each item.
+///
+/// Temporary files are only touched once the process that created them has
+/// exited, and journals and partial downloads only while nothing holds their
+/// lock. Every file is opened to look for journals and leases, so point this
+/// at an application's state directory rather than a large tree.
+pub fn recover<P: AsRef<Path>>(root: P) -> io::Result<RecoveryReport> {
+    let mut report = RecoveryReport::default();
+    recover_dir(root.as_ref(), &mut report)?;
+    Ok(report)
+}
+
+fn recover_dir(dir: &Path, report: &mut RecoveryReport) -> io::Result<()> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    for path in paths {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            // Handled together with an earlier entry.
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if let Some((original, purpose, pid)) = temp_name(name) {
+            if !process_alive(pid) {
+                recover_temp(&path, original, purpose, report)?;
+            }
+        } else if metadata.is_dir() {
+            recover_dir(&path, report)?;
+        } else if metadata.is_file() {
+            recover_file(&path, name, report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to split a temporary name into the name it stands in for,
+/// if any, its purpose and the PID of its creator.
+fn temp_name(name: &str) -> Option<(Option<&str>, &str, u32)> {
+    // `TempFile` and `TempDir` names are `.tmp-<pid>-<nanos>-<n>`.
+    if let Some(rest) = name.strip_prefix(".tmp-") {
+        let pid = rest.split('-').next()?.parse().ok()?;
+        return Some((None, "tmp", pid));
+    }
+    let (stem, n) = name.strip_prefix('.')?.rsplit_once('-')?;
+    n.parse::<u64>().ok()?;
+    let (stem, pid) = stem.rsplit_once('-')?;
+    let pid = pid.parse().ok()?;
+    let (original, purpose) = stem.rsplit_once('.')?;
+    SIBLING_PURPOSES
+        .contains(&purpose)
+        .then_some((Some(original), purpose, pid))
+}
+
+fn recover_temp(
+    path: &Path,
+    original: Option<&str>,
+    purpose: &str,
+    report: &mut RecoveryReport,
+) -> io::Result<()> {
+    if let (Some(original), "old") = (original, purpose) {
+        let live = path.with_file_name(original);
+        if fs::symlink_metadata(&live).is_err() {
+            fs::rename(path, &live)?;
+            report.rolled_back.push(live);
+            return Ok(());
+        }
+    }
+    remove(path)?;
+    report.removed.push(path.to_path_buf());
+    Ok(())
+}
+
+fn recover_file(path: &Path, name: &str, report: &mut RecoveryReport) -> io::Result<()> {
+    if let Some(dest) = name
+        .strip_prefix('.')
+        .and_then(|name| name.strip_suffix(".part"))
+    {
+        if path.with_file_name(dest).exists() {
+            // A download replacing `dest` holds the lock until it is done.
+            let file = File::open(path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => return Ok(()),
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+            remove(path)?;
+            let mut validator = path.as_os_str().to_owned();
+            validator.push(".validator");
+            match fs::remove_file(validator) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            report.removed.push(path.to_path_buf());
+        } else {
+            report.kept.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    if path.extension().is_some_and(|ext| ext == "pid") {
+        let owner = fs::read_to_string(path)?.trim().parse::<u32>().ok();
+        if owner.is_some_and(|pid| !process_alive(pid)) {
+            remove(path)?;
+            report.removed.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut head = Vec::new();
+    let read = File::open(path).and_then(|file| {
+        file.take(JOURNAL_HEADER.len().max(LEASE_HEADER.len()) as u64 + 1)
+            .read_to_end(&mut head)
+    });
+    match read {
+        Ok(_) => {}
+        // Windows refuses to read a locked file, such as a journal being run.
+        #[cfg(windows)]
+        Err(e) if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    let first_line = head.split(|&b| b == b'\n').next().unwrap_or_default();
+    if first_line == JOURNAL_HEADER.as_bytes() {
+        match journal::recover(path)? {
+            Some(true) => report.completed.push(path.to_path_buf()),
+            Some(false) => report.rolled_back.push(path.to_path_buf()),
+            None => {}
+        }
+    } else if first_line == LEASE_HEADER.as_bytes() {
+        let expired = Lease::read(path)?.is_some_and(|info| info.expires < SystemTime::now());
+        if expired {
+            remove(path)?;
+            report.removed.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to remove the file or directory tree at `path`.
+fn remove(path: &Path) -> io::Result<()> {
+    let result = if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_cleans_up_after_a_crash() {
+        // arrange
+        let dir = "assets/recover_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/.site.old-4000000000-0/inner", dir)).unwrap();
+        let path = |name: &str| Path::new(dir).join(name);
+        // A PID that cannot belong to a running process.
+        let dead = 4_000_000_000u32;
+        fs::write(path(&format!(".config.toml.tmp-{}-3", dead)), "half").unwrap();
+        fs::write(path(&format!(".tmp-{}-1f-0", dead)), "").unwrap();
+        let live_tmp = format!(".notes.txt.tmp-{}-0", std::process::id());
+        fs::write(path(&live_tmp), "in use").unwrap();
+        fs::write(path("done.bin"), "done").unwrap();
+        fs::write(path(".done.bin.part"), "do").unwrap();
+        fs::write(path(".done.bin.part.validator"), "\"v1\"").unwrap();
+        fs::write(path(".big.iso.part"), "resumable").unwrap();
+        fs::write(path("daemon.pid"), format!("{}\n", dead)).unwrap();
+        fs::write(path("a.txt"), "a").unwrap();
+        fs::write(
+            path("copy.journal"),
+            format!(
+                "{}\ncopy\nentry\t{}\t{}\nbegin\n",
+                JOURNAL_HEADER,
+                path("a.txt").display(),
+                path("b.txt").display()
+            ),
+        )
+        .unwrap();
+        fs::write(path("torn.journal"), format!("{}\ncopy\n", JOURNAL_HEADER)).unwrap();
+        fs::write(
+            path("job.lease"),
+            format!("{}\nowner 1-1\nexpires 0\n", LEASE_HEADER),
+        )
+        .unwrap();
+
+        // act
+        let report = recover(dir).unwrap();
+
+        // assert
+        assert_eq!(vec![path("copy.journal")], report.completed);
+        assert_eq!(vec![path("site"), path("torn.journal")], report.rolled_back);
+        assert_eq!(
+            vec![
+                path(&format!(".config.toml.tmp-{}-3", dead)),
+                path(".done.bin.part"),
+                path(&format!(".tmp-{}-1f-0", dead)),
+                path("daemon.pid"),
+                path("job.lease"),
+            ],
+            report.removed
+        );
+        assert_eq!(vec![path(".big.iso.part")], report.kept);
+        assert_eq!("a", fs::read_to_string(path("b.txt")).unwrap());
+        assert!(path("site/inner").is_dir());
+        assert!(path(&live_tmp).exists());
+        assert!(!path(".done.bin.part.validator").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recover_skips_journals_and_downloads_in_use() {
+        // arrange
+        let dir = "assets/recover_in_use_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = |name: &str| Path::new(dir).join(name);
+        fs::write(path("a.txt"), "a").unwrap();
+        fs::write(
+            path("copy.journal"),
+            format!(
+                "{}\ncopy\nentry\t{}\t{}\nbegin\n",
+                JOURNAL_HEADER,
+                path("a.txt").display(),
+                path("b.txt").display()
+            ),
+        )
+        .unwrap();
+        fs::write(path("done.bin"), "done").unwrap();
+        fs::write(path(".done.bin.part"), "do").unwrap();
+        let journal = File::open(path("copy.journal")).unwrap();
+        journal.lock().unwrap();
+        let part = File::open(path(".done.bin.part")).unwrap();
+        part.lock().unwrap();
+
+        // act
+        let report = recover(dir).unwrap();
+
+        // assert
+        assert_eq!(RecoveryReport::default(), report);
+        assert!(path(".done.bin.part").exists());
+        assert!(!path("b.txt").exists());
+        drop((journal, part));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
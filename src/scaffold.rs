@@ -0,0 +1,424 @@
+//! Create directory structures from a declarative spec.
+use crate::{json::Value, temp_sibling};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryKind {
+    Dir,
+    File(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SpecEntry {
+    path: PathBuf,
+    kind: EntryKind,
+    mode: Option<u32>,
+}
+
+/// A declarative description of directories and files to create with [`scaffold`].
+/// All paths are relative to the scaffold root.
+///
+/// A spec can be built in code:
+/// ```
+/// use file_manager::scaffold::Spec;
+///
+/// let spec = Spec::new()
+///     .dir("src")
+///     .file("src/main.rs", "fn main() {}")
+///     .file("run.sh", "#!/bin/sh\n")
+///     .mode(0o755);
+/// ```
+/// or parsed from a string tree with [`Spec::parse_tree`] or JSON with [`Spec::from_json`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Spec {
+    entries: Vec<SpecEntry>,
+}
+
+impl Spec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a directory. Missing parent directories are created as well.
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.entries.push(SpecEntry {
+            path: path.as_ref().to_path_buf(),
+            kind: EntryKind::Dir,
+            mode: None,
+        });
+        self
+    }
+
+    /// Add a file with initial `contents`. Missing parent directories are created as well.
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(mut self, path: P, contents: C) -> Self {
+        self.entries.push(SpecEntry {
+            path: path.as_ref().to_path_buf(),
+            kind: EntryKind::File(contents.as_ref().to_vec()),
+            mode: None,
+        });
+        self
+    }
+
+    /// Set the Unix permission bits of the most recently added entry.
+    /// Ignored on platforms without Unix permissions.
+    pub fn mode(mut self, mode: u32) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.mode = Some(mode);
+        }
+        self
+    }
+
+    /// Parse an indented string tree.
+    /// Each line names one entry; names ending in `/` are directories and
+    /// more deeply indented lines below a directory are created inside it.
+    /// A file may be given contents with `name = contents`, where `\n` in
+    /// the contents is replaced by a newline.
+    ///
+    /// ```text
+    /// src/
+    ///   main.rs = fn main() {}
+    ///   bin/
+    /// README.md
+    /// ```
+    pub fn parse_tree(tree: &str) -> io::Result<Spec> {
+        let mut spec = Spec::new();
+        // Stack of (indent, directory path) for the currently open directories.
+        let mut parents: Vec<(usize, PathBuf)> = Vec::new();
+
+        for (line_no, line) in tree.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indent = line.len() - line.trim_start().len();
+            while matches!(parents.last(), Some((parent_indent, _)) if *parent_indent >= indent) {
+                parents.pop();
+            }
+            let base = parents.last().map(|(_, p)| p.clone()).unwrap_or_default();
+
+            let (name, contents) = match line.trim().split_once(" = ") {
+                Some((name, contents)) => (name.trim_end(), Some(contents)),
+                None => (line.trim(), None),
+            };
+            if let Some(dir) = name.strip_suffix('/') {
+                if contents.is_some() {
+                    return Err(invalid_spec(format!(
+                        "line {}: directory `{}` cannot have contents",
+                        line_no + 1,
+                        name
+                    )));
+                }
+                let path = base.join(dir);
+                spec = spec.dir(&path);
+                parents.push((indent, path));
+            } else {
+                let contents = contents.unwrap_or_default().replace("\\n", "\n");
+                spec = spec.file(base.join(name), contents);
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Parse a JSON spec.
+    /// Objects are directories, strings are files with that content and `null` is an empty file.
+    /// An object with a `"$contents"` key is a file; `"$mode"` sets permissions (e.g. `"0755"`)
+    /// on either kind of entry.
+    ///
+    /// ```json
+    /// { "src": { "main.rs": "fn main() {}" }, "run.sh": { "$contents": "", "$mode": "0755" } }
+    /// ```
+    pub fn from_json(json: &str) -> io::Result<Spec> {
+        let mut spec = Spec::new();
+        match Value::parse(json)? {
+            Value::Object(fields) => {
+                for (name, value) in &fields {
+                    spec.add_json_entry(PathBuf::from(name), value)?;
+                }
+                Ok(spec)
+            }
+            _ => Err(invalid_spec("top level must be an object".to_owned())),
+        }
+    }
+
+    fn add_json_entry(&mut self, path: PathBuf, value: &Value) -> io::Result<()> {
+        let (kind, mode) = match value {
+            Value::Null => (EntryKind::File(Vec::new()), None),
+            Value::String(contents) => (EntryKind::File(contents.clone().into_bytes()), None),
+            Value::Object(fields) => {
+                let mode = value.get("$mode").map(parse_mode).transpose()?;
+                match value.get("$contents") {
+                    Some(contents) => {
+                        let contents = contents.as_str().ok_or_else(|| {
+                            invalid_spec(format!(
+                                "{}: `$contents` must be a string",
+                                path.display()
+                            ))
+                        })?;
+                        (EntryKind::File(contents.as_bytes().to_vec()), mode)
+                    }
+                    None => {
+                        self.entries.push(SpecEntry {
+                            path: path.clone(),
+                            kind: EntryKind::Dir,
+                            mode,
+                        });
+                        for (name, child) in fields.iter().filter(|(k, _)| !k.starts_with('$')) {
+                            self.add_json_entry(path.join(name), child)?;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {
+                return Err(invalid_spec(format!(
+                    "{}: expected an object, string or null",
+                    path.display()
+                )))
+            }
+        };
+        self.entries.push(SpecEntry { path, kind, mode });
+        Ok(())
+    }
+}
+
+fn parse_mode(value: &Value) -> io::Result<u32> {
+    value
+        .as_str()
+        .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+        .ok_or_else(|| invalid_spec(format!("invalid mode {}", value)))
+}
+
+fn invalid_spec(msg: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid scaffold spec: {}", msg),
+    )
+}
+
+/// Create every directory and file described by `spec` under `root`.
+///
+/// The structure is created as close to atomically as the platform allows:
+/// when `root` does not exist yet, everything is built in a sibling staging
+/// directory which is renamed into place at the end. When `root` already exists,
+/// the spec is checked against it first (no file may already exist) and anything
+/// created is removed again if a later step fails.
+pub fn scaffold<P: AsRef<Path>>(root: P, spec: &Spec) -> io::Result<()> {
+    let root = root.as_ref();
+    for entry in &spec.entries {
+        let valid = entry.path.components().count() > 0
+            && entry
+                .path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !valid {
+            return Err(invalid_spec(format!(
+                "`{}` must be a relative path inside the root",
+                entry.path.display()
+            )));
+        }
+    }
+
+    if root.exists() {
+        for entry in &spec.entries {
+            if matches!(entry.kind, EntryKind::File(_)) && root.join(&entry.path).exists() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists", root.join(&entry.path).display()),
+                ));
+            }
+        }
+        let mut created = Vec::new();
+        let result = create_entries(root, spec, &mut created)
+            .and_then(|dir_modes| set_dir_modes(root, dir_modes));
+        if result.is_err() {
+            for path in created.iter().rev() {
+                let _ = fs::remove_file(path).or_else(|_| fs::remove_dir(path));
+            }
+        }
+        return result;
+    }
+
+    if root.file_name().is_none() {
+        return Err(invalid_spec(format!("invalid root {}", root.display())));
+    }
+    let staging = temp_sibling(root, "scaffold");
+
+    // Directory modes are applied once in place, so a read-only directory
+    // cannot stop the staging directory from being cleaned up.
+    let result = fs::create_dir_all(&staging)
+        .and_then(|_| create_entries(&staging, spec, &mut Vec::new()))
+        .and_then(|dir_modes| fs::rename(&staging, root).map(|_| dir_modes));
+    match result {
+        Ok(dir_modes) => set_dir_modes(root, dir_modes),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            Err(e)
+        }
+    }
+}
+
+/// Create the entries of `spec` under `root`, recording every path that did not exist before.
+///
+/// # Returns
+/// The modes of the directories, for [`set_dir_modes`] to apply once nothing
+/// more has to be written into them.
+fn create_entries(
+    root: &Path,
+    spec: &Spec,
+    created: &mut Vec<PathBuf>,
+) -> io::Result<Vec<(PathBuf, u32)>> {
+    let mut dir_modes = Vec::new();
+    for entry in &spec.entries {
+        let path = root.join(&entry.path);
+        let dir = match entry.kind {
+            EntryKind::Dir => path.as_path(),
+            EntryKind::File(_) => path.parent().unwrap_or(root),
+        };
+        create_dirs(dir, created)?;
+
+        if let EntryKind::File(contents) = &entry.kind {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            created.push(path.clone());
+            file.write_all(contents)?;
+            file.flush()?;
+        }
+        match (&entry.kind, entry.mode) {
+            (EntryKind::Dir, Some(mode)) => dir_modes.push((entry.path.clone(), mode)),
+            (EntryKind::File(_), Some(mode)) => set_mode(&path, mode)?,
+            (_, None) => {}
+        }
+    }
+    Ok(dir_modes)
+}
+
+/// Helper function to apply the `dir_modes` of directories under `root`, deepest first.
+fn set_dir_modes(root: &Path, mut dir_modes: Vec<(PathBuf, u32)>) -> io::Result<()> {
+    dir_modes.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+    for (dir, mode) in dir_modes {
+        set_mode(&root.join(dir), mode)?;
+    }
+    Ok(())
+}
+
+/// Like `fs::create_dir_all`, but records each directory it creates.
+fn create_dirs(dir: &Path, created: &mut Vec<PathBuf>) -> io::Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dirs(parent, created)?;
+    }
+    fs::create_dir(dir)?;
+    created.push(dir.to_path_buf());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaffold_from_tree_works() {
+        // arrange
+        let root = "assets/scaffold_tree_test";
+        let _ = fs::remove_dir_all(root);
+        let tree = "src/\n  main.rs = fn main() {}\\n\n  bin/\nREADME.md\n";
+
+        // act
+        let spec = Spec::parse_tree(tree).unwrap();
+        let result = scaffold(root, &spec);
+
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(
+            "fn main() {}\n",
+            fs::read_to_string(format!("{}/src/main.rs", root)).unwrap()
+        );
+        assert!(Path::new(&format!("{}/src/bin", root)).is_dir());
+        assert!(Path::new(&format!("{}/README.md", root)).is_file());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn scaffold_from_json_matches_builder() {
+        // arrange
+        let json = r#"{"src": {"lib.rs": "// lib"}, "run.sh": {"$contents": "", "$mode": "0755"}}"#;
+        let built = Spec::new()
+            .dir("src")
+            .file("src/lib.rs", "// lib")
+            .file("run.sh", "")
+            .mode(0o755);
+
+        // act
+        let parsed = Spec::from_json(json);
+
+        // assert
+        assert_eq!(built, parsed.unwrap());
+    }
+
+    #[test]
+    // Make sure nothing is left behind when the spec conflicts with an existing root.
+    fn scaffold_into_existing_root_rolls_back() {
+        // arrange
+        let root = "assets/scaffold_existing_test";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(format!("{}/taken.txt", root), "keep").unwrap();
+        let spec = Spec::new()
+            .file("new/a.txt", "a")
+            .file("taken.txt", "overwrite?");
+
+        // act
+        let result = scaffold(root, &spec);
+
+        // assert
+        assert_eq!(ErrorKind::AlreadyExists, result.unwrap_err().kind());
+        assert!(!Path::new(&format!("{}/new", root)).exists());
+        assert_eq!(
+            "keep",
+            fs::read_to_string(format!("{}/taken.txt", root)).unwrap()
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn scaffold_read_only_dir_with_children_works() {
+        // arrange
+        use std::os::unix::fs::PermissionsExt;
+        let root = "assets/scaffold_read_only_test";
+        let _ = fs::remove_dir_all(root);
+        let spec = Spec::from_json(r#"{"ro": {"$mode": "0555", "a.txt": "x"}}"#).unwrap();
+
+        // act
+        let result = scaffold(root, &spec);
+
+        // assert
+        let ro = format!("{}/ro", root);
+        assert!(result.is_ok());
+        assert_eq!("x", fs::read_to_string(format!("{}/a.txt", ro)).unwrap());
+        assert_eq!(
+            0o555,
+            fs::metadata(&ro).unwrap().permissions().mode() & 0o777
+        );
+        set_mode(Path::new(&ro), 0o755).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
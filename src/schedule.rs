@@ -0,0 +1,406 @@
+//! A lightweight background scheduler for periodic maintenance jobs.
+use std::{
+    io::{self, ErrorKind},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A cron-like expression with the five standard fields:
+/// minute, hour, day of month, month and day of week.
+/// Times are evaluated in UTC.
+///
+/// Each field accepts `*`, single values, ranges (`1-5`), steps (`*/15`, `0-30/10`)
+/// and comma separated lists of those. The shorthands `@hourly`, `@daily`,
+/// `@weekly`, `@monthly` and `@yearly` are also accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronExpr {
+    /// Parse a cron expression such as `"*/5 * * * *"`.
+    pub fn parse(expr: &str) -> io::Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid_cron(expr, "expected 5 fields"));
+        }
+
+        let minutes = parse_field(fields[0], 0, 59)?;
+        let hours = parse_field(fields[1], 0, 23)?;
+        let days_of_month = parse_field(fields[2], 1, 31)?;
+        let months = parse_field(fields[3], 1, 12)?;
+        // Both 0 and 7 mean Sunday.
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(CronExpr {
+            minutes,
+            hours: hours as u32,
+            days_of_month: days_of_month as u32,
+            months: months as u16,
+            days_of_week: days_of_week as u8,
+            day_of_month_restricted: fields[2] != "*",
+            day_of_week_restricted: fields[4] != "*",
+        })
+    }
+
+    /// Returns the first time strictly after `after` that matches this expression,
+    /// or `None` if the expression cannot match within the next few years
+    /// (for example `0 0 30 2 *`).
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let after = after.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let mut t = (after / 60 + 1) * 60;
+        let limit = t + 5 * 366 * 86_400;
+
+        while t < limit {
+            let days = t / 86_400;
+            let (_, month, day) = civil_from_days(days as i64);
+            let weekday = (days + 4) % 7;
+            let hour = (t % 86_400) / 3600;
+            let minute = (t % 3600) / 60;
+
+            if self.months & (1 << month) == 0 || !self.day_matches(day, weekday as u32) {
+                t = (days + 1) * 86_400;
+            } else if self.hours & (1 << hour) == 0 {
+                t = (t / 3600 + 1) * 3600;
+            } else if self.minutes & (1 << minute) == 0 {
+                t += 60;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(t));
+            }
+        }
+        None
+    }
+
+    /// Standard cron semantics: when both day fields are restricted,
+    /// a day matches if either of them does.
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let dom = self.days_of_month & (1 << day) != 0;
+        let dow = self.days_of_week & (1 << weekday) != 0;
+        match (self.day_of_month_restricted, self.day_of_week_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+}
+
+/// Parse one cron field into a bitmask where bit `n` is set if `n` matches.
+fn parse_field(field: &str, min: u64, max: u64) -> io::Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| invalid_cron(field, "invalid step"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, field)?, parse_value(end, field)?)
+        } else {
+            let value = parse_value(range, field)?;
+            // `5/10` means "from 5 to the end in steps of 10".
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid_cron(field, "value out of range"));
+        }
+        let mut value = start;
+        while value <= end {
+            mask |= 1 << value;
+            value += step;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(value: &str, field: &str) -> io::Result<u64> {
+    value
+        .parse::<u64>()
+        .map_err(|_| invalid_cron(field, "invalid number"))
+}
+
+fn invalid_cron(expr: &str, reason: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid cron expression `{}`: {}", expr, reason),
+    )
+}
+
+/// Convert days since the unix epoch to a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// When a job should run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Run repeatedly with this much time between runs.
+    Every(Duration),
+    /// Run whenever the cron expression matches.
+    Cron(CronExpr),
+}
+
+impl Schedule {
+    fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Every(interval) => Some(after + *interval),
+            Schedule::Cron(expr) => expr.next_after(after),
+        }
+    }
+}
+
+type Task = Box<dyn FnMut() -> io::Result<()> + Send>;
+type ErrorHandler = Box<dyn FnMut(&str, io::Error) + Send>;
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    task: Task,
+    next_run: Option<SystemTime>,
+}
+
+/// Runs registered maintenance jobs on a background thread.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use file_manager::schedule::Scheduler;
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler.every("flush", Duration::from_secs(30), || Ok(()));
+/// scheduler.cron("nightly-cleanup", "0 3 * * *", || Ok(())).unwrap();
+/// let handle = scheduler.start();
+/// // ...
+/// handle.stop();
+/// ```
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    on_error: Option<ErrorHandler>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `job` to run every `interval`, starting one interval after the scheduler starts.
+    pub fn every<F>(&mut self, name: &str, interval: Duration, job: F) -> &mut Self
+    where
+        F: FnMut() -> io::Result<()> + Send + 'static,
+    {
+        self.add(name, Schedule::Every(interval), job)
+    }
+
+    /// Register `job` to run whenever the cron expression `expr` matches.
+    pub fn cron<F>(&mut self, name: &str, expr: &str, job: F) -> io::Result<&mut Self>
+    where
+        F: FnMut() -> io::Result<()> + Send + 'static,
+    {
+        let expr = CronExpr::parse(expr)?;
+        Ok(self.add(name, Schedule::Cron(expr), job))
+    }
+
+    /// Register `job` to run on `schedule`.
+    pub fn add<F>(&mut self, name: &str, schedule: Schedule, job: F) -> &mut Self
+    where
+        F: FnMut() -> io::Result<()> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name: name.to_owned(),
+            schedule,
+            task: Box::new(job),
+            next_run: None,
+        });
+        self
+    }
+
+    /// Set a handler invoked with the job name whenever a job returns an error.
+    /// Failed jobs stay scheduled and run again at their next due time.
+    pub fn on_error<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: FnMut(&str, io::Error) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(handler));
+        self
+    }
+
+    /// Start running the registered jobs on a background thread.
+    ///
+    /// # Returns
+    /// A `SchedulerHandle` that stops the scheduler when dropped.
+    pub fn start(mut self) -> SchedulerHandle {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = Arc::clone(&stop);
+
+        let thread = thread::spawn(move || {
+            let now = SystemTime::now();
+            for job in &mut self.jobs {
+                job.next_run = job.schedule.next_after(now);
+            }
+
+            let (lock, signal) = &*thread_stop;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let now = SystemTime::now();
+                let next = self.jobs.iter().filter_map(|j| j.next_run).min();
+                let wait = match next {
+                    Some(next) => next.duration_since(now).unwrap_or(Duration::ZERO),
+                    // Nothing left to run; sleep until stopped.
+                    None => Duration::from_secs(3600),
+                };
+                if !wait.is_zero() {
+                    stopped = signal.wait_timeout(stopped, wait).unwrap().0;
+                    continue;
+                }
+
+                drop(stopped);
+                self.run_due(now);
+                stopped = lock.lock().unwrap();
+            }
+        });
+
+        SchedulerHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn run_due(&mut self, now: SystemTime) {
+        for job in &mut self.jobs {
+            match job.next_run {
+                Some(due) if due <= now => {}
+                _ => continue,
+            }
+            if let Err(e) = (job.task)() {
+                if let Some(handler) = &mut self.on_error {
+                    handler(&job.name, e);
+                }
+            }
+            job.next_run = job.schedule.next_after(SystemTime::now());
+        }
+    }
+}
+
+/// Handle to a running `Scheduler`. Dropping it stops the background thread.
+pub struct SchedulerHandle {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Stop the scheduler and wait for any running job to finish.
+    pub fn stop(self) {
+        // Handled by drop.
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        signal.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn cron_next_after_works() {
+        // arrange
+        // 2024-01-01T00:00:00Z was a Monday.
+        let start = at(1_704_067_200);
+        let every_quarter_hour = CronExpr::parse("*/15 * * * *").unwrap();
+        let weekday_mornings = CronExpr::parse("30 9 * * 1-5").unwrap();
+        let sundays = CronExpr::parse("0 0 * * 7").unwrap();
+
+        // act
+        let quarter = every_quarter_hour.next_after(start);
+        let morning = weekday_mornings.next_after(start);
+        let sunday = sundays.next_after(start);
+
+        // assert
+        assert_eq!(Some(at(1_704_067_200 + 15 * 60)), quarter);
+        assert_eq!(Some(at(1_704_067_200 + 9 * 3600 + 30 * 60)), morning);
+        assert_eq!(Some(at(1_704_067_200 + 6 * 86_400)), sunday);
+    }
+
+    #[test]
+    fn cron_parse_rejects_invalid_expressions() {
+        // act
+        let too_few = CronExpr::parse("* * *");
+        let out_of_range = CronExpr::parse("61 * * * *");
+        let bad_step = CronExpr::parse("*/0 * * * *");
+
+        // assert
+        assert!(too_few.is_err());
+        assert!(out_of_range.is_err());
+        assert!(bad_step.is_err());
+        assert_eq!(None, CronExpr::parse("0 0 30 2 *").unwrap().next_after(at(0)));
+    }
+
+    #[test]
+    fn scheduler_runs_interval_jobs() {
+        // arrange
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let mut scheduler = Scheduler::new();
+        scheduler.every("count", Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        // act
+        let handle = scheduler.start();
+        thread::sleep(Duration::from_millis(200));
+        handle.stop();
+        let after_stop = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+
+        // assert
+        assert!(after_stop >= 2);
+        assert_eq!(after_stop, runs.load(Ordering::SeqCst));
+    }
+}
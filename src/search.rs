@@ -0,0 +1,223 @@
+//! Saved searches that act as virtual folders.
+use crate::{
+    find::{FindIter, FindQuery},
+    json::Value,
+    watch::{Event, EventKind, Watcher},
+    write_atomic,
+};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const EXTENSION: &str = "search.json";
+
+/// A named [`FindQuery`] stored as `<name>.search.json` in a directory, whose
+/// results are computed when asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedSearch {
+    path: PathBuf,
+    name: String,
+    query: FindQuery,
+}
+
+impl SavedSearch {
+    /// Save `query` as `name` in `store_dir`, replacing any search of the same name.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `name` is empty, starts with `.` or contains a path separator.
+    pub fn create<P: AsRef<Path>>(store_dir: P, name: &str, query: FindQuery) -> io::Result<Self> {
+        let path = search_path(store_dir.as_ref(), name)?;
+        let search = Value::Object(vec![
+            ("name".to_owned(), Value::from(name)),
+            ("query".to_owned(), query.to_value()?),
+        ]);
+        fs::create_dir_all(store_dir)?;
+        write_atomic(&path, format!("{}\n", search).as_bytes())?;
+        Ok(SavedSearch {
+            path,
+            name: name.to_owned(),
+            query,
+        })
+    }
+
+    /// Load the search saved as `name` in `store_dir`.
+    pub fn open<P: AsRef<Path>>(store_dir: P, name: &str) -> io::Result<Self> {
+        let path = search_path(store_dir.as_ref(), name)?;
+        let search = Value::parse(&fs::read_to_string(&path)?)?;
+        let query = search.get("query").ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid saved search {}", path.display()),
+            )
+        })?;
+        Ok(SavedSearch {
+            query: FindQuery::from_value(query)?,
+            path,
+            name: name.to_owned(),
+        })
+    }
+
+    /// Every search saved in `store_dir`, sorted by name.
+    pub fn list<P: AsRef<Path>>(store_dir: P) -> io::Result<Vec<SavedSearch>> {
+        let store_dir = store_dir.as_ref();
+        let mut names = Vec::new();
+        for entry in fs::read_dir(store_dir)? {
+            let file_name = entry?.file_name();
+            let name = file_name
+                .to_str()
+                .and_then(|n| n.strip_suffix(EXTENSION)?.strip_suffix('.'));
+            if let Some(name) = name {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        names
+            .iter()
+            .map(|name| SavedSearch::open(store_dir, name))
+            .collect()
+    }
+
+    /// Remove the search from its store.
+    pub fn delete(self) -> io::Result<()> {
+        fs::remove_file(self.path)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn query(&self) -> &FindQuery {
+        &self.query
+    }
+
+    /// Run the search and collect the sorted results.
+    pub fn materialize(&self) -> io::Result<Vec<PathBuf>> {
+        self.query.run()
+    }
+
+    /// Run the search lazily.
+    pub fn iter(&self) -> io::Result<FindIter> {
+        self.query.iter()
+    }
+
+    /// Run the search and keep its results current with a [`Watcher`] on the root.
+    pub fn watch(&self) -> io::Result<LiveSearch> {
+        let watcher = Watcher::new(self.query.root(), true)?;
+        let results = self.query.iter()?.collect::<io::Result<_>>()?;
+        Ok(LiveSearch {
+            query: self.query.clone(),
+            watcher,
+            results,
+        })
+    }
+}
+
+/// The results of a [`SavedSearch`], updated from file system changes.
+pub struct LiveSearch {
+    query: FindQuery,
+    watcher: Watcher,
+    results: BTreeSet<PathBuf>,
+}
+
+impl LiveSearch {
+    /// The current results, sorted.
+    pub fn results(&self) -> &BTreeSet<PathBuf> {
+        &self.results
+    }
+
+    /// Apply the changes since the last call.
+    ///
+    /// # Returns
+    /// How the results changed: `Created` for new matches, `Removed` for paths
+    /// that were deleted or stopped matching and `Modified` for matches that changed.
+    pub fn refresh(&mut self) -> io::Result<Vec<Event>> {
+        let mut changes = Vec::new();
+        for event in self.watcher.poll()? {
+            let matches = match fs::symlink_metadata(&event.path) {
+                Ok(metadata) => self.query.matches(&event.path, &metadata),
+                Err(e) if e.kind() == ErrorKind::NotFound => false,
+                Err(e) => return Err(e),
+            };
+            let was_result = self.results.contains(&event.path);
+            let kind = match (was_result, matches) {
+                (false, true) => {
+                    self.results.insert(event.path.clone());
+                    EventKind::Created
+                }
+                (true, false) => {
+                    self.results.remove(&event.path);
+                    EventKind::Removed
+                }
+                (true, true) if event.kind == EventKind::Modified => EventKind::Modified,
+                _ => continue,
+            };
+            changes.push(Event {
+                kind,
+                path: event.path,
+            });
+        }
+        Ok(changes)
+    }
+}
+
+fn search_path(store_dir: &Path, name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid saved search name `{}`", name),
+        ));
+    }
+    Ok(store_dir.join(format!("{}.{}", name, EXTENSION)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_search_persists_and_stays_fresh() {
+        // arrange
+        let dir = "assets/search_test";
+        let _ = fs::remove_dir_all(dir);
+        let (store, files) = (format!("{}/searches", dir), format!("{}/files", dir));
+        fs::create_dir_all(&files).unwrap();
+        fs::write(format!("{}/a.log", files), "").unwrap();
+        fs::write(format!("{}/b.txt", files), "").unwrap();
+        SavedSearch::create(&store, "logs", FindQuery::new(&files).extension("log")).unwrap();
+
+        // act
+        let search = SavedSearch::open(&store, "logs").unwrap();
+        let materialized = search.materialize().unwrap();
+        let mut live = search.watch().unwrap();
+        fs::write(format!("{}/c.log", files), "").unwrap();
+        fs::remove_file(format!("{}/a.log", files)).unwrap();
+        let mut changes = live.refresh().unwrap();
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        let listed = SavedSearch::list(&store).unwrap();
+        let invalid = SavedSearch::create(&store, "../escape", FindQuery::new(&files));
+
+        // assert
+        let file = |name: &str| Path::new(&files).join(name);
+        assert_eq!(vec![file("a.log")], materialized);
+        assert_eq!(
+            vec![
+                Event {
+                    kind: EventKind::Removed,
+                    path: file("a.log")
+                },
+                Event {
+                    kind: EventKind::Created,
+                    path: file("c.log")
+                },
+            ],
+            changes
+        );
+        assert_eq!(&BTreeSet::from([file("c.log")]), live.results());
+        assert_eq!(vec![search], listed);
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
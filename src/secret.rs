@@ -0,0 +1,370 @@
+//! Reading and writing secrets such as tokens and private keys.
+use crate::temp_sibling;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    ptr,
+    sync::{
+        atomic::{compiler_fence, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// How many locked secrets share each locked page, keyed by the page's address.
+/// Locking works on whole pages and isn't counted by the kernel, so a page is
+/// only unlocked once no secret on it is locked any more.
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Whether to lock a secret's memory so it can't be written to swap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryLock {
+    /// Leave the memory pageable.
+    #[default]
+    Off,
+    /// Lock the memory if the platform and its limits allow it, otherwise carry
+    /// on unlocked. Check [`SecretBytes::is_locked`] for the outcome.
+    BestEffort,
+    /// Fail if the memory can't be locked.
+    Required,
+}
+
+/// Options for [`read_secret_with`].
+#[derive(Debug, Clone, Default)]
+pub struct ReadSecretOptions {
+    /// Lock the buffer holding the secret into RAM with `mlock` or `VirtualLock`.
+    pub lock_memory: MemoryLock,
+}
+
+/// Bytes that are overwritten with zeros when dropped and never shown by `Debug`.
+pub struct SecretBytes {
+    bytes: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    /// The secret itself. Avoid copying it into buffers that aren't zeroized.
+    pub fn expose(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The buffer itself, for transforming the secret in place.
+    #[cfg(feature = "encrypt")]
+    pub(crate) fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+
+    /// Returns `true` if the secret's memory is locked and can't be swapped out.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Replace the buffer with an empty one of `capacity` bytes, locked according
+    /// to `lock`, moving the contents over.
+    fn reallocate(&mut self, capacity: usize, lock: MemoryLock) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(capacity);
+        let locked = match lock {
+            MemoryLock::Off => false,
+            MemoryLock::BestEffort => lock_memory(bytes.as_ptr(), bytes.capacity()).is_ok(),
+            MemoryLock::Required => {
+                lock_memory(bytes.as_ptr(), bytes.capacity())?;
+                true
+            }
+        };
+        bytes.extend_from_slice(&self.bytes);
+        let old = std::mem::replace(self, SecretBytes { bytes, locked });
+        drop(old);
+        Ok(())
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        SecretBytes {
+            bytes,
+            locked: false,
+        }
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        zeroize(&mut self.bytes);
+        if self.locked {
+            unlock_memory(self.bytes.as_ptr(), self.bytes.capacity());
+        }
+    }
+}
+
+/// Helper function to overwrite the whole allocation of `bytes` with zeros.
+fn zeroize(bytes: &mut Vec<u8>) {
+    let start = bytes.as_mut_ptr();
+    for i in 0..bytes.capacity() {
+        // SAFETY: `i` is within the allocation. Volatile writes keep the compiler
+        // from removing stores to memory that is about to be freed.
+        unsafe { ptr::write_volatile(start.add(i), 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+    bytes.clear();
+}
+
+/// Read the secret stored at `path`.
+///
+/// The buffer is sized from the file's metadata up front, so the secret is read
+/// straight into its final allocation. If the file grows while it is read, the
+/// old allocation is zeroized before it is freed.
+pub fn read_secret<P: AsRef<Path>>(path: P) -> io::Result<SecretBytes> {
+    read_secret_with(path, &ReadSecretOptions::default())
+}
+
+/// Like [`read_secret`], with options such as locking the secret into RAM.
+///
+/// With [`MemoryLock::Required`], this fails if locking isn't possible, for
+/// example when `RLIMIT_MEMLOCK` is too low or the platform has no way to lock
+/// memory. The buffer is locked before any of the secret is read into it.
+pub fn read_secret_with<P: AsRef<Path>>(
+    path: P,
+    options: &ReadSecretOptions,
+) -> io::Result<SecretBytes> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let mut secret = SecretBytes::from(Vec::new());
+    // One spare byte tells a complete read apart from a file that grew.
+    secret.reallocate(len + 1, options.lock_memory)?;
+    loop {
+        if secret.bytes.len() == secret.bytes.capacity() {
+            secret.reallocate(secret.bytes.capacity() * 2, options.lock_memory)?;
+        }
+        let bytes = &mut secret.bytes;
+        let filled = bytes.len();
+        bytes.resize(bytes.capacity(), 0);
+        match file.read(&mut bytes[filled..]) {
+            Ok(0) => {
+                bytes.truncate(filled);
+                return Ok(secret);
+            }
+            Ok(n) => bytes.truncate(filled + n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => bytes.truncate(filled),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Helper function to lock the `len` bytes at `start` into RAM, counting the
+/// lock on every page they touch.
+fn lock_memory(start: *const u8, len: usize) -> io::Result<()> {
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(PoisonError::into_inner);
+    lock_range(start, len)?;
+    for page in pages(start, len) {
+        *locked.entry(page).or_insert(0) += 1;
+    }
+    Ok(())
+}
+
+/// Helper function to undo [`lock_memory`], unlocking the pages no other
+/// locked secret shares.
+fn unlock_memory(start: *const u8, len: usize) {
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(PoisonError::into_inner);
+    for page in pages(start, len) {
+        match locked.get_mut(&page) {
+            Some(count) if *count > 1 => *count -= 1,
+            _ => {
+                locked.remove(&page);
+                unlock_range(page as *const u8, page_size());
+            }
+        }
+    }
+}
+
+/// The addresses of the pages the `len` bytes at `start` touch.
+fn pages(start: *const u8, len: usize) -> impl Iterator<Item = usize> {
+    let page = page_size();
+    let first = start as usize / page * page;
+    let end = start as usize + len.max(1);
+    (first..end).step_by(page)
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf takes no pointers.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(windows)]
+fn page_size() -> usize {
+    use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+    // SAFETY: a zeroed SYSTEM_INFO is a valid value of the plain C struct, and
+    // GetSystemInfo only writes to it.
+    unsafe {
+        let mut info: SYSTEM_INFO = std::mem::zeroed();
+        GetSystemInfo(&mut info);
+        info.dwPageSize as usize
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn page_size() -> usize {
+    4096
+}
+
+/// Helper function to lock the `len` bytes at `start` into RAM.
+#[cfg(unix)]
+fn lock_range(start: *const u8, len: usize) -> io::Result<()> {
+    // SAFETY: the caller passes a live allocation.
+    if unsafe { libc::mlock(start.cast(), len) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    Err(io::Error::new(
+        e.kind(),
+        format!(
+            "could not lock {} bytes of memory: {} (check RLIMIT_MEMLOCK)",
+            len, e
+        ),
+    ))
+}
+
+/// Helper function to lock the `len` bytes at `start` into RAM.
+#[cfg(windows)]
+fn lock_range(start: *const u8, len: usize) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualLock;
+
+    // SAFETY: the caller passes a live allocation.
+    if unsafe { VirtualLock(start.cast(), len) } != 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    Err(io::Error::new(
+        e.kind(),
+        format!(
+            "could not lock {} bytes of memory: {} (check the working set size)",
+            len, e
+        ),
+    ))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock_range(_start: *const u8, _len: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "locking memory is not supported on this platform",
+    ))
+}
+
+/// Helper function to undo [`lock_range`].
+fn unlock_range(start: *const u8, len: usize) {
+    // SAFETY: the range is a page that was locked.
+    #[cfg(unix)]
+    unsafe {
+        libc::munlock(start.cast(), len);
+    }
+    #[cfg(windows)]
+    unsafe {
+        windows_sys::Win32::System::Memory::VirtualUnlock(start.cast(), len);
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = (start, len);
+}
+
+/// Write `secret` to `path`, readable and writable only by the owner.
+///
+/// The secret goes to a new file created with mode `0600` that then replaces
+/// `path`, so it is never readable by others, not even briefly, and an existing
+/// file with looser permissions is not reused. On Windows the file inherits the
+/// ACL of its directory.
+pub fn write_secret<P: AsRef<Path>>(path: P, secret: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = temp_sibling(path, "secret");
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = (|| {
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(secret)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_round_trip() {
+        // arrange
+        let dir = "assets/secret_round_trip_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/token", dir);
+        fs::write(&path, "old").unwrap();
+
+        // act
+        write_secret(&path, b"hunter2").unwrap();
+        let secret = read_secret(&path).unwrap();
+        let options = ReadSecretOptions {
+            lock_memory: MemoryLock::BestEffort,
+        };
+        let locked = read_secret_with(&path, &options).unwrap();
+        let debug = format!("{:?}", secret);
+
+        // assert
+        assert_eq!(b"hunter2", secret.expose());
+        assert_eq!(b"hunter2", locked.expose());
+        assert!(!secret.is_locked());
+        assert!(!debug.contains("hunter2"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_locks_are_counted_per_page() {
+        // arrange
+        let page = page_size();
+        let buf = vec![0u8; 2 * page];
+        let start = buf.as_ptr().wrapping_add(buf.as_ptr().align_offset(page));
+        let second = start.wrapping_add(16);
+        let is_locked = || LOCKED_PAGES.lock().unwrap().contains_key(&(start as usize));
+
+        // act
+        // Both secrets fit on one page, which only this test's buffer uses.
+        let locked = lock_memory(start, 16).is_ok() && lock_memory(second, 16).is_ok();
+        unlock_memory(start, 16);
+        let still_locked = is_locked();
+        unlock_memory(second, 16);
+
+        // assert
+        if locked {
+            assert!(still_locked);
+            assert!(!is_locked());
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Memory-mapped files shared between processes.
+use crate::pidfile::process_alive;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    slice,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+const REGION_MAGIC: u32 = u32::from_le_bytes(*b"FMSR");
+
+/// Bytes before the data: magic, lock word and data size, padded to a cache line.
+const HEADER_LEN: usize = 64;
+const LOCK_OFFSET: usize = 4;
+const SIZE_OFFSET: usize = 8;
+
+/// A file mapped into memory by several processes, with a lock guarding its data.
+///
+/// The file starts with a small header holding the lock word, which stores the PID
+/// of the process holding the lock. If that process dies while holding it, the
+/// next process to wait for the lock takes it over.
+pub struct SharedRegion {
+    path: PathBuf,
+    map: Mapping,
+    size: usize,
+}
+
+// SAFETY: the mapping is plain shared memory, and its data is only handed out
+// through `RegionGuard`, which holds the lock.
+unsafe impl Send for SharedRegion {}
+unsafe impl Sync for SharedRegion {}
+
+impl SharedRegion {
+    /// Create the file at `path` with room for `size` bytes of data, all zero,
+    /// replacing any existing region there.
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len((HEADER_LEN + size) as u64)?;
+        let map = Mapping::new(&file, HEADER_LEN + size, true)?;
+        let region = SharedRegion { path, map, size };
+        region
+            .word_u64(SIZE_OFFSET)
+            .store(size as u64, Ordering::Relaxed);
+        // Written last, so `open` never accepts a region without its size.
+        region.word(0).store(REGION_MAGIC, Ordering::Release);
+        Ok(region)
+    }
+
+    /// Map the existing region at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let len = file.metadata()?.len() as usize;
+        let message = format!("not a shared region: {}", path.display());
+        let invalid = || io::Error::new(ErrorKind::InvalidData, message.clone());
+        if len < HEADER_LEN {
+            return Err(invalid());
+        }
+        let map = Mapping::new(&file, len, true)?;
+        let mut region = SharedRegion { path, map, size: 0 };
+        if region.word(0).load(Ordering::Acquire) != REGION_MAGIC {
+            return Err(invalid());
+        }
+        let size = region.word_u64(SIZE_OFFSET).load(Ordering::Relaxed) as usize;
+        if size > len - HEADER_LEN {
+            return Err(invalid());
+        }
+        region.size = size;
+        Ok(region)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of data bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Block until the lock is held and return access to the data.
+    pub fn lock(&self) -> RegionGuard<'_> {
+        let pid = std::process::id();
+        let lock = self.word(LOCK_OFFSET);
+        loop {
+            match lock.compare_exchange(0, pid, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return RegionGuard { region: self },
+                Err(holder) if holder != pid && !process_alive(holder) => {
+                    // The holder died; take over unless another waiter got there first.
+                    if lock
+                        .compare_exchange(holder, pid, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        return RegionGuard { region: self };
+                    }
+                }
+                Err(holder) => wait(lock, holder),
+            }
+        }
+    }
+
+    /// Take the lock if it is free and return access to the data.
+    pub fn try_lock(&self) -> Option<RegionGuard<'_>> {
+        self.word(LOCK_OFFSET)
+            .compare_exchange(0, std::process::id(), Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RegionGuard { region: self })
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: the header lies within the mapping, which is page aligned.
+        unsafe { &*(self.map.ptr.add(offset) as *const AtomicU32) }
+    }
+
+    fn word_u64(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: the header lies within the mapping, which is page aligned.
+        unsafe { &*(self.map.ptr.add(offset) as *const AtomicU64) }
+    }
+}
+
+/// Access to a [`SharedRegion`]'s data while holding its lock.
+pub struct RegionGuard<'a> {
+    region: &'a SharedRegion,
+}
+
+impl Deref for RegionGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the data follows the header within the mapping, and the lock
+        // keeps other users of the region away from it.
+        unsafe { slice::from_raw_parts(self.region.map.ptr.add(HEADER_LEN), self.region.size) }
+    }
+}
+
+impl DerefMut for RegionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for `deref`.
+        unsafe { slice::from_raw_parts_mut(self.region.map.ptr.add(HEADER_LEN), self.region.size) }
+    }
+}
+
+impl Drop for RegionGuard<'_> {
+    fn drop(&mut self) {
+        let lock = self.region.word(LOCK_OFFSET);
+        lock.store(0, Ordering::Release);
+        wake(lock);
+    }
+}
+
+/// Helper function to wait until `word` may no longer be `value`.
+///
+/// The wait is short, so a holder that died is noticed soon after.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wait(word: &AtomicU32, value: u32) {
+    let timeout = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 10_000_000,
+    };
+    // SAFETY: the word lives in shared memory for the duration of the call.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            value,
+            &timeout as *const libc::timespec,
+        );
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn wait(_word: &AtomicU32, _value: u32) {
+    std::thread::sleep(std::time::Duration::from_millis(1));
+}
+
+/// Helper function to wake every process waiting on `word`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wake(word: &AtomicU32) {
+    // SAFETY: the word lives in shared memory for the duration of the call.
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, i32::MAX);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn wake(_word: &AtomicU32) {}
+
+/// A shared mapping of the first `len` bytes of a file, which must not be empty.
+pub(crate) struct Mapping {
+    pub(crate) ptr: *mut u8,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(windows)]
+    handle: windows_sys::Win32::Foundation::HANDLE,
+}
+
+impl Mapping {
+    #[cfg(unix)]
+    pub(crate) fn new(file: &File, len: usize, writable: bool) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let protection = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        // SAFETY: maps `len` bytes of an open file; failure is checked below.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                protection,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn new(file: &File, len: usize, writable: bool) -> io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::{
+            Foundation::CloseHandle,
+            System::Memory::{
+                CreateFileMappingW, MapViewOfFile, FILE_MAP_ALL_ACCESS, FILE_MAP_READ,
+                PAGE_READONLY, PAGE_READWRITE,
+            },
+        };
+
+        let (protection, access) = if writable {
+            (PAGE_READWRITE, FILE_MAP_ALL_ACCESS)
+        } else {
+            (PAGE_READONLY, FILE_MAP_READ)
+        };
+        // SAFETY: both handles are checked, and the mapping handle is closed on failure.
+        unsafe {
+            let handle = CreateFileMappingW(
+                file.as_raw_handle(),
+                std::ptr::null(),
+                protection,
+                0,
+                0,
+                std::ptr::null(),
+            );
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let view = MapViewOfFile(handle, access, 0, 0, len);
+            if view.Value.is_null() {
+                let e = io::Error::last_os_error();
+                CloseHandle(handle);
+                return Err(e);
+            }
+            Ok(Mapping {
+                ptr: view.Value.cast(),
+                handle,
+            })
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub(crate) fn new(_file: &File, _len: usize, _writable: bool) -> io::Result<Self> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "memory mapping is not supported on this platform",
+        ))
+    }
+
+    /// Write changes in the first `len` bytes back to the file.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn flush(&self, len: usize) -> io::Result<()> {
+        // SAFETY: the range lies within the mapping.
+        #[cfg(unix)]
+        if unsafe { libc::msync(self.ptr.cast(), len, libc::MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(windows)]
+        if unsafe { windows_sys::Win32::System::Memory::FlushViewOfFile(self.ptr.cast(), len) } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(not(any(unix, windows)))]
+        let _ = len;
+        Ok(())
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is not used after this.
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+        #[cfg(windows)]
+        unsafe {
+            use windows_sys::Win32::{
+                Foundation::CloseHandle,
+                System::Memory::{UnmapViewOfFile, MEMORY_MAPPED_VIEW_ADDRESS},
+            };
+            UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS {
+                Value: self.ptr.cast(),
+            });
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::Arc, thread};
+
+    #[test]
+    fn shared_region_lock_works() {
+        // arrange
+        let dir = "assets/shm_lock_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/region", dir);
+        let producer = Arc::new(SharedRegion::create(&path, 8).unwrap());
+        // Far above any real PID, so it names a process that has exited.
+        producer
+            .word(LOCK_OFFSET)
+            .store(1073741800, Ordering::Relaxed);
+
+        // act
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let region = Arc::clone(&producer);
+                thread::spawn(move || {
+                    for _ in 0..250 {
+                        let mut data = region.lock();
+                        let value = u64::from_le_bytes(data[..8].try_into().unwrap());
+                        data.copy_from_slice(&(value + 1).to_le_bytes());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let consumer = SharedRegion::open(&path).unwrap();
+        let held = consumer.lock();
+        let contended = producer.try_lock().is_none();
+        let total = u64::from_le_bytes(held[..8].try_into().unwrap());
+        drop(held);
+
+        // assert
+        assert_eq!(8, consumer.size());
+        assert_eq!(1000, total);
+        assert!(contended);
+        drop((producer, consumer));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! Flush or close every open writer at shutdown.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+
+/// A writer's buffer, or `None` once it was closed by [`close_all`].
+type Slot = Mutex<Option<BufWriter<File>>>;
+
+/// Every live [`ManagedWriter`], pruned as writers are dropped.
+static REGISTRY: Mutex<Vec<Weak<Slot>>> = Mutex::new(Vec::new());
+
+/// A buffered file writer that [`flush_all`] and [`close_all`] can reach.
+///
+/// Applications can hand these out freely and still make everything durable
+/// from a single shutdown hook, without keeping track of the handles.
+#[derive(Debug)]
+pub struct ManagedWriter {
+    path: PathBuf,
+    inner: Arc<Slot>,
+}
+
+impl ManagedWriter {
+    fn register(path: &Path, file: File) -> Self {
+        let inner = Arc::new(Mutex::new(Some(BufWriter::new(file))));
+        let mut registry = REGISTRY.lock().unwrap();
+        registry.retain(|writer| writer.strong_count() > 0);
+        registry.push(Arc::downgrade(&inner));
+        ManagedWriter {
+            path: path.to_path_buf(),
+            inner,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `true` once [`close_all`] has closed this writer.
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().unwrap().is_none()
+    }
+}
+
+impl Write for ManagedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.lock().unwrap().as_mut() {
+            Some(writer) => writer.write(buf),
+            None => Err(closed(&self.path)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.lock().unwrap().as_mut() {
+            Some(writer) => writer.flush(),
+            None => Err(closed(&self.path)),
+        }
+    }
+}
+
+fn closed(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::BrokenPipe,
+        format!("writer was closed at shutdown: {}", path.display()),
+    )
+}
+
+/// Open a [`ManagedWriter`] for the file at `file_path`, creating it if needed.
+/// If `truncate == true`, the file will be truncated before writing.
+pub fn open_managed_writer<P: AsRef<Path>>(
+    file_path: P,
+    truncate: bool,
+) -> io::Result<ManagedWriter> {
+    let path = file_path.as_ref();
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(truncate)
+        .create(true)
+        .open(path)?;
+    Ok(ManagedWriter::register(path, file))
+}
+
+/// Open a [`ManagedWriter`] that appends to the file at `file_path`, creating it if needed.
+pub fn open_managed_appender<P: AsRef<Path>>(file_path: P) -> io::Result<ManagedWriter> {
+    let path = file_path.as_ref();
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    Ok(ManagedWriter::register(path, file))
+}
+
+/// Helper function to take a snapshot of the live writers, so none of them are
+/// locked while the registry is.
+fn live_writers() -> Vec<Arc<Slot>> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// Flush every open [`ManagedWriter`] and sync its file to disk.
+///
+/// Every writer is attempted even if some fail; the first error is returned.
+/// This takes locks, so call it from a shutdown hook or a thread that handles
+/// signals, not from inside a signal handler.
+///
+/// # Returns
+/// The number of writers flushed.
+pub fn flush_all() -> io::Result<usize> {
+    let mut flushed = 0;
+    let mut first_error = None;
+    for writer in live_writers() {
+        if let Some(writer) = writer.lock().unwrap().as_mut() {
+            match writer.flush().and_then(|_| writer.get_ref().sync_all()) {
+                Ok(()) => flushed += 1,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+    }
+    first_error.map_or(Ok(flushed), Err)
+}
+
+/// Flush, sync and close every open [`ManagedWriter`]. Later writes to them fail
+/// with `BrokenPipe`.
+///
+/// Every writer is closed even if some fail; the first error is returned.
+///
+/// # Returns
+/// The number of writers closed.
+pub fn close_all() -> io::Result<usize> {
+    let mut closed = 0;
+    let mut first_error = None;
+    for writer in live_writers() {
+        let Some(writer) = writer.lock().unwrap().take() else {
+            continue;
+        };
+        let result = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all());
+        match result {
+            Ok(()) => closed += 1,
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    first_error.map_or(Ok(closed), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn flush_and_close_all_work() {
+        // arrange
+        let dir = "assets/shutdown_flush_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (log, data) = (format!("{}/app.log", dir), format!("{}/data.txt", dir));
+        let mut appender = open_managed_appender(&log).unwrap();
+        let mut writer = open_managed_writer(&data, true).unwrap();
+
+        // act
+        appender.write_all(b"started\n").unwrap();
+        writer.write_all(b"partial").unwrap();
+        let unflushed = fs::read_to_string(&log).unwrap();
+        flush_all().unwrap();
+        let flushed = fs::read_to_string(&log).unwrap();
+        writer.write_all(b" and the rest").unwrap();
+        close_all().unwrap();
+        let late = appender.write_all(b"too late\n");
+
+        // assert
+        assert_eq!("", unflushed);
+        assert_eq!("started\n", flushed);
+        assert_eq!("partial and the rest", fs::read_to_string(&data).unwrap());
+        assert!(writer.is_closed());
+        assert_eq!(ErrorKind::BrokenPipe, late.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
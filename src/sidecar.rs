@@ -0,0 +1,196 @@
+//! Keep SHA-256 sidecars current for files written through the crate.
+use crate::{checksum::sha256_reader, conditional::update_file, write_atomic};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The per-directory manifest used by [`SidecarMode::Manifest`], in the format
+/// of `sha256sum`.
+pub const MANIFEST_NAME: &str = "SHA256SUMS";
+
+/// How integrity data is kept for written files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SidecarMode {
+    #[default]
+    Off,
+    /// A `<name>.sha256` file next to each written file.
+    PerFile,
+    /// One line per written file in a [`MANIFEST_NAME`] file in its directory.
+    Manifest,
+}
+
+/// Directory roots with sidecars enabled, as canonical paths.
+static ROOTS: Mutex<Vec<(PathBuf, SidecarMode)>> = Mutex::new(Vec::new());
+
+/// Keep checksums current for every file under `root` written by
+/// [`write_to_file`], [`append_to_file`], [`write_if_match`], [`update_file`],
+/// [`render_template_to_file`] and the [`transform`](crate::transform) functions.
+/// [`SidecarMode::Off`] stops it again. The setting for the deepest root
+/// containing a file wins.
+///
+/// Writers handed out to the caller, such as [`open_buffered_file_writer`],
+/// cannot be tracked; call [`update_sidecar`] when done with them.
+///
+/// [`write_to_file`]: crate::write_to_file
+/// [`append_to_file`]: crate::append_to_file
+/// [`write_if_match`]: crate::conditional::write_if_match
+/// [`render_template_to_file`]: crate::template::render_template_to_file
+/// [`open_buffered_file_writer`]: crate::open_buffered_file_writer
+pub fn set_sidecar_mode<P: AsRef<Path>>(root: P, mode: SidecarMode) -> io::Result<()> {
+    let root = root.as_ref().canonicalize()?;
+    let mut roots = ROOTS.lock().unwrap();
+    roots.retain(|(r, _)| *r != root);
+    if mode != SidecarMode::Off {
+        roots.push((root, mode));
+    }
+    Ok(())
+}
+
+/// The [`SidecarMode`] that applies to the file at `path`.
+pub fn sidecar_mode<P: AsRef<Path>>(path: P) -> SidecarMode {
+    let roots = ROOTS.lock().unwrap();
+    if roots.is_empty() {
+        return SidecarMode::Off;
+    }
+    let path = path.as_ref();
+    let Some(dir) = path
+        .parent()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .and_then(|p| p.canonicalize().ok())
+    else {
+        return SidecarMode::Off;
+    };
+    roots
+        .iter()
+        .filter(|(root, _)| dir.starts_with(root))
+        .max_by_key(|(root, _)| root.as_os_str().len())
+        .map_or(SidecarMode::Off, |(_, mode)| *mode)
+}
+
+/// Record the current checksum of the file at `path` according to its
+/// [`sidecar_mode`]. Does nothing when it is off or `path` is itself a sidecar.
+pub fn update_sidecar<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mode = sidecar_mode(path);
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(());
+    };
+    if mode == SidecarMode::Off || name == MANIFEST_NAME || name.ends_with(".sha256") {
+        return Ok(());
+    }
+    let line = format!("{}  {}\n", sha256_reader(File::open(path)?)?, name);
+    match mode {
+        SidecarMode::Off => Ok(()),
+        SidecarMode::PerFile => write_atomic(&sidecar_path(path), line.as_bytes()),
+        SidecarMode::Manifest => {
+            update_file(path.with_file_name(MANIFEST_NAME), |manifest| {
+                let mut lines: Vec<&str> = manifest
+                    .lines()
+                    .filter(|l| manifest_entry(l).is_none_or(|(_, n)| n != name))
+                    .collect();
+                lines.push(line.trim_end());
+                lines.sort_by_key(|l| manifest_entry(l).map(|(_, n)| n));
+                lines.iter().map(|l| format!("{}\n", l)).collect()
+            })?;
+            Ok(())
+        }
+    }
+}
+
+/// Check the file at `path` against its `.sha256` sidecar or the manifest in
+/// its directory, whichever exists.
+///
+/// # Returns
+/// `Ok(true)` if the checksum matches and `Ok(false)` if it doesn't.
+///
+/// # Errors
+/// `NotFound` if neither records the file.
+pub fn verify_sidecar<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let path = path.as_ref();
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let recorded = match fs::read_to_string(sidecar_path(path)) {
+        Ok(sidecar) => sidecar
+            .lines()
+            .find_map(manifest_entry)
+            .map(|(hash, _)| hash.to_owned()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            match fs::read_to_string(path.with_file_name(MANIFEST_NAME)) {
+                Ok(manifest) => manifest
+                    .lines()
+                    .filter_map(manifest_entry)
+                    .find(|(_, n)| *n == name)
+                    .map(|(hash, _)| hash.to_owned()),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(e) => return Err(e),
+    };
+    let recorded = recorded.ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("no checksum recorded for {}", path.display()),
+        )
+    })?;
+    Ok(sha256_reader(File::open(path)?)?.eq_ignore_ascii_case(&recorded))
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or(path.as_os_str()));
+    name.push(".sha256");
+    path.with_file_name(name)
+}
+
+/// Helper function to split a `sha256sum` line into hash and file name.
+/// Binary mode entries (`hash *name`) are accepted too.
+fn manifest_entry(line: &str) -> Option<(&str, &str)> {
+    let (hash, name) = line.split_once(' ')?;
+    Some((hash, name.strip_prefix([' ', '*'])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{append_to_file, write_to_file};
+
+    #[test]
+    fn sidecars_follow_writes() {
+        // arrange
+        let dir = "assets/sidecar_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let nested = format!("{}/nested", dir);
+        fs::create_dir_all(&nested).unwrap();
+        let (a, b) = (format!("{}/a.txt", dir), format!("{}/b.txt", nested));
+
+        // act
+        set_sidecar_mode(dir, SidecarMode::PerFile).unwrap();
+        set_sidecar_mode(&nested, SidecarMode::Manifest).unwrap();
+        write_to_file(&a, true, "alpha").unwrap();
+        append_to_file(&a, "more").unwrap();
+        write_to_file(&b, true, "beta").unwrap();
+        set_sidecar_mode(dir, SidecarMode::Off).unwrap();
+        set_sidecar_mode(&nested, SidecarMode::Off).unwrap();
+        let verified = (verify_sidecar(&a).unwrap(), verify_sidecar(&b).unwrap());
+        fs::write(&b, "tampered").unwrap();
+
+        // assert
+        assert_eq!((true, true), verified);
+        assert!(!verify_sidecar(&b).unwrap());
+        assert!(Path::new(&format!("{}.sha256", a)).exists());
+        assert!(fs::read_to_string(format!("{}/{}", nested, MANIFEST_NAME))
+            .unwrap()
+            .ends_with("  b.txt\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
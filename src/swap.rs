@@ -0,0 +1,456 @@
+//! Blue/green file updates that can be rolled back, and atomic directory swaps.
+use crate::temp_sibling;
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A file whose contents are replaced by publishing numbered versions.
+///
+/// Each version is stored next to the file as `<name>.v<N>`. On Unix the file
+/// itself is a symlink to the live version, so readers always open a complete
+/// payload. Elsewhere it is a pointer file holding the live version's file name;
+/// use [`SwapFile::current_path`] or [`SwapFile::read`] to follow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapFile {
+    path: PathBuf,
+}
+
+impl SwapFile {
+    /// Manage the file at `path`. Nothing is created until the first publish.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        SwapFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file holding `version`.
+    pub fn version_path(&self, version: u64) -> PathBuf {
+        let mut name = self.file_name();
+        name.push(format!(".v{}", version));
+        self.path.with_file_name(name)
+    }
+
+    /// The live version, or `None` if nothing was published yet.
+    pub fn current_version(&self) -> io::Result<Option<u64>> {
+        #[cfg(unix)]
+        let target = fs::read_link(&self.path).map(OsString::from);
+        #[cfg(not(unix))]
+        let target = fs::read_to_string(&self.path).map(|s| OsString::from(s.trim()));
+        let target = match target {
+            Ok(target) => target,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match target.to_str().and_then(|t| self.parse_version(t)) {
+            Some(version) => Ok(Some(version)),
+            None => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not point to a version", self.path.display()),
+            )),
+        }
+    }
+
+    /// The file holding the live version.
+    pub fn current_path(&self) -> io::Result<PathBuf> {
+        match self.current_version()? {
+            Some(version) => Ok(self.version_path(version)),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("nothing published at {}", self.path.display()),
+            )),
+        }
+    }
+
+    /// The contents of the live version.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(self.current_path()?)
+    }
+
+    /// The contents of `version`, whether live or not.
+    ///
+    /// # Errors
+    /// `NotFound` if the version was never published or has been pruned.
+    pub fn read_version(&self, version: u64) -> io::Result<Vec<u8>> {
+        fs::read(self.version_path(version)).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => io::Error::new(
+                ErrorKind::NotFound,
+                format!("no version {} of {}", version, self.path.display()),
+            ),
+            _ => e,
+        })
+    }
+
+    /// The newest stored version published at or before `time`, going by the
+    /// modification times of the version files.
+    pub fn version_as_of(&self, time: SystemTime) -> io::Result<Option<u64>> {
+        for version in self.versions()?.into_iter().rev() {
+            let published = match fs::metadata(self.version_path(version)) {
+                Ok(metadata) => metadata.modified()?,
+                // Pruned since listing.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if published <= time {
+                return Ok(Some(version));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The contents of the newest version published at or before `time`.
+    ///
+    /// Rollbacks are not tracked, so this is the newest version written by
+    /// then, which is not necessarily the one that was live.
+    ///
+    /// # Errors
+    /// `NotFound` if no stored version is that old.
+    pub fn read_as_of(&self, time: SystemTime) -> io::Result<Vec<u8>> {
+        match self.version_as_of(time)? {
+            Some(version) => self.read_version(version),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no version of {} as of then", self.path.display()),
+            )),
+        }
+    }
+
+    /// Every stored version, oldest first.
+    pub fn versions(&self) -> io::Result<Vec<u64>> {
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if let Some(version) = entry?
+                .file_name()
+                .to_str()
+                .and_then(|n| self.parse_version(n))
+            {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Store `contents` as a new version, flush it to disk and make it live.
+    /// Returns the new version number.
+    pub fn publish(&self, contents: &[u8]) -> io::Result<u64> {
+        let mut version = self.versions()?.last().map_or(1, |v| v + 1);
+        loop {
+            let path = self.version_path(version);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let written = file.write_all(contents).and_then(|_| file.sync_all());
+                    if let Err(e) = written {
+                        let _ = fs::remove_file(&path);
+                        return Err(e);
+                    }
+                    break;
+                }
+                // Another publisher took this number.
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => version += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        self.point_to(version)?;
+        Ok(version)
+    }
+
+    /// Make the newest version older than the live one live again and return it.
+    ///
+    /// Newer versions are kept, so a later publish never reuses their numbers.
+    /// Fails with `NotFound` if there is no older version.
+    pub fn rollback(&self) -> io::Result<u64> {
+        let current = self.current_version()?.unwrap_or(u64::MAX);
+        let previous = self.versions()?.into_iter().rev().find(|&v| v < current);
+        match previous {
+            Some(version) => {
+                self.point_to(version)?;
+                Ok(version)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no version to roll back to at {}", self.path.display()),
+            )),
+        }
+    }
+
+    /// Remove all but the newest `keep` versions. The live version is always kept.
+    pub fn prune(&self, keep: usize) -> io::Result<()> {
+        let current = self.current_version()?;
+        let versions = self.versions()?;
+        let old = &versions[..versions.len().saturating_sub(keep)];
+        for &version in old.iter().filter(|&&v| Some(v) != current) {
+            fs::remove_file(self.version_path(version))?;
+        }
+        Ok(())
+    }
+
+    fn file_name(&self) -> OsString {
+        self.path
+            .file_name()
+            .unwrap_or(self.path.as_os_str())
+            .to_os_string()
+    }
+
+    fn parse_version(&self, name: &str) -> Option<u64> {
+        let suffix = name.strip_prefix(self.file_name().to_str()?)?;
+        let digits = suffix.strip_prefix(".v")?;
+        // `u64::from_str` accepts a leading `+`, which would give one version two names.
+        if digits.starts_with('+') {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    /// Atomically make `version` live by replacing the link or pointer file.
+    fn point_to(&self, version: u64) -> io::Result<()> {
+        let target = self.version_path(version);
+        let target = target.file_name().unwrap_or(target.as_os_str());
+        #[cfg(unix)]
+        {
+            let tmp_path = temp_sibling(&self.path, "link");
+            std::os::unix::fs::symlink(target, &tmp_path)?;
+            fs::rename(&tmp_path, &self.path).inspect_err(|_| {
+                let _ = fs::remove_file(&tmp_path);
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            use crate::write_atomic;
+
+            let mut pointer = target.to_string_lossy().into_owned();
+            pointer.push('\n');
+            write_atomic(&self.path, pointer.as_bytes())
+        }
+    }
+}
+
+/// Replace the directory at `live_dir` with the fully prepared `staging_dir`,
+/// so readers see either the old tree or the new one, never a mix.
+///
+/// - If `live_dir` does not exist, `staging_dir` is renamed to it.
+/// - On Unix, if `live_dir` is a symlink, a new link to `staging_dir` is renamed
+///   over it. The directory it pointed to is left for readers still inside it.
+/// - Otherwise the two directories are exchanged atomically where the platform
+///   supports it (`renameat2` on Linux, `renamex_np` on macOS) and the old tree,
+///   now at `staging_dir`, is removed. Elsewhere the old tree is renamed aside
+///   first, leaving a brief moment without `live_dir`, and is put back if the
+///   new one cannot be moved into place.
+///
+/// Both paths must be on the same filesystem.
+pub fn publish_dir<P: AsRef<Path>, Q: AsRef<Path>>(staging_dir: P, live_dir: Q) -> io::Result<()> {
+    let (staging, live) = (staging_dir.as_ref(), live_dir.as_ref());
+    if !fs::metadata(staging)?.is_dir() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a directory", staging.display()),
+        ));
+    }
+    let live_type = match fs::symlink_metadata(live) {
+        Ok(metadata) => metadata.file_type(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return fs::rename(staging, live),
+        Err(e) => return Err(e),
+    };
+    #[cfg(unix)]
+    if live_type.is_symlink() {
+        return flip_symlink(staging, live);
+    }
+    #[cfg(not(unix))]
+    let _ = live_type;
+    if exchange(staging, live)? {
+        return fs::remove_dir_all(staging);
+    }
+    let aside = temp_sibling(live, "old");
+    fs::rename(live, &aside)?;
+    if let Err(e) = fs::rename(staging, live) {
+        let _ = fs::rename(&aside, live);
+        return Err(e);
+    }
+    fs::remove_dir_all(&aside)
+}
+
+/// Helper function to atomically point the symlink at `link` to `target`.
+#[cfg(unix)]
+fn flip_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    // A link next to its target stays valid when both are moved together.
+    let target = match (target.parent(), link.parent(), target.file_name()) {
+        (Some(a), Some(b), Some(name)) if a == b => PathBuf::from(name),
+        _ => fs::canonicalize(target)?,
+    };
+    let tmp_path = temp_sibling(link, "link");
+    std::os::unix::fs::symlink(target, &tmp_path)?;
+    fs::rename(&tmp_path, link).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// Helper function to atomically exchange the entries at `a` and `b`.
+/// Returns `false` if the platform or filesystem cannot.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn exchange(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+    };
+    let (a, b) = (c_path(a)?, c_path(b)?);
+    // SAFETY: `a` and `b` are valid C strings for the duration of the call.
+    #[cfg(target_os = "linux")]
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    // SAFETY: as above.
+    #[cfg(target_os = "macos")]
+    let result = unsafe { libc::renamex_np(a.as_ptr(), b.as_ptr(), libc::RENAME_SWAP) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSUP) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn swap_file_publish_and_rollback() {
+        // arrange
+        let dir = "assets/swap_publish_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let swap = SwapFile::new(format!("{}/app.toml", dir));
+
+        // act
+        let first = swap.publish(b"port = 80").unwrap();
+        let second = swap.publish(b"port = 8080").unwrap();
+        let published = swap.read().unwrap();
+        let rolled_back = swap.rollback().unwrap();
+        let restored = swap.read().unwrap();
+        let third = swap.publish(b"port = 443").unwrap();
+        swap.prune(1).unwrap();
+        let kept = swap.versions().unwrap();
+        let exhausted = swap.rollback();
+
+        // assert
+        assert_eq!((1, 2, 3), (first, second, third));
+        assert_eq!(b"port = 8080".to_vec(), published);
+        assert_eq!(1, rolled_back);
+        assert_eq!(b"port = 80".to_vec(), restored);
+        assert_eq!(vec![3], kept);
+        assert_eq!(ErrorKind::NotFound, exhausted.unwrap_err().kind());
+        #[cfg(unix)]
+        assert_eq!(b"port = 443".to_vec(), fs::read(swap.path()).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn publish_dir_swaps_whole_trees() {
+        // arrange
+        let dir = "assets/swap_publish_dir_test";
+        let _ = fs::remove_dir_all(dir);
+        let (live, staging) = (format!("{}/site", dir), format!("{}/site.new", dir));
+        let stage = |file: &str| {
+            fs::create_dir_all(&staging).unwrap();
+            fs::write(format!("{}/{}", staging, file), file).unwrap();
+        };
+
+        // act
+        stage("v1.html");
+        publish_dir(&staging, &live).unwrap();
+        stage("v2.html");
+        publish_dir(&staging, &live).unwrap();
+        let not_a_dir = publish_dir(format!("{}/v2.html", live), &live);
+
+        // assert
+        let mut files: Vec<_> = fs::read_dir(&live)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(vec![OsString::from("v2.html")], files);
+        assert!(!Path::new(&staging).exists());
+        assert_eq!(ErrorKind::InvalidInput, not_a_dir.unwrap_err().kind());
+        #[cfg(unix)]
+        {
+            let (current, release) = (format!("{}/current", dir), format!("{}/v3", dir));
+            std::os::unix::fs::symlink("site", &current).unwrap();
+            fs::create_dir(&release).unwrap();
+            fs::write(format!("{}/v3.html", release), "v3").unwrap();
+            publish_dir(&release, &current).unwrap();
+            assert_eq!(Path::new("v3"), fs::read_link(&current).unwrap());
+            assert_eq!(
+                "v3",
+                fs::read_to_string(format!("{}/v3.html", current)).unwrap()
+            );
+            assert!(Path::new(&live).exists());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn swap_file_reads_history() {
+        // arrange
+        let dir = "assets/swap_history_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let swap = SwapFile::new(format!("{}/app.toml", dir));
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for (i, contents) in ["port = 80", "port = 8080", "port = 443"]
+            .iter()
+            .enumerate()
+        {
+            let version = swap.publish(contents.as_bytes()).unwrap();
+            let published = start + Duration::from_secs(60 * i as u64);
+            fs::File::options()
+                .write(true)
+                .open(swap.version_path(version))
+                .unwrap()
+                .set_modified(published)
+                .unwrap();
+        }
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // act
+        let second = swap.read_version(2).unwrap();
+        let as_of = swap.read_as_of(at(90)).unwrap();
+        let latest = swap.read_as_of(at(3600)).unwrap();
+        let too_early = swap.read_as_of(at(0) - Duration::from_secs(1));
+        let missing = swap.read_version(9);
+
+        // assert
+        assert_eq!(b"port = 8080".to_vec(), second);
+        assert_eq!(b"port = 8080".to_vec(), as_of);
+        assert_eq!(b"port = 443".to_vec(), latest);
+        assert_eq!(ErrorKind::NotFound, too_early.unwrap_err().kind());
+        assert_eq!(ErrorKind::NotFound, missing.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
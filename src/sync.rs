@@ -0,0 +1,472 @@
+//! One-way directory synchronization.
+use crate::{
+    conflict::{local_side, resolved_path, ConflictResolver, FileConflict},
+    error::{Context, FileManagerError, Operation},
+    watch::{EventKind, Watcher},
+    OverwritePolicy,
+};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Options for [`sync_dir`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    /// Remove files and directories from the destination that do not exist in the source.
+    pub delete_extraneous: bool,
+}
+
+/// What a sync changed in the destination, as paths relative to the sync roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub copied: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+    /// Files left alone because a [`ConflictResolver`] said to skip them.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Make `dst` mirror `src`.
+/// Files are copied when they are missing from `dst` or differ in size or modification time,
+/// and copies keep the source modification time so unchanged files are skipped next time.
+/// Symlinks in `src` are not followed.
+pub fn sync_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: &SyncOptions,
+) -> io::Result<SyncReport> {
+    sync_dir_with_resolver(src, dst, options, &mut OverwritePolicy::Overwrite)
+}
+
+/// Like [`sync_dir`], asking `resolver` what to do with each file in `dst`
+/// that would be replaced. With [`OverwritePolicy::Rename`] the source is
+/// copied next to the existing file, which is kept even with
+/// `delete_extraneous`.
+pub fn sync_dir_with_resolver<P, Q, R>(
+    src: P,
+    dst: Q,
+    options: &SyncOptions,
+    resolver: &mut R,
+) -> io::Result<SyncReport>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: ConflictResolver + ?Sized,
+{
+    let mut report = SyncReport::default();
+    sync_tree(
+        src.as_ref(),
+        dst.as_ref(),
+        Path::new(""),
+        options,
+        resolver,
+        &mut report,
+    )?;
+    Ok(report)
+}
+
+fn sync_tree<R: ConflictResolver + ?Sized>(
+    src: &Path,
+    dst: &Path,
+    rel: &Path,
+    options: &SyncOptions,
+    resolver: &mut R,
+    report: &mut SyncReport,
+) -> io::Result<()> {
+    fs::create_dir_all(dst).context(Operation::Create, dst)?;
+
+    let mut seen = Vec::new();
+    for entry in fs::read_dir(src).context(Operation::Read, src)? {
+        let entry = entry.context(Operation::Read, src)?;
+        let name = entry.file_name();
+        let (src_path, dst_path) = (entry.path(), dst.join(&name));
+        let file_type = entry.file_type().context(Operation::Stat, &src_path)?;
+
+        if file_type.is_dir() {
+            if dst_path.is_file() {
+                fs::remove_file(&dst_path).context(Operation::Delete, &dst_path)?;
+            }
+            sync_tree(
+                &src_path,
+                &dst_path,
+                &rel.join(&name),
+                options,
+                resolver,
+                report,
+            )?;
+        } else if file_type.is_file() {
+            if dst_path.is_dir() {
+                fs::remove_dir_all(&dst_path).context(Operation::Delete, &dst_path)?;
+            }
+            if needs_copy(&src_path, &dst_path)? {
+                let target = if dst_path.is_file() {
+                    let hash = resolver.wants_hashes();
+                    let conflict = FileConflict {
+                        source: local_side(&src_path, hash)?,
+                        destination: local_side(&dst_path, hash)?,
+                    };
+                    let resolution = resolver.resolve(&conflict)?;
+                    resolved_path(resolution, &dst_path, "sync", Path::exists)?
+                } else {
+                    Some(dst_path)
+                };
+                match target.as_deref().and_then(Path::file_name) {
+                    Some(target_name) => {
+                        copy_preserving_mtime(&src_path, &dst.join(target_name))?;
+                        report.copied.push(rel.join(target_name));
+                        seen.push(target_name.to_os_string());
+                    }
+                    None => report.skipped.push(rel.join(&name)),
+                }
+            }
+        } else {
+            continue;
+        }
+        seen.push(name);
+    }
+
+    if options.delete_extraneous {
+        for entry in fs::read_dir(dst).context(Operation::Read, dst)? {
+            let entry = entry.context(Operation::Read, dst)?;
+            if seen.contains(&entry.file_name()) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type().context(Operation::Stat, &path)?.is_dir() {
+                fs::remove_dir_all(&path).context(Operation::Delete, &path)?;
+            } else {
+                fs::remove_file(&path).context(Operation::Delete, &path)?;
+            }
+            report.deleted.push(rel.join(entry.file_name()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `dst` is missing or differs from `src` in size or modification time.
+fn needs_copy(src: &Path, dst: &Path) -> io::Result<bool> {
+    let dst_meta = match fs::metadata(dst) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(FileManagerError::new(Operation::Stat, dst, e).into()),
+    };
+    let src_meta = fs::metadata(src).context(Operation::Stat, src)?;
+    if src_meta.len() != dst_meta.len() {
+        return Ok(true);
+    }
+    // Allow for filesystems that store timestamps with coarser precision.
+    let src_modified = src_meta.modified().context(Operation::Stat, src)?;
+    let dst_modified = dst_meta.modified().context(Operation::Stat, dst)?;
+    let drift = match (src_modified, dst_modified) {
+        (a, b) if a >= b => a.duration_since(b),
+        (a, b) => b.duration_since(a),
+    };
+    Ok(drift.unwrap_or_default() >= Duration::from_secs(1))
+}
+
+fn copy_preserving_mtime(src: &Path, dst: &Path) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).context(Operation::Create, parent)?;
+    }
+    fs::copy(src, dst).context(Operation::Copy, src)?;
+    let modified = fs::metadata(src)
+        .and_then(|metadata| metadata.modified())
+        .context(Operation::Stat, src)?;
+    fs::File::options()
+        .write(true)
+        .open(dst)
+        .and_then(|file| file.set_modified(modified))
+        .context(Operation::Write, dst)?;
+    Ok(())
+}
+
+/// Options for [`sync_watch`].
+#[derive(Debug, Clone, Copy)]
+pub struct SyncWatchOptions {
+    pub sync: SyncOptions,
+    /// How often the source is checked for changes.
+    pub poll_interval: Duration,
+    /// Changes are applied once no new events have arrived for this long.
+    pub debounce: Duration,
+    /// How many times a failed change is retried before it is reported.
+    pub max_retries: u32,
+    /// Delay between retries.
+    pub retry_delay: Duration,
+}
+
+impl Default for SyncWatchOptions {
+    fn default() -> Self {
+        SyncWatchOptions {
+            sync: SyncOptions::default(),
+            poll_interval: Duration::from_millis(500),
+            debounce: Duration::from_millis(250),
+            max_retries: 3,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Shared state between a [`SyncWatchHandle`] and its worker thread.
+#[derive(Default)]
+struct SyncWatchState {
+    stopped: bool,
+    errors: Vec<io::Error>,
+}
+
+/// Handle to a running [`sync_watch`]. Dropping it stops the background sync.
+pub struct SyncWatchHandle {
+    state: Arc<(Mutex<SyncWatchState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SyncWatchHandle {
+    /// Take the errors of changes that still failed after all retries.
+    pub fn take_errors(&self) -> Vec<io::Error> {
+        std::mem::take(&mut self.state.0.lock().unwrap().errors)
+    }
+
+    /// Stop syncing, applying any changes that are already pending.
+    pub fn stop(self) {
+        // Handled by drop.
+    }
+}
+
+impl Drop for SyncWatchHandle {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.state;
+        lock.lock().unwrap().stopped = true;
+        signal.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sync `src` into `dst` and keep it in sync as changes happen.
+///
+/// An initial [`sync_dir`] runs before this function returns. Afterwards a background
+/// thread watches `src`, waits until changes settle for `options.debounce`, and applies
+/// them to `dst`. Failed changes are retried `options.max_retries` times; changes that
+/// still fail are available from [`SyncWatchHandle::take_errors`].
+pub fn sync_watch<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: SyncWatchOptions,
+) -> io::Result<SyncWatchHandle> {
+    let src = src.as_ref().to_path_buf();
+    let dst = dst.as_ref().to_path_buf();
+
+    // Start watching first so changes made during the initial sync are not missed.
+    let mut watcher = Watcher::new(&src, true).context(Operation::Read, &src)?;
+    sync_dir(&src, &dst, &options.sync)?;
+
+    let state = Arc::new((Mutex::new(SyncWatchState::default()), Condvar::new()));
+    let thread_state = Arc::clone(&state);
+    let thread = thread::spawn(move || {
+        let (lock, signal) = &*thread_state;
+        let mut pending: BTreeMap<PathBuf, EventKind> = BTreeMap::new();
+        let mut last_event = Instant::now();
+
+        loop {
+            let stopped = {
+                let guard = lock.lock().unwrap();
+                let (guard, _) = signal
+                    .wait_timeout_while(guard, options.poll_interval, |s| !s.stopped)
+                    .unwrap();
+                guard.stopped
+            };
+
+            match watcher.poll() {
+                Ok(events) if !events.is_empty() => {
+                    for event in events {
+                        pending.insert(event.path, event.kind);
+                    }
+                    last_event = Instant::now();
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let e = FileManagerError::new(Operation::Read, &src, e);
+                    lock.lock().unwrap().errors.push(e.into());
+                }
+            }
+
+            if !pending.is_empty() && (stopped || last_event.elapsed() >= options.debounce) {
+                let errors = apply_changes(&src, &dst, std::mem::take(&mut pending), &options);
+                lock.lock().unwrap().errors.extend(errors);
+            }
+            if stopped {
+                break;
+            }
+        }
+    });
+
+    Ok(SyncWatchHandle {
+        state,
+        thread: Some(thread),
+    })
+}
+
+/// Apply debounced changes to `dst`, retrying failures. Returns the errors that remain.
+fn apply_changes(
+    src: &Path,
+    dst: &Path,
+    changes: BTreeMap<PathBuf, EventKind>,
+    options: &SyncWatchOptions,
+) -> Vec<io::Error> {
+    // Removals are applied deepest first, everything else parents first.
+    let (removals, updates): (Vec<_>, Vec<_>) = changes
+        .into_iter()
+        .partition(|(path, kind)| *kind == EventKind::Removed && !path.exists());
+
+    let mut errors = Vec::new();
+    for (path, _) in removals.iter().rev().chain(updates.iter()) {
+        let Ok(rel) = path.strip_prefix(src) else {
+            continue;
+        };
+        let mut attempt = 0;
+        loop {
+            match apply_change(path, &dst.join(rel), options) {
+                Ok(()) => break,
+                Err(_) if attempt < options.max_retries => {
+                    attempt += 1;
+                    thread::sleep(options.retry_delay);
+                }
+                // The error names the path that failed.
+                Err(e) => {
+                    errors.push(e);
+                    break;
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Bring `dst_path` in line with the current state of `src_path`.
+fn apply_change(src_path: &Path, dst_path: &Path, options: &SyncWatchOptions) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(src_path) {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(FileManagerError::new(Operation::Stat, src_path, e).into()),
+    };
+    match metadata {
+        Some(metadata) if metadata.is_dir() => {
+            if dst_path.is_file() {
+                fs::remove_file(dst_path).context(Operation::Delete, dst_path)?;
+            }
+            Ok(fs::create_dir_all(dst_path).context(Operation::Create, dst_path)?)
+        }
+        Some(metadata) if metadata.is_file() => {
+            if dst_path.is_dir() {
+                fs::remove_dir_all(dst_path).context(Operation::Delete, dst_path)?;
+            }
+            if needs_copy(src_path, dst_path)? {
+                copy_preserving_mtime(src_path, dst_path)?;
+            }
+            Ok(())
+        }
+        Some(_) => Ok(()),
+        None if options.sync.delete_extraneous => {
+            let result = match fs::symlink_metadata(dst_path) {
+                Ok(m) if m.is_dir() => fs::remove_dir_all(dst_path),
+                Ok(_) => fs::remove_file(dst_path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e),
+            };
+            Ok(result.context(Operation::Delete, dst_path)?)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Poll `condition` until it holds or `timeout` passes.
+    fn wait_until(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while !condition() {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn sync_dir_works() {
+        // arrange
+        let src = "assets/sync_dir_src_test";
+        let dst = "assets/sync_dir_dst_test";
+        let _ = fs::remove_dir_all(src);
+        let _ = fs::remove_dir_all(dst);
+        fs::create_dir_all(format!("{}/nested", src)).unwrap();
+        fs::create_dir_all(dst).unwrap();
+        fs::write(format!("{}/a.txt", src), "a").unwrap();
+        fs::write(format!("{}/nested/b.txt", src), "b").unwrap();
+        fs::write(format!("{}/stale.txt", dst), "stale").unwrap();
+        let options = SyncOptions {
+            delete_extraneous: true,
+        };
+
+        // act
+        let first = sync_dir(src, dst, &options).unwrap();
+        let second = sync_dir(src, dst, &options).unwrap();
+
+        // assert
+        assert_eq!(2, first.copied.len());
+        assert_eq!(vec![PathBuf::from("stale.txt")], first.deleted);
+        assert_eq!(SyncReport::default(), second);
+        assert_eq!(
+            "b",
+            fs::read_to_string(format!("{}/nested/b.txt", dst)).unwrap()
+        );
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dst).unwrap();
+    }
+
+    #[test]
+    fn sync_watch_applies_changes() {
+        // arrange
+        let src = "assets/sync_watch_src_test";
+        let dst = "assets/sync_watch_dst_test";
+        let _ = fs::remove_dir_all(src);
+        let _ = fs::remove_dir_all(dst);
+        fs::create_dir_all(src).unwrap();
+        fs::write(format!("{}/initial.txt", src), "initial").unwrap();
+        let options = SyncWatchOptions {
+            sync: SyncOptions {
+                delete_extraneous: true,
+            },
+            poll_interval: Duration::from_millis(10),
+            debounce: Duration::from_millis(20),
+            ..Default::default()
+        };
+
+        // act
+        let handle = sync_watch(src, dst, options).unwrap();
+        let initial_synced = Path::new(&format!("{}/initial.txt", dst)).exists();
+        fs::create_dir_all(format!("{}/new", src)).unwrap();
+        fs::write(format!("{}/new/file.txt", src), "new").unwrap();
+        fs::remove_file(format!("{}/initial.txt", src)).unwrap();
+        let synced = wait_until(Duration::from_secs(5), || {
+            Path::new(&format!("{}/new/file.txt", dst)).exists()
+                && !Path::new(&format!("{}/initial.txt", dst)).exists()
+        });
+        let errors = handle.take_errors();
+        handle.stop();
+
+        // assert
+        assert!(initial_synced);
+        assert!(synced);
+        assert!(errors.is_empty());
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dst).unwrap();
+    }
+}
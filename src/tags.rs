@@ -0,0 +1,334 @@
+//! User tags and notes on files, kept in an index file.
+use crate::{json::Value, write_atomic};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// The extended attribute tags are mirrored to, as a comma separated list,
+/// following the freedesktop.org convention.
+pub const TAGS_XATTR: &str = "user.xdg.tags";
+
+/// The extended attribute notes are mirrored to.
+pub const NOTE_XATTR: &str = "user.xdg.comment";
+
+/// The tags and note of one file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagEntry {
+    pub tags: BTreeSet<String>,
+    pub note: Option<String>,
+}
+
+/// Tags and notes on files, saved to a single JSON index after every change.
+///
+/// Files are identified by their canonical path, so renaming a file outside of
+/// [`Tags::rename`] loses its tags unless they were mirrored to extended attributes.
+#[derive(Debug)]
+pub struct Tags {
+    index_path: PathBuf,
+    mirror_xattrs: bool,
+    entries: BTreeMap<PathBuf, TagEntry>,
+}
+
+impl Tags {
+    /// Load the index at `index_path`, or start empty if it does not exist.
+    pub fn open<P: AsRef<Path>>(index_path: P) -> io::Result<Self> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&index_path) {
+            Ok(text) => parse_index(&index_path, &text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Tags {
+            index_path,
+            mirror_xattrs: false,
+            entries,
+        })
+    }
+
+    /// Also write tags and notes to the [`TAGS_XATTR`] and [`NOTE_XATTR`]
+    /// extended attributes of each file, so other applications see them.
+    /// Supported on Linux and macOS; elsewhere changes fail with `Unsupported`.
+    pub fn mirror_xattrs(mut self, mirror: bool) -> Self {
+        self.mirror_xattrs = mirror;
+        self
+    }
+
+    /// Add `tag` to the file at `path`.
+    pub fn tag<P: AsRef<Path>>(&mut self, path: P, tag: &str) -> io::Result<()> {
+        let tag = tag.trim();
+        if tag.is_empty() || tag.contains(',') {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid tag `{}`", tag),
+            ));
+        }
+        self.update(path.as_ref(), |entry| {
+            entry.tags.insert(tag.to_owned());
+        })
+    }
+
+    /// Remove `tag` from the file at `path`.
+    pub fn untag<P: AsRef<Path>>(&mut self, path: P, tag: &str) -> io::Result<()> {
+        self.update(path.as_ref(), |entry| {
+            entry.tags.remove(tag.trim());
+        })
+    }
+
+    /// Attach `note` to the file at `path`, or remove its note with `None`.
+    pub fn set_note<P: AsRef<Path>>(&mut self, path: P, note: Option<&str>) -> io::Result<()> {
+        self.update(path.as_ref(), |entry| entry.note = note.map(str::to_owned))
+    }
+
+    /// The tags and note of the file at `path`, if it has any.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&TagEntry> {
+        let path = path.as_ref().canonicalize().ok()?;
+        self.entries.get(&path)
+    }
+
+    /// Every file tagged `tag`, sorted by path.
+    pub fn find_by_tag(&self, tag: &str) -> Vec<&Path> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.tags.contains(tag))
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Every tag in use, with the number of files carrying it.
+    pub fn all_tags(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for tag in self.entries.values().flat_map(|entry| &entry.tags) {
+            *counts.entry(tag.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Move the file at `from` to `to`, keeping its tags and note.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> io::Result<()> {
+        let from = from.as_ref().canonicalize()?;
+        fs::rename(&from, to.as_ref())?;
+        if let Some(entry) = self.entries.remove(&from) {
+            self.entries.insert(to.as_ref().canonicalize()?, entry);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Forget files that no longer exist.
+    ///
+    /// # Returns
+    /// The number of files removed from the index.
+    pub fn prune(&mut self) -> io::Result<usize> {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| path.exists());
+        let pruned = before - self.entries.len();
+        if pruned > 0 {
+            self.save()?;
+        }
+        Ok(pruned)
+    }
+
+    /// Helper function to change the entry of the file at `path`, then save
+    /// the index and mirror the entry.
+    fn update(&mut self, path: &Path, change: impl FnOnce(&mut TagEntry)) -> io::Result<()> {
+        let path = path.canonicalize()?;
+        if path.to_str().is_none() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("path is not valid UTF-8: {}", path.display()),
+            ));
+        }
+        let entry = self.entries.entry(path.clone()).or_default();
+        change(entry);
+        let entry = entry.clone();
+        if entry == TagEntry::default() {
+            self.entries.remove(&path);
+        }
+        self.save()?;
+        if self.mirror_xattrs {
+            let tags = entry.tags.iter().cloned().collect::<Vec<_>>().join(",");
+            xattr::set(
+                &path,
+                TAGS_XATTR,
+                Some(tags).filter(|t| !t.is_empty()).as_deref(),
+            )?;
+            xattr::set(&path, NOTE_XATTR, entry.note.as_deref())?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let files = self
+            .entries
+            .iter()
+            .map(|(path, entry)| {
+                let mut fields = vec![
+                    // Checked to be UTF-8 by `update`.
+                    ("path".to_owned(), Value::from(path.to_str().unwrap())),
+                    (
+                        "tags".to_owned(),
+                        Value::Array(entry.tags.iter().map(|t| Value::from(t.as_str())).collect()),
+                    ),
+                ];
+                if let Some(note) = &entry.note {
+                    fields.push(("note".to_owned(), Value::from(note.as_str())));
+                }
+                Value::Object(fields)
+            })
+            .collect();
+        let index = Value::Object(vec![("files".to_owned(), Value::Array(files))]);
+        write_atomic(&self.index_path, format!("{}\n", index).as_bytes())
+    }
+}
+
+fn parse_index(index_path: &Path, text: &str) -> io::Result<BTreeMap<PathBuf, TagEntry>> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid tag index {}", index_path.display()),
+        )
+    };
+    let index = Value::parse(text)?;
+    let items = index
+        .get("files")
+        .and_then(Value::as_array)
+        .ok_or_else(invalid)?;
+    items
+        .iter()
+        .map(|item| {
+            let path = item
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(invalid)?;
+            let tags = item
+                .get("tags")
+                .and_then(Value::as_array)
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|t| t.as_str().map(str::to_owned).ok_or_else(invalid))
+                .collect::<io::Result<_>>()?;
+            let note = item.get("note").and_then(Value::as_str).map(str::to_owned);
+            Ok((PathBuf::from(path), TagEntry { tags, note }))
+        })
+        .collect()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod xattr {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    /// Set the extended attribute `name` of `path` to `value`, or remove it with `None`.
+    pub(super) fn set(path: &Path, name: &str, value: Option<&str>) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let c_name = CString::new(name)?;
+        let result = match value {
+            Some(value) => unsafe { set_raw(&c_path, &c_name, value.as_bytes()) },
+            None => unsafe { remove_raw(&c_path, &c_name) },
+        };
+        if result == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(code) if code == NO_ATTR && value.is_none() => Ok(()),
+            Some(libc::ENOTSUP) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+            _ => Err(e),
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const NO_ATTR: libc::c_int = libc::ENODATA;
+
+    #[cfg(target_os = "macos")]
+    const NO_ATTR: libc::c_int = libc::ENOATTR;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn set_raw(path: &CString, name: &CString, value: &[u8]) -> libc::c_int {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn remove_raw(path: &CString, name: &CString) -> libc::c_int {
+        libc::removexattr(path.as_ptr(), name.as_ptr())
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn set_raw(path: &CString, name: &CString, value: &[u8]) -> libc::c_int {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            0,
+        )
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn remove_raw(path: &CString, name: &CString) -> libc::c_int {
+        libc::removexattr(path.as_ptr(), name.as_ptr(), 0)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+mod xattr {
+    use std::{io, path::Path};
+
+    pub(super) fn set(_path: &Path, _name: &str, _value: Option<&str>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_indexed_and_found() {
+        // arrange
+        let dir = "assets/tags_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let index = format!("{}/tags.json", dir);
+        let (march, april) = (format!("{}/march.pdf", dir), format!("{}/april.pdf", dir));
+        fs::write(&march, "").unwrap();
+        fs::write(&april, "").unwrap();
+
+        // act
+        let mut tags = Tags::open(&index).unwrap();
+        tags.tag(&march, "invoice").unwrap();
+        tags.tag(&march, "paid").unwrap();
+        tags.tag(&april, "invoice").unwrap();
+        tags.set_note(&april, Some("due May 1st")).unwrap();
+        tags.untag(&march, "invoice").unwrap();
+        let invalid = tags.tag(&march, "a,b");
+        tags.rename(&april, format!("{}/2024-04.pdf", dir)).unwrap();
+        let reopened = Tags::open(&index).unwrap();
+
+        // assert
+        assert_eq!(ErrorKind::InvalidInput, invalid.unwrap_err().kind());
+        let renamed = Path::new(dir).join("2024-04.pdf").canonicalize().unwrap();
+        assert_eq!(vec![renamed.as_path()], reopened.find_by_tag("invoice"));
+        assert_eq!(
+            Some("due May 1st"),
+            reopened.get(&renamed).unwrap().note.as_deref()
+        );
+        assert_eq!(
+            BTreeMap::from([("invoice", 1), ("paid", 1)]),
+            reopened.all_tags()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
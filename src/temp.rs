@@ -0,0 +1,211 @@
+//! Temporary files and directories that are deleted when dropped.
+use crate::{copy_dir_verified, copy_file_verified};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A uniquely named file that is deleted when dropped, unless kept with
+/// [`TempFile::persist`].
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    file: File,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Create an empty file in the system temp directory.
+    pub fn new() -> io::Result<Self> {
+        TempFile::new_in(env::temp_dir())
+    }
+
+    /// Create an empty file in `dir`. Create it on the filesystem the result
+    /// will be persisted to, so [`TempFile::persist`] can rename it.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let (path, file) = create_unique(dir.as_ref(), |path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(path)
+        })?;
+        Ok(TempFile {
+            path,
+            file,
+            persisted: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Sync the file and move it to `path`, replacing any file there.
+    ///
+    /// The rename is atomic when `path` is on the same filesystem. Otherwise the
+    /// file is copied next to `path`, verified by checksum and renamed into place.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        self.file.sync_all()?;
+        match fs::rename(&self.path, path) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                copy_file_verified(&self.path, path)?;
+            }
+            Err(e) => return Err(e),
+            Ok(()) => self.persisted = true,
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for TempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+/// A uniquely named directory that is deleted with its contents when dropped,
+/// unless kept with [`TempDir::persist`].
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempDir {
+    /// Create an empty directory in the system temp directory.
+    pub fn new() -> io::Result<Self> {
+        TempDir::new_in(env::temp_dir())
+    }
+
+    /// Create an empty directory in `dir`.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let (path, ()) = create_unique(dir.as_ref(), |path| fs::create_dir(path))?;
+        Ok(TempDir {
+            path,
+            persisted: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move the directory to `path`, which must not exist.
+    ///
+    /// The rename is atomic when `path` is on the same filesystem. Otherwise the
+    /// tree is copied next to `path`, verified by checksum and renamed into place.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match fs::rename(&self.path, path) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                copy_dir_verified(&self.path, path)?;
+            }
+            Err(e) => return Err(e),
+            Ok(()) => self.persisted = true,
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Helper function to call `create` with fresh names in `dir` until one does
+/// not exist yet.
+fn create_unique<T>(
+    dir: &Path,
+    mut create: impl FnMut(&Path) -> io::Result<T>,
+) -> io::Result<(PathBuf, T)> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    loop {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(".tmp-{}-{:x}-{}", std::process::id(), nanos, n));
+        match create(&path) {
+            Ok(created) => return Ok((path, created)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_entries_are_removed_unless_persisted() {
+        // arrange
+        let dir = "assets/temp_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let kept = format!("{}/kept.txt", dir);
+
+        // act
+        let mut dropped = TempFile::new_in(dir).unwrap();
+        dropped.write_all(b"scratch").unwrap();
+        let dropped_path = dropped.path().to_path_buf();
+        drop(dropped);
+        let mut file = TempFile::new_in(dir).unwrap();
+        file.write_all(b"result").unwrap();
+        file.persist(&kept).unwrap();
+        let tree = TempDir::new_in(dir).unwrap();
+        fs::write(tree.path().join("inner.txt"), "").unwrap();
+        let tree_path = tree.path().to_path_buf();
+        drop(tree);
+        let system = TempFile::new().unwrap();
+        let system_path = system.path().to_path_buf();
+        let in_system_dir = system_path.starts_with(env::temp_dir());
+        drop(system);
+
+        // assert
+        assert!(!dropped_path.exists());
+        assert_eq!("result", fs::read_to_string(&kept).unwrap());
+        assert!(!tree_path.exists());
+        assert!(in_system_dir && !system_path.exists());
+        assert_eq!(1, fs::read_dir(dir).unwrap().count());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
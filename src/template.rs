@@ -0,0 +1,125 @@
+//! Render templates with `{{var}}` placeholders to files.
+use crate::{sidecar::update_sidecar, write_atomic};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Replace every `{{name}}` in `template` with its value from `values`.
+/// Whitespace inside the braces is ignored, so `{{ name }}` works too.
+///
+/// # Errors
+/// `InvalidInput` naming every placeholder without a value, or `InvalidData`
+/// for a `{{` that is never closed.
+pub fn render_template<K, V>(template: &str, values: &HashMap<K, V>) -> io::Result<String>
+where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<str>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut missing: Vec<&str> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unclosed `{{{{` at byte {}",
+                    template.len() - rest.len() + start
+                ),
+            )
+        })?;
+        let name = rest[start + 2..start + end].trim();
+        match values.get(name) {
+            Some(value) => rendered.push_str(value.as_ref()),
+            None if !missing.contains(&name) => missing.push(name),
+            None => {}
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+
+    if !missing.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("no value for {}", missing.join(", ")),
+        ));
+    }
+    Ok(rendered)
+}
+
+/// Render the template at `template_path` with [`render_template`] and write
+/// the result atomically to `dest`. Nothing is written if rendering fails.
+pub fn render_template_to_file<K, V, P, Q>(
+    template_path: P,
+    values: &HashMap<K, V>,
+    dest: Q,
+) -> io::Result<()>
+where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<str>,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let template = fs::read_to_string(template_path)?;
+    let rendered = render_template(&template, values)?;
+    write_atomic(dest.as_ref(), rendered.as_bytes())?;
+    update_sidecar(dest)
+}
+
+/// Like [`render_template_to_file`], using the Jinja-like engine of `minijinja`,
+/// with conditionals, loops and filters. Undefined variables are errors.
+#[cfg(feature = "templates")]
+pub fn render_jinja_to_file<S, P, Q>(template_path: P, values: &S, dest: Q) -> io::Result<()>
+where
+    S: serde::Serialize,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let template = fs::read_to_string(template_path)?;
+    let mut env = minijinja::Environment::new();
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+    let rendered = env
+        .render_str(&template, minijinja::value::Serde(values))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    write_atomic(dest.as_ref(), rendered.as_bytes())?;
+    update_sidecar(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template_to_file_works() {
+        // arrange
+        let dir = "assets/template_render_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (template, dest) = (format!("{}/app.toml.in", dir), format!("{}/app.toml", dir));
+        fs::write(&template, "name = \"{{name}}\"\nport = {{ port }}\n").unwrap();
+        let mut values = HashMap::from([("name", "demo")]);
+
+        // act
+        let missing = render_template_to_file(&template, &values, &dest);
+        let written_early = Path::new(&dest).exists();
+        values.insert("port", "8080");
+        render_template_to_file(&template, &values, &dest).unwrap();
+        let unclosed = render_template("{{name", &values);
+
+        // assert
+        assert_eq!(ErrorKind::InvalidInput, missing.unwrap_err().kind());
+        assert!(!written_early);
+        assert_eq!(
+            "name = \"demo\"\nport = 8080\n",
+            fs::read_to_string(&dest).unwrap()
+        );
+        assert_eq!(ErrorKind::InvalidData, unclosed.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+//! Check [`FileSystem`] implementations against the guarantees the crate relies on.
+//!
+//! [`check_file_system`] applies a seeded pseudo-random sequence of operations,
+//! and [`ops_from_bytes`] turns fuzzer input into one, so a backend can be
+//! validated the way the crate validates [`LocalFs`](crate::vfs::LocalFs) and
+//! [`MemoryFs`](crate::vfs::MemoryFs).
+use crate::vfs::{FileSystem, VfsKind};
+use std::{collections::BTreeMap, error::Error, fmt, io, path::Path};
+
+/// The paths operations are applied to, few enough that they often collide.
+const PATHS: &[&str] = &["a", "b", "c", "a/b", "a/c", "b/a", "a/b/c"];
+
+/// An operation applied by [`check_ops`]. Paths are relative to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsOp {
+    CreateFile(String),
+    CreateDir(String),
+    WriteAt {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    SetLen {
+        path: String,
+        len: u64,
+    },
+    RemoveFile(String),
+    RemoveDir(String),
+    Rename {
+        from: String,
+        to: String,
+    },
+}
+
+/// A guarantee a [`FileSystem`] broke, found by [`check_ops`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The index of the operation after which the guarantee was broken.
+    pub step: usize,
+    pub op: FsOp,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} ({:?}): {}", self.step, self.op, self.message)
+    }
+}
+
+impl Error for Violation {}
+
+/// Decode a sequence of operations from arbitrary bytes, for fuzzers. Every
+/// input decodes to some sequence; trailing bytes that do not make up a whole
+/// operation are ignored.
+pub fn ops_from_bytes(data: &[u8]) -> Vec<FsOp> {
+    let mut bytes = data.iter().copied();
+    let mut ops = Vec::new();
+    while let Some(op) = decode_op(&mut bytes) {
+        ops.push(op);
+    }
+    ops
+}
+
+/// Generate `count` operations from `seed`. The same seed always gives the
+/// same operations, so a failing seed reproduces the failure.
+pub fn generate_ops(seed: u64, count: usize) -> Vec<FsOp> {
+    let mut state = seed;
+    let mut bytes = std::iter::repeat_with(move || {
+        // SplitMix64.
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as u8
+    });
+    (0..count).filter_map(|_| decode_op(&mut bytes)).collect()
+}
+
+/// Apply [`generate_ops`]`(seed, count)` to `fs`, which should be empty, with
+/// [`check_ops`].
+pub fn check_file_system<F: FileSystem + ?Sized>(
+    fs: &F,
+    seed: u64,
+    count: usize,
+) -> Result<(), Violation> {
+    check_ops(fs, &generate_ops(seed, count))
+}
+
+/// Apply `ops` to `fs`, which should be empty, checking after each one that:
+///
+/// - a successful write, truncation or creation is visible to the next read,
+///   with the rest of the file unchanged;
+/// - a successful rename makes the whole source visible at the destination and
+///   nothing at the source, and a removal leaves nothing behind;
+/// - a failed operation changes none of the paths it was given;
+/// - directories list their entries sorted by name, with the kinds and lengths
+///   that [`FileSystem::metadata`] reports.
+///
+/// Operations may fail, for example on a missing parent; only what they leave
+/// behind is checked.
+pub fn check_ops<F: FileSystem + ?Sized>(fs: &F, ops: &[FsOp]) -> Result<(), Violation> {
+    for (step, op) in ops.iter().enumerate() {
+        check_op(fs, op).map_err(|message| Violation {
+            step,
+            op: op.clone(),
+            message,
+        })?;
+    }
+    Ok(())
+}
+
+/// What a [`FileSystem`] holds at a path, read through the trait.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Snapshot {
+    Missing,
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Snapshot>),
+}
+
+fn check_op<F: FileSystem + ?Sized>(fs: &F, op: &FsOp) -> Result<(), String> {
+    let paths: Vec<&str> = match op {
+        FsOp::CreateFile(path)
+        | FsOp::CreateDir(path)
+        | FsOp::WriteAt { path, .. }
+        | FsOp::SetLen { path, .. }
+        | FsOp::RemoveFile(path)
+        | FsOp::RemoveDir(path) => vec![path],
+        FsOp::Rename { from, to } => vec![from, to],
+    };
+    let before = paths
+        .iter()
+        .map(|path| snapshot(fs, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let result = match op {
+        FsOp::CreateFile(path) => fs.create_file(Path::new(path)),
+        FsOp::CreateDir(path) => fs.create_dir(Path::new(path)),
+        FsOp::WriteAt { path, offset, data } => fs.write_at(Path::new(path), *offset, data),
+        FsOp::SetLen { path, len } => fs.set_len(Path::new(path), *len),
+        FsOp::RemoveFile(path) => fs.remove_file(Path::new(path)),
+        FsOp::RemoveDir(path) => fs.remove_dir(Path::new(path)),
+        FsOp::Rename { from, to } => fs.rename(Path::new(from), Path::new(to)),
+    };
+    let after = paths
+        .iter()
+        .map(|path| snapshot(fs, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Err(e) = result {
+        return if before == after {
+            Ok(())
+        } else {
+            Err(format!("failed with `{}` but changed {:?}", e, paths))
+        };
+    }
+    let expected = match (op, &before[..]) {
+        (FsOp::CreateFile(_), _) => vec![Snapshot::File(Vec::new())],
+        (FsOp::CreateDir(_), _) => vec![Snapshot::Dir(BTreeMap::new())],
+        // Whether an empty write past the end extends the file is left open.
+        (FsOp::WriteAt { data, .. }, _) if data.is_empty() && after == before => before.clone(),
+        (FsOp::WriteAt { offset, data, .. }, [Snapshot::File(old)]) => {
+            let mut new = old.clone();
+            let end = *offset as usize + data.len();
+            if new.len() < end {
+                new.resize(end, 0);
+            }
+            new[*offset as usize..end].copy_from_slice(data);
+            vec![Snapshot::File(new)]
+        }
+        (FsOp::SetLen { len, .. }, [Snapshot::File(old)]) => {
+            let mut new = old.clone();
+            new.resize(*len as usize, 0);
+            vec![Snapshot::File(new)]
+        }
+        (FsOp::RemoveFile(_) | FsOp::RemoveDir(_), _) => vec![Snapshot::Missing],
+        (FsOp::Rename { from, to }, [source, _]) if from != to => {
+            vec![Snapshot::Missing, source.clone()]
+        }
+        (FsOp::Rename { .. }, _) => before.clone(),
+        _ => return Err(format!("succeeded on {:?}", before)),
+    };
+    if after != expected {
+        return Err(format!("expected {:?}, found {:?}", expected, after));
+    }
+    Ok(())
+}
+
+/// Helper function to read everything at `path`, checking that listings are
+/// sorted and agree with the metadata of their entries.
+fn snapshot<F: FileSystem + ?Sized>(fs: &F, path: &str) -> Result<Snapshot, String> {
+    let describe = |e: io::Error| format!("cannot read {}: {}", path, e);
+    let metadata = match fs.metadata(Path::new(path)) {
+        Ok(metadata) => metadata,
+        // A file where a parent directory should be.
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(Snapshot::Missing)
+        }
+        Err(e) => return Err(describe(e)),
+    };
+    match metadata.kind {
+        VfsKind::File => {
+            let data = fs.read_all(Path::new(path)).map_err(describe)?;
+            if data.len() as u64 != metadata.len {
+                return Err(format!(
+                    "{} has {} bytes but its metadata says {}",
+                    path,
+                    data.len(),
+                    metadata.len
+                ));
+            }
+            Ok(Snapshot::File(data))
+        }
+        VfsKind::Dir => {
+            let entries = fs.read_dir(Path::new(path)).map_err(describe)?;
+            if !entries.windows(2).all(|pair| pair[0].name < pair[1].name) {
+                return Err(format!("{} is not listed sorted by name", path));
+            }
+            let mut children = BTreeMap::new();
+            for entry in entries {
+                let child_path = format!("{}/{}", path, entry.name);
+                let child = snapshot(fs, &child_path)?;
+                let kind = match child {
+                    Snapshot::File(_) => Some(VfsKind::File),
+                    Snapshot::Dir(_) => Some(VfsKind::Dir),
+                    Snapshot::Missing => None,
+                };
+                if kind != Some(entry.kind) {
+                    return Err(format!("{} is listed as a {:?}", child_path, entry.kind));
+                }
+                children.insert(entry.name, child);
+            }
+            Ok(Snapshot::Dir(children))
+        }
+    }
+}
+
+/// Helper function to decode one operation, or `None` when `bytes` run out.
+fn decode_op(bytes: &mut impl Iterator<Item = u8>) -> Option<FsOp> {
+    let tag = bytes.next()?;
+    let mut path = || Some(PATHS[bytes.next()? as usize % PATHS.len()].to_owned());
+    let op = match tag % 7 {
+        0 => FsOp::CreateFile(path()?),
+        1 => FsOp::CreateDir(path()?),
+        2 => {
+            let path = path()?;
+            let offset = (bytes.next()? % 16) as u64;
+            let len = bytes.next()? % 8 + 1;
+            let data = (0..len).map(|_| bytes.next()).collect::<Option<_>>()?;
+            FsOp::WriteAt { path, offset, data }
+        }
+        3 => {
+            let path = path()?;
+            let len = (bytes.next()? % 24) as u64;
+            FsOp::SetLen { path, len }
+        }
+        4 => FsOp::RemoveFile(path()?),
+        5 => FsOp::RemoveDir(path()?),
+        _ => {
+            let from = path()?;
+            let to = path()?;
+            FsOp::Rename { from, to }
+        }
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{LocalFs, MemoryFs};
+    use std::fs;
+
+    #[test]
+    fn builtin_file_systems_pass_checks() {
+        // arrange
+        let dir = "assets/test_util_test";
+        let _ = fs::remove_dir_all(dir);
+        let fuzzed = ops_from_bytes(b"\x01\x00\x02\x00\x03\x01hi\x06\x00\x01");
+
+        // act
+        let memory: Vec<_> = (0..20)
+            .map(|seed| check_file_system(&MemoryFs::new(), seed, 200))
+            .collect();
+        let local: Vec<_> = (0..5)
+            .map(|seed| {
+                fs::create_dir_all(dir).unwrap();
+                let result = check_file_system(&LocalFs::new(dir).unwrap(), seed, 200);
+                fs::remove_dir_all(dir).unwrap();
+                result
+            })
+            .collect();
+        let fuzzed_result = check_ops(&MemoryFs::new(), &fuzzed);
+
+        // assert
+        assert_eq!(generate_ops(7, 50), generate_ops(7, 50));
+        assert_eq!(
+            vec![
+                FsOp::CreateDir("a".to_owned()),
+                FsOp::WriteAt {
+                    path: "a".to_owned(),
+                    offset: 3,
+                    data: b"hi".to_vec(),
+                },
+                FsOp::Rename {
+                    from: "a".to_owned(),
+                    to: "b".to_owned(),
+                },
+            ],
+            fuzzed
+        );
+        assert!(memory.iter().all(Result::is_ok), "{:?}", memory);
+        assert!(local.iter().all(Result::is_ok), "{:?}", local);
+        assert!(fuzzed_result.is_ok());
+    }
+}
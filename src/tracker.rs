@@ -0,0 +1,295 @@
+//! Detect file changes without trusting modification times.
+use crate::{
+    checksum::{to_hex, Sha256},
+    watch::{Event, EventKind},
+    write_atomic,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const STATE_HEADER: &str = "file-manager-tracker v1";
+
+/// Bytes hashed from each of the start, middle and end of a file.
+const SAMPLE_LEN: u64 = 16 * 1024;
+
+/// What a [`ChangeTracker`] remembers about a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime: u128,
+    /// SHA-256 of the file's size and a sample of its contents, as hex.
+    pub quick_hash: String,
+}
+
+/// Tracks files by size, mtime and a quick content hash, persisted in a state file.
+///
+/// A file counts as modified when its size or quick hash differs from the
+/// recorded one, so content changes are found even when the mtime didn't move
+/// (coarse FAT timestamps, some network shares), and files that were only
+/// touched are not reported.
+#[derive(Debug)]
+pub struct ChangeTracker {
+    state_path: PathBuf,
+    records: BTreeMap<PathBuf, FileRecord>,
+}
+
+impl ChangeTracker {
+    /// Load the state file at `state_path`, or start empty if it does not exist.
+    pub fn open<P: AsRef<Path>>(state_path: P) -> io::Result<Self> {
+        let state_path = state_path.as_ref().to_path_buf();
+        let records = match File::open(&state_path) {
+            Ok(file) => load_records(BufReader::new(file))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ChangeTracker {
+            state_path,
+            records,
+        })
+    }
+
+    /// The recorded state of `path`, if it is tracked.
+    pub fn record<P: AsRef<Path>>(&self, path: P) -> Option<&FileRecord> {
+        self.records.get(path.as_ref())
+    }
+
+    /// Returns `true` if the file at `path` differs from its record or is untracked.
+    pub fn has_changed<P: AsRef<Path>>(&self, path: P) -> io::Result<bool> {
+        let path = path.as_ref();
+        match (self.records.get(path), record_of(path)) {
+            (None, _) => Ok(true),
+            (Some(_), Err(e)) if e.kind() == ErrorKind::NotFound => Ok(true),
+            (Some(_), Err(e)) => Err(e),
+            (Some(old), Ok(new)) => Ok(differs(old, &new)),
+        }
+    }
+
+    /// Scan every file beneath `root`, report what changed since the last scan
+    /// and update the records in memory. Call [`ChangeTracker::save`] to persist them.
+    /// Removals are reported first, then creations and modifications, each sorted by path.
+    pub fn scan<P: AsRef<Path>>(&mut self, root: P) -> io::Result<Vec<Event>> {
+        let root = root.as_ref();
+        let mut files = Vec::new();
+        collect_files(root, &mut files)?;
+
+        let mut created = Vec::new();
+        let mut modified = Vec::new();
+        let mut current = BTreeMap::new();
+        for path in files {
+            let record = record_of(&path)?;
+            match self.records.get(&path) {
+                None => created.push(path.clone()),
+                Some(old) if differs(old, &record) => modified.push(path.clone()),
+                Some(_) => {}
+            }
+            current.insert(path, record);
+        }
+
+        let mut removed: Vec<PathBuf> = self
+            .records
+            .keys()
+            .filter(|path| path.starts_with(root) && !current.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in &removed {
+            self.records.remove(path);
+        }
+        self.records.extend(current);
+
+        removed.sort_by(|a, b| b.cmp(a));
+        created.sort();
+        modified.sort();
+        let events = removed
+            .into_iter()
+            .map(|path| (EventKind::Removed, path))
+            .chain(created.into_iter().map(|path| (EventKind::Created, path)))
+            .chain(modified.into_iter().map(|path| (EventKind::Modified, path)))
+            .map(|(kind, path)| Event { kind, path })
+            .collect();
+        Ok(events)
+    }
+
+    /// Atomically write the records to the state file.
+    pub fn save(&self) -> io::Result<()> {
+        let mut state = String::new();
+        state.push_str(STATE_HEADER);
+        state.push('\n');
+        for (path, record) in &self.records {
+            let path = match path.to_str() {
+                Some(s) if !s.contains(['\t', '\n', '\r']) => s,
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("path cannot be tracked: {}", path.display()),
+                    ))
+                }
+            };
+            state.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                record.size, record.mtime, record.quick_hash, path
+            ));
+        }
+        write_atomic(&self.state_path, state.as_bytes())
+    }
+}
+
+/// Returns `true` if the size or content sample changed. The mtime is deliberately ignored.
+fn differs(old: &FileRecord, new: &FileRecord) -> bool {
+    old.size != new.size || old.quick_hash != new.quick_hash
+}
+
+fn record_of(path: &Path) -> io::Result<FileRecord> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    Ok(FileRecord {
+        size: metadata.len(),
+        mtime: mtime_of(&metadata),
+        quick_hash: quick_hash(&mut file, metadata.len())?,
+    })
+}
+
+fn mtime_of(metadata: &fs::Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos())
+}
+
+/// Hash the length and the start, middle and end of `file`. Small files are hashed whole.
+fn quick_hash(file: &mut File, len: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(&len.to_le_bytes());
+    let offsets = if len <= 3 * SAMPLE_LEN {
+        vec![(0, len)]
+    } else {
+        vec![
+            (0, SAMPLE_LEN),
+            (len / 2 - SAMPLE_LEN / 2, SAMPLE_LEN),
+            (len - SAMPLE_LEN, SAMPLE_LEN),
+        ]
+    };
+    let mut buf = Vec::new();
+    for (offset, sample_len) in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        buf.clear();
+        file.by_ref().take(sample_len).read_to_end(&mut buf)?;
+        hasher.update(&buf);
+    }
+    Ok(to_hex(&hasher.finish()))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn load_records<R: BufRead>(reader: R) -> io::Result<BTreeMap<PathBuf, FileRecord>> {
+    let invalid = |msg: String| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid tracker state: {}", msg),
+        )
+    };
+    let mut lines = reader.lines();
+    match lines.next().transpose()? {
+        Some(header) if header == STATE_HEADER => {}
+        _ => return Err(invalid("missing header".to_owned())),
+    }
+    let mut records = BTreeMap::new();
+    for line in lines {
+        let line = line?;
+        let mut fields = line.splitn(4, '\t');
+        let record = (|| {
+            let size = fields.next()?.parse().ok()?;
+            let mtime = fields.next()?.parse().ok()?;
+            let quick_hash = fields.next()?.to_owned();
+            let path = PathBuf::from(fields.next()?);
+            Some((
+                path,
+                FileRecord {
+                    size,
+                    mtime,
+                    quick_hash,
+                },
+            ))
+        })();
+        match record {
+            Some((path, record)) => records.insert(path, record),
+            None => return Err(invalid(format!("malformed record `{}`", line))),
+        };
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn change_tracker_ignores_mtimes() {
+        // arrange
+        let dir = "assets/tracker_scan_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/files/sub", dir)).unwrap();
+        let root = PathBuf::from(format!("{}/files", dir));
+        let state = format!("{}/state", dir);
+        let (same_mtime, touched) = (root.join("same_mtime.txt"), root.join("sub/touched.txt"));
+        fs::write(&same_mtime, "aaaa").unwrap();
+        fs::write(&touched, "bbbb").unwrap();
+        let mut tracker = ChangeTracker::open(&state).unwrap();
+        let initial = tracker.scan(&root).unwrap();
+        tracker.save().unwrap();
+
+        // act
+        let mtime = fs::metadata(&same_mtime).unwrap().modified().unwrap();
+        fs::write(&same_mtime, "cccc").unwrap();
+        File::options()
+            .write(true)
+            .open(&same_mtime)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let mut reopened = ChangeTracker::open(&state).unwrap();
+        let touched_changed = reopened.has_changed(&touched).unwrap();
+        let events = reopened.scan(&root).unwrap();
+
+        // assert
+        assert_eq!(2, initial.len());
+        assert!(!touched_changed);
+        assert_eq!(
+            vec![Event {
+                kind: EventKind::Modified,
+                path: same_mtime.clone(),
+            }],
+            events
+        );
+        assert_eq!(4, reopened.record(&same_mtime).unwrap().size);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
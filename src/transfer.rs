@@ -0,0 +1,401 @@
+//! Copy or move files between any two [`FileSystem`] backends.
+use crate::{
+    checksum::{self, Sha256},
+    conflict::{resolved_path, ConflictResolver, FileConflict, FileSide},
+    error::{Context, Operation},
+    vfs::{FileSystem, VfsKind},
+    OverwritePolicy,
+};
+use std::{
+    ffi::OsString,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Options for [`transfer`].
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    /// Bytes read and written per call to the backends.
+    pub chunk_size: usize,
+    /// How often a failed read or write is retried when the error looks transient,
+    /// such as a timeout or a dropped connection.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each further one.
+    pub retry_delay: Duration,
+    /// Read every file back from the destination and compare its SHA-256 with
+    /// what was read from the source.
+    pub verify: bool,
+    /// What to do when a destination file already exists.
+    pub policy: OverwritePolicy,
+    /// Remove the sources once everything was transferred, turning the copy into a move.
+    pub remove_source: bool,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            chunk_size: 1024 * 1024,
+            retries: 3,
+            retry_delay: Duration::from_millis(100),
+            verify: true,
+            policy: OverwritePolicy::default(),
+            remove_source: false,
+        }
+    }
+}
+
+/// Progress of a running [`transfer_with_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// The source file being transferred.
+    pub path: PathBuf,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Copy the file or directory tree at `src_path` in `src_fs` to `dst_path` in `dst_fs`.
+///
+/// Files are streamed in chunks, written under a temporary name and only renamed
+/// into place once complete and verified, so an interrupted transfer never
+/// leaves a truncated file behind.
+///
+/// # Returns
+/// The destination paths of the transferred files.
+pub fn transfer<S, D>(
+    src_fs: &S,
+    src_path: &Path,
+    dst_fs: &D,
+    dst_path: &Path,
+    options: &TransferOptions,
+) -> io::Result<Vec<PathBuf>>
+where
+    S: FileSystem + ?Sized,
+    D: FileSystem + ?Sized,
+{
+    transfer_with_progress(src_fs, src_path, dst_fs, dst_path, options, |_| {})
+}
+
+/// Like [`transfer`], calling `progress` after every chunk.
+pub fn transfer_with_progress<S, D, F>(
+    src_fs: &S,
+    src_path: &Path,
+    dst_fs: &D,
+    dst_path: &Path,
+    options: &TransferOptions,
+    progress: F,
+) -> io::Result<Vec<PathBuf>>
+where
+    S: FileSystem + ?Sized,
+    D: FileSystem + ?Sized,
+    F: FnMut(&TransferProgress),
+{
+    let mut policy = options.policy;
+    transfer_with_resolver(
+        src_fs,
+        src_path,
+        dst_fs,
+        dst_path,
+        options,
+        &mut policy,
+        progress,
+    )
+}
+
+/// Like [`transfer_with_progress`], asking `resolver` what to do with each
+/// destination file that already exists instead of applying `options.policy`.
+pub fn transfer_with_resolver<S, D, R, F>(
+    src_fs: &S,
+    src_path: &Path,
+    dst_fs: &D,
+    dst_path: &Path,
+    options: &TransferOptions,
+    resolver: &mut R,
+    mut progress: F,
+) -> io::Result<Vec<PathBuf>>
+where
+    S: FileSystem + ?Sized,
+    D: FileSystem + ?Sized,
+    R: ConflictResolver + ?Sized,
+    F: FnMut(&TransferProgress),
+{
+    let mut pairs = Vec::new();
+    plan(src_fs, src_path, dst_path, &mut pairs)?;
+    let mut state = TransferProgress {
+        path: PathBuf::new(),
+        bytes_done: 0,
+        bytes_total: pairs.iter().map(|(_, _, len)| len.unwrap_or(0)).sum(),
+    };
+
+    let mut transferred = Vec::new();
+    for (src, dst, len) in &pairs {
+        if len.is_none() {
+            if !dst_fs.exists(dst) {
+                dst_fs.create_dir(dst).context(Operation::Create, dst)?;
+            }
+            continue;
+        }
+        let dst = match resolve_destination(src_fs, src, dst_fs, dst, resolver)? {
+            Some(dst) => dst,
+            None => continue,
+        };
+        state.path = src.clone();
+        copy_file(
+            src_fs,
+            src,
+            dst_fs,
+            &dst,
+            options,
+            &mut state,
+            &mut progress,
+        )?;
+        transferred.push(dst);
+    }
+
+    if options.remove_source {
+        // Deepest first, so directories are empty by the time they are removed.
+        for (src, _, len) in pairs.iter().rev() {
+            match len {
+                Some(_) => src_fs.remove_file(src),
+                None => src_fs.remove_dir(src),
+            }
+            .context(Operation::Delete, src)?;
+        }
+    }
+    Ok(transferred)
+}
+
+/// Helper function to list `(source, destination, length)` for everything under
+/// `src`, parents first. Directories have no length.
+fn plan<S: FileSystem + ?Sized>(
+    src_fs: &S,
+    src: &Path,
+    dst: &Path,
+    pairs: &mut Vec<(PathBuf, PathBuf, Option<u64>)>,
+) -> io::Result<()> {
+    let metadata = src_fs.metadata(src).context(Operation::Stat, src)?;
+    match metadata.kind {
+        VfsKind::File => pairs.push((src.to_path_buf(), dst.to_path_buf(), Some(metadata.len))),
+        VfsKind::Dir => {
+            pairs.push((src.to_path_buf(), dst.to_path_buf(), None));
+            for entry in src_fs.read_dir(src).context(Operation::Read, src)? {
+                plan(
+                    src_fs,
+                    &src.join(&entry.name),
+                    &dst.join(&entry.name),
+                    pairs,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to ask `resolver` about `dst` if it exists, returning
+/// `None` to skip it.
+fn resolve_destination<S, D, R>(
+    src_fs: &S,
+    src: &Path,
+    dst_fs: &D,
+    dst: &Path,
+    resolver: &mut R,
+) -> io::Result<Option<PathBuf>>
+where
+    S: FileSystem + ?Sized,
+    D: FileSystem + ?Sized,
+    R: ConflictResolver + ?Sized,
+{
+    if !dst_fs.exists(dst) {
+        return Ok(Some(dst.to_path_buf()));
+    }
+    let hash = resolver.wants_hashes();
+    let conflict = FileConflict {
+        source: vfs_side(src_fs, src, hash)?,
+        destination: vfs_side(dst_fs, dst, hash)?,
+    };
+    let resolution = resolver.resolve(&conflict)?;
+    resolved_path(resolution, dst, "transfer", |path| dst_fs.exists(path))
+}
+
+/// Helper function to describe the file at `path` in `fs`, hashing it if `hash`.
+fn vfs_side<F: FileSystem + ?Sized>(fs: &F, path: &Path, hash: bool) -> io::Result<FileSide> {
+    let metadata = fs.metadata(path)?;
+    let sha256 = if hash {
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut offset = 0;
+        loop {
+            let n = fs.read_at(path, offset, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            offset += n as u64;
+        }
+        Some(checksum::to_hex(&hasher.finish()))
+    } else {
+        None
+    };
+    Ok(FileSide {
+        path: path.to_path_buf(),
+        size: metadata.len,
+        modified: Some(metadata.modified),
+        sha256,
+    })
+}
+
+fn copy_file<S, D, F>(
+    src_fs: &S,
+    src: &Path,
+    dst_fs: &D,
+    dst: &Path,
+    options: &TransferOptions,
+    state: &mut TransferProgress,
+    progress: &mut F,
+) -> io::Result<()>
+where
+    S: FileSystem + ?Sized,
+    D: FileSystem + ?Sized,
+    F: FnMut(&TransferProgress),
+{
+    let mut part_name = OsString::from(".");
+    part_name.push(dst.file_name().unwrap_or(dst.as_os_str()));
+    part_name.push(".part");
+    let part = dst.with_file_name(part_name);
+    if dst_fs.exists(&part) {
+        dst_fs
+            .remove_file(&part)
+            .context(Operation::Delete, &part)?;
+    }
+    dst_fs
+        .create_file(&part)
+        .context(Operation::Create, &part)?;
+
+    let result = (|| {
+        let mut buf = vec![0; options.chunk_size.max(1)];
+        let mut hash = Sha256::new();
+        let mut offset = 0;
+        loop {
+            let n = retry(options, || src_fs.read_at(src, offset, &mut buf))
+                .context(Operation::Read, src)?;
+            if n == 0 {
+                break;
+            }
+            retry(options, || dst_fs.write_at(&part, offset, &buf[..n]))
+                .context(Operation::Write, &part)?;
+            hash.update(&buf[..n]);
+            offset += n as u64;
+            state.bytes_done += n as u64;
+            progress(state);
+        }
+        if options.verify {
+            let mut written = Sha256::new();
+            let mut offset = 0;
+            loop {
+                let n = retry(options, || dst_fs.read_at(&part, offset, &mut buf))
+                    .context(Operation::Read, &part)?;
+                if n == 0 {
+                    break;
+                }
+                written.update(&buf[..n]);
+                offset += n as u64;
+            }
+            if written.finish() != hash.finish() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("verification failed for {}", dst.display()),
+                ));
+            }
+        }
+        Ok(dst_fs.rename(&part, dst).context(Operation::Move, &part)?)
+    })();
+    if result.is_err() {
+        let _ = dst_fs.remove_file(&part);
+    }
+    result
+}
+
+/// Helper function to run `op`, retrying errors that may go away on their own.
+fn retry<T>(options: &TransferOptions, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < options.retries && is_transient(e.kind()) => {
+                attempt += 1;
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn transfer_between_backends_works() {
+        // arrange
+        let src = MemoryFs::new();
+        src.create_dir(Path::new("docs")).unwrap();
+        src.write_all(Path::new("docs/a.txt"), b"alpha").unwrap();
+        src.write_all(Path::new("docs/b.txt"), &[7; 5000]).unwrap();
+        let dst = MemoryFs::new();
+        dst.create_dir(Path::new("backup")).unwrap();
+        let options = TransferOptions {
+            chunk_size: 1024,
+            remove_source: true,
+            ..TransferOptions::default()
+        };
+        let mut updates = Vec::new();
+
+        // act
+        let moved = transfer_with_progress(
+            &src,
+            Path::new("docs"),
+            &dst,
+            Path::new("backup/docs"),
+            &options,
+            |p| updates.push(p.bytes_done),
+        )
+        .unwrap();
+        src.write_all(Path::new("a.txt"), b"again").unwrap();
+        let refused = transfer(
+            &src,
+            Path::new("a.txt"),
+            &dst,
+            Path::new("backup/docs/a.txt"),
+            &TransferOptions::default(),
+        );
+
+        // assert
+        assert_eq!(2, moved.len());
+        assert_eq!(
+            b"alpha".to_vec(),
+            dst.read_all(Path::new("backup/docs/a.txt")).unwrap()
+        );
+        assert_eq!(
+            5000,
+            dst.read_all(Path::new("backup/docs/b.txt")).unwrap().len()
+        );
+        assert_eq!(Some(&5005), updates.last());
+        assert!(!src.exists(Path::new("docs")));
+        assert_eq!(ErrorKind::AlreadyExists, refused.unwrap_err().kind());
+    }
+}
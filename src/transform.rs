@@ -0,0 +1,292 @@
+//! Stream files through user transforms into new files.
+use crate::{is_stdio_path, open_input, sidecar::update_sidecar, temp_sibling};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Stream `src` line by line through `transform` into `dst`, keeping lines for
+/// which it returns `Some`. Either path may be `"-"` for stdin or stdout.
+///
+/// Only one line is held in memory at a time. Lines are passed without their
+/// terminator, which is restored on output. The output is written under a
+/// temporary name and renamed over `dst` when complete, so `dst` may be `src`.
+///
+/// # Returns
+/// The number of lines written.
+pub fn transform_lines<P, Q, F>(src: P, dst: Q, mut transform: F) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut input = open_input(src)?;
+    // `move` closes the input before the rename, which Windows needs when `dst == src`.
+    write_output(dst.as_ref(), move |output| {
+        let mut line = String::new();
+        let mut written = 0;
+        while input.read_line(&mut line)? > 0 {
+            let content = line.strip_suffix('\n').unwrap_or(&line);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            if let Some(new_line) = transform(content) {
+                output.write_all(new_line.as_bytes())?;
+                output.write_all(&line.as_bytes()[content.len()..])?;
+                written += 1;
+            }
+            line.clear();
+        }
+        Ok(written)
+    })
+}
+
+/// One step of a [`Pipeline`], such as decompression, decryption or a filter.
+///
+/// Chunks arrive in order but with arbitrary boundaries, so stages that work on
+/// records must buffer partial ones themselves.
+pub trait Stage {
+    /// Transform the next chunk, returning the bytes to pass on.
+    fn process(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Flush anything still buffered once the input is exhausted.
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Vec<u8>> Stage for F {
+    fn process(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self(chunk))
+    }
+}
+
+/// A chain of [`Stage`]s that streams a file through each of them in turn.
+///
+/// ```
+/// use file_manager::transform::Pipeline;
+///
+/// let pipeline = Pipeline::new()
+///     .stage(|chunk: &[u8]| chunk.to_ascii_uppercase())
+///     .stage(|chunk: &[u8]| chunk.iter().copied().filter(|b| *b != b'\r').collect());
+/// ```
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    chunk_size: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            stages: Vec::new(),
+            chunk_size: 64 * 1024,
+        }
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("stages", &self.stages.len())
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Append `stage` to the end of the pipeline.
+    pub fn stage<S: Stage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Read the source in chunks of `chunk_size` bytes, 64 KiB by default.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Stream `src` through every stage into `dst`, with the same path and
+    /// atomicity rules as [`transform_lines`].
+    ///
+    /// # Returns
+    /// The number of bytes written.
+    pub fn run<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, src: P, dst: Q) -> io::Result<u64> {
+        let mut input = open_input(src)?;
+        write_output(dst.as_ref(), move |output| {
+            let mut buf = vec![0; self.chunk_size];
+            let mut written = 0;
+            loop {
+                let n = match input.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                written += self.push(0, buf[..n].to_vec(), output)?;
+            }
+            for i in 0..self.stages.len() {
+                let tail = self.stages[i].finish()?;
+                written += self.push(i + 1, tail, output)?;
+            }
+            Ok(written)
+        })
+    }
+
+    /// Helper function to feed `chunk` through the stages from `from` onwards
+    /// and write what comes out.
+    fn push(&mut self, from: usize, mut chunk: Vec<u8>, output: &mut dyn Write) -> io::Result<u64> {
+        for stage in &mut self.stages[from..] {
+            if chunk.is_empty() {
+                return Ok(0);
+            }
+            chunk = stage.process(&chunk)?;
+        }
+        output.write_all(&chunk)?;
+        Ok(chunk.len() as u64)
+    }
+}
+
+/// Stream `src` through `transform` chunk by chunk into `dst`, with the same
+/// path and atomicity rules as [`transform_lines`].
+///
+/// # Returns
+/// The number of bytes written.
+pub fn transform_bytes<P, Q, F>(src: P, dst: Q, transform: F) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(&[u8]) -> Vec<u8> + 'static,
+{
+    Pipeline::new().stage(transform).run(src, dst)
+}
+
+/// Helper function to run `write` against `dst`, or stdout if it is `"-"`.
+/// Files are written to a temporary sibling that is synced and renamed over
+/// `dst` on success and removed on failure.
+pub(crate) fn write_output<T>(
+    dst: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> io::Result<T> {
+    if is_stdio_path(dst) {
+        let mut stdout = io::stdout().lock();
+        let result = write(&mut stdout)?;
+        stdout.flush()?;
+        return Ok(result);
+    }
+
+    let tmp_path = temp_sibling(dst, "transform");
+    let result = (|| {
+        let mut output = BufWriter::new(File::create_new(&tmp_path)?);
+        let result = write(&mut output)?;
+        output
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, dst)?;
+        update_sidecar(dst)?;
+        Ok(result)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_lines_filters_and_maps() {
+        // arrange
+        let dir = "assets/transform_lines_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (log, clean) = (format!("{}/app.log", dir), format!("{}/clean.log", dir));
+        fs::write(&log, "DEBUG a\r\nINFO b\nDEBUG c\nWARN d").unwrap();
+
+        // act
+        let written = transform_lines(&log, &clean, |line| {
+            (!line.starts_with("DEBUG")).then(|| line.to_lowercase())
+        })
+        .unwrap();
+        let in_place = transform_lines(&log, &log, |line| Some(line.replace(' ', "="))).unwrap();
+
+        // assert
+        assert_eq!(2, written);
+        assert_eq!("info b\nwarn d", fs::read_to_string(&clean).unwrap());
+        assert_eq!(4, in_place);
+        assert_eq!(
+            "DEBUG=a\r\nINFO=b\nDEBUG=c\nWARN=d",
+            fs::read_to_string(&log).unwrap()
+        );
+        assert_eq!(2, fs::read_dir(dir).unwrap().count());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Joins lines split across chunk boundaries and drops comment lines.
+    struct DropComments {
+        partial: Vec<u8>,
+    }
+
+    impl DropComments {
+        fn keep(line: &[u8]) -> Vec<u8> {
+            if line.starts_with(b"#") {
+                Vec::new()
+            } else {
+                line.to_vec()
+            }
+        }
+    }
+
+    impl Stage for DropComments {
+        fn process(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+            self.partial.extend_from_slice(chunk);
+            let mut kept = Vec::new();
+            while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.partial.drain(..=end).collect();
+                kept.extend(DropComments::keep(&line));
+            }
+            Ok(kept)
+        }
+
+        fn finish(&mut self) -> io::Result<Vec<u8>> {
+            Ok(DropComments::keep(&std::mem::take(&mut self.partial)))
+        }
+    }
+
+    #[test]
+    fn pipeline_streams_through_stages() {
+        // arrange
+        let dir = "assets/transform_pipeline_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let (src, dst) = (format!("{}/in.txt", dir), format!("{}/out.txt", dir));
+        fs::write(&src, "# header\nalpha\n# note\nbeta").unwrap();
+        let mut pipeline = Pipeline::new()
+            .chunk_size(3)
+            .stage(DropComments {
+                partial: Vec::new(),
+            })
+            .stage(|chunk: &[u8]| chunk.to_ascii_uppercase());
+
+        // act
+        let written = pipeline.run(&src, &dst).unwrap();
+        let upper = fs::read_to_string(&dst).unwrap();
+        let reversed = transform_bytes(&dst, &dst, |chunk: &[u8]| {
+            chunk.iter().rev().copied().collect()
+        });
+
+        // assert
+        assert_eq!(10, written);
+        assert_eq!("ALPHA\nBETA", upper);
+        assert_eq!(Ok(10), reversed.map_err(|e| e.kind()));
+        assert_eq!("ATEB\nAHPLA", fs::read_to_string(&dst).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
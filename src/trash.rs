@@ -0,0 +1,524 @@
+//! Move files to the platform's trash instead of deleting them.
+//!
+//! On Linux and the BSDs this follows the FreeDesktop trash specification, so
+//! file managers can restore what was trashed, and [`list_trash`] and
+//! [`restore`] are supported. On Windows files go to the Recycle Bin and on
+//! macOS to `~/.Trash`, where only the system's own tools can put them back.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A file or directory in the trash, listed by [`list_trash`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashItem {
+    /// Where it was trashed from.
+    pub original_path: PathBuf,
+    /// Where it is now, inside the trash.
+    pub trashed_path: PathBuf,
+    /// `None` if the trash did not record a valid time.
+    pub deleted_at: Option<SystemTime>,
+}
+
+/// Move the file, directory or symlink at `path` to the user's trash.
+///
+/// On Linux and the BSDs something on another filesystem than the home trash
+/// is copied there, verified and then removed.
+pub fn trash<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    fs::symlink_metadata(path)?;
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot trash {}", path.display()),
+        )
+    })?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    platform::trash(&parent.join(name))
+}
+
+/// Everything in the user's trash, oldest first.
+///
+/// # Errors
+/// `Unsupported` on Windows and macOS.
+pub fn list_trash() -> io::Result<Vec<TrashItem>> {
+    platform::list()
+}
+
+/// Move `item` from the trash back to its original path.
+///
+/// # Errors
+/// `AlreadyExists` if something is at the original path again, and
+/// `Unsupported` on Windows and macOS.
+pub fn restore(item: &TrashItem) -> io::Result<()> {
+    platform::restore(item)
+}
+
+/// Permanently delete everything in the user's trash.
+pub fn empty_trash() -> io::Result<()> {
+    platform::empty()
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::TrashItem;
+    use crate::{move_dir, move_file, numbered_file_name};
+    use std::{
+        env,
+        ffi::OsString,
+        fs::{self, OpenOptions},
+        io::{self, ErrorKind, Write},
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    /// A trash directory laid out as the FreeDesktop specification describes.
+    pub(super) struct Trash {
+        pub(super) dir: PathBuf,
+    }
+
+    impl Trash {
+        /// The home trash, in `$XDG_DATA_HOME/Trash` or `~/.local/share/Trash`.
+        fn home() -> io::Result<Self> {
+            let absolute = |name: &str| {
+                env::var_os(name)
+                    .map(PathBuf::from)
+                    .filter(|p| p.is_absolute())
+            };
+            let data = match absolute("XDG_DATA_HOME") {
+                Some(data) => data,
+                None => absolute("HOME")
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            ErrorKind::NotFound,
+                            "cannot find the trash: HOME is not set",
+                        )
+                    })?
+                    .join(".local/share"),
+            };
+            Ok(Trash {
+                dir: data.join("Trash"),
+            })
+        }
+
+        pub(super) fn trash(&self, path: &Path) -> io::Result<()> {
+            let (files, info) = (self.dir.join("files"), self.dir.join("info"));
+            fs::create_dir_all(&files)?;
+            fs::create_dir_all(&info)?;
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            // Reserving the info file first keeps concurrent trashers apart.
+            let mut n = 0;
+            let (trashed_name, info_path, mut info_file) = loop {
+                let candidate = match n {
+                    0 => name.to_os_string(),
+                    n => numbered_file_name(name, n),
+                };
+                let mut info_name = candidate.clone();
+                info_name.push(".trashinfo");
+                let info_path = info.join(info_name);
+                match OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&info_path)
+                {
+                    Ok(file) if !files.join(&candidate).exists() => {
+                        break (candidate, info_path, file)
+                    }
+                    // Left behind without its info file; keep looking.
+                    Ok(_) => fs::remove_file(&info_path)?,
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                    Err(e) => return Err(e),
+                }
+                n += 1;
+            };
+            let result = (|| {
+                let contents = format!(
+                    "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                    encode_path(path),
+                    local_time(SystemTime::now())
+                );
+                info_file.write_all(contents.as_bytes())?;
+                info_file.sync_all()?;
+                let trashed_path = files.join(&trashed_name);
+                if fs::symlink_metadata(path)?.is_dir() {
+                    move_dir(path, &trashed_path)?;
+                } else {
+                    move_file(path, &trashed_path)?;
+                }
+                Ok(())
+            })();
+            if result.is_err() {
+                let _ = fs::remove_file(&info_path);
+            }
+            result
+        }
+
+        pub(super) fn list(&self) -> io::Result<Vec<TrashItem>> {
+            let entries = match fs::read_dir(self.dir.join("info")) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+            let mut items = Vec::new();
+            for entry in entries {
+                let info_path = entry?.path();
+                if info_path.extension().is_none_or(|ext| ext != "trashinfo") {
+                    continue;
+                }
+                let trashed_path = self
+                    .dir
+                    .join("files")
+                    .join(info_path.file_stem().unwrap_or_default());
+                if fs::symlink_metadata(&trashed_path).is_err() {
+                    continue;
+                }
+                let info = fs::read_to_string(&info_path)?;
+                let field = |key: &str| {
+                    info.lines()
+                        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                };
+                let Some(original_path) = field("Path").and_then(decode_path) else {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid trash info {}", info_path.display()),
+                    ));
+                };
+                items.push(TrashItem {
+                    original_path,
+                    trashed_path,
+                    deleted_at: field("DeletionDate").and_then(parse_local_time),
+                });
+            }
+            items.sort_by_key(|item| item.deleted_at);
+            Ok(items)
+        }
+
+        pub(super) fn restore(&self, item: &TrashItem) -> io::Result<()> {
+            let original = &item.original_path;
+            if fs::symlink_metadata(original).is_ok() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("cannot restore to {}: it exists", original.display()),
+                ));
+            }
+            if let Some(parent) = original.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if fs::symlink_metadata(&item.trashed_path)?.is_dir() {
+                move_dir(&item.trashed_path, original)?;
+            } else {
+                move_file(&item.trashed_path, original)?;
+            }
+            let mut info_name = item
+                .trashed_path
+                .file_name()
+                .unwrap_or_default()
+                .to_os_string();
+            info_name.push(".trashinfo");
+            match fs::remove_file(self.dir.join("info").join(info_name)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        }
+
+        pub(super) fn empty(&self) -> io::Result<()> {
+            for sub in ["files", "info"] {
+                let entries = match fs::read_dir(self.dir.join(sub)) {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                for entry in entries {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        fs::remove_dir_all(entry.path())?;
+                    } else {
+                        fs::remove_file(entry.path())?;
+                    }
+                }
+            }
+            match fs::remove_file(self.dir.join("directorysizes")) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    pub(super) fn trash(path: &Path) -> io::Result<()> {
+        Trash::home()?.trash(path)
+    }
+
+    pub(super) fn list() -> io::Result<Vec<TrashItem>> {
+        Trash::home()?.list()
+    }
+
+    pub(super) fn restore(item: &TrashItem) -> io::Result<()> {
+        Trash::home()?.restore(item)
+    }
+
+    pub(super) fn empty() -> io::Result<()> {
+        Trash::home()?.empty()
+    }
+
+    /// Helper function to percent-encode `path` as the specification requires.
+    fn encode_path(path: &Path) -> String {
+        let mut encoded = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        encoded
+    }
+
+    fn decode_path(encoded: &str) -> Option<PathBuf> {
+        let mut bytes = Vec::new();
+        let mut rest = encoded.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == b'%' {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            } else {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+        Some(PathBuf::from(OsString::from_vec(bytes)))
+    }
+
+    /// Helper function to format `time` as `YYYY-MM-DDThh:mm:ss` in local time.
+    fn local_time(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as libc::time_t;
+        // SAFETY: `tm` is plain data, filled in by `localtime_r`.
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        // SAFETY: both pointers are valid for the duration of the call.
+        unsafe { libc::localtime_r(&secs, &mut tm) };
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+
+    fn parse_local_time(s: &str) -> Option<SystemTime> {
+        let (date, time) = s.split_once('T')?;
+        let mut date = date.splitn(3, '-').map(str::parse::<i32>);
+        let mut time = time.splitn(3, ':').map(str::parse::<i32>);
+        // SAFETY: `tm` is plain data.
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = date.next()?.ok()? - 1900;
+        tm.tm_mon = date.next()?.ok()? - 1;
+        tm.tm_mday = date.next()?.ok()?;
+        tm.tm_hour = time.next()?.ok()?;
+        tm.tm_min = time.next()?.ok()?;
+        tm.tm_sec = time.next()?.ok()?;
+        tm.tm_isdst = -1;
+        // SAFETY: `tm` is valid for the duration of the call.
+        let secs = unsafe { libc::mktime(&mut tm) };
+        u64::try_from(secs)
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::TrashItem;
+    use crate::{move_dir, move_file, numbered_file_name};
+    use std::{
+        env, fs,
+        io::{self, ErrorKind},
+        path::{Path, PathBuf},
+    };
+
+    fn trash_dir() -> io::Result<PathBuf> {
+        env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".Trash"))
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    "cannot find the trash: HOME is not set",
+                )
+            })
+    }
+
+    pub(super) fn trash(path: &Path) -> io::Result<()> {
+        let dir = trash_dir()?;
+        fs::create_dir_all(&dir)?;
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let mut target = dir.join(name);
+        let mut n = 1;
+        while fs::symlink_metadata(&target).is_ok() {
+            target = dir.join(numbered_file_name(name, n));
+            n += 1;
+        }
+        if fs::symlink_metadata(path)?.is_dir() {
+            move_dir(path, &target)?;
+        } else {
+            move_file(path, &target)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn list() -> io::Result<Vec<TrashItem>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn restore(_item: &TrashItem) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn empty() -> io::Result<()> {
+        for entry in fs::read_dir(trash_dir()?)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            ErrorKind::Unsupported,
+            "the macOS trash does not record where files came from",
+        )
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::TrashItem;
+    use std::{
+        io::{self, ErrorKind},
+        os::windows::ffi::OsStrExt,
+        path::Path,
+    };
+    use windows_sys::Win32::UI::Shell::{
+        SHEmptyRecycleBinW, SHFileOperationW, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI,
+        FOF_SILENT, FO_DELETE, SHERB_NOCONFIRMATION, SHERB_NOPROGRESSUI, SHERB_NOSOUND,
+        SHFILEOPSTRUCTW,
+    };
+
+    pub(super) fn trash(path: &Path) -> io::Result<()> {
+        // A list of paths, each NUL-terminated, ending with an empty one.
+        let from: Vec<u16> = path.as_os_str().encode_wide().chain([0, 0]).collect();
+        let mut operation = SHFILEOPSTRUCTW {
+            wFunc: FO_DELETE,
+            pFrom: from.as_ptr(),
+            fFlags: (FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT) as u16,
+            ..Default::default()
+        };
+        // SAFETY: `operation` and the path list it points to outlive the call.
+        match unsafe { SHFileOperationW(&mut operation) } {
+            0 if operation.fAnyOperationsAborted == 0 => Ok(()),
+            0 => Err(io::Error::new(
+                ErrorKind::Interrupted,
+                "trashing was cancelled",
+            )),
+            code => Err(io::Error::other(format!(
+                "cannot move {} to the Recycle Bin: error {:#x}",
+                path.display(),
+                code
+            ))),
+        }
+    }
+
+    pub(super) fn list() -> io::Result<Vec<TrashItem>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn restore(_item: &TrashItem) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn empty() -> io::Result<()> {
+        let flags = SHERB_NOCONFIRMATION | SHERB_NOPROGRESSUI | SHERB_NOSOUND;
+        // SAFETY: null window and root mean no UI and every drive.
+        let result = unsafe { SHEmptyRecycleBinW(std::ptr::null_mut(), std::ptr::null(), flags) };
+        // An already empty Recycle Bin reports a failure as well.
+        if result < 0 && result != 0x8000FFFFu32 as i32 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(())
+    }
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            ErrorKind::Unsupported,
+            "listing and restoring the Recycle Bin is not supported",
+        )
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use platform::Trash;
+
+    #[test]
+    fn trashed_files_can_be_listed_and_restored() {
+        // arrange
+        let dir = "assets/trash_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/work/notes", dir)).unwrap();
+        let work = Path::new(dir).join("work").canonicalize().unwrap();
+        let trash = Trash {
+            dir: Path::new(dir).join("Trash"),
+        };
+        fs::write(work.join("draft 1.txt"), "first").unwrap();
+        fs::write(work.join("notes/a.txt"), "a").unwrap();
+
+        // act
+        trash.trash(&work.join("draft 1.txt")).unwrap();
+        fs::write(work.join("draft 1.txt"), "second").unwrap();
+        trash.trash(&work.join("draft 1.txt")).unwrap();
+        trash.trash(&work.join("notes")).unwrap();
+        let listed = trash.list().unwrap();
+        let first = listed
+            .iter()
+            .find(|item| item.trashed_path.ends_with("draft 1.txt"))
+            .unwrap();
+        trash.restore(first).unwrap();
+        let remaining = trash.list().unwrap();
+        trash.empty().unwrap();
+        let emptied = trash.list().unwrap();
+
+        // assert
+        assert_eq!(3, listed.len());
+        assert!(listed.iter().all(|item| item.deleted_at.is_some()));
+        assert_eq!(
+            "first",
+            fs::read_to_string(work.join("draft 1.txt")).unwrap()
+        );
+        assert!(!work.join("notes").exists());
+        let mut names: Vec<_> = remaining
+            .iter()
+            .map(|item| item.trashed_path.file_name().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["draft 1 (1).txt", "notes"], names);
+        assert!(remaining
+            .iter()
+            .all(|item| item.original_path.starts_with(&work)));
+        assert!(emptied.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,610 @@
+//! A minimal filesystem abstraction that storage backends can implement.
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+/// The kind of a filesystem entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsKind {
+    File,
+    Dir,
+}
+
+/// Metadata for an entry in a [`FileSystem`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsMetadata {
+    pub kind: VfsKind,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// A single entry returned by [`FileSystem::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsDirEntry {
+    pub name: String,
+    pub kind: VfsKind,
+}
+
+/// A hierarchical store of files and directories.
+///
+/// Paths are relative to the filesystem's root; a leading `/` is ignored and
+/// `..` components are rejected with `InvalidInput`. The empty path is the root.
+pub trait FileSystem: Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+
+    /// List the entries in the directory at `path`, sorted by name.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>>;
+
+    /// Read up to `buf.len()` bytes starting at `offset`, returning how many were read.
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write `data` at `offset`, extending the file with zeros if needed.
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Create an empty file. Fails with `AlreadyExists` if `path` exists.
+    fn create_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a directory. The parent must already exist.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Move `from` to `to`, replacing `to` if it is a file.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Truncate or zero-extend the file at `path` to `len` bytes.
+    fn set_len(&self, path: &Path, len: u64) -> io::Result<()>;
+
+    /// Read the whole file at `path`.
+    fn read_all(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; self.metadata(path)?.len as usize];
+        // `read_at` may return fewer bytes than asked for.
+        let mut read = 0;
+        while read < data.len() {
+            match self.read_at(path, read as u64, &mut data[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Create or replace the file at `path` with `data`.
+    fn write_all(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        match self.create_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => self.set_len(path, 0)?,
+            result => result?,
+        }
+        self.write_at(path, 0, data)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+}
+
+/// Helper function to turn a [`FileSystem`] path into a normalized relative path.
+pub(crate) fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::RootDir | Component::CurDir => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("path escapes the filesystem root: {}", path.display()),
+                ))
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// A [`FileSystem`] backed by a directory on the local disk.
+#[derive(Debug, Clone)]
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    /// Serve the contents of `root`, which must be an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        if !fs::metadata(&root)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a directory: {}", root.display()),
+            ));
+        }
+        Ok(LocalFs { root })
+    }
+
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(self.root.join(normalize(path)?))
+    }
+}
+
+impl FileSystem for LocalFs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let metadata = fs::metadata(self.resolve(path)?)?;
+        Ok(VfsMetadata {
+            kind: if metadata.is_dir() {
+                VfsKind::Dir
+            } else {
+                VfsKind::File
+            },
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.resolve(path)?)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            // Like `metadata`, describe what a symlink points to.
+            let is_dir = if file_type.is_symlink() {
+                entry.path().is_dir()
+            } else {
+                file_type.is_dir()
+            };
+            let kind = if is_dir { VfsKind::Dir } else { VfsKind::File };
+            entries.push(VfsDirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                kind,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = fs::File::open(self.resolve(path)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(self.resolve(path)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.resolve(path)?)
+            .map(drop)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(self.resolve(path)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(self.resolve(path)?)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(self.resolve(path)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(self.resolve(from)?, self.resolve(to)?)
+    }
+
+    fn set_len(&self, path: &Path, len: u64) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.resolve(path)?)?
+            .set_len(len)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File { data: Vec<u8>, modified: SystemTime },
+    Dir { modified: SystemTime },
+}
+
+/// A [`FileSystem`] that keeps everything in memory.
+#[derive(Debug)]
+pub struct MemoryFs {
+    nodes: RwLock<BTreeMap<PathBuf, Node>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            PathBuf::new(),
+            Node::Dir {
+                modified: SystemTime::now(),
+            },
+        );
+        MemoryFs {
+            nodes: RwLock::new(nodes),
+        }
+    }
+}
+
+impl MemoryFs {
+    /// Create an empty in-memory filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such file or directory: {}", path.display()),
+    )
+}
+
+/// Helper function to check that the parent of `path` is an existing directory.
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "the root already exists"))?;
+    match nodes.get(parent) {
+        Some(Node::Dir { .. }) => Ok(()),
+        Some(Node::File { .. }) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+        None => Err(not_found(parent)),
+    }
+}
+
+fn file_mut<'a>(
+    nodes: &'a mut BTreeMap<PathBuf, Node>,
+    path: &Path,
+) -> io::Result<&'a mut Vec<u8>> {
+    match nodes.get_mut(path) {
+        Some(Node::File { data, modified }) => {
+            *modified = SystemTime::now();
+            Ok(data)
+        }
+        Some(Node::Dir { .. }) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+        None => Err(not_found(path)),
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let path = normalize(path)?;
+        match self.read().get(&path) {
+            Some(Node::File { data, modified }) => Ok(VfsMetadata {
+                kind: VfsKind::File,
+                len: data.len() as u64,
+                modified: *modified,
+            }),
+            Some(Node::Dir { modified }) => Ok(VfsMetadata {
+                kind: VfsKind::Dir,
+                len: 0,
+                modified: *modified,
+            }),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        let path = normalize(path)?;
+        let nodes = self.read();
+        match nodes.get(&path) {
+            Some(Node::Dir { .. }) => {}
+            Some(Node::File { .. }) => return Err(io::Error::from(io::ErrorKind::NotADirectory)),
+            None => return Err(not_found(&path)),
+        }
+        let entries = nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path.as_path()))
+            .map(|(child, node)| VfsDirEntry {
+                name: child
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                kind: match node {
+                    Node::File { .. } => VfsKind::File,
+                    Node::Dir { .. } => VfsKind::Dir,
+                },
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let path = normalize(path)?;
+        match self.read().get(&path) {
+            Some(Node::File { data, .. }) => {
+                let start = (offset as usize).min(data.len());
+                let n = buf.len().min(data.len() - start);
+                buf[..n].copy_from_slice(&data[start..start + n]);
+                Ok(n)
+            }
+            Some(Node::Dir { .. }) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        let file = file_mut(&mut nodes, &path)?;
+        let end = offset as usize + data.len();
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        check_parent(&nodes, &path)?;
+        if nodes.contains_key(&path) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        nodes.insert(
+            path,
+            Node::File {
+                data: Vec::new(),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        check_parent(&nodes, &path)?;
+        if nodes.contains_key(&path) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        nodes.insert(
+            path,
+            Node::Dir {
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        match nodes.get(&path) {
+            Some(Node::File { .. }) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(Node::Dir { .. }) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        match nodes.get(&path) {
+            Some(Node::Dir { .. }) if path.as_os_str().is_empty() => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "cannot remove the root",
+            )),
+            Some(Node::Dir { .. }) => {
+                if nodes
+                    .keys()
+                    .any(|child| child.parent() == Some(path.as_path()))
+                {
+                    return Err(io::Error::from(io::ErrorKind::DirectoryNotEmpty));
+                }
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(Node::File { .. }) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = normalize(from)?;
+        let to = normalize(to)?;
+        let mut nodes = self.write();
+        if !nodes.contains_key(&from) {
+            return Err(not_found(&from));
+        }
+        if from.as_os_str().is_empty() || to.starts_with(&from) && to != from {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot move a directory into itself",
+            ));
+        }
+        check_parent(&nodes, &to)?;
+        match nodes.get(&to) {
+            Some(Node::Dir { .. }) if from != to => {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            }
+            _ => {}
+        }
+        let moved: Vec<PathBuf> = nodes
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in moved {
+            if let Some(node) = nodes.remove(&path) {
+                let suffix = path.strip_prefix(&from).unwrap_or(Path::new(""));
+                nodes.insert(to.join(suffix), node);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_len(&self, path: &Path, len: u64) -> io::Result<()> {
+        let path = normalize(path)?;
+        let mut nodes = self.write();
+        file_mut(&mut nodes, &path)?.resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_fs_works() {
+        // arrange
+        let fs = MemoryFs::new();
+
+        // act
+        fs.create_dir(Path::new("/docs")).unwrap();
+        fs.write_all(Path::new("docs/a.txt"), b"hello").unwrap();
+        fs.write_at(Path::new("docs/a.txt"), 7, b"!").unwrap();
+        fs.rename(Path::new("docs"), Path::new("notes")).unwrap();
+        let listing = fs.read_dir(Path::new("")).unwrap();
+        let contents = fs.read_all(Path::new("notes/a.txt")).unwrap();
+        let escaped = fs.metadata(Path::new("../etc"));
+        let not_empty = fs.remove_dir(Path::new("notes"));
+
+        // assert
+        assert_eq!(
+            vec![VfsDirEntry {
+                name: "notes".to_string(),
+                kind: VfsKind::Dir,
+            }],
+            listing
+        );
+        assert_eq!(b"hello\0\0!".to_vec(), contents);
+        assert!(!fs.exists(Path::new("docs/a.txt")));
+        assert_eq!(io::ErrorKind::InvalidInput, escaped.unwrap_err().kind());
+        assert_eq!(
+            io::ErrorKind::DirectoryNotEmpty,
+            not_empty.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn local_fs_works() {
+        // arrange
+        let dir = "assets/vfs_local_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let local = LocalFs::new(dir).unwrap();
+
+        // act
+        local.create_dir(Path::new("sub")).unwrap();
+        local.write_all(Path::new("sub/b.txt"), b"abcdef").unwrap();
+        local.set_len(Path::new("sub/b.txt"), 3).unwrap();
+        let mut buf = [0; 8];
+        let n = local.read_at(Path::new("sub/b.txt"), 1, &mut buf).unwrap();
+        let metadata = local.metadata(Path::new("/sub")).unwrap();
+
+        // assert
+        assert_eq!(b"bc", &buf[..n]);
+        assert_eq!(VfsKind::Dir, metadata.kind);
+        assert_eq!(
+            "abc",
+            fs::read_to_string(format!("{}/sub/b.txt", dir)).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Returns at most two bytes per call, as a backend may.
+    struct ShortReads(MemoryFs);
+
+    impl FileSystem for ShortReads {
+        fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+            self.0.metadata(path)
+        }
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+            self.0.read_dir(path)
+        }
+        fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(2);
+            self.0.read_at(path, offset, &mut buf[..len])
+        }
+        fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+            self.0.write_at(path, offset, data)
+        }
+        fn create_file(&self, path: &Path) -> io::Result<()> {
+            self.0.create_file(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            self.0.create_dir(path)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.0.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            self.0.remove_dir(path)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.0.rename(from, to)
+        }
+        fn set_len(&self, path: &Path, len: u64) -> io::Result<()> {
+            self.0.set_len(path, len)
+        }
+    }
+
+    #[test]
+    fn read_all_reads_past_short_reads() {
+        // arrange
+        let fs = ShortReads(MemoryFs::new());
+        fs.write_all(Path::new("a.txt"), b"hello").unwrap();
+
+        // act
+        let contents = fs.read_all(Path::new("a.txt")).unwrap();
+
+        // assert
+        assert_eq!(b"hello".to_vec(), contents);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn local_fs_lists_symlinked_dirs_as_dirs() {
+        // arrange
+        let dir = "assets/vfs_symlink_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/real", dir)).unwrap();
+        std::os::unix::fs::symlink("real", format!("{}/link", dir)).unwrap();
+        let local = LocalFs::new(dir).unwrap();
+
+        // act
+        let listing = local.read_dir(Path::new("")).unwrap();
+        let metadata = local.metadata(Path::new("link")).unwrap();
+
+        // assert
+        assert_eq!(VfsKind::Dir, metadata.kind);
+        assert!(listing.iter().all(|entry| entry.kind == VfsKind::Dir));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Information about the filesystem volume holding a path.
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The volume a path lives on, as reported by [`volume_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeInfo {
+    /// The filesystem type, such as `ext4`, `apfs` or `NTFS`, or `unknown`.
+    pub fs_type: String,
+    /// Where the volume is mounted.
+    pub mount_point: PathBuf,
+    /// Whether the path itself is the mount point.
+    pub is_mount_point: bool,
+    pub read_only: bool,
+    pub total_bytes: u64,
+    /// Free space, including space reserved for the superuser.
+    pub free_bytes: u64,
+    /// Free space usable by the current user.
+    pub available_bytes: u64,
+}
+
+impl VolumeInfo {
+    /// Returns `true` if `self` and `other` are the same volume, so renames
+    /// between them don't need to copy.
+    pub fn same_volume(&self, other: &VolumeInfo) -> bool {
+        self.mount_point == other.mount_point
+    }
+}
+
+/// Describe the volume holding `path`. Symlinks are followed.
+pub fn volume_info<P: AsRef<Path>>(path: P) -> io::Result<VolumeInfo> {
+    let path = path.as_ref().canonicalize()?;
+    platform::volume_info(&path)
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::VolumeInfo;
+    use std::{
+        ffi::CString,
+        io, mem,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    };
+
+    pub(super) fn volume_info(path: &Path) -> io::Result<VolumeInfo> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: a zeroed statvfs is a valid value of the plain C struct.
+        let mut stats: libc::statvfs = unsafe { mem::zeroed() };
+        // SAFETY: `c_path` is NUL terminated and `stats` is valid to write to.
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (mount_point, fs_type) = mount(path, &c_path)?;
+        let block = stats.f_frsize as u64;
+        Ok(VolumeInfo {
+            fs_type,
+            is_mount_point: mount_point == path,
+            mount_point,
+            read_only: stats.f_flag & libc::ST_RDONLY != 0,
+            total_bytes: stats.f_blocks as u64 * block,
+            free_bytes: stats.f_bfree as u64 * block,
+            available_bytes: stats.f_bavail as u64 * block,
+        })
+    }
+
+    /// The mount point and filesystem type from the mount table, picking the
+    /// longest mount point that contains `path`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn mount(path: &Path, _c_path: &CString) -> io::Result<(PathBuf, String)> {
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        let mut best: Option<(PathBuf, String)> = None;
+        for line in mountinfo.lines() {
+            // Fields: id parent major:minor root mount-point options ... - fstype source
+            let Some((fields, rest)) = line.split_once(" - ") else {
+                continue;
+            };
+            let (Some(mount_point), Some(fs_type)) =
+                (fields.split(' ').nth(4), rest.split(' ').next())
+            else {
+                continue;
+            };
+            let mount_point = PathBuf::from(unescape(mount_point));
+            let longer = best
+                .as_ref()
+                .is_none_or(|(best, _)| mount_point.as_os_str().len() >= best.as_os_str().len());
+            if path.starts_with(&mount_point) && longer {
+                best = Some((mount_point, fs_type.to_owned()));
+            }
+        }
+        best.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no mount found for {}", path.display()),
+            )
+        })
+    }
+
+    /// Undo the octal escapes (`\040` for a space) used in the mount table.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn unescape(field: &str) -> std::ffi::OsString {
+        use std::os::unix::ffi::OsStringExt;
+
+        let bytes = field.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let escaped = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+            match escaped.and_then(|d| u8::from_str_radix(std::str::from_utf8(d).ok()?, 8).ok()) {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 4;
+                }
+                None => {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        std::ffi::OsString::from_vec(out)
+    }
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    fn mount(_path: &Path, c_path: &CString) -> io::Result<(PathBuf, String)> {
+        use std::ffi::{CStr, OsStr};
+
+        // SAFETY: a zeroed statfs is a valid value of the plain C struct.
+        let mut stats: libc::statfs = unsafe { mem::zeroed() };
+        // SAFETY: `c_path` is NUL terminated and `stats` is valid to write to.
+        if unsafe { libc::statfs(c_path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the kernel NUL terminates both names within their arrays.
+        let (mount_point, fs_type) = unsafe {
+            (
+                CStr::from_ptr(stats.f_mntonname.as_ptr()),
+                CStr::from_ptr(stats.f_fstypename.as_ptr()),
+            )
+        };
+        Ok((
+            PathBuf::from(OsStr::from_bytes(mount_point.to_bytes())),
+            fs_type.to_string_lossy().into_owned(),
+        ))
+    }
+
+    /// Without a mount table, walk up until the device changes.
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    fn mount(path: &Path, _c_path: &CString) -> io::Result<(PathBuf, String)> {
+        use std::os::unix::fs::MetadataExt;
+
+        let device = std::fs::metadata(path)?.dev();
+        let mut mount_point = path;
+        while let Some(parent) = mount_point.parent() {
+            if std::fs::metadata(parent)?.dev() != device {
+                break;
+            }
+            mount_point = parent;
+        }
+        Ok((mount_point.to_path_buf(), "unknown".to_owned()))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::VolumeInfo;
+    use std::{
+        ffi::OsString,
+        io,
+        os::windows::ffi::{OsStrExt, OsStringExt},
+        path::{Path, PathBuf},
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetDiskFreeSpaceExW, GetVolumeInformationW, GetVolumePathNameW,
+    };
+
+    const FILE_READ_ONLY_VOLUME: u32 = 0x0008_0000;
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    fn from_wide(buf: &[u16]) -> OsString {
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        OsString::from_wide(&buf[..len])
+    }
+
+    pub(super) fn volume_info(path: &Path) -> io::Result<VolumeInfo> {
+        let mut volume = [0u16; 1024];
+        let mut fs_name = [0u16; 64];
+        let mut flags = 0;
+        let (mut available, mut total, mut free) = (0, 0, 0);
+        // SAFETY: all buffers are valid for the lengths passed, and the input
+        // strings are NUL terminated.
+        unsafe {
+            if GetVolumePathNameW(
+                wide(path).as_ptr(),
+                volume.as_mut_ptr(),
+                volume.len() as u32,
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let ok = GetVolumeInformationW(
+                volume.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut flags,
+                fs_name.as_mut_ptr(),
+                fs_name.len() as u32,
+            );
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if GetDiskFreeSpaceExW(volume.as_ptr(), &mut available, &mut total, &mut free) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // Canonical paths carry the `\\?\` prefix, which the volume path lacks.
+        let mount_point = PathBuf::from(from_wide(&volume));
+        let plain = path.to_string_lossy();
+        let plain = plain.strip_prefix(r"\\?\").unwrap_or(&plain);
+        Ok(VolumeInfo {
+            fs_type: from_wide(&fs_name).to_string_lossy().into_owned(),
+            is_mount_point: Path::new(plain) == mount_point,
+            mount_point,
+            read_only: flags & FILE_READ_ONLY_VOLUME != 0,
+            total_bytes: total,
+            free_bytes: free,
+            available_bytes: available,
+        })
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::VolumeInfo;
+    use std::{io, path::Path};
+
+    pub(super) fn volume_info(_path: &Path) -> io::Result<VolumeInfo> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "volume information is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_info_works() {
+        // arrange
+        let root = if cfg!(windows) { "C:\\" } else { "/" };
+
+        // act
+        let assets = volume_info("assets").unwrap();
+        let root = volume_info(root).unwrap();
+
+        // assert
+        assert!(!assets.is_mount_point);
+        assert!(Path::new("assets")
+            .canonicalize()
+            .unwrap()
+            .starts_with(&assets.mount_point));
+        assert!(assets.total_bytes >= assets.free_bytes);
+        assert!(assets.free_bytes >= assets.available_bytes);
+        assert!(!assets.fs_type.is_empty());
+        assert!(root.is_mount_point);
+    }
+}
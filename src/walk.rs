@@ -0,0 +1,214 @@
+//! Walk a directory tree with control over depth, symlinks and order.
+use std::{
+    fs::{self, FileType, Metadata},
+    io,
+    path::{Path, PathBuf},
+    vec,
+};
+
+/// An entry found by a [`Walk`].
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    /// How many directories below the root the entry is; the root has depth 0.
+    pub depth: usize,
+    /// The type of the entry itself, or of its target when following symlinks.
+    pub file_type: FileType,
+    pub metadata: Metadata,
+}
+
+/// A depth-first walk of the tree at a root, configured with builder methods
+/// and run by iterating it. Each directory is yielded before its contents.
+///
+/// ```no_run
+/// use file_manager::walk::walk;
+///
+/// for entry in walk("src").max_depth(2).sort(true) {
+///     let entry = entry?;
+///     println!("{}{}", "  ".repeat(entry.depth), entry.path.display());
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Walk {
+    root: PathBuf,
+    max_depth: usize,
+    follow_symlinks: bool,
+    sort: bool,
+}
+
+/// Walk the tree at `root`, including `root` itself. Symlinks are listed but
+/// not followed, and entries come in the order the OS lists them.
+pub fn walk<P: AsRef<Path>>(root: P) -> Walk {
+    Walk {
+        root: root.as_ref().to_path_buf(),
+        max_depth: usize::MAX,
+        follow_symlinks: false,
+        sort: false,
+    }
+}
+
+impl Walk {
+    /// Don't descend below `depth`; 0 yields only the root.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Follow symlinks, reporting their targets' types and metadata and
+    /// walking into linked directories. Links back to a directory being
+    /// walked are reported as errors instead of looping.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Yield the entries of each directory sorted by file name.
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+}
+
+impl IntoIterator for Walk {
+    type Item = io::Result<WalkEntry>;
+    type IntoIter = WalkIter;
+
+    fn into_iter(self) -> WalkIter {
+        let root = self.root.clone();
+        WalkIter {
+            walk: self,
+            stack: Vec::new(),
+            ancestors: Vec::new(),
+            root: Some(root),
+        }
+    }
+}
+
+/// The iterator of a [`Walk`].
+#[derive(Debug)]
+pub struct WalkIter {
+    walk: Walk,
+    /// The entries left in each directory being walked, innermost last.
+    stack: Vec<vec::IntoIter<PathBuf>>,
+    /// The canonical paths of the directories on the stack, to detect symlink loops.
+    ancestors: Vec<PathBuf>,
+    root: Option<PathBuf>,
+}
+
+impl WalkIter {
+    /// Helper function to read the entry at `path` and, for directories
+    /// within the depth limit, queue their contents.
+    fn visit(&mut self, path: PathBuf, depth: usize) -> io::Result<WalkEntry> {
+        let metadata = if self.walk.follow_symlinks {
+            fs::metadata(&path)?
+        } else {
+            fs::symlink_metadata(&path)?
+        };
+        if metadata.is_dir() && depth < self.walk.max_depth {
+            let canonical = if self.walk.follow_symlinks {
+                let canonical = path.canonicalize()?;
+                if self.ancestors.contains(&canonical) {
+                    return Err(io::Error::other(format!(
+                        "symlink loop at {}",
+                        path.display()
+                    )));
+                }
+                Some(canonical)
+            } else {
+                None
+            };
+            let mut children = fs::read_dir(&path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            if self.walk.sort {
+                children.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+            }
+            self.ancestors.extend(canonical);
+            self.stack.push(children.into_iter());
+        }
+        Ok(WalkEntry {
+            path,
+            depth,
+            file_type: metadata.file_type(),
+            metadata,
+        })
+    }
+}
+
+impl Iterator for WalkIter {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            return Some(self.visit(root, 0));
+        }
+        loop {
+            let depth = self.stack.len();
+            match self.stack.last_mut()?.next() {
+                Some(path) => match self.visit(path, depth) {
+                    // Removed since its directory was read.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    result => return Some(result),
+                },
+                None => {
+                    self.stack.pop();
+                    if self.walk.follow_symlinks {
+                        self.ancestors.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_respects_depth_and_order() {
+        // arrange
+        let dir = "assets/walk_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/b/deep/deeper", dir)).unwrap();
+        fs::write(format!("{}/c.txt", dir), "").unwrap();
+        fs::write(format!("{}/a.txt", dir), "").unwrap();
+        fs::write(format!("{}/b/deep/x.txt", dir), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", format!("{}/b/up", dir)).unwrap();
+        let relative = |entry: io::Result<WalkEntry>| {
+            let entry = entry.unwrap();
+            let path = entry.path.strip_prefix(dir).unwrap().to_path_buf();
+            (path.to_string_lossy().replace('\\', "/"), entry.depth)
+        };
+
+        // act
+        let shallow: Vec<_> = walk(dir)
+            .max_depth(2)
+            .sort(true)
+            .into_iter()
+            .map(relative)
+            .collect();
+        let followed: Vec<_> = walk(dir).follow_symlinks(true).into_iter().collect();
+
+        // assert
+        let expected = [
+            ("", 0),
+            ("a.txt", 1),
+            ("b", 1),
+            ("b/deep", 2),
+            ("b/up", 2),
+            ("c.txt", 1),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .filter(|(path, _)| cfg!(unix) || *path != "b/up")
+            .map(|&(path, depth)| (path.to_owned(), depth))
+            .collect();
+        assert_eq!(expected, shallow);
+        let loops = followed.iter().filter(|entry| entry.is_err()).count();
+        assert_eq!(if cfg!(unix) { 1 } else { 0 }, loops);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
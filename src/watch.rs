@@ -0,0 +1,954 @@
+//! Watch files and directories for changes.
+use crate::diff::{diff_lines, LineChange};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod inotify;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod kqueue;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use inotify::DirectoryChanges;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+use kqueue::DirectoryChanges;
+#[cfg(not(any(
+    windows,
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+use unsupported::DirectoryChanges;
+#[cfg(windows)]
+use windows::DirectoryChanges;
+
+const EVENT_LOG_HEADER: &str = "file-manager-events v1";
+
+/// What happened to a watched path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Modified,
+    Removed,
+    /// The path was renamed; the next event is its new name. Only reported
+    /// when [rename detection](Watcher::detect_renames) is on.
+    RenamedFrom,
+    /// The new name of the path in the previous event.
+    RenamedTo,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Modified => "modified",
+            EventKind::Removed => "removed",
+            EventKind::RenamedFrom => "renamed-from",
+            EventKind::RenamedTo => "renamed-to",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "created" => EventKind::Created,
+            "modified" => EventKind::Modified,
+            "removed" => EventKind::Removed,
+            "renamed-from" => EventKind::RenamedFrom,
+            "renamed-to" => EventKind::RenamedTo,
+            _ => return None,
+        })
+    }
+}
+
+/// A change observed by a [`Watcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub path: PathBuf,
+}
+
+/// An [`Event`] read back from an event log by [`replay`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedEvent {
+    /// Position of the event in the log, counting from 1. Consumers can store
+    /// the last one they handled and skip up to it on the next replay.
+    pub sequence: u64,
+    /// When the event was observed, with millisecond precision.
+    pub observed_at: SystemTime,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileState {
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Device and inode numbers, where available.
+    id: Option<(u64, u64)>,
+}
+
+/// Watches a file or directory by comparing snapshots of its metadata.
+///
+/// Each call to [`Watcher::poll`] rescans the watched path and reports what changed
+/// since the previous call, which works on every platform and filesystem.
+/// On Linux, Android, Windows, macOS and the BSDs, directories are watched with
+/// the platform's change notifications (inotify, `ReadDirectoryChangesW` or
+/// kqueue) so only the paths the kernel reports are rescanned, falling back to
+/// a full rescan if notifications are lost. With inotify, renames within the
+/// watched tree are paired as the kernel reports them.
+/// On Linux and Android, [`inotify`] exposes the kernel's own events when exact
+/// semantics are needed.
+pub struct Watcher {
+    root: PathBuf,
+    recursive: bool,
+    snapshot: HashMap<PathBuf, FileState>,
+    native: Option<DirectoryChanges>,
+    detect_renames: bool,
+    log: Option<EventLog>,
+}
+
+/// The log a [`Watcher`] appends its events to.
+struct EventLog {
+    file: File,
+    next_sequence: u64,
+}
+
+impl Watcher {
+    /// Start watching `path`. When `recursive == true`, changes in nested
+    /// subdirectories are reported as well; otherwise only direct children are watched.
+    pub fn new<P: AsRef<Path>>(path: P, recursive: bool) -> io::Result<Self> {
+        let root = path.as_ref().to_path_buf();
+        // Start listening before the first scan so no change slips in between.
+        let native = if root.is_dir() {
+            DirectoryChanges::new(&root, recursive).ok()
+        } else {
+            None
+        };
+        let mut snapshot = HashMap::new();
+        scan(&root, recursive, &mut snapshot)?;
+        Ok(Watcher {
+            root,
+            recursive,
+            snapshot,
+            native,
+            detect_renames: false,
+            log: None,
+        })
+    }
+
+    /// Report a removal and a creation in the same poll as a rename when
+    /// the removed path and the created one have the same size, modification
+    /// time and, on Unix, inode. A renamed directory is reported once, not
+    /// together with its contents. Detection is best effort: moves that change
+    /// a file's metadata are still reported as a removal and a creation.
+    pub fn detect_renames(mut self, detect: bool) -> Self {
+        self.detect_renames = detect;
+        self
+    }
+
+    /// Append every event returned by [`Watcher::poll`] to the log at `path`,
+    /// creating it if needed, so consumers can catch up with [`replay`].
+    ///
+    /// # Errors
+    /// `InvalidData` if `path` exists and is not an event log.
+    pub fn record_to<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut next_sequence = 1;
+        match replay(path, |_| Ok(())) {
+            Ok(count) => next_sequence += count,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        self.log = Some(EventLog {
+            file,
+            next_sequence,
+        });
+        Ok(self)
+    }
+
+    /// The path being watched.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Rescan the watched path and return the changes since the last poll.
+    /// Creations are ordered parents first and removals children first.
+    pub fn poll(&mut self) -> io::Result<Vec<Event>> {
+        let events = match &mut self.native {
+            Some(native) => match native.changed_paths()? {
+                Some(paths) => {
+                    let renames = native.renamed_paths();
+                    self.rescan_paths(paths, &renames)?
+                }
+                None => self.rescan()?,
+            },
+            None => self.rescan()?,
+        };
+        if let Some(log) = &mut self.log {
+            log.append(&events)?;
+        }
+        Ok(events)
+    }
+
+    /// Rescan the whole watched path.
+    fn rescan(&mut self) -> io::Result<Vec<Event>> {
+        let mut current = HashMap::new();
+        scan(&self.root, self.recursive, &mut current)?;
+        Ok(self.update(current, |_| true, &[]))
+    }
+
+    /// Rescan only `paths` and their descendants. `renames` are the pairs among
+    /// them the kernel reported as renames.
+    fn rescan_paths(
+        &mut self,
+        mut paths: Vec<PathBuf>,
+        renames: &[(PathBuf, PathBuf)],
+    ) -> io::Result<Vec<Event>> {
+        paths.sort();
+        paths.dedup();
+        let mut current = HashMap::new();
+        for path in &paths {
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            current.insert(path.clone(), state_of(&metadata));
+            if self.recursive && metadata.is_dir() {
+                scan_dir(path, true, &mut current)?;
+            }
+        }
+        let recursive = self.recursive;
+        Ok(self.update(
+            current,
+            |path| {
+                paths
+                    .iter()
+                    .any(|changed| path == changed || recursive && path.starts_with(changed))
+            },
+            renames,
+        ))
+    }
+
+    /// Diff `current` against the part of the snapshot for which `covers` is true,
+    /// then replace that part of the snapshot with `current`.
+    fn update(
+        &mut self,
+        current: HashMap<PathBuf, FileState>,
+        covers: impl Fn(&Path) -> bool,
+        renames: &[(PathBuf, PathBuf)],
+    ) -> Vec<Event> {
+        let mut created = Vec::new();
+        let mut modified = Vec::new();
+        let mut removed = Vec::new();
+        for (path, state) in &current {
+            match self.snapshot.get(path) {
+                None => created.push(path.clone()),
+                Some(old) if old.is_dir != state.is_dir => {
+                    removed.push(path.clone());
+                    created.push(path.clone());
+                }
+                Some(old) if !state.is_dir && old != state => modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        for path in self.snapshot.keys() {
+            if covers(path) && !current.contains_key(path) {
+                removed.push(path.clone());
+            }
+        }
+        removed.sort_by(|a, b| b.cmp(a));
+        created.sort();
+        modified.sort();
+        let renamed = if self.detect_renames {
+            self.pair_renames(&mut removed, &mut created, &current, renames)
+        } else {
+            Vec::new()
+        };
+        for path in &removed {
+            self.snapshot.remove(path);
+        }
+        for (from, _) in &renamed {
+            self.snapshot.retain(|path, _| !path.starts_with(from));
+        }
+        self.snapshot.extend(current);
+
+        removed
+            .into_iter()
+            .map(|path| (EventKind::Removed, path))
+            .chain(renamed.into_iter().flat_map(|(from, to)| {
+                [(EventKind::RenamedFrom, from), (EventKind::RenamedTo, to)]
+            }))
+            .chain(created.into_iter().map(|path| (EventKind::Created, path)))
+            .chain(modified.into_iter().map(|path| (EventKind::Modified, path)))
+            .map(|(kind, path)| Event { kind, path })
+            .collect()
+    }
+
+    /// Helper function to take the removals and creations that are renames out
+    /// of `removed` and `created`, returning them as `(from, to)` pairs.
+    /// `reported` are renames the kernel reported, which pair up even if the
+    /// metadata changed.
+    fn pair_renames(
+        &self,
+        removed: &mut Vec<PathBuf>,
+        created: &mut Vec<PathBuf>,
+        current: &HashMap<PathBuf, FileState>,
+        reported: &[(PathBuf, PathBuf)],
+    ) -> Vec<(PathBuf, PathBuf)> {
+        let mut pairs: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (from, to) in reported {
+            let paired = pairs.iter().any(|(a, b)| a == from || b == to);
+            if !paired
+                && removed.contains(from)
+                && created.contains(to)
+                && self.snapshot[from].is_dir == current[to].is_dir
+            {
+                pairs.push((from.clone(), to.clone()));
+            }
+        }
+        // Otherwise only states seen exactly once on each side pair up unambiguously.
+        let mut candidates: HashMap<FileState, (Vec<&PathBuf>, Vec<&PathBuf>)> = HashMap::new();
+        for path in removed.iter() {
+            if pairs.iter().any(|(from, _)| from == path) {
+                continue;
+            }
+            candidates
+                .entry(self.snapshot[path])
+                .or_default()
+                .0
+                .push(path);
+        }
+        for path in created.iter() {
+            if pairs.iter().any(|(_, to)| to == path) {
+                continue;
+            }
+            if let Some((_, to)) = candidates.get_mut(&current[path]) {
+                to.push(path);
+            }
+        }
+        pairs.extend(candidates.into_values().filter_map(|(from, to)| {
+            match (&from[..], &to[..]) {
+                ([from], [to]) if from != to => Some(((*from).clone(), (*to).clone())),
+                _ => None,
+            }
+        }));
+        pairs.sort();
+        // Drop the contents of renamed directories, reported by their parent.
+        let mut renamed: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (from, to) in pairs {
+            let covered = renamed.iter().any(|(parent_from, parent_to)| {
+                match (from.strip_prefix(parent_from), to.strip_prefix(parent_to)) {
+                    (Ok(a), Ok(b)) => a == b,
+                    _ => false,
+                }
+            });
+            if !covered {
+                renamed.push((from, to));
+            }
+        }
+        // Removals and creations under a renamed directory are part of the rename.
+        removed.retain(|path| !renamed.iter().any(|(from, _)| path.starts_with(from)));
+        created.retain(|path| !renamed.iter().any(|(_, to)| path.starts_with(to)));
+        renamed
+    }
+}
+
+impl EventLog {
+    /// Append `events` with one write, so a crash never leaves half a poll behind.
+    fn append(&mut self, events: &[Event]) -> io::Result<()> {
+        let mut lines = String::new();
+        if self.file.metadata()?.len() == 0 {
+            lines.push_str(EVENT_LOG_HEADER);
+            lines.push('\n');
+        }
+        let observed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        for (sequence, event) in (self.next_sequence..).zip(events) {
+            let path = match event.path.to_str() {
+                Some(path) if !path.contains(['\n', '\r']) => path,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("cannot log path {}", event.path.display()),
+                    ))
+                }
+            };
+            lines.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                sequence,
+                observed_at,
+                event.kind.as_str(),
+                path
+            ));
+        }
+        self.file.write_all(lines.as_bytes())?;
+        self.next_sequence += events.len() as u64;
+        Ok(())
+    }
+}
+
+/// Call `handler` with each event recorded in the `log` written by
+/// [`Watcher::record_to`], oldest first, returning how many there were.
+/// Replay stops at the first error from `handler`.
+///
+/// # Errors
+/// `InvalidData` if `log` is not an event log.
+pub fn replay<P, F>(log: P, mut handler: F) -> io::Result<u64>
+where
+    P: AsRef<Path>,
+    F: FnMut(LoggedEvent) -> io::Result<()>,
+{
+    let log = log.as_ref();
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid event log {}", log.display()),
+        )
+    };
+    let mut lines = BufReader::new(File::open(log)?).lines();
+    match lines.next().transpose()? {
+        Some(header) if header == EVENT_LOG_HEADER => {}
+        None => return Ok(0),
+        Some(_) => return Err(invalid()),
+    }
+    let mut count = 0;
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.splitn(4, '\t').collect();
+        let [sequence, observed_at, kind, path] = fields[..] else {
+            return Err(invalid());
+        };
+        let number = |field: &str| field.parse::<u64>().map_err(|_| invalid());
+        handler(LoggedEvent {
+            sequence: number(sequence)?,
+            observed_at: UNIX_EPOCH + Duration::from_millis(number(observed_at)?),
+            event: Event {
+                kind: EventKind::parse(kind).ok_or_else(invalid)?,
+                path: PathBuf::from(path),
+            },
+        })?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Options for [`watch`] and [`watch_channel`].
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// Report changes in nested subdirectories too.
+    pub recursive: bool,
+    /// Report renames as [`EventKind::RenamedFrom`] and [`EventKind::RenamedTo`] pairs.
+    pub detect_renames: bool,
+    /// How often the watched path is checked for changes.
+    pub poll_interval: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            recursive: true,
+            detect_renames: true,
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Handle to a running [`watch`]. Dropping it stops watching.
+pub struct WatchHandle {
+    state: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    /// Stop watching, after delivering the events of a poll in progress.
+    pub fn stop(self) {
+        // Handled by drop.
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        let (stopped, signal) = &*self.state;
+        *stopped.lock().unwrap() = true;
+        signal.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Watch `path` on a background thread, calling `callback` with each change,
+/// or with the error when a poll fails.
+pub fn watch<P, F>(path: P, options: WatchOptions, mut callback: F) -> io::Result<WatchHandle>
+where
+    P: AsRef<Path>,
+    F: FnMut(io::Result<Event>) + Send + 'static,
+{
+    let mut watcher = Watcher::new(path, options.recursive)?.detect_renames(options.detect_renames);
+    let state = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread_state = Arc::clone(&state);
+    let thread = thread::spawn(move || {
+        let (stopped, signal) = &*thread_state;
+        loop {
+            let guard = stopped.lock().unwrap();
+            let (guard, _) = signal
+                .wait_timeout_while(guard, options.poll_interval, |stopped| !*stopped)
+                .unwrap();
+            if *guard {
+                break;
+            }
+            drop(guard);
+            match watcher.poll() {
+                Ok(events) => events.into_iter().for_each(|event| callback(Ok(event))),
+                Err(e) => callback(Err(e)),
+            }
+        }
+    });
+    Ok(WatchHandle {
+        state,
+        thread: Some(thread),
+    })
+}
+
+/// Watch `path` on a background thread, sending each change, or the error
+/// when a poll fails, to the returned receiver.
+pub fn watch_channel<P: AsRef<Path>>(
+    path: P,
+    options: WatchOptions,
+) -> io::Result<(WatchHandle, mpsc::Receiver<io::Result<Event>>)> {
+    let (sender, receiver) = mpsc::channel();
+    let handle = watch(path, options, move |event| {
+        // Nobody is listening anymore; keep going until the handle is dropped.
+        let _ = sender.send(event);
+    })?;
+    Ok((handle, receiver))
+}
+
+/// An [`Event`] from a [`DiffWatcher`], with the lines that changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEvent {
+    pub event: Event,
+    /// The lines added and removed, for text files the watcher can compare:
+    /// all lines are added for a created file and removed for a removed one.
+    /// `None` for directories and for files that are not UTF-8 or over the size
+    /// limit, before or after the change.
+    pub diff: Option<Vec<LineChange>>,
+}
+
+/// A [`Watcher`] that remembers the contents of text files, so each change
+/// comes with a line-level diff of what changed.
+///
+/// Meant for small text files such as configuration: every text file up to
+/// the size limit is kept in memory.
+pub struct DiffWatcher {
+    watcher: Watcher,
+    max_bytes: u64,
+    contents: HashMap<PathBuf, String>,
+}
+
+impl DiffWatcher {
+    /// Start watching `path` like [`Watcher::new`], diffing UTF-8 files of up
+    /// to `max_bytes`.
+    pub fn new<P: AsRef<Path>>(path: P, recursive: bool, max_bytes: u64) -> io::Result<Self> {
+        let watcher = Watcher::new(path, recursive)?;
+        let mut contents = HashMap::new();
+        for path in watcher.snapshot.keys() {
+            if let Some(text) = read_text(path, max_bytes)? {
+                contents.insert(path.clone(), text);
+            }
+        }
+        Ok(DiffWatcher {
+            watcher,
+            max_bytes,
+            contents,
+        })
+    }
+
+    /// The path being watched.
+    pub fn path(&self) -> &Path {
+        self.watcher.path()
+    }
+
+    /// Rescan the watched path and return the changes since the last poll, in
+    /// the order of [`Watcher::poll`].
+    pub fn poll(&mut self) -> io::Result<Vec<DiffEvent>> {
+        let mut changes = Vec::new();
+        for event in self.watcher.poll()? {
+            let old = self.contents.remove(&event.path);
+            let new = match event.kind {
+                EventKind::Removed | EventKind::RenamedFrom => None,
+                EventKind::Created | EventKind::Modified | EventKind::RenamedTo => {
+                    read_text(&event.path, self.max_bytes)?
+                }
+            };
+            let diff = match (event.kind, &old, &new) {
+                (EventKind::Created, _, Some(new)) => Some(diff_lines("", new)),
+                (EventKind::Modified, Some(old), Some(new)) => Some(diff_lines(old, new)),
+                (EventKind::Removed, Some(old), _) => Some(diff_lines(old, "")),
+                _ => None,
+            };
+            if let Some(new) = new {
+                self.contents.insert(event.path.clone(), new);
+            }
+            changes.push(DiffEvent { event, diff });
+        }
+        Ok(changes)
+    }
+}
+
+/// Helper function to read the file at `path` if it is UTF-8 text of at most `max_bytes`.
+fn read_text(path: &Path, max_bytes: u64) -> io::Result<Option<String>> {
+    let result = fs::metadata(path).and_then(|metadata| {
+        if !metadata.is_file() || metadata.len() > max_bytes {
+            return Ok(None);
+        }
+        Ok(String::from_utf8(fs::read(path)?).ok())
+    });
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        result => result,
+    }
+}
+
+#[cfg(not(any(
+    windows,
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+mod unsupported {
+    use std::{
+        io,
+        path::{Path, PathBuf},
+    };
+
+    /// Stands in for native change notifications on platforms without a backend.
+    pub(crate) enum DirectoryChanges {}
+
+    impl DirectoryChanges {
+        pub(crate) fn new(_root: &Path, _recursive: bool) -> io::Result<Self> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+
+        pub(crate) fn changed_paths(&mut self) -> io::Result<Option<Vec<PathBuf>>> {
+            match *self {}
+        }
+
+        pub(crate) fn renamed_paths(&mut self) -> Vec<(PathBuf, PathBuf)> {
+            match *self {}
+        }
+    }
+}
+
+/// Record the state of `path` and, for directories, its children.
+fn scan(
+    path: &Path,
+    recursive: bool,
+    snapshot: &mut HashMap<PathBuf, FileState>,
+) -> io::Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        snapshot.insert(path.to_path_buf(), state_of(&metadata));
+        return Ok(());
+    }
+    scan_dir(path, recursive, snapshot)
+}
+
+fn scan_dir(
+    dir: &Path,
+    recursive: bool,
+    snapshot: &mut HashMap<PathBuf, FileState>,
+) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // The directory vanished mid-scan; it will be reported as removed.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let path = entry.path();
+        snapshot.insert(path.clone(), state_of(&metadata));
+        if recursive && metadata.is_dir() {
+            scan_dir(&path, recursive, snapshot)?;
+        }
+    }
+    Ok(())
+}
+
+fn state_of(metadata: &fs::Metadata) -> FileState {
+    FileState {
+        is_dir: metadata.is_dir(),
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+        id: file_id(metadata),
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::ChangeKind;
+
+    #[test]
+    fn watcher_poll_reports_changes() {
+        // arrange
+        let dir = "assets/watch_poll_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        fs::write(format!("{}/changed.txt", dir), "a").unwrap();
+        fs::write(format!("{}/removed.txt", dir), "").unwrap();
+        let mut watcher = Watcher::new(dir, true).unwrap();
+
+        // act
+        fs::write(format!("{}/changed.txt", dir), "longer").unwrap();
+        fs::remove_file(format!("{}/removed.txt", dir)).unwrap();
+        fs::write(format!("{}/sub/new.txt", dir), "").unwrap();
+        let events = watcher.poll().unwrap();
+        let quiet = watcher.poll().unwrap();
+
+        // assert
+        let event = |kind, path: &str| Event {
+            kind,
+            path: PathBuf::from(format!("{}/{}", dir, path)),
+        };
+        assert_eq!(
+            vec![
+                event(EventKind::Removed, "removed.txt"),
+                event(EventKind::Created, "sub/new.txt"),
+                event(EventKind::Modified, "changed.txt"),
+            ],
+            events
+        );
+        assert!(quiet.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recorded_events_replay_in_order() {
+        // arrange
+        let dir = "assets/watch_replay_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/watched", dir)).unwrap();
+        let watched = format!("{}/watched", dir);
+        let log = format!("{}/events.log", dir);
+        let mut watcher = Watcher::new(&watched, true)
+            .unwrap()
+            .record_to(&log)
+            .unwrap();
+
+        // act
+        fs::write(format!("{}/a.txt", watched), "a").unwrap();
+        let first = watcher.poll().unwrap();
+        drop(watcher);
+        let mut watcher = Watcher::new(&watched, true)
+            .unwrap()
+            .record_to(&log)
+            .unwrap();
+        fs::remove_file(format!("{}/a.txt", watched)).unwrap();
+        let second = watcher.poll().unwrap();
+        let mut replayed = Vec::new();
+        let count = replay(&log, |logged| {
+            replayed.push((logged.sequence, logged.event));
+            Ok(())
+        })
+        .unwrap();
+        fs::write(&log, "not an event log\n").unwrap();
+        let invalid = replay(&log, |_| Ok(()));
+
+        // assert
+        let expected: Vec<_> = (1..).zip(first.into_iter().chain(second)).collect();
+        assert_eq!(2, count);
+        assert_eq!(expected, replayed);
+        assert_eq!(io::ErrorKind::InvalidData, invalid.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diff_watcher_reports_changed_lines() {
+        // arrange
+        let dir = "assets/watch_diff_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let config = format!("{}/app.toml", dir);
+        fs::write(&config, "port = 80\nhost = a\n").unwrap();
+        fs::write(format!("{}/blob.bin", dir), [0xff, 0xfe]).unwrap();
+        let mut watcher = DiffWatcher::new(dir, false, 1024).unwrap();
+
+        // act
+        fs::write(&config, "port = 8080\nhost = a\n").unwrap();
+        fs::write(format!("{}/blob.bin", dir), [0xff, 0xfe, 0xfd]).unwrap();
+        let events = watcher.poll().unwrap();
+
+        // assert
+        let diffs: Vec<_> = events
+            .iter()
+            .map(|e| (e.event.path.file_name().unwrap().to_owned(), e.diff.clone()))
+            .collect();
+        let change = |kind, text: &str| LineChange {
+            kind,
+            line: 1,
+            text: text.to_owned(),
+        };
+        assert_eq!(
+            vec![
+                (
+                    "app.toml".into(),
+                    Some(vec![
+                        change(ChangeKind::Removed, "port = 80"),
+                        change(ChangeKind::Added, "port = 8080"),
+                    ])
+                ),
+                ("blob.bin".into(), None),
+            ],
+            diffs
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn watch_channel_reports_renames() {
+        // arrange
+        let dir = "assets/watch_channel_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/old/inner", dir)).unwrap();
+        fs::write(format!("{}/old/inner/a.txt", dir), "a").unwrap();
+        fs::write(format!("{}/draft.txt", dir), "draft").unwrap();
+        let options = WatchOptions {
+            poll_interval: Duration::from_millis(10),
+            ..WatchOptions::default()
+        };
+        let (handle, events) = watch_channel(dir, options).unwrap();
+        let next = || {
+            events
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+                .unwrap()
+        };
+
+        // act
+        fs::rename(format!("{}/draft.txt", dir), format!("{}/final.txt", dir)).unwrap();
+        let file_events = [next(), next()];
+        fs::rename(format!("{}/old", dir), format!("{}/new", dir)).unwrap();
+        let dir_events = [next(), next()];
+        handle.stop();
+
+        // assert
+        let event = |kind, path: &str| Event {
+            kind,
+            path: PathBuf::from(format!("{}/{}", dir, path)),
+        };
+        assert_eq!(
+            [
+                event(EventKind::RenamedFrom, "draft.txt"),
+                event(EventKind::RenamedTo, "final.txt"),
+            ],
+            file_events
+        );
+        assert_eq!(
+            [
+                event(EventKind::RenamedFrom, "old"),
+                event(EventKind::RenamedTo, "new"),
+            ],
+            dir_events
+        );
+        assert!(events.try_recv().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn watcher_pairs_renames_reported_by_inotify() {
+        // arrange
+        let dir = "assets/watch_inotify_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        fs::write(format!("{}/a.txt", dir), "a").unwrap();
+        let mut watcher = Watcher::new(dir, true).unwrap().detect_renames(true);
+
+        // act
+        fs::rename(format!("{}/a.txt", dir), format!("{}/sub/b.txt", dir)).unwrap();
+        fs::write(format!("{}/sub/b.txt", dir), "changed").unwrap();
+        fs::create_dir(format!("{}/sub/new", dir)).unwrap();
+        fs::write(format!("{}/sub/new/c.txt", dir), "").unwrap();
+        let events = watcher.poll().unwrap();
+        fs::write(format!("{}/sub/new/c.txt", dir), "c").unwrap();
+        let nested = watcher.poll().unwrap();
+
+        // assert
+        let event = |kind, path: &str| Event {
+            kind,
+            path: PathBuf::from(format!("{}/{}", dir, path)),
+        };
+        assert!(watcher.native.is_some());
+        assert_eq!(
+            vec![
+                event(EventKind::RenamedFrom, "a.txt"),
+                event(EventKind::RenamedTo, "sub/b.txt"),
+                event(EventKind::Created, "sub/new"),
+                event(EventKind::Created, "sub/new/c.txt"),
+            ],
+            events
+        );
+        assert_eq!(vec![event(EventKind::Modified, "sub/new/c.txt")], nested);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,481 @@
+//! A thin, safe wrapper around Linux inotify, and the native change
+//! notifications [`Watcher`](super::Watcher) uses on Linux and Android.
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsString},
+    fs, io, mem,
+    ops::{BitOr, BitOrAssign},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The events [`DirectoryChanges`] watches each directory for.
+const DIRECTORY_EVENTS: Mask = Mask(
+    libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_MOVE
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF
+        | libc::IN_ONLYDIR,
+);
+
+/// A set of inotify event flags, used both to select events in
+/// [`Inotify::add_watch`] and to describe a delivered [`InotifyEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mask(u32);
+
+impl Mask {
+    pub const ACCESS: Mask = Mask(libc::IN_ACCESS);
+    pub const MODIFY: Mask = Mask(libc::IN_MODIFY);
+    pub const ATTRIB: Mask = Mask(libc::IN_ATTRIB);
+    pub const CLOSE_WRITE: Mask = Mask(libc::IN_CLOSE_WRITE);
+    pub const CLOSE_NOWRITE: Mask = Mask(libc::IN_CLOSE_NOWRITE);
+    pub const OPEN: Mask = Mask(libc::IN_OPEN);
+    pub const MOVED_FROM: Mask = Mask(libc::IN_MOVED_FROM);
+    pub const MOVED_TO: Mask = Mask(libc::IN_MOVED_TO);
+    pub const CREATE: Mask = Mask(libc::IN_CREATE);
+    pub const DELETE: Mask = Mask(libc::IN_DELETE);
+    pub const DELETE_SELF: Mask = Mask(libc::IN_DELETE_SELF);
+    pub const MOVE_SELF: Mask = Mask(libc::IN_MOVE_SELF);
+    pub const CLOSE: Mask = Mask(libc::IN_CLOSE);
+    pub const MOVE: Mask = Mask(libc::IN_MOVE);
+    pub const ALL_EVENTS: Mask = Mask(libc::IN_ALL_EVENTS);
+
+    /// Only watch `path` if it is a directory.
+    pub const ONLYDIR: Mask = Mask(libc::IN_ONLYDIR);
+    /// Don't follow `path` if it is a symlink.
+    pub const DONT_FOLLOW: Mask = Mask(libc::IN_DONT_FOLLOW);
+    /// Stop reporting events for children once they are unlinked.
+    pub const EXCL_UNLINK: Mask = Mask(libc::IN_EXCL_UNLINK);
+    /// Add to the mask of an existing watch instead of replacing it.
+    pub const MASK_ADD: Mask = Mask(libc::IN_MASK_ADD);
+    /// Remove the watch after the first event.
+    pub const ONESHOT: Mask = Mask(libc::IN_ONESHOT);
+
+    /// The watch was removed, explicitly or because its target went away.
+    pub const IGNORED: Mask = Mask(libc::IN_IGNORED);
+    /// The subject of the event is a directory.
+    pub const ISDIR: Mask = Mask(libc::IN_ISDIR);
+    /// The event queue overflowed and events were dropped.
+    pub const Q_OVERFLOW: Mask = Mask(libc::IN_Q_OVERFLOW);
+    /// The filesystem containing the watched object was unmounted.
+    pub const UNMOUNT: Mask = Mask(libc::IN_UNMOUNT);
+
+    /// Build a mask from raw `IN_*` bits.
+    pub const fn from_bits(bits: u32) -> Self {
+        Mask(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if every flag in `other` is set in `self`.
+    pub const fn contains(self, other: Mask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if any flag in `other` is set in `self`.
+    pub const fn intersects(self, other: Mask) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Mask {
+    type Output = Mask;
+
+    fn bitor(self, rhs: Mask) -> Mask {
+        Mask(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Mask {
+    fn bitor_assign(&mut self, rhs: Mask) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Identifies a watch added with [`Inotify::add_watch`].
+///
+/// Adding a watch for a path that is already watched returns the same descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WatchDescriptor(i32);
+
+impl WatchDescriptor {
+    /// The raw descriptor number used by the kernel.
+    pub fn as_raw(self) -> i32 {
+        self.0
+    }
+}
+
+/// A single event read from an [`Inotify`] instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InotifyEvent {
+    /// The watch the event belongs to. It is `-1` for [`Mask::Q_OVERFLOW`].
+    pub wd: WatchDescriptor,
+    pub mask: Mask,
+    /// Pairs the [`Mask::MOVED_FROM`] and [`Mask::MOVED_TO`] halves of a rename.
+    pub cookie: u32,
+    /// The name of the affected entry, for events on a watched directory's children.
+    pub name: Option<OsString>,
+}
+
+/// An inotify instance. The file descriptor is closed when it is dropped.
+#[derive(Debug)]
+pub struct Inotify {
+    fd: OwnedFd,
+}
+
+impl Inotify {
+    /// Create a new inotify instance.
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: inotify_init1 takes no pointers.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just returned by the kernel and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Inotify { fd })
+    }
+
+    /// Start watching `path` for the events in `mask`.
+    pub fn add_watch<P: AsRef<Path>>(&self, path: P, mask: Mask) -> io::Result<WatchDescriptor> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `path` is a valid NUL-terminated string for the duration of the call.
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask.0) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(WatchDescriptor(wd))
+    }
+
+    /// Stop watching. An [`Mask::IGNORED`] event is queued for `wd`.
+    pub fn rm_watch(&self, wd: WatchDescriptor) -> io::Result<()> {
+        // SAFETY: inotify_rm_watch takes no pointers.
+        if unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd.0 as _) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Block until at least one event is available and return every queued event.
+    pub fn read_events(&self) -> io::Result<Vec<InotifyEvent>> {
+        self.read_events_timeout(None)
+    }
+
+    /// Like [`Inotify::read_events`], but gives up after `timeout` and returns
+    /// an empty list. `None` waits forever.
+    pub fn read_events_timeout(&self, timeout: Option<Duration>) -> io::Result<Vec<InotifyEvent>> {
+        if !self.wait_readable(timeout)? {
+            return Ok(Vec::new());
+        }
+        // Large enough for at least one event with a NAME_MAX name.
+        let mut buf = vec![0u8; 64 * 1024];
+        let n = loop {
+            // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n >= 0 {
+                break n as usize;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        };
+        Ok(parse_events(&buf[..n]))
+    }
+
+    fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let timeout_ms = match timeout {
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            // SAFETY: `pollfd` is a valid array of one element.
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                n if n > 0 => return Ok(true),
+                0 => return Ok(false),
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsFd for Inotify {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// An inotify instance with a watch on every watched directory.
+///
+/// inotify reports changes to a directory's entries by name, so only
+/// directories are watched, and the two halves of a rename within the tree are
+/// paired by their cookie. If the event queue overflows, the per-user watch
+/// limit is reached or the root itself goes away, the caller is told to fall
+/// back to full rescans.
+pub(crate) struct DirectoryChanges {
+    root: PathBuf,
+    recursive: bool,
+    inotify: Inotify,
+    watched: HashMap<PathBuf, WatchDescriptor>,
+    paths: HashMap<WatchDescriptor, PathBuf>,
+    renames: Vec<(PathBuf, PathBuf)>,
+    exhausted: bool,
+}
+
+impl DirectoryChanges {
+    /// Register `root` and, when `recursive`, the directories beneath it.
+    pub(crate) fn new(root: &Path, recursive: bool) -> io::Result<Self> {
+        let mut changes = DirectoryChanges {
+            root: root.to_path_buf(),
+            recursive,
+            inotify: Inotify::new()?,
+            watched: HashMap::new(),
+            paths: HashMap::new(),
+            renames: Vec::new(),
+            exhausted: false,
+        };
+        changes.watch_tree(root)?;
+        Ok(changes)
+    }
+
+    /// Drain pending events without blocking and return the affected paths.
+    ///
+    /// Returns `None` when events may have been missed, in which case the caller
+    /// must rescan the whole tree.
+    pub(crate) fn changed_paths(&mut self) -> io::Result<Option<Vec<PathBuf>>> {
+        self.renames.clear();
+        if self.exhausted {
+            return Ok(None);
+        }
+        let mut changed = Vec::new();
+        let mut moved_from = HashMap::new();
+        let mut overflowed = false;
+        loop {
+            let events = self.inotify.read_events_timeout(Some(Duration::ZERO))?;
+            if events.is_empty() {
+                break;
+            }
+            for event in events {
+                if event.mask.contains(Mask::Q_OVERFLOW) {
+                    overflowed = true;
+                    continue;
+                }
+                // Events for watches that were removed since are stale.
+                let Some(dir) = self.paths.get(&event.wd).cloned() else {
+                    continue;
+                };
+                if event.mask.contains(Mask::IGNORED) {
+                    self.watched.remove(&dir);
+                    self.paths.remove(&event.wd);
+                    continue;
+                }
+                let Some(name) = event.name else {
+                    if event.mask.intersects(Mask::DELETE_SELF | Mask::MOVE_SELF) {
+                        // Events for a moved root would carry the wrong paths.
+                        if dir == self.root {
+                            self.unwatch(&self.root.clone());
+                            self.exhausted = true;
+                        }
+                        changed.push(dir);
+                    }
+                    continue;
+                };
+                let path = dir.join(name);
+                let is_dir = event.mask.contains(Mask::ISDIR);
+                if event.mask.contains(Mask::MOVED_FROM) {
+                    // The watches follow the directory; they are added back
+                    // under its new name if it stays within the tree.
+                    if is_dir {
+                        self.unwatch(&path);
+                    }
+                    moved_from.insert(event.cookie, path.clone());
+                } else if event.mask.contains(Mask::MOVED_TO) {
+                    if let Some(from) = moved_from.remove(&event.cookie) {
+                        self.renames.push((from, path.clone()));
+                    }
+                }
+                if is_dir && self.recursive && event.mask.intersects(Mask::CREATE | Mask::MOVED_TO)
+                {
+                    self.watch_tree(&path)?;
+                }
+                changed.push(path);
+            }
+        }
+        if overflowed && !self.exhausted {
+            // Directories created or moved since may not be watched; start over.
+            let root = self.root.clone();
+            self.unwatch(&root);
+            self.watch_tree(&root)?;
+        }
+        if overflowed || self.exhausted {
+            return Ok(None);
+        }
+        Ok(Some(changed))
+    }
+
+    /// The renames within the tree among the paths last returned by
+    /// [`DirectoryChanges::changed_paths`], as `(from, to)` pairs.
+    pub(crate) fn renamed_paths(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        mem::take(&mut self.renames)
+    }
+
+    /// Watch the directory `dir` and, when recursive, the directories beneath it.
+    fn watch_tree(&mut self, dir: &Path) -> io::Result<()> {
+        if !self.watch(dir)? || !self.recursive {
+            return Ok(());
+        }
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.watch_tree(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch the directory `dir`, returning `false` if it is gone, is not a
+    /// directory or the watch limit was reached.
+    fn watch(&mut self, dir: &Path) -> io::Result<bool> {
+        if self.exhausted {
+            return Ok(false);
+        }
+        match self.inotify.add_watch(dir, DIRECTORY_EVENTS) {
+            Ok(wd) => {
+                if let Some(old) = self.paths.insert(wd, dir.to_path_buf()) {
+                    self.watched.remove(&old);
+                }
+                self.watched.insert(dir.to_path_buf(), wd);
+                Ok(true)
+            }
+            Err(e) => match e.raw_os_error() {
+                Some(libc::ENOENT) | Some(libc::ENOTDIR) => Ok(false),
+                Some(libc::ENOSPC) => {
+                    self.unwatch(&self.root.clone());
+                    self.exhausted = true;
+                    Ok(false)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Stop watching `path` and every directory beneath it.
+    fn unwatch(&mut self, path: &Path) {
+        let gone: Vec<PathBuf> = self
+            .watched
+            .keys()
+            .filter(|watched| watched.starts_with(path))
+            .cloned()
+            .collect();
+        for watched in gone {
+            if let Some(wd) = self.watched.remove(&watched) {
+                self.paths.remove(&wd);
+                // The directory may be gone already, taking its watch with it.
+                let _ = self.inotify.rm_watch(wd);
+            }
+        }
+    }
+}
+
+/// Decode the packed `struct inotify_event` records the kernel returned.
+fn parse_events(mut buf: &[u8]) -> Vec<InotifyEvent> {
+    const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+    let mut events = Vec::new();
+    while buf.len() >= HEADER {
+        let field =
+            |at: usize| u32::from_ne_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        let wd = field(0) as i32;
+        let mask = field(4);
+        let cookie = field(8);
+        let len = field(12) as usize;
+        let end = (HEADER + len).min(buf.len());
+        let name = &buf[HEADER..end];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        events.push(InotifyEvent {
+            wd: WatchDescriptor(wd),
+            mask: Mask(mask),
+            cookie,
+            name: (!name.is_empty()).then(|| OsString::from_vec(name.to_vec())),
+        });
+        buf = &buf[end..];
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn inotify_reports_renames_with_cookies() {
+        // arrange
+        let dir = "assets/inotify_rename_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let inotify = Inotify::new().unwrap();
+        let wd = inotify.add_watch(dir, Mask::CREATE | Mask::MOVE).unwrap();
+
+        // act
+        fs::write(format!("{}/a.txt", dir), "").unwrap();
+        fs::rename(format!("{}/a.txt", dir), format!("{}/b.txt", dir)).unwrap();
+        let mut events = Vec::new();
+        while events.len() < 3 {
+            let batch = inotify
+                .read_events_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            assert!(!batch.is_empty(), "timed out waiting for events");
+            events.extend(batch);
+        }
+        inotify.rm_watch(wd).unwrap();
+        let ignored = inotify
+            .read_events_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // assert
+        let names: Vec<_> = events
+            .iter()
+            .map(|e| e.name.clone().unwrap().into_string().unwrap())
+            .collect();
+        assert_eq!(vec!["a.txt", "a.txt", "b.txt"], names);
+        assert!(events.iter().all(|e| e.wd == wd));
+        assert!(events[0].mask.contains(Mask::CREATE));
+        assert!(events[1].mask.contains(Mask::MOVED_FROM));
+        assert!(events[2].mask.contains(Mask::MOVED_TO));
+        assert_ne!(0, events[1].cookie);
+        assert_eq!(events[1].cookie, events[2].cookie);
+        assert!(ignored.iter().any(|e| e.mask.contains(Mask::IGNORED)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
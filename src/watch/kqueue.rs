@@ -0,0 +1,238 @@
+//! Native change notifications on macOS and the BSDs using kqueue.
+use crate::fd::{self, FdPermit};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs, io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const OPEN_FLAGS: libc::c_int = libc::O_EVTONLY | libc::O_CLOEXEC;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_CLOEXEC;
+
+const REMOVED: u32 = libc::NOTE_DELETE | libc::NOTE_RENAME | libc::NOTE_REVOKE;
+const CHANGED: u32 = libc::NOTE_WRITE | libc::NOTE_EXTEND | libc::NOTE_ATTRIB;
+
+/// A kqueue with a vnode filter registered for every watched file and directory.
+///
+/// kqueue reports events on open descriptors rather than paths, so each entry
+/// in the tree is kept open. If the process runs out of descriptors, or the
+/// crate's descriptor budget is used up, the caller is told to fall back to
+/// full rescans.
+pub(crate) struct DirectoryChanges {
+    root: PathBuf,
+    recursive: bool,
+    kq: OwnedFd,
+    watched: HashMap<PathBuf, (OwnedFd, FdPermit)>,
+    paths: HashMap<RawFd, PathBuf>,
+    exhausted: bool,
+}
+
+impl DirectoryChanges {
+    /// Register `root` and the entries beneath it.
+    pub(crate) fn new(root: &Path, recursive: bool) -> io::Result<Self> {
+        // SAFETY: kqueue takes no arguments.
+        let kq = unsafe { libc::kqueue() };
+        if kq < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut changes = DirectoryChanges {
+            root: root.to_path_buf(),
+            recursive,
+            // SAFETY: `kq` was just returned by the kernel and is owned by nobody else.
+            kq: unsafe { OwnedFd::from_raw_fd(kq) },
+            watched: HashMap::new(),
+            paths: HashMap::new(),
+            exhausted: false,
+        };
+        changes.watch_tree(root)?;
+        Ok(changes)
+    }
+
+    /// Drain pending events without blocking and return the affected paths.
+    ///
+    /// Returns `None` when events may have been missed, in which case the caller
+    /// must rescan the whole tree.
+    pub(crate) fn changed_paths(&mut self) -> io::Result<Option<Vec<PathBuf>>> {
+        if self.exhausted {
+            return Ok(None);
+        }
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+        loop {
+            // SAFETY: a zeroed kevent is a valid value of the plain C struct.
+            let mut events: [libc::kevent; 64] = unsafe { mem::zeroed() };
+            let timeout = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            // SAFETY: `events` is valid for `events.len()` entries.
+            let n = unsafe {
+                libc::kevent(
+                    self.kq.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    events.as_mut_ptr(),
+                    events.len() as _,
+                    &timeout,
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            for event in &events[..n as usize] {
+                let Some(path) = self.paths.get(&(event.ident as RawFd)).cloned() else {
+                    continue;
+                };
+                let fflags = event.fflags;
+                if fflags & REMOVED != 0 {
+                    self.unwatch(&path);
+                    changed.push(path.clone());
+                    removed.push(path);
+                } else if fflags & libc::NOTE_WRITE != 0 && path.is_dir() {
+                    if path == self.root || self.recursive {
+                        self.watch_new_children(&path, &mut changed)?;
+                    }
+                } else if fflags & CHANGED != 0 && !path.is_dir() {
+                    changed.push(path);
+                }
+            }
+            if (n as usize) < events.len() {
+                break;
+            }
+        }
+        // A path may have been replaced before its removal was seen.
+        for path in removed {
+            if path.exists() && !self.watched.contains_key(&path) {
+                self.watch_tree(&path)?;
+            }
+        }
+        if self.exhausted {
+            return Ok(None);
+        }
+        Ok(Some(changed))
+    }
+
+    /// kqueue does not name a rename's new path; the caller pairs renames from
+    /// the rescanned metadata.
+    pub(crate) fn renamed_paths(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        Vec::new()
+    }
+
+    /// Start watching entries of `dir` that aren't watched yet and record them as changed.
+    fn watch_new_children(&mut self, dir: &Path, changed: &mut Vec<PathBuf>) -> io::Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if !self.watched.contains_key(&path) {
+                self.watch_tree(&path)?;
+                changed.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch `path` and, for directories within the watched depth, its children.
+    fn watch_tree(&mut self, path: &Path) -> io::Result<()> {
+        self.watch(path)?;
+        if !(path == self.root || self.recursive) || !path.is_dir() {
+            return Ok(());
+        }
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let child = entry?.path();
+            if self.recursive {
+                self.watch_tree(&child)?;
+            } else {
+                self.watch(&child)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn watch(&mut self, path: &Path) -> io::Result<()> {
+        if self.watched.contains_key(path) {
+            return Ok(());
+        }
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let Some(permit) = fd::try_acquire(1) else {
+            self.exhausted = true;
+            return Ok(());
+        };
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        let fd = unsafe { libc::open(c_path.as_ptr(), OPEN_FLAGS) };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENOENT) => Ok(()),
+                Some(libc::EMFILE) | Some(libc::ENFILE) => {
+                    self.exhausted = true;
+                    Ok(())
+                }
+                _ => Err(e),
+            };
+        }
+        // SAFETY: `fd` was just returned by the kernel and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: a zeroed kevent is a valid value of the plain C struct.
+        let mut change: libc::kevent = unsafe { mem::zeroed() };
+        change.ident = fd.as_raw_fd() as _;
+        change.filter = libc::EVFILT_VNODE as _;
+        change.flags = (libc::EV_ADD | libc::EV_CLEAR) as _;
+        change.fflags = REMOVED | CHANGED;
+        // SAFETY: `change` is a single valid kevent and no events are requested back.
+        let result = unsafe {
+            libc::kevent(
+                self.kq.as_raw_fd(),
+                &change,
+                1,
+                ptr::null_mut(),
+                0,
+                ptr::null(),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.paths.insert(fd.as_raw_fd(), path.to_path_buf());
+        self.watched.insert(path.to_path_buf(), (fd, permit));
+        Ok(())
+    }
+
+    /// Stop watching `path` and everything beneath it. Closing a descriptor
+    /// removes its kqueue registration.
+    fn unwatch(&mut self, path: &Path) {
+        let gone: Vec<PathBuf> = self
+            .watched
+            .keys()
+            .filter(|watched| watched.starts_with(path))
+            .cloned()
+            .collect();
+        for watched in gone {
+            if let Some((fd, _)) = self.watched.remove(&watched) {
+                self.paths.remove(&fd.as_raw_fd());
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Native directory change notifications using `ReadDirectoryChangesW`.
+use std::{
+    ffi::OsString,
+    io,
+    os::windows::{
+        ffi::{OsStrExt, OsStringExt},
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_IO_INCOMPLETE, ERROR_NOTIFY_ENUM_DIR, FALSE, INVALID_HANDLE_VALUE, TRUE},
+    Storage::FileSystem::{
+        CreateFileW, ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OVERLAPPED,
+        FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_CREATION, FILE_NOTIFY_CHANGE_DIR_NAME,
+        FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE,
+        FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+    System::{
+        Threading::CreateEventW,
+        IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+    },
+};
+
+/// Size of the notification buffer in `u32`s. Changes that don't fit are
+/// reported by the kernel as an overflow.
+const BUFFER_LEN: usize = 16 * 1024;
+
+/// An outstanding overlapped `ReadDirectoryChangesW` call on a directory.
+pub(crate) struct DirectoryChanges {
+    root: PathBuf,
+    recursive: bool,
+    dir: OwnedHandle,
+    _event: OwnedHandle,
+    // Boxed so their addresses stay fixed while the kernel writes to them.
+    overlapped: Box<OVERLAPPED>,
+    buffer: Box<[u32; BUFFER_LEN]>,
+}
+
+// SAFETY: the raw event handle in `overlapped` is owned by `_event`, and
+// directory handles and overlapped reads may be used from any thread.
+unsafe impl Send for DirectoryChanges {}
+
+impl DirectoryChanges {
+    /// Open `root` and start listening for changes beneath it.
+    pub(crate) fn new(root: &Path, recursive: bool) -> io::Result<Self> {
+        let wide: Vec<u16> = root.as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `wide` is a valid NUL-terminated UTF-16 string.
+        let dir = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                ptr::null(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+                ptr::null_mut(),
+            )
+        };
+        if dir == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `dir` is a valid handle that nothing else owns.
+        let dir = unsafe { OwnedHandle::from_raw_handle(dir) };
+        // SAFETY: all pointer arguments may be null.
+        let event = unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) };
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `event` is a valid handle that nothing else owns.
+        let event = unsafe { OwnedHandle::from_raw_handle(event) };
+
+        let mut overlapped: Box<OVERLAPPED> = Box::default();
+        overlapped.hEvent = event.as_raw_handle();
+        let mut changes = DirectoryChanges {
+            root: root.to_path_buf(),
+            recursive,
+            dir,
+            _event: event,
+            overlapped,
+            buffer: Box::new([0; BUFFER_LEN]),
+        };
+        changes.issue()?;
+        Ok(changes)
+    }
+
+    /// Start the next asynchronous read.
+    fn issue(&mut self) -> io::Result<()> {
+        let filter = FILE_NOTIFY_CHANGE_FILE_NAME
+            | FILE_NOTIFY_CHANGE_DIR_NAME
+            | FILE_NOTIFY_CHANGE_SIZE
+            | FILE_NOTIFY_CHANGE_LAST_WRITE
+            | FILE_NOTIFY_CHANGE_CREATION;
+        // SAFETY: the buffer and OVERLAPPED are heap allocations owned by `self`,
+        // and `Drop` waits for the read to finish before they are freed.
+        let ok = unsafe {
+            ReadDirectoryChangesW(
+                self.dir.as_raw_handle(),
+                self.buffer.as_mut_ptr().cast(),
+                (BUFFER_LEN * 4) as u32,
+                if self.recursive { TRUE } else { FALSE },
+                filter,
+                ptr::null_mut(),
+                &mut *self.overlapped,
+                None,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Drain completed notifications without blocking and return the affected paths.
+    ///
+    /// Returns `None` when the kernel dropped notifications because its buffer
+    /// overflowed, in which case the caller must rescan the whole tree.
+    pub(crate) fn changed_paths(&mut self) -> io::Result<Option<Vec<PathBuf>>> {
+        let mut paths = Vec::new();
+        loop {
+            let mut bytes = 0;
+            // SAFETY: `overlapped` belongs to the read issued on `dir`.
+            let ok = unsafe {
+                GetOverlappedResult(
+                    self.dir.as_raw_handle(),
+                    &*self.overlapped,
+                    &mut bytes,
+                    FALSE,
+                )
+            };
+            if ok == 0 {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error().map(|code| code as u32) {
+                    Some(ERROR_IO_INCOMPLETE) => return Ok(Some(paths)),
+                    Some(ERROR_NOTIFY_ENUM_DIR) => {
+                        self.issue()?;
+                        return Ok(None);
+                    }
+                    _ => return Err(e),
+                }
+            }
+            if bytes == 0 {
+                self.issue()?;
+                return Ok(None);
+            }
+            self.parse(bytes as usize, &mut paths);
+            self.issue()?;
+        }
+    }
+
+    /// Renames are not paired from the notifications; the caller pairs them
+    /// from the rescanned metadata.
+    pub(crate) fn renamed_paths(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        Vec::new()
+    }
+
+    /// Append the paths named in the first `len` bytes of the buffer.
+    fn parse(&self, len: usize, paths: &mut Vec<PathBuf>) {
+        // SAFETY: the buffer is plain data and at least `len` bytes long.
+        let bytes: &[u8] = unsafe { std::slice::from_raw_parts(self.buffer.as_ptr().cast(), len) };
+        let mut offset = 0;
+        while offset + 12 <= len {
+            let field = |at: usize| {
+                u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+            };
+            let next = field(offset) as usize;
+            let name_len = field(offset + 8) as usize;
+            let name_start = offset + 12;
+            let name_end = (name_start + name_len).min(len);
+            let name: Vec<u16> = bytes[name_start..name_end]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            paths.push(self.root.join(OsString::from_wide(&name)));
+            if next == 0 {
+                break;
+            }
+            offset += next;
+        }
+    }
+}
+
+impl Drop for DirectoryChanges {
+    fn drop(&mut self) {
+        let mut bytes = 0;
+        // SAFETY: cancels the read issued with `overlapped`, then waits for the
+        // kernel to release the buffer before it is freed.
+        unsafe {
+            CancelIoEx(self.dir.as_raw_handle(), &*self.overlapped);
+            GetOverlappedResult(
+                self.dir.as_raw_handle(),
+                &*self.overlapped,
+                &mut bytes,
+                TRUE,
+            );
+        }
+    }
+}
@@ -0,0 +1,737 @@
+//! ZIP archives, readable one entry at a time.
+use crate::{
+    archive::{
+        archive_source, create_new_file, file_mode, mtime_secs, safe_join, set_dir_modes, set_mode,
+        ExtractionLimits, LimitTracker,
+    },
+    checksum::Crc32,
+    error::{Context, Operation},
+    temp_sibling,
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Take, Write},
+    path::{Path, PathBuf},
+};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR: u32 = 0x0605_4b50;
+const LOCAL_HEADER_LEN: u64 = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_OF_CENTRAL_DIR_LEN: u64 = 22;
+/// Version 2.0, the first with deflate and directories.
+const VERSION_NEEDED: u16 = 20;
+/// Unix attributes, written by version 3.0 of the specification.
+const VERSION_MADE_BY: u16 = (3 << 8) | 30;
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// A file or directory listed in a ZIP archive's central directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// Path of the entry inside the archive, with `/` separators. Directory
+    /// names end with `/`.
+    pub name: String,
+    pub is_dir: bool,
+    /// Size of the entry once extracted.
+    pub size: u64,
+    /// Size of the entry's data in the archive.
+    pub compressed_size: u64,
+    crc32: u32,
+    method: u16,
+    flags: u16,
+    /// Permission bits, when the archive was made on Unix.
+    mode: Option<u32>,
+    header_offset: u64,
+}
+
+/// Reads the entries of a ZIP archive on demand, seeking straight to each one
+/// instead of unpacking the archive.
+pub struct ZipReader<R: Read + Seek> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipReader<BufReader<File>> {
+    /// Open the ZIP archive at `path` and read its central directory.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ZipReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> ZipReader<R> {
+    /// Read the central directory of the ZIP archive in `reader`.
+    ///
+    /// # Errors
+    /// `InvalidData` if `reader` does not hold a ZIP archive, and `Unsupported`
+    /// for ZIP64 archives and archives split across several files.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let (offset, count) = find_central_dir(&mut reader)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut reader_buf = BufReader::new(&mut reader);
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(read_central_header(&mut reader_buf)?);
+        }
+        Ok(ZipReader { reader, entries })
+    }
+
+    /// The entries of the archive, in the order they are stored.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// A reader over the extracted contents of the entry called `name`. The
+    /// checksum is verified once it has been read to the end.
+    ///
+    /// # Errors
+    /// `NotFound` if there is no such entry, and `Unsupported` if it is
+    /// encrypted or compressed with a method other than deflate.
+    pub fn by_name(&mut self, name: &str) -> io::Result<ZipFile<'_, R>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("no entry `{}` in archive", name),
+                )
+            })?;
+        entry_reader(&mut self.reader, entry)
+    }
+
+    /// Extract the file called `name` to `path`, replacing any file there. A
+    /// symlink at `path` is replaced too, not written through.
+    pub fn extract_file<P: AsRef<Path>>(&mut self, name: &str, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mode = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.mode);
+        let mut data = self.by_name(name)?;
+        let mut file = replace_file(path)?;
+        io::copy(&mut data, &mut file)?;
+        if let Some(mode) = mode {
+            set_mode(path, mode)?;
+        }
+        Ok(())
+    }
+}
+
+/// The contents of one entry of a [`ZipReader`], decompressed as it is read.
+pub struct ZipFile<'a, R: Read> {
+    data: EntryData<'a, R>,
+    crc: Option<Crc32>,
+    expected_crc: u32,
+    remaining: u64,
+    name: &'a str,
+}
+
+enum EntryData<'a, R: Read> {
+    Stored(Take<&'a mut R>),
+    Deflated(DeflateDecoder<Take<&'a mut R>>),
+}
+
+impl<R: Read> Read for ZipFile<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Never read past the recorded size, whatever the compressed data says.
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = match &mut self.data {
+            EntryData::Stored(data) => data.read(&mut buf[..max])?,
+            EntryData::Deflated(data) => data.read(&mut buf[..max])?,
+        };
+        self.remaining -= n as u64;
+        let Some(crc) = self.crc.as_mut() else {
+            return Ok(n);
+        };
+        crc.update(&buf[..n]);
+        if n == 0 || self.remaining == 0 {
+            let actual = self.crc.take().map(Crc32::finish);
+            if self.remaining > 0 {
+                return Err(invalid_archive(&format!("`{}` is truncated", self.name)));
+            }
+            if actual != Some(self.expected_crc) {
+                return Err(invalid_archive(&format!(
+                    "checksum mismatch in `{}`",
+                    self.name
+                )));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Archive `dir` into a ZIP file at `archive_path`, replacing any file there.
+///
+/// Entries are prefixed with the name of `dir`, so [`extract_zip`] recreates
+/// the directory inside its destination. Files are deflated; symlinks and
+/// special files are skipped.
+///
+/// # Errors
+/// `Unsupported` if a file or the archive would need ZIP64, at 4 GiB.
+pub fn create_zip<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, archive_path: Q) -> io::Result<()> {
+    let archive_path = archive_path.as_ref();
+    let (dir, root_name) = archive_source(dir.as_ref(), archive_path)?;
+    let tmp_path = temp_sibling(archive_path, "tmp");
+    let result = (|| {
+        let mut zip = ZipWriter::new(BufWriter::new(File::create_new(&tmp_path)?));
+        append_dir_recursive(&mut zip, &dir, &root_name)?;
+        let file = zip.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, archive_path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Extract the ZIP archive at `archive_path` into `dest`, with the default
+/// [`ExtractionLimits`].
+///
+/// Entries with absolute paths or `..` components, or that would be written
+/// through a symlink, are rejected before anything is written for them.
+///
+/// # Returns
+/// The paths of the extracted files.
+pub fn extract_zip<P: AsRef<Path>, Q: AsRef<Path>>(
+    archive_path: P,
+    dest: Q,
+) -> io::Result<Vec<PathBuf>> {
+    let archive_path = archive_path.as_ref();
+    let archive = File::open(archive_path).context(Operation::Open, archive_path)?;
+    let archive_len = archive
+        .metadata()
+        .context(Operation::Stat, archive_path)?
+        .len();
+    let mut tracker = LimitTracker::new(ExtractionLimits::default(), archive_len);
+    let mut zip = ZipReader::new(BufReader::new(archive)).context(Operation::Read, archive_path)?;
+    let dest = dest.as_ref();
+    fs::create_dir_all(dest).context(Operation::Create, dest)?;
+    let mut extracted = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in &zip.entries {
+        tracker.entry(&entry.name)?;
+        let path = safe_join(dest, &entry.name)?;
+        if entry.is_dir {
+            fs::create_dir_all(&path).context(Operation::Create, &path)?;
+            // Applied last, so read-only directories can still be filled.
+            dirs.extend(entry.mode.map(|mode| (path, mode)));
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(Operation::Create, parent)?;
+        }
+        let data = entry_reader(&mut zip.reader, entry).context(Operation::Read, archive_path)?;
+        let file = replace_file(&path).context(Operation::Create, &path)?;
+        tracker.copy(&entry.name, data, file)?;
+        if let Some(mode) = entry.mode {
+            set_mode(&path, mode).context(Operation::Write, &path)?;
+        }
+        extracted.push(path);
+    }
+    set_dir_modes(&dirs)?;
+    Ok(extracted)
+}
+
+/// Helper function to create a new file at `path` in place of any file or
+/// symlink there, so nothing is ever written through a symlink.
+fn replace_file(path: &Path) -> io::Result<File> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path)?,
+        _ => {}
+    }
+    create_new_file(path)
+}
+
+/// Writes a ZIP archive, patching each local header once its entry's sizes
+/// and checksum are known.
+struct ZipWriter<W: Write + Seek> {
+    out: W,
+    central_dir: Vec<u8>,
+    count: u64,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    fn new(out: W) -> Self {
+        ZipWriter {
+            out,
+            central_dir: Vec::new(),
+            count: 0,
+        }
+    }
+
+    fn append_dir(&mut self, name: &str, mode: u32, mtime: u64) -> io::Result<()> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        let offset = self.start_entry(&name, METHOD_STORED, mtime)?;
+        self.end_entry(&name, METHOD_STORED, mtime, mode, offset, 0, 0, 0)
+    }
+
+    fn append_file<R: Read>(
+        &mut self,
+        name: &str,
+        mode: u32,
+        mtime: u64,
+        mut data: R,
+    ) -> io::Result<()> {
+        let offset = self.start_entry(name, METHOD_DEFLATED, mtime)?;
+        let data_start = self.out.stream_position()?;
+        let mut crc = Crc32::new();
+        let mut size = 0u64;
+        let mut encoder = DeflateEncoder::new(&mut self.out, Compression::default());
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = match data.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            crc.update(&buf[..n]);
+            encoder.write_all(&buf[..n])?;
+            size += n as u64;
+        }
+        encoder.finish()?;
+        let compressed_size = self.out.stream_position()? - data_start;
+        let crc = crc.finish();
+        self.end_entry(
+            name,
+            METHOD_DEFLATED,
+            mtime,
+            mode,
+            offset,
+            crc,
+            compressed_size,
+            size,
+        )
+    }
+
+    /// Write a local header with the checksum and sizes left blank, returning
+    /// its offset.
+    fn start_entry(&mut self, name: &str, method: u16, mtime: u64) -> io::Result<u64> {
+        let offset = self.out.stream_position()?;
+        let (time, date) = dos_date_time(mtime);
+        let mut header = Vec::with_capacity(LOCAL_HEADER_LEN as usize + name.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&name_len(name)?.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.out.write_all(&header)?;
+        Ok(offset)
+    }
+
+    /// Fill in the local header at `offset` and record the entry in the
+    /// central directory.
+    #[allow(clippy::too_many_arguments)]
+    fn end_entry(
+        &mut self,
+        name: &str,
+        method: u16,
+        mtime: u64,
+        mode: u32,
+        offset: u64,
+        crc: u32,
+        compressed_size: u64,
+        size: u64,
+    ) -> io::Result<()> {
+        let (offset32, compressed32, size32) =
+            (zip32(offset)?, zip32(compressed_size)?, zip32(size)?);
+        let end = self.out.stream_position()?;
+        zip32(end)?;
+        self.out.seek(SeekFrom::Start(offset + 14))?;
+        let mut sizes = Vec::with_capacity(12);
+        sizes.extend_from_slice(&crc.to_le_bytes());
+        sizes.extend_from_slice(&compressed32.to_le_bytes());
+        sizes.extend_from_slice(&size32.to_le_bytes());
+        self.out.write_all(&sizes)?;
+        self.out.seek(SeekFrom::Start(end))?;
+
+        let (time, date) = dos_date_time(mtime);
+        let header = &mut self.central_dir;
+        header.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        header.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&sizes);
+        header.extend_from_slice(&name_len(name)?.to_le_bytes());
+        // Extra field and comment lengths, disk number and internal attributes.
+        header.extend_from_slice(&[0; 8]);
+        let kind = if name.ends_with('/') {
+            0o040000
+        } else {
+            0o100000
+        };
+        let dos_dir = u32::from(name.ends_with('/')) << 4;
+        header.extend_from_slice(&(((kind | (mode & 0o7777)) << 16) | dos_dir).to_le_bytes());
+        header.extend_from_slice(&offset32.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the central directory and return the underlying writer.
+    fn finish(mut self) -> io::Result<W> {
+        let offset = zip32(self.out.stream_position()?)?;
+        let size = zip32(self.central_dir.len() as u64)?;
+        let count = u16::try_from(self.count).map_err(|_| zip64_unsupported())?;
+        self.out.write_all(&self.central_dir)?;
+        let mut end = Vec::with_capacity(END_OF_CENTRAL_DIR_LEN as usize);
+        end.extend_from_slice(&END_OF_CENTRAL_DIR.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn append_dir_recursive<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    dir: &Path,
+    archive_name: &str,
+) -> io::Result<()> {
+    let metadata = fs::metadata(dir)?;
+    zip.append_dir(archive_name, file_mode(&metadata), mtime_secs(&metadata))?;
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("cannot archive non UTF-8 path {}", path.display()),
+            )
+        })?;
+        let entry_name = format!("{}/{}", archive_name, name);
+        let metadata = fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            append_dir_recursive(zip, &path, &entry_name)?;
+        } else if metadata.is_file() {
+            zip.append_file(
+                &entry_name,
+                file_mode(&metadata),
+                mtime_secs(&metadata),
+                File::open(&path)?,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Helper function to find the central directory from the end record,
+/// returning its offset and number of entries.
+fn find_central_dir<R: Read + Seek>(reader: &mut R) -> io::Result<(u64, usize)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    // The end record is followed by a comment of at most 64 KiB.
+    let tail_len = len.min(END_OF_CENTRAL_DIR_LEN + u16::MAX as u64);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+    let start = (0..tail
+        .len()
+        .saturating_sub(END_OF_CENTRAL_DIR_LEN as usize - 1))
+        .rev()
+        .find(|&i| tail[i..i + 4] == END_OF_CENTRAL_DIR.to_le_bytes())
+        .ok_or_else(|| invalid_archive("no end of central directory record"))?;
+    let end = &tail[start..];
+    if u16_at(end, 4) != 0 || u16_at(end, 6) != 0 || u16_at(end, 8) != u16_at(end, 10) {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "split ZIP archives are not supported",
+        ));
+    }
+    let count = u16_at(end, 10);
+    let offset = u32_at(end, 16);
+    if count == u16::MAX || offset == u32::MAX {
+        return Err(zip64_unsupported());
+    }
+    Ok((offset as u64, count as usize))
+}
+
+fn read_central_header<R: Read>(reader: &mut R) -> io::Result<ZipEntry> {
+    let mut header = [0; CENTRAL_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if u32_at(&header, 0) != CENTRAL_HEADER {
+        return Err(invalid_archive("invalid central directory header"));
+    }
+    let mut name = vec![0; u16_at(&header, 28) as usize];
+    reader.read_exact(&mut name)?;
+    let skip = u16_at(&header, 30) as u64 + u16_at(&header, 32) as u64;
+    io::copy(&mut reader.take(skip), &mut io::sink())?;
+    let (compressed_size, size, header_offset) = (
+        u32_at(&header, 20),
+        u32_at(&header, 24),
+        u32_at(&header, 42),
+    );
+    if [compressed_size, size, header_offset].contains(&u32::MAX) {
+        return Err(zip64_unsupported());
+    }
+    let name = String::from_utf8_lossy(&name).into_owned();
+    let attributes = u32_at(&header, 38);
+    let made_on_unix = u16_at(&header, 4) >> 8 == 3;
+    Ok(ZipEntry {
+        is_dir: name.ends_with('/'),
+        name,
+        size: size as u64,
+        compressed_size: compressed_size as u64,
+        crc32: u32_at(&header, 16),
+        method: u16_at(&header, 10),
+        flags: u16_at(&header, 8),
+        mode: (made_on_unix && attributes >> 16 != 0).then_some((attributes >> 16) & 0o7777),
+        header_offset: header_offset as u64,
+    })
+}
+
+/// Helper function to seek `reader` to the data of `entry` and decompress it.
+fn entry_reader<'a, R: Read + Seek>(
+    reader: &'a mut R,
+    entry: &'a ZipEntry,
+) -> io::Result<ZipFile<'a, R>> {
+    if entry.flags & FLAG_ENCRYPTED != 0 {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("`{}` is encrypted", entry.name),
+        ));
+    }
+    reader.seek(SeekFrom::Start(entry.header_offset))?;
+    let mut header = [0; LOCAL_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    if u32_at(&header, 0) != LOCAL_HEADER {
+        return Err(invalid_archive(&format!(
+            "invalid local header for `{}`",
+            entry.name
+        )));
+    }
+    let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+    reader.seek(SeekFrom::Current(skip))?;
+    let compressed = reader.take(entry.compressed_size);
+    let data = match entry.method {
+        METHOD_STORED => EntryData::Stored(compressed),
+        METHOD_DEFLATED => EntryData::Deflated(DeflateDecoder::new(compressed)),
+        method => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "`{}` uses unsupported compression method {}",
+                    entry.name, method
+                ),
+            ))
+        }
+    };
+    Ok(ZipFile {
+        data,
+        crc: Some(Crc32::new()),
+        expected_crc: entry.crc32,
+        remaining: entry.size,
+        name: &entry.name,
+    })
+}
+
+/// Helper function to convert seconds since the Unix epoch, in UTC, to an
+/// MS-DOS time and date. Times before 1980 are clamped to its start.
+fn dos_date_time(secs: u64) -> (u16, u16) {
+    let days = secs / 86_400;
+    let secs_of_day = secs % 86_400;
+    // Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = year.min(2107);
+    let time =
+        ((secs_of_day / 3600) << 11) | ((secs_of_day % 3600 / 60) << 5) | (secs_of_day % 60 / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+fn name_len(name: &str) -> io::Result<u16> {
+    u16::try_from(name.len()).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("entry name too long: {}", name),
+        )
+    })
+}
+
+fn zip32(value: u64) -> io::Result<u32> {
+    u32::try_from(value)
+        .ok()
+        .filter(|&value| value != u32::MAX)
+        .ok_or_else(zip64_unsupported)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn zip64_unsupported() -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, "ZIP64 archives are not supported")
+}
+
+fn invalid_archive(msg: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid ZIP archive: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{extraction_limit_exceeded, ExtractionLimit};
+
+    #[test]
+    fn zip_round_trip_and_single_entry_extraction() {
+        // arrange
+        let dir = "assets/zip_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/site/css", dir)).unwrap();
+        let page = "<p>hello</p>\n".repeat(1000);
+        fs::write(format!("{}/site/index.html", dir), &page).unwrap();
+        fs::write(format!("{}/site/css/main.css", dir), "p {}").unwrap();
+        fs::write(format!("{}/site/empty.txt", dir), "").unwrap();
+        let archive = format!("{}/site.zip", dir);
+        let mut evil = ZipWriter::new(io::Cursor::new(Vec::new()));
+        evil.append_file("../escape.txt", 0o644, 0, &b"gotcha"[..])
+            .unwrap();
+        fs::write(
+            format!("{}/evil.zip", dir),
+            evil.finish().unwrap().into_inner(),
+        )
+        .unwrap();
+
+        // act
+        create_zip(format!("{}/site", dir), &archive).unwrap();
+        let mut zip = ZipReader::open(&archive).unwrap();
+        let names: Vec<_> = zip.entries().iter().map(|e| e.name.clone()).collect();
+        let mut css = String::new();
+        zip.by_name("site/css/main.css")
+            .unwrap()
+            .read_to_string(&mut css)
+            .unwrap();
+        zip.extract_file("site/index.html", format!("{}/single.html", dir))
+            .unwrap();
+        let missing = zip.by_name("site/missing.txt").map(|_| ());
+        let extracted = extract_zip(&archive, format!("{}/out", dir)).unwrap();
+        let traversal =
+            extract_zip(format!("{}/evil.zip", dir), format!("{}/evil", dir)).unwrap_err();
+
+        // assert
+        assert_eq!(
+            vec![
+                "site/",
+                "site/css/",
+                "site/css/main.css",
+                "site/empty.txt",
+                "site/index.html",
+            ],
+            names
+        );
+        assert!(zip.entries()[4].compressed_size < zip.entries()[4].size);
+        assert_eq!("p {}", css);
+        assert_eq!(
+            page,
+            fs::read_to_string(format!("{}/single.html", dir)).unwrap()
+        );
+        assert_eq!(ErrorKind::NotFound, missing.unwrap_err().kind());
+        assert_eq!(3, extracted.len());
+        assert_eq!(
+            page,
+            fs::read_to_string(format!("{}/out/site/index.html", dir)).unwrap()
+        );
+        assert_eq!(
+            Some(ExtractionLimit::PathTraversal),
+            extraction_limit_exceeded(&traversal).map(|e| e.limit)
+        );
+        assert!(!Path::new(&format!("{}/escape.txt", dir)).exists());
+        assert_eq!((0, 0x21), dos_date_time(0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extraction_never_writes_through_symlinks() {
+        // arrange
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = "assets/zip_symlink_test";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/out/ro", dir)).unwrap();
+        fs::write(format!("{}/victim.txt", dir), "safe").unwrap();
+        symlink("../../victim.txt", format!("{}/out/ro/a.txt", dir)).unwrap();
+        symlink("victim.txt", format!("{}/single.txt", dir)).unwrap();
+        let archive = format!("{}/ro.zip", dir);
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        zip.append_dir("ro", 0o555, 0).unwrap();
+        zip.append_file("ro/a.txt", 0o644, 0, &b"gotcha"[..])
+            .unwrap();
+        zip.finish().unwrap();
+
+        // act
+        let through_link = extract_zip(&archive, format!("{}/out", dir));
+        fs::remove_file(format!("{}/out/ro/a.txt", dir)).unwrap();
+        let extracted = extract_zip(&archive, format!("{}/out", dir)).unwrap();
+        ZipReader::open(&archive)
+            .unwrap()
+            .extract_file("ro/a.txt", format!("{}/single.txt", dir))
+            .unwrap();
+        let mode = fs::metadata(format!("{}/out/ro", dir))
+            .unwrap()
+            .permissions()
+            .mode();
+
+        // assert
+        assert_eq!(
+            Some(ExtractionLimit::PathTraversal),
+            extraction_limit_exceeded(&through_link.unwrap_err()).map(|e| e.limit)
+        );
+        assert_eq!(1, extracted.len());
+        assert_eq!(0o555, mode & 0o777);
+        assert_eq!(
+            "safe",
+            fs::read_to_string(format!("{}/victim.txt", dir)).unwrap()
+        );
+        assert!(!fs::symlink_metadata(format!("{}/single.txt", dir))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        fs::set_permissions(format!("{}/out/ro", dir), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}